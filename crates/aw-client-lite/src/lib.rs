@@ -77,6 +77,18 @@ impl AwClient {
         Ok(())
     }
 
+    pub async fn delete_event(&self, bucket_id: &str, event_id: i64) -> Result<()> {
+        let url = format!("{}/buckets/{}/events/{}", self.api_url, bucket_id, event_id);
+        self.client
+            .delete(&url)
+            .send()
+            .await
+            .context("Failed to send delete event request")?
+            .error_for_status()
+            .context("Failed to delete event")?;
+        Ok(())
+    }
+
     pub async fn get_events(
         &self,
         bucket_id: &str,
@@ -0,0 +1,50 @@
+//! Compares the sampling dhash against the previous resize + grayscale
+//! implementation on a 4K frame.
+//!
+//! Run with `cargo bench -p aw-watcher-screenshot --bench dhash`.
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/hash.rs"]
+mod hash;
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use image::{DynamicImage, RgbaImage, imageops};
+
+/// The original implementation: resize the full frame, then convert to grayscale.
+fn dhash_resize(image: &DynamicImage) -> u64 {
+    let resolution = 8;
+    let resized = imageops::resize(
+        image,
+        resolution + 1,
+        resolution,
+        imageops::FilterType::Nearest,
+    );
+    let gray = imageops::grayscale(&resized);
+
+    let mut hash = 0u64;
+    for y in 0..resolution {
+        for x in 0..resolution {
+            if gray.get_pixel(x, y)[0] < gray.get_pixel(x + 1, y)[0] {
+                hash |= 1 << (y * resolution + x);
+            }
+        }
+    }
+    hash
+}
+
+fn frame_4k() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(3840, 2160, |x, y| {
+        image::Rgba([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8, 255])
+    }))
+}
+
+fn bench_dhash(c: &mut Criterion) {
+    let frame = frame_4k();
+    let mut group = c.benchmark_group("dhash_4k");
+    group.bench_function("resize", |b| b.iter(|| dhash_resize(black_box(&frame))));
+    group.bench_function("sampled", |b| b.iter(|| hash::dhash(black_box(&frame))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_dhash);
+criterion_main!(benches);
@@ -0,0 +1,383 @@
+//! Capture through DXGI Desktop Duplication (Windows 8+).
+//!
+//! The desktop image is copied on the GPU and read back once per capture,
+//! which costs far less CPU than xcap's GDI path. Duplication also reports
+//! which regions changed since the previous capture; they are passed on as
+//! damage so the filter can skip hashing monitors that did not change.
+//!
+//! Outputs in HDR mode are duplicated in scRGB floating point and tone
+//! mapped to sRGB, see `crate::hdr`; without tone mapping they are read as
+//! 8-bit, which clips highlights.
+//!
+//! Rotated outputs are not supported: their desktop image is delivered in
+//! the panel's native orientation.
+
+use super::{CaptureBackend, CaptureTarget, Frame, clip_region};
+use crate::event::Rect;
+use crate::hdr::ToneMap;
+use anyhow::{Error, Result};
+use image::{DynamicImage, RgbaImage};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Mutex;
+use windows::Win32::Foundation::{HMODULE, RECT};
+use windows::Win32::Graphics::Direct3D::D3D_DRIVER_TYPE_UNKNOWN;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_MAP_READ,
+    D3D11_MAPPED_SUBRESOURCE, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D,
+};
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020, DXGI_FORMAT_B8G8R8A8_UNORM,
+    DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_MODE_ROTATION_IDENTITY, DXGI_MODE_ROTATION_UNSPECIFIED,
+    DXGI_SAMPLE_DESC,
+};
+use windows::Win32::Graphics::Dxgi::{
+    CreateDXGIFactory1, DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_NOT_FOUND, DXGI_ERROR_WAIT_TIMEOUT,
+    DXGI_OUTDUPL_FRAME_INFO, DXGI_OUTDUPL_MOVE_RECT, IDXGIFactory1, IDXGIOutput1, IDXGIOutput5,
+    IDXGIOutput6, IDXGIOutputDuplication, IDXGIResource,
+};
+use windows::core::Interface;
+
+/// How long to wait for a new desktop frame before reusing the previous one.
+const FRAME_TIMEOUT_MS: u32 = 50;
+
+/// A desktop image and the regions changed since the previous one, if known.
+type DesktopFrame = (RgbaImage, Option<Vec<Rect>>);
+
+/// The duplication of one output and the last image read from it.
+struct Duplication {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    duplication: IDXGIOutputDuplication,
+    /// Set for outputs in HDR mode, whose frames are scRGB.
+    tone_map: Option<ToneMap>,
+    last: Option<RgbaImage>,
+}
+
+// SAFETY: D3D11 devices are free-threaded; the immediate context and the
+// duplication are only used while holding the backend's lock.
+unsafe impl Send for Duplication {}
+
+pub struct DxgiBackend {
+    /// Duplications by monitor id, created on first capture.
+    outputs: Mutex<HashMap<u32, Duplication>>,
+    /// SDR white level for tone mapping HDR outputs, `None` to read them as 8-bit.
+    sdr_white_nits: Option<f32>,
+}
+
+impl DxgiBackend {
+    pub fn new(sdr_white_nits: Option<f32>) -> Self {
+        Self {
+            outputs: Mutex::new(HashMap::new()),
+            sdr_white_nits,
+        }
+    }
+}
+
+impl CaptureBackend for DxgiBackend {
+    fn name(&self) -> &'static str {
+        "dxgi"
+    }
+
+    fn capture(&self, target: &CaptureTarget, region: Option<Rect>) -> Result<Frame, Error> {
+        let mut outputs = self.outputs.lock().unwrap_or_else(|e| e.into_inner());
+        let mut retried = false;
+        let (image, damage) = loop {
+            if !outputs.contains_key(&target.id) {
+                outputs.insert(target.id, unsafe {
+                    Duplication::new(target, self.sdr_white_nits)?
+                });
+            }
+            let duplication = outputs.get_mut(&target.id).expect("inserted above");
+            match unsafe { duplication.next_frame() } {
+                Ok(frame) => break frame,
+                // Mode changes, desktop switches (UAC, lock screen) and
+                // fullscreen apps invalidate the duplication
+                Err(e) if e.code() == DXGI_ERROR_ACCESS_LOST && !retried => {
+                    outputs.remove(&target.id);
+                    retried = true;
+                }
+                Err(e) => {
+                    outputs.remove(&target.id);
+                    return Err(e.into());
+                }
+            }
+        };
+
+        let Some(region) = region else {
+            return Ok(Frame {
+                image: DynamicImage::ImageRgba8(image),
+                region: None,
+                damage,
+            });
+        };
+        let region = clip_region(region, image.width(), image.height())?;
+        let image =
+            image::imageops::crop_imm(&image, region.x, region.y, region.width, region.height)
+                .to_image();
+        // Damage relative to the region
+        let damage = damage.map(|damage| {
+            damage
+                .iter()
+                .filter_map(|rect| rect.intersect(&region))
+                .map(|rect| Rect {
+                    x: rect.x - region.x,
+                    y: rect.y - region.y,
+                    ..rect
+                })
+                .collect()
+        });
+        Ok(Frame {
+            image: DynamicImage::ImageRgba8(image),
+            region: Some(region),
+            damage,
+        })
+    }
+}
+
+impl Duplication {
+    /// Duplicate the DXGI output showing `target`, in scRGB if it is in HDR
+    /// mode and `sdr_white_nits` is set.
+    unsafe fn new(target: &CaptureTarget, sdr_white_nits: Option<f32>) -> Result<Self, Error> {
+        unsafe {
+            let factory: IDXGIFactory1 = CreateDXGIFactory1()?;
+            for adapter_index in 0.. {
+                let adapter = match factory.EnumAdapters1(adapter_index) {
+                    Ok(adapter) => adapter,
+                    Err(e) if e.code() == DXGI_ERROR_NOT_FOUND => break,
+                    Err(e) => return Err(e.into()),
+                };
+                let mut output_index = 0;
+                while let Ok(output) = adapter.EnumOutputs(output_index) {
+                    output_index += 1;
+                    let desc = output.GetDesc()?;
+                    // xcap uses the HMONITOR as monitor id
+                    let coordinates = desc.DesktopCoordinates;
+                    if desc.Monitor.0 as usize as u32 != target.id
+                        && (coordinates.left, coordinates.top) != (target.x, target.y)
+                    {
+                        continue;
+                    }
+                    if desc.Rotation != DXGI_MODE_ROTATION_IDENTITY
+                        && desc.Rotation != DXGI_MODE_ROTATION_UNSPECIFIED
+                    {
+                        anyhow::bail!("Monitor {} is rotated", target.id);
+                    }
+
+                    let mut device = None;
+                    let mut context = None;
+                    D3D11CreateDevice(
+                        &adapter,
+                        D3D_DRIVER_TYPE_UNKNOWN,
+                        HMODULE::default(),
+                        D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                        None,
+                        D3D11_SDK_VERSION,
+                        Some(&mut device),
+                        None,
+                        Some(&mut context),
+                    )?;
+                    let device: ID3D11Device =
+                        device.ok_or_else(|| anyhow::anyhow!("No D3D11 device"))?;
+                    let context =
+                        context.ok_or_else(|| anyhow::anyhow!("No D3D11 device context"))?;
+                    let tone_map = match (sdr_white_nits, output.cast::<IDXGIOutput6>()) {
+                        (Some(sdr_white_nits), Ok(output)) => {
+                            let desc = output.GetDesc1()?;
+                            (desc.ColorSpace == DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020).then(
+                                || {
+                                    tracing::info!(
+                                        monitor_id = target.id,
+                                        peak_nits = desc.MaxLuminance,
+                                        "HDR output, tone mapping captures"
+                                    );
+                                    ToneMap::new(sdr_white_nits, desc.MaxLuminance)
+                                },
+                            )
+                        }
+                        _ => None,
+                    };
+                    let duplication = if tone_map.is_some() {
+                        output.cast::<IDXGIOutput5>()?.DuplicateOutput1(
+                            &device,
+                            0,
+                            &[DXGI_FORMAT_R16G16B16A16_FLOAT],
+                        )?
+                    } else {
+                        output.cast::<IDXGIOutput1>()?.DuplicateOutput(&device)?
+                    };
+                    return Ok(Self {
+                        device,
+                        context,
+                        duplication,
+                        tone_map,
+                        last: None,
+                    });
+                }
+            }
+            anyhow::bail!("No DXGI output shows monitor {}", target.id)
+        }
+    }
+
+    /// The current desktop image and the regions changed since the previous call.
+    ///
+    /// The changed regions are unknown (`None`) for the first frame.
+    unsafe fn next_frame(&mut self) -> windows::core::Result<DesktopFrame> {
+        unsafe {
+            let mut info = DXGI_OUTDUPL_FRAME_INFO::default();
+            let mut resource: Option<IDXGIResource> = None;
+            match self
+                .duplication
+                .AcquireNextFrame(FRAME_TIMEOUT_MS, &mut info, &mut resource)
+            {
+                // Nothing was presented since the previous capture
+                Err(e) if e.code() == DXGI_ERROR_WAIT_TIMEOUT && self.last.is_some() => {
+                    return Ok((self.last.clone().unwrap_or_default(), Some(Vec::new())));
+                }
+                result => result?,
+            }
+
+            let result = if info.AccumulatedFrames == 0 && self.last.is_some() {
+                // Only the pointer moved
+                Ok((self.last.clone().unwrap_or_default(), Some(Vec::new())))
+            } else {
+                self.read_frame(&info, resource)
+            };
+            let _ = self.duplication.ReleaseFrame();
+            result
+        }
+    }
+
+    unsafe fn read_frame(
+        &mut self,
+        info: &DXGI_OUTDUPL_FRAME_INFO,
+        resource: Option<IDXGIResource>,
+    ) -> windows::core::Result<DesktopFrame> {
+        unsafe {
+            let damage = if self.last.is_some() {
+                Some(self.changed_rects(info)?)
+            } else {
+                None
+            };
+
+            let texture: ID3D11Texture2D = resource
+                .ok_or_else(|| windows::core::Error::from(DXGI_ERROR_ACCESS_LOST))?
+                .cast()?;
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            texture.GetDesc(&mut desc);
+            let expected = match self.tone_map {
+                Some(_) => DXGI_FORMAT_R16G16B16A16_FLOAT,
+                None => DXGI_FORMAT_B8G8R8A8_UNORM,
+            };
+            if desc.Format != expected {
+                // Anything but the requested format would be misread below;
+                // recreate the duplication (e.g. after HDR was toggled)
+                return Err(windows::core::Error::from(DXGI_ERROR_ACCESS_LOST));
+            }
+            let staging_desc = D3D11_TEXTURE2D_DESC {
+                Width: desc.Width,
+                Height: desc.Height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: desc.Format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_STAGING,
+                BindFlags: 0,
+                CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+                MiscFlags: 0,
+            };
+            let mut staging = None;
+            self.device
+                .CreateTexture2D(&staging_desc, None, Some(&mut staging))?;
+            let staging =
+                staging.ok_or_else(|| windows::core::Error::from(DXGI_ERROR_ACCESS_LOST))?;
+            self.context.CopyResource(&staging, &texture);
+
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.context
+                .Map(&staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+            let (width, height) = (desc.Width as usize, desc.Height as usize);
+            let mut image = RgbaImage::new(desc.Width, desc.Height);
+            let bytes_per_pixel = if self.tone_map.is_some() { 8 } else { 4 };
+            for (y, dst) in image.chunks_exact_mut(width * 4).enumerate().take(height) {
+                let src = std::slice::from_raw_parts(
+                    (mapped.pData as *const u8).add(y * mapped.RowPitch as usize),
+                    width * bytes_per_pixel,
+                );
+                let pixels = dst
+                    .chunks_exact_mut(4)
+                    .zip(src.chunks_exact(bytes_per_pixel));
+                match &self.tone_map {
+                    Some(tone_map) => {
+                        for (dst, src) in pixels {
+                            let channel = |i: usize| {
+                                half::f16::from_le_bytes([src[i * 2], src[i * 2 + 1]]).to_f32()
+                            };
+                            let [r, g, b] = tone_map.map([channel(0), channel(1), channel(2)]);
+                            dst.copy_from_slice(&[r, g, b, 255]);
+                        }
+                    }
+                    None => {
+                        for (dst, src) in pixels {
+                            dst.copy_from_slice(&[src[2], src[1], src[0], 255]);
+                        }
+                    }
+                }
+            }
+            self.context.Unmap(&staging, 0);
+
+            self.last = Some(image.clone());
+            Ok((image, damage))
+        }
+    }
+
+    /// Dirty and moved-to rectangles of the acquired frame.
+    unsafe fn changed_rects(
+        &self,
+        info: &DXGI_OUTDUPL_FRAME_INFO,
+    ) -> windows::core::Result<Vec<Rect>> {
+        unsafe {
+            let size = info.TotalMetadataBufferSize as usize;
+            let mut rects = Vec::new();
+            if size == 0 {
+                return Ok(rects);
+            }
+
+            let mut moves =
+                vec![DXGI_OUTDUPL_MOVE_RECT::default(); size / size_of::<DXGI_OUTDUPL_MOVE_RECT>()];
+            let mut required = 0;
+            self.duplication.GetFrameMoveRects(
+                (moves.len() * size_of::<DXGI_OUTDUPL_MOVE_RECT>()) as u32,
+                moves.as_mut_ptr(),
+                &mut required,
+            )?;
+            moves.truncate(required as usize / size_of::<DXGI_OUTDUPL_MOVE_RECT>());
+            rects.extend(moves.iter().filter_map(|m| to_rect(&m.DestinationRect)));
+
+            let mut dirty = vec![RECT::default(); size / size_of::<RECT>()];
+            self.duplication.GetFrameDirtyRects(
+                (dirty.len() * size_of::<RECT>()) as u32,
+                dirty.as_mut_ptr(),
+                &mut required,
+            )?;
+            dirty.truncate(required as usize / size_of::<RECT>());
+            rects.extend(dirty.iter().filter_map(to_rect));
+            Ok(rects)
+        }
+    }
+}
+
+fn to_rect(rect: &RECT) -> Option<Rect> {
+    (rect.right > rect.left && rect.bottom > rect.top && rect.left >= 0 && rect.top >= 0).then(
+        || Rect {
+            x: rect.left as u32,
+            y: rect.top as u32,
+            width: (rect.right - rect.left) as u32,
+            height: (rect.bottom - rect.top) as u32,
+        },
+    )
+}
@@ -0,0 +1,202 @@
+//! Screen capture backends.
+//!
+//! Monitors are always enumerated through xcap; a `CaptureBackend` grabs the
+//! pixels of one of them. The backend is chosen with `capture.backend`, and
+//! `auto` picks the best one available for the platform and session: DXGI
+//! desktop duplication on Windows, ScreenCaptureKit on macOS, and the portal
+//! on Wayland when xcap fails.
+
+pub mod xcap;
+
+#[cfg(windows)]
+pub mod dxgi;
+#[cfg(target_os = "linux")]
+pub mod portal;
+#[cfg(target_os = "macos")]
+pub mod sck;
+
+use crate::config::CaptureBackendKind;
+use crate::event::Rect;
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+
+/// The monitor to capture, in desktop coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct CaptureTarget {
+    pub id: u32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A captured monitor image.
+pub struct Frame {
+    pub image: DynamicImage,
+    /// The part of the monitor in `image`, when not the whole screen.
+    pub region: Option<Rect>,
+    /// Regions changed since the previous capture of this monitor, in
+    /// `image` coordinates, when the backend tracks them.
+    pub damage: Option<Vec<Rect>>,
+}
+
+/// Grabs monitor images. Called on blocking threads, one per monitor.
+pub trait CaptureBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Capture `target`, or only `region` of it (in monitor pixel coordinates).
+    fn capture(&self, target: &CaptureTarget, region: Option<Rect>) -> Result<Frame, Error>;
+
+    /// A fingerprint of a cheap, low-detail sample of `target` (or `region`
+    /// of it), to skip the full capture when it did not change. `None` when
+    /// the backend has no cheaper way to tell than a full capture.
+    fn probe(&self, _target: &CaptureTarget, _region: Option<Rect>) -> Result<Option<u64>, Error> {
+        Ok(None)
+    }
+}
+
+/// Create the backend selected in the capture configuration.
+///
+/// With `sdr_white_nits`, backends that can read HDR surfaces (DXGI) tone
+/// map them, assuming SDR white is shown at that level.
+#[cfg_attr(not(windows), allow(unused_variables))]
+pub fn from_kind(
+    kind: CaptureBackendKind,
+    sdr_white_nits: Option<f32>,
+) -> Result<Arc<dyn CaptureBackend>, Error> {
+    match kind {
+        CaptureBackendKind::Xcap => Ok(Arc::new(xcap::XcapBackend)),
+        #[cfg(target_os = "linux")]
+        CaptureBackendKind::Portal => Ok(Arc::new(portal::PortalBackend::new())),
+        #[cfg(not(target_os = "linux"))]
+        CaptureBackendKind::Portal => Err(anyhow::anyhow!(
+            "The portal backend is only available on Linux"
+        )),
+        #[cfg(windows)]
+        CaptureBackendKind::Dxgi => Ok(Arc::new(dxgi::DxgiBackend::new(sdr_white_nits))),
+        #[cfg(not(windows))]
+        CaptureBackendKind::Dxgi => Err(anyhow::anyhow!(
+            "The DXGI backend is only available on Windows"
+        )),
+        #[cfg(target_os = "macos")]
+        CaptureBackendKind::ScreenCaptureKit => Ok(Arc::new(sck::SckBackend::new())),
+        #[cfg(not(target_os = "macos"))]
+        CaptureBackendKind::ScreenCaptureKit => Err(anyhow::anyhow!(
+            "The ScreenCaptureKit backend is only available on macOS"
+        )),
+        CaptureBackendKind::Auto => Ok(auto(sdr_white_nits)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn auto(_: Option<f32>) -> Arc<dyn CaptureBackend> {
+    if portal::is_wayland_session() {
+        Arc::new(Fallback::new(
+            Arc::new(xcap::XcapBackend),
+            Arc::new(portal::PortalBackend::new()),
+        ))
+    } else {
+        Arc::new(xcap::XcapBackend)
+    }
+}
+
+/// Desktop duplication where available (not e.g. in some remote sessions).
+#[cfg(windows)]
+fn auto(sdr_white_nits: Option<f32>) -> Arc<dyn CaptureBackend> {
+    Arc::new(Fallback::new(
+        Arc::new(dxgi::DxgiBackend::new(sdr_white_nits)),
+        Arc::new(xcap::XcapBackend),
+    ))
+}
+
+/// ScreenCaptureKit where available (macOS 13+).
+#[cfg(target_os = "macos")]
+fn auto(_: Option<f32>) -> Arc<dyn CaptureBackend> {
+    Arc::new(Fallback::new(
+        Arc::new(sck::SckBackend::new()),
+        Arc::new(xcap::XcapBackend),
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn auto(_: Option<f32>) -> Arc<dyn CaptureBackend> {
+    Arc::new(xcap::XcapBackend)
+}
+
+/// Uses `primary` until it fails once, then `fallback` for good.
+#[cfg_attr(
+    not(any(target_os = "linux", target_os = "macos", windows)),
+    allow(dead_code)
+)]
+struct Fallback {
+    primary: Arc<dyn CaptureBackend>,
+    fallback: Arc<dyn CaptureBackend>,
+    failed: AtomicBool,
+}
+
+#[cfg_attr(
+    not(any(target_os = "linux", target_os = "macos", windows)),
+    allow(dead_code)
+)]
+impl Fallback {
+    fn new(primary: Arc<dyn CaptureBackend>, fallback: Arc<dyn CaptureBackend>) -> Self {
+        Self {
+            primary,
+            fallback,
+            failed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl CaptureBackend for Fallback {
+    fn name(&self) -> &'static str {
+        if self.failed.load(Ordering::Relaxed) {
+            self.fallback.name()
+        } else {
+            self.primary.name()
+        }
+    }
+
+    fn capture(&self, target: &CaptureTarget, region: Option<Rect>) -> Result<Frame, Error> {
+        if !self.failed.load(Ordering::Relaxed) {
+            match self.primary.capture(target, region) {
+                Ok(frame) => return Ok(frame),
+                Err(e) => {
+                    if !self.failed.swap(true, Ordering::Relaxed) {
+                        warn!(
+                            error = %e,
+                            "{} capture failed, switching to the {} backend",
+                            self.primary.name(),
+                            self.fallback.name()
+                        );
+                    }
+                }
+            }
+        }
+        self.fallback.capture(target, region)
+    }
+
+    fn probe(&self, target: &CaptureTarget, region: Option<Rect>) -> Result<Option<u64>, Error> {
+        if self.failed.load(Ordering::Relaxed) {
+            self.fallback.probe(target, region)
+        } else {
+            self.primary.probe(target, region)
+        }
+    }
+}
+
+/// Clip `region` to a `width` x `height` monitor.
+pub fn clip_region(region: Rect, width: u32, height: u32) -> Result<Rect, Error> {
+    let screen = Rect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    };
+    region
+        .intersect(&screen)
+        .ok_or_else(|| anyhow::anyhow!("Capture region lies outside the monitor"))
+}
@@ -0,0 +1,155 @@
+//! Capture through the xdg-desktop-portal Screenshot interface.
+//!
+//! On Wayland, clients cannot read other windows' pixels; GNOME and KDE only
+//! hand out screenshots through the portal. The portal returns one image of
+//! the whole desktop, so it is taken once and shared by the per-monitor
+//! capture threads of a tick, each cropping out its own monitor. The first
+//! capture may ask the user for permission.
+
+use super::{CaptureBackend, CaptureTarget, Frame, clip_region};
+use crate::event::Rect;
+use anyhow::{Error, Result};
+use ashpd::desktop::screenshot::Screenshot;
+use image::{DynamicImage, RgbaImage};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use xcap::Monitor;
+
+/// Screenshots younger than this are reused by the other monitors of a tick.
+const SHARED_SCREENSHOT_AGE: Duration = Duration::from_millis(500);
+
+/// A desktop screenshot and the desktop bounds it covers.
+#[derive(Clone)]
+struct Desktop {
+    image: Arc<RgbaImage>,
+    /// Bounding box of all monitors, in desktop coordinates.
+    bounds: CaptureTarget,
+    taken: Instant,
+}
+
+pub struct PortalBackend {
+    last: Mutex<Option<Desktop>>,
+}
+
+impl PortalBackend {
+    pub fn new() -> Self {
+        Self {
+            last: Mutex::new(None),
+        }
+    }
+
+    /// The current desktop screenshot, taking a new one unless another
+    /// monitor of this tick already did.
+    fn desktop(&self) -> Result<Desktop, Error> {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(desktop) = &*last
+            && desktop.taken.elapsed() < SHARED_SCREENSHOT_AGE
+        {
+            return Ok(desktop.clone());
+        }
+
+        let desktop = Desktop {
+            bounds: desktop_bounds()?,
+            image: Arc::new(take_screenshot()?),
+            taken: Instant::now(),
+        };
+        *last = Some(desktop.clone());
+        Ok(desktop)
+    }
+}
+
+impl CaptureBackend for PortalBackend {
+    fn name(&self) -> &'static str {
+        "portal"
+    }
+
+    fn capture(&self, target: &CaptureTarget, region: Option<Rect>) -> Result<Frame, Error> {
+        let Desktop { image, bounds, .. } = self.desktop()?;
+        // The screenshot is in physical pixels, monitor geometry may be logical
+        let scale = image.width() as f64 / bounds.width.max(1) as f64;
+        let scaled = |v: f64| (v * scale).round() as u32;
+
+        let monitor = Rect {
+            x: scaled((target.x - bounds.x) as f64),
+            y: scaled((target.y - bounds.y) as f64),
+            width: scaled(target.width as f64),
+            height: scaled(target.height as f64),
+        };
+        let bounds = Rect {
+            x: 0,
+            y: 0,
+            width: image.width(),
+            height: image.height(),
+        };
+        let mut crop = monitor
+            .intersect(&bounds)
+            .ok_or_else(|| anyhow::anyhow!("Monitor {} is outside the screenshot", target.id))?;
+        let region = match region {
+            Some(region) => {
+                let region = clip_region(region, crop.width, crop.height)?;
+                crop = Rect {
+                    x: crop.x + region.x,
+                    y: crop.y + region.y,
+                    ..region
+                };
+                Some(region)
+            }
+            None => None,
+        };
+
+        let image = image::imageops::crop_imm(&*image, crop.x, crop.y, crop.width, crop.height);
+        Ok(Frame {
+            image: DynamicImage::ImageRgba8(image.to_image()),
+            region,
+            damage: None,
+        })
+    }
+}
+
+/// Whether the watcher runs in a Wayland session.
+pub fn is_wayland_session() -> bool {
+    std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland")
+        || std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+/// Bounding box of all monitors.
+fn desktop_bounds() -> Result<CaptureTarget, Error> {
+    let mut bounds: Option<(i32, i32, i32, i32)> = None;
+    for monitor in Monitor::all()? {
+        let (x, y) = (monitor.x()?, monitor.y()?);
+        let (right, bottom) = (x + monitor.width()? as i32, y + monitor.height()? as i32);
+        bounds = Some(match bounds {
+            Some((x0, y0, r0, b0)) => (x0.min(x), y0.min(y), r0.max(right), b0.max(bottom)),
+            None => (x, y, right, bottom),
+        });
+    }
+    let (x, y, right, bottom) = bounds.ok_or_else(|| anyhow::anyhow!("No monitors found"))?;
+    Ok(CaptureTarget {
+        id: 0,
+        x,
+        y,
+        width: (right - x) as u32,
+        height: (bottom - y) as u32,
+    })
+}
+
+/// Take a non-interactive screenshot through the portal.
+fn take_screenshot() -> Result<RgbaImage, Error> {
+    // Capture threads are blocking threads of the watcher's runtime
+    let response = tokio::runtime::Handle::current().block_on(async {
+        Screenshot::request()
+            .interactive(false)
+            .modal(false)
+            .send()
+            .await?
+            .response()
+    })?;
+    let path = response
+        .uri()
+        .to_file_path()
+        .map_err(|()| anyhow::anyhow!("Portal returned a non-file URI: {}", response.uri()))?;
+    let image = image::open(&path);
+    // The portal saves into the user's pictures folder; do not leave copies behind
+    let _ = std::fs::remove_file(&path);
+    Ok(image?.to_rgba8())
+}
@@ -0,0 +1,84 @@
+//! Capture through xcap (GDI on Windows, CoreGraphics on macOS, X11 on Linux).
+
+use super::{CaptureBackend, CaptureTarget, Frame, clip_region};
+use crate::event::Rect;
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use xcap::Monitor;
+
+/// Patches per row and column sampled by `probe`.
+const PROBE_GRID: u32 = 5;
+
+/// Side length of a probe patch in pixels.
+const PROBE_PATCH: u32 = 32;
+
+pub struct XcapBackend;
+
+/// Monitors are looked up by id rather than by a point inside them: with
+/// mixed DPI scaling, stored coordinates can resolve to a different display
+/// after the scale or arrangement changes.
+fn find_monitor(id: u32) -> Result<Monitor, Error> {
+    Monitor::all()?
+        .into_iter()
+        .find(|monitor| monitor.id().is_ok_and(|monitor_id| monitor_id == id))
+        .ok_or_else(|| anyhow::anyhow!("Monitor {} is no longer connected", id))
+}
+
+impl CaptureBackend for XcapBackend {
+    fn name(&self) -> &'static str {
+        "xcap"
+    }
+
+    fn capture(&self, target: &CaptureTarget, region: Option<Rect>) -> Result<Frame, Error> {
+        let monitor = find_monitor(target.id)?;
+        let (image, region) = match region {
+            Some(region) => {
+                let region = clip_region(region, monitor.width()?, monitor.height()?)?;
+                let image =
+                    monitor.capture_region(region.x, region.y, region.width, region.height)?;
+                (image, Some(region))
+            }
+            None => (monitor.capture_image()?, None),
+        };
+        Ok(Frame {
+            image: DynamicImage::ImageRgba8(image),
+            region,
+            damage: None,
+        })
+    }
+
+    /// Hash a grid of small patches spread over the monitor. Grabbing them
+    /// copies a few kilobytes instead of the whole screen.
+    fn probe(&self, target: &CaptureTarget, region: Option<Rect>) -> Result<Option<u64>, Error> {
+        // Wayland compositors capture the whole screen for every region
+        #[cfg(target_os = "linux")]
+        if super::portal::is_wayland_session() {
+            return Ok(None);
+        }
+
+        let monitor = find_monitor(target.id)?;
+        let (width, height) = (monitor.width()?, monitor.height()?);
+        let area = match region {
+            Some(region) => clip_region(region, width, height)?,
+            None => Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+        };
+        let (patch_width, patch_height) =
+            (PROBE_PATCH.min(area.width), PROBE_PATCH.min(area.height));
+        let mut hasher = DefaultHasher::new();
+        for row in 0..PROBE_GRID {
+            for column in 0..PROBE_GRID {
+                let x = area.x + (area.width - patch_width) * column / (PROBE_GRID - 1);
+                let y = area.y + (area.height - patch_height) * row / (PROBE_GRID - 1);
+                let patch = monitor.capture_region(x, y, patch_width, patch_height)?;
+                patch.as_raw().hash(&mut hasher);
+            }
+        }
+        Ok(Some(hasher.finish()))
+    }
+}
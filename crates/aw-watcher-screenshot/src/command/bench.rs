@@ -0,0 +1,238 @@
+//! Performance self-test.
+//!
+//! Measures the cost of each pipeline step on this machine (per-monitor
+//! capture latency, perceptual hashing, WebP encoding and S3 upload throughput) and
+//! recommends interval and quality settings from the results.
+
+use crate::config::Config;
+use crate::encode::encode_webp;
+use crate::hash::PerceptualHash;
+use crate::worker_impl::s3::open_bucket;
+use anyhow::{Error, Result};
+use chrono::Utc;
+use clap::Args;
+use image::DynamicImage;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use xcap::Monitor;
+
+/// WebP qualities measured in addition to the configured one.
+const CANDIDATE_QUALITIES: [u8; 4] = [50, 75, 90, 100];
+
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Repetitions per measurement
+    #[arg(long, default_value_t = 5)]
+    iterations: u32,
+    /// Share of one CPU core the watcher may use on average (0-1)
+    #[arg(long, default_value_t = 0.1)]
+    cpu_budget: f64,
+    /// Skip the S3 upload measurement
+    #[arg(long)]
+    skip_upload: bool,
+}
+
+/// Measurements for one monitor.
+struct MonitorBench {
+    name: String,
+    capture: Duration,
+    hash: Duration,
+    /// (quality, encode time, encoded size)
+    encodes: Vec<(u8, Duration, usize)>,
+    /// A frame encoded at the configured quality, used for the upload test.
+    sample: Vec<u8>,
+}
+
+pub async fn run(config: &Config, args: BenchArgs) -> Result<(), Error> {
+    let quality = config.cache.webp_quality;
+    let mut qualities = CANDIDATE_QUALITIES.to_vec();
+    if !qualities.contains(&quality) {
+        qualities.push(quality);
+        qualities.sort();
+    }
+
+    let iterations = args.iterations.max(1);
+    let hasher = config.capture.hash_algorithm.hasher();
+    let hash_name = hasher.name();
+    let monitors = tokio::task::spawn_blocking(move || {
+        bench_monitors(iterations, &qualities, quality, hasher.as_ref())
+    })
+    .await??;
+    if monitors.is_empty() {
+        return Err(anyhow::anyhow!("No monitor could be captured"));
+    }
+
+    println!("{:<32} {:>10} {:>10}", "Monitor", "Capture", hash_name);
+    for monitor in &monitors {
+        println!(
+            "{:<32} {:>8.1}ms {:>8.2}ms",
+            monitor.name,
+            ms(monitor.capture),
+            ms(monitor.hash)
+        );
+    }
+
+    println!(
+        "\n{:<8} {:>12} {:>12}",
+        "Quality", "Encode/tick", "Size/tick"
+    );
+    let per_quality: Vec<(u8, Duration, usize)> = monitors[0]
+        .encodes
+        .iter()
+        .map(|(q, _, _)| {
+            let (time, size) = monitors
+                .iter()
+                .flat_map(|m| m.encodes.iter().filter(|(mq, _, _)| mq == q))
+                .fold((Duration::ZERO, 0), |(t, s), (_, time, size)| {
+                    (t + *time, s + size)
+                });
+            (*q, time, size)
+        })
+        .collect();
+    for (q, time, size) in &per_quality {
+        println!("{:<8} {:>10.1}ms {:>10}KB", q, ms(*time), size / 1024);
+    }
+
+    let throughput = if config.s3.enabled && !args.skip_upload {
+        match bench_upload(config, &monitors[0].sample, iterations).await {
+            Ok(throughput) => {
+                println!("\nS3 upload: {:.2} MB/s", throughput / 1e6);
+                Some(throughput)
+            }
+            Err(e) => {
+                error!(error = %e, "Upload measurement failed");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Work per tick if every monitor changed: capture + hash + encode
+    let fixed: Duration = monitors.iter().map(|m| m.capture + m.hash).sum();
+    let interval_for = |(_, encode, size): &(u8, Duration, usize)| {
+        let cpu = (fixed + *encode).as_secs_f64() / args.cpu_budget.clamp(0.01, 1.0);
+        let upload = throughput.map_or(0.0, |t| *size as f64 / t);
+        cpu.max(upload).ceil().max(1.0) as u64
+    };
+
+    println!(
+        "\nRecommendations (cpu budget {:.0}%):",
+        args.cpu_budget * 100.0
+    );
+    let configured = per_quality.iter().find(|(q, _, _)| *q == quality);
+    if let Some(configured) = configured {
+        let interval = interval_for(configured);
+        println!(
+            "- At webp_quality = {}, use interval_secs >= {} (configured: {})",
+            quality, interval, config.trigger.interval_secs
+        );
+    }
+    match per_quality
+        .iter()
+        .filter(|entry| interval_for(entry) <= config.trigger.interval_secs)
+        .map(|(q, _, _)| *q)
+        .max()
+    {
+        Some(best) => println!(
+            "- At interval_secs = {}, the highest affordable webp_quality is {}",
+            config.trigger.interval_secs, best
+        ),
+        None => println!(
+            "- No measured quality fits interval_secs = {}; increase the interval",
+            config.trigger.interval_secs
+        ),
+    }
+    Ok(())
+}
+
+/// Measure capture, hashing and encoding for every monitor, keeping a frame
+/// encoded at `sample_quality` for the upload test.
+fn bench_monitors(
+    iterations: u32,
+    qualities: &[u8],
+    sample_quality: u8,
+    hasher: &dyn PerceptualHash,
+) -> Result<Vec<MonitorBench>, Error> {
+    let mut results = Vec::new();
+    for monitor in Monitor::all()? {
+        let name = monitor.name()?;
+        info!(monitor = %name, "Benchmarking monitor");
+
+        let mut image = None;
+        let capture = time(iterations, || match monitor.capture_image() {
+            Ok(frame) => image = Some(DynamicImage::ImageRgba8(frame)),
+            Err(e) => error!(monitor = %name, error = %e, "Capture failed"),
+        });
+        let Some(image) = image else {
+            continue;
+        };
+
+        let hash = time(iterations, || {
+            std::hint::black_box(hasher.hash(&image));
+        });
+
+        let mut encodes = Vec::new();
+        let mut sample = Vec::new();
+        for &quality in qualities {
+            let mut output = Vec::new();
+            let elapsed = time(iterations, || {
+                output.clear();
+                encode_webp(&image, quality as f32, &mut output);
+            });
+            encodes.push((quality, elapsed, output.len()));
+            if quality == sample_quality {
+                sample = output;
+            }
+        }
+
+        results.push(MonitorBench {
+            name,
+            capture,
+            hash,
+            encodes,
+            sample,
+        });
+    }
+    Ok(results)
+}
+
+/// Upload a sample frame a few times and return the throughput in bytes/s.
+async fn bench_upload(config: &Config, data: &[u8], iterations: u32) -> Result<f64, Error> {
+    let bucket = open_bucket(&config.s3)?;
+    let key = format!(
+        "{}aw-watcher-screenshot-bench/{}.webp",
+        config.s3.key_prefix.as_deref().unwrap_or(""),
+        Utc::now().format("%Y%m%d_%H%M%S")
+    );
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        bucket
+            .put_object_with_content_type(&key, data, "image/webp")
+            .await?;
+    }
+    let elapsed = start.elapsed();
+
+    if let Err(e) = bucket.delete_object(&key).await {
+        error!(key, error = %e, "Failed to delete benchmark object");
+    }
+    Ok(data.len() as f64 * iterations as f64 / elapsed.as_secs_f64())
+}
+
+/// Median duration of `iterations` runs of `f`.
+fn time(iterations: u32, mut f: impl FnMut()) -> Duration {
+    let mut samples: Vec<Duration> = (0..iterations)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
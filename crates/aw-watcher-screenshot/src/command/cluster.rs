@@ -0,0 +1,218 @@
+//! Recurring-screen clustering.
+//!
+//! Groups the perceptual hashes recorded in the aw-server bucket into clusters
+//! of visually similar screens, reports the most common ones and labels the
+//! stored events retroactively with a `cluster` id per image. Only hashes of
+//! the configured `capture.hash_algorithm` are compared.
+
+use crate::config::Config;
+use crate::hash::hamming_distance;
+use anyhow::{Error, Result};
+use aw_client_lite::AwClient;
+use chrono::{DateTime, Duration, Utc};
+use clap::Args;
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::{error, info};
+
+#[derive(Args, Debug)]
+pub struct ClusterArgs {
+    /// Start of the range (RFC 3339)
+    #[arg(long)]
+    from: DateTime<Utc>,
+    /// End of the range (RFC 3339)
+    #[arg(long)]
+    to: DateTime<Utc>,
+    /// Number of most common clusters to keep
+    #[arg(long, default_value_t = 10)]
+    clusters: usize,
+    /// Maximum hamming distance between a hash and its cluster center
+    #[arg(long, default_value_t = 10)]
+    radius: u32,
+    /// Only print the clusters, do not update events
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// A group of similar screens.
+struct Cluster {
+    center: u64,
+    /// Total event time spent on screens of this cluster.
+    duration: Duration,
+}
+
+pub async fn run(config: &Config, args: ClusterArgs) -> Result<(), Error> {
+    let client = AwClient::new(&config.aw_server.host, config.aw_server.port);
+    let bucket_id = format!(
+        "{}_{}",
+        config.aw_server.bucket_id, config.aw_server.hostname
+    );
+
+    let mut events = client
+        .get_events(&bucket_id, Some(args.from), Some(args.to), None)
+        .await?;
+    info!(events = events.len(), "Fetched events for clustering");
+    let algorithm = config.capture.hash_algorithm.name();
+
+    // Time spent per distinct hash
+    let mut weights: HashMap<u64, Duration> = HashMap::new();
+    for event in &events {
+        for hash in event_hashes(&event.data, algorithm) {
+            *weights.entry(hash).or_insert_with(Duration::zero) += event.duration;
+        }
+    }
+
+    let clusters = cluster_hashes(&weights, args.radius, args.clusters);
+    for (id, cluster) in clusters.iter().enumerate() {
+        println!(
+            "cluster {:>3}  center {:016x}  time {}",
+            id,
+            cluster.center,
+            format_duration(cluster.duration)
+        );
+    }
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let mut updated = 0;
+    for event in events.iter_mut() {
+        if event.id.is_none() || !label_event(&mut event.data, &clusters, args.radius, algorithm) {
+            continue;
+        }
+        // Inserting an event with an existing id replaces it on the server
+        match client.insert_event(&bucket_id, event).await {
+            Ok(()) => updated += 1,
+            Err(e) => error!(id = ?event.id, error = %e, "Failed to update event"),
+        }
+    }
+    info!(updated, "Labeled events with cluster ids");
+    Ok(())
+}
+
+/// Extract the parsed `dhash` of every image in an event's data that was
+/// hashed with `algorithm`.
+fn event_hashes(data: &serde_json::Map<String, Value>, algorithm: &str) -> Vec<u64> {
+    let Some(Value::Array(images)) = data.get("images") else {
+        return Vec::new();
+    };
+    images
+        .iter()
+        .filter_map(|image| image_hash(image, algorithm))
+        .collect()
+}
+
+/// The parsed `dhash` of an image, if it was hashed with `algorithm`.
+/// Images recorded without `hash_algorithm` carry a difference hash.
+fn image_hash(image: &Value, algorithm: &str) -> Option<u64> {
+    let image_algorithm = image
+        .get("hash_algorithm")
+        .and_then(|a| a.as_str())
+        .unwrap_or("dhash");
+    if image_algorithm != algorithm {
+        return None;
+    }
+    u64::from_str_radix(image.get("dhash")?.as_str()?, 16).ok()
+}
+
+/// Set the `cluster` field of every image in an event. Returns whether anything changed.
+fn label_event(
+    data: &mut serde_json::Map<String, Value>,
+    clusters: &[Cluster],
+    radius: u32,
+    algorithm: &str,
+) -> bool {
+    let Some(Value::Array(images)) = data.get_mut("images") else {
+        return false;
+    };
+
+    let mut changed = false;
+    for image in images.iter_mut() {
+        let Some(hash) = image_hash(image, algorithm) else {
+            continue;
+        };
+        let label = nearest_cluster(clusters, hash, radius)
+            .map(Value::from)
+            .unwrap_or(Value::Null);
+        if let Some(image) = image.as_object_mut()
+            && image.get("cluster") != Some(&label)
+        {
+            image.insert("cluster".to_string(), label);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Index of the closest cluster within `radius`, if any.
+fn nearest_cluster(clusters: &[Cluster], hash: u64, radius: u32) -> Option<usize> {
+    clusters
+        .iter()
+        .enumerate()
+        .map(|(id, cluster)| (id, hamming_distance(cluster.center, hash)))
+        .filter(|(_, distance)| *distance <= radius)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(id, _)| id)
+}
+
+/// Leader clustering: visit hashes from most to least time spent, join the
+/// first cluster within `radius` or start a new one. The `limit` clusters
+/// with the most time are returned, most common first.
+fn cluster_hashes(weights: &HashMap<u64, Duration>, radius: u32, limit: usize) -> Vec<Cluster> {
+    let mut hashes: Vec<_> = weights.iter().collect();
+    hashes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (hash, weight) in hashes {
+        match clusters
+            .iter_mut()
+            .find(|c| hamming_distance(c.center, *hash) <= radius)
+        {
+            Some(cluster) => cluster.duration += *weight,
+            None => clusters.push(Cluster {
+                center: *hash,
+                duration: *weight,
+            }),
+        }
+    }
+
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.duration));
+    clusters.truncate(limit);
+    clusters
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.num_seconds();
+    format!("{}h{:02}m{:02}s", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_hashes_groups_similar() {
+        let mut weights = HashMap::new();
+        weights.insert(0b0000, Duration::seconds(30));
+        weights.insert(0b0001, Duration::seconds(20));
+        weights.insert(u64::MAX, Duration::seconds(10));
+
+        let clusters = cluster_hashes(&weights, 2, 10);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].center, 0);
+        assert_eq!(clusters[0].duration, Duration::seconds(50));
+        assert_eq!(nearest_cluster(&clusters, 0b0011, 2), Some(0));
+        assert_eq!(nearest_cluster(&clusters, 0xFFFF, 2), None);
+    }
+
+    #[test]
+    fn test_image_hash_matches_algorithm() {
+        let legacy = serde_json::json!({ "dhash": "00000000000000ff" });
+        let phash = serde_json::json!({ "dhash": "00000000000000ff", "hash_algorithm": "phash" });
+        assert_eq!(image_hash(&legacy, "dhash"), Some(0xff));
+        assert_eq!(image_hash(&legacy, "phash"), None);
+        assert_eq!(image_hash(&phash, "phash"), Some(0xff));
+        assert_eq!(image_hash(&phash, "dhash"), None);
+    }
+}
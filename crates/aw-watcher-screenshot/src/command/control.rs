@@ -0,0 +1,61 @@
+//! Send a control command to a running watcher.
+
+use crate::config::Config;
+use crate::control;
+use anyhow::{Error, Result};
+use clap::{Args, ValueEnum};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ControlCommand {
+    /// Skip captures until resumed, or for --minutes
+    Pause,
+    /// Capture again after a pause
+    Resume,
+    /// Capture all monitors now
+    Capture,
+    /// Print whether capture is paused or running
+    Status,
+    /// Store pixelated/thumbnail images only
+    LowFidelity,
+    /// Store full images again
+    FullFidelity,
+    /// Print whether low-fidelity mode is on
+    Fidelity,
+}
+
+#[derive(Args, Debug)]
+pub struct ControlArgs {
+    #[arg(value_enum)]
+    command: ControlCommand,
+    /// With `pause`, resume automatically after this many minutes
+    #[arg(long)]
+    minutes: Option<u32>,
+}
+
+pub async fn run(config: &Config, args: ControlArgs) -> Result<(), Error> {
+    let path = config
+        .control
+        .path
+        .clone()
+        .unwrap_or_else(control::default_path);
+    let command = match args.command {
+        ControlCommand::Pause => "pause",
+        ControlCommand::Resume => "resume",
+        ControlCommand::Capture => "capture",
+        ControlCommand::Status => "status",
+        ControlCommand::LowFidelity => "low-fidelity",
+        ControlCommand::FullFidelity => "full-fidelity",
+        ControlCommand::Fidelity => "fidelity",
+    };
+    let command = match (args.command, args.minutes) {
+        (ControlCommand::Pause, Some(minutes)) => format!("{} {}", command, minutes),
+        (_, Some(_)) => anyhow::bail!("--minutes only applies to pause"),
+        _ => command.to_string(),
+    };
+    let reply = control::send(&path, &command).await?;
+    println!("{}", reply);
+    if reply.starts_with("error") {
+        anyhow::bail!("Command failed: {}", reply);
+    }
+    Ok(())
+}
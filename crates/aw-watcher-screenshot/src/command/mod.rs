@@ -0,0 +1,7 @@
+//! One-shot subcommands that operate on captured data instead of running the pipeline.
+
+pub mod bench;
+pub mod cluster;
+pub mod control;
+pub mod purge;
+pub mod timelapse;
@@ -0,0 +1,285 @@
+//! Time-range purge.
+//!
+//! Deletes everything captured between two timestamps: cached frames on disk,
+//! spilled frames, deferred uploads, the uploaded S3 objects and the
+//! aw-server events that refer to them, so a request like "delete everything
+//! from yesterday afternoon" is honored end to end. Any failed deletion makes
+//! the command fail once the rest is done.
+
+use crate::config::Config;
+use crate::timezone::TimePolicy;
+use crate::worker_impl::s3::{drop_queued, open_bucket};
+use crate::worker_impl::spill::{is_spill_file, spill_dir};
+use anyhow::{Context, Error, Result};
+use aw_client_lite::AwClient;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use clap::Args;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+#[derive(Args, Debug)]
+pub struct PurgeArgs {
+    /// Start of the range (RFC 3339)
+    #[arg(long)]
+    from: DateTime<Utc>,
+    /// End of the range (RFC 3339)
+    #[arg(long)]
+    to: DateTime<Utc>,
+    /// Only list what would be deleted
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub async fn run(config: &Config, args: PurgeArgs) -> Result<(), Error> {
+    anyhow::ensure!(args.from <= args.to, "--from must not be after --to");
+    let time_policy = TimePolicy::new(config.time.local_time);
+    let in_range = |time: DateTime<Utc>| time >= args.from && time <= args.to;
+    let mut failures = 0;
+
+    // Events first: they name the objects to delete, including those of
+    // remote agents whose keys are not in the local layout
+    let client = AwClient::new(&config.aw_server.host, config.aw_server.port);
+    let bucket_id = format!(
+        "{}_{}",
+        config.aw_server.bucket_id, config.aw_server.hostname
+    );
+    let events = client
+        .get_events(&bucket_id, Some(args.from), Some(args.to), None)
+        .await
+        .context("Failed to fetch aw-server events")?;
+    // Heartbeats merge frames into one event, so any event overlapping the
+    // range is purged
+    let events: Vec<_> = events
+        .into_iter()
+        .filter(|event| event.timestamp <= args.to && event.calculate_endtime() >= args.from)
+        .collect();
+
+    let mut object_keys = BTreeSet::new();
+    for event in &events {
+        collect_object_keys(&Value::Object(event.data.clone()), &mut object_keys);
+    }
+
+    // Cached frames, including spooled uploads
+    let mut files = Vec::new();
+    let cache_dir = PathBuf::from(&config.cache.cache_dir);
+    if cache_dir.exists() {
+        collect_files(&cache_dir, &args, time_policy, &mut files)?;
+    }
+    // Spill files carry no capture time; any written since `from` may hold
+    // frames of the range
+    if config.spill.enabled {
+        collect_spill_files(&spill_dir(&config.spill, &cache_dir), args.from, &mut files)?;
+    }
+
+    if config.s3.enabled {
+        let bucket = open_bucket(&config.s3)?;
+        // Objects are stored under hourly prefixes; list them to also catch
+        // uploads whose event never reached the server
+        let mut hour = args.from.duration_trunc(Duration::hours(1))?;
+        while hour <= args.to {
+            let prefix = format!("{}/", time_policy.format(hour, "%Y/%m/%d/%H"));
+            match bucket.list(prefix.clone(), None).await {
+                Ok(pages) => {
+                    for object in pages.iter().flat_map(|page| &page.contents) {
+                        if frame_time(&object.key, time_policy).is_some_and(in_range) {
+                            object_keys.insert(object.key.clone());
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(prefix, error = %e, "Failed to list S3 objects");
+                    failures += 1;
+                }
+            }
+            hour += Duration::hours(1);
+        }
+
+        info!(objects = object_keys.len(), "Purging S3 objects");
+        for key in &object_keys {
+            if args.dry_run {
+                println!("s3     {}", key);
+                continue;
+            }
+            if let Err(e) = bucket.delete_object(key).await {
+                error!(key, error = %e, "Failed to delete S3 object");
+                failures += 1;
+            }
+        }
+    } else if !object_keys.is_empty() {
+        warn!(
+            objects = object_keys.len(),
+            "S3 is disabled, leaving objects referenced by events in place"
+        );
+    }
+
+    info!(files = files.len(), "Purging cached files");
+    for path in &files {
+        if args.dry_run {
+            println!("file   {}", path.display());
+            continue;
+        }
+        if let Err(e) = fs::remove_file(path) {
+            error!(path = %path.display(), error = %e, "Failed to delete cached file");
+            failures += 1;
+        }
+    }
+
+    // Deferred uploads would otherwise put purged frames back on S3
+    if !args.dry_run && cache_dir.exists() {
+        let purged = |path: &Path, key: &str| {
+            files.iter().any(|file| file == path)
+                || frame_time(key, time_policy).is_some_and(in_range)
+        };
+        match drop_queued(&cache_dir, purged).await {
+            Ok(dropped) => info!(dropped, "Purged queued uploads"),
+            Err(e) => {
+                error!(error = %e, "Failed to purge the upload queue");
+                failures += 1;
+            }
+        }
+    }
+
+    info!(events = events.len(), "Purging aw-server events");
+    for event in &events {
+        let Some(id) = event.id else {
+            continue;
+        };
+        if args.dry_run {
+            println!("event  {} {}", id, event.timestamp.to_rfc3339());
+            continue;
+        }
+        if let Err(e) = client.delete_event(&bucket_id, id).await {
+            error!(id, error = %e, "Failed to delete event");
+            failures += 1;
+        }
+    }
+
+    anyhow::ensure!(
+        failures == 0,
+        "Purge incomplete: {} deletions failed, see the log and run it again",
+        failures
+    );
+    Ok(())
+}
+
+/// Every `object_key` in an event's data (images, thumbnails).
+fn collect_object_keys(value: &Value, keys: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map {
+                match value {
+                    Value::String(key) if name == "object_key" && !key.is_empty() => {
+                        keys.insert(key.clone());
+                    }
+                    _ => collect_object_keys(value, keys),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_object_keys(value, keys);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Recursively collect cached files captured within the requested range.
+fn collect_files(
+    dir: &Path,
+    args: &PurgeArgs,
+    time_policy: TimePolicy,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, args, time_policy, files)?;
+            continue;
+        }
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if frame_time(name, time_policy).is_some_and(|time| time >= args.from && time <= args.to) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Collect the spill files in `dir` and its instance slots written since
+/// `since`.
+fn collect_spill_files(
+    dir: &Path,
+    since: DateTime<Utc>,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::new(e).context(format!("Failed to read {}", dir.display()))),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_spill_files(&entry.path(), since, files)?;
+            continue;
+        }
+        if !entry.file_name().to_str().is_some_and(is_spill_file) {
+            continue;
+        }
+        let modified: DateTime<Utc> = entry.metadata()?.modified()?.into();
+        if modified >= since {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Capture time of a `{%Y%m%d_%H%M%S%3f}_{suffix}.{ext}` file name or
+/// object key, in the time zone it was written with.
+fn frame_time(name: &str, time_policy: TimePolicy) -> Option<DateTime<Utc>> {
+    let name = name.rsplit('/').next()?;
+    // "%Y%m%d_%H%M%S%3f" is 8 + 1 + 9 characters
+    let time = name.get(..18)?;
+    if name.as_bytes().get(18) != Some(&b'_') {
+        return None;
+    }
+    time_policy.parse(time, "%Y%m%d_%H%M%S%3f")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_frame_time() {
+        let policy = TimePolicy::new(false);
+        let expected =
+            Utc.with_ymd_and_hms(2024, 5, 1, 14, 30, 5).unwrap() + Duration::milliseconds(123);
+        assert_eq!(
+            frame_time("2024/05/01/14/20240501_143005123_1.webp", policy),
+            Some(expected)
+        );
+        assert_eq!(
+            frame_time("20240501_143005123_0_thumb.avif", policy),
+            Some(expected)
+        );
+        assert_eq!(frame_time("upload_queue.json", policy), None);
+
+        let data = serde_json::json!({
+            "images": [{ "object_key": "a.webp" }, { "object_key": "" }],
+            "thumbnail": { "object_key": "b_thumb.webp" },
+        });
+        let mut keys = BTreeSet::new();
+        collect_object_keys(&data, &mut keys);
+        assert_eq!(
+            keys.into_iter().collect::<Vec<_>>(),
+            vec!["a.webp", "b_thumb.webp"]
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Timelapse video export.
+//!
+//! Assembles the cached frames of each monitor into a video by piping raw
+//! RGBA frames into `ffmpeg`. Frames can be held according to the real time
+//! between captures (with idle gaps compressed) and stamped with their
+//! capture time.
+
+use crate::config::Config;
+use crate::encode::FRAME_EXTENSIONS;
+use crate::retention::FLAGGED_MARKER;
+use crate::timezone::TimePolicy;
+use crate::worker_impl::s3::UPLOAD_SPOOL_DIR;
+use anyhow::{Context, Error, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, ValueEnum};
+use image::{RgbaImage, imageops};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum VideoFormat {
+    Mp4,
+    Webm,
+}
+
+impl VideoFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::Webm => "webm",
+        }
+    }
+
+    fn codec_args(self) -> &'static [&'static str] {
+        match self {
+            VideoFormat::Mp4 => &["-c:v", "libx264", "-pix_fmt", "yuv420p"],
+            VideoFormat::Webm => &["-c:v", "libvpx-vp9", "-pix_fmt", "yuv420p"],
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct TimelapseArgs {
+    /// Start of the range (RFC 3339, e.g. 2024-05-01T09:00:00Z)
+    #[arg(long)]
+    from: DateTime<Utc>,
+    /// End of the range (RFC 3339)
+    #[arg(long)]
+    to: DateTime<Utc>,
+    /// Output frame rate
+    #[arg(long, default_value_t = 10)]
+    fps: u32,
+    /// Follow real time sped up by this factor instead of showing every frame once
+    #[arg(long)]
+    speed: Option<f64>,
+    /// With --speed, gaps longer than this are shown as a single frame
+    #[arg(long, default_value_t = 60)]
+    idle_secs: u64,
+    /// Burn the capture time into each frame
+    #[arg(long)]
+    timestamp: bool,
+    /// Output container
+    #[arg(long, value_enum, default_value_t = VideoFormat::Mp4)]
+    format: VideoFormat,
+    /// Directory receiving one video per monitor
+    #[arg(long, default_value = ".")]
+    output: PathBuf,
+    /// Path to the ffmpeg executable
+    #[arg(long, default_value = "ffmpeg")]
+    ffmpeg: String,
+}
+
+/// A cached frame on disk.
+struct Frame {
+    timestamp: DateTime<Utc>,
+    path: PathBuf,
+}
+
+pub fn run(config: &Config, args: TimelapseArgs) -> Result<(), Error> {
+    let cache_dir = PathBuf::from(&config.cache.cache_dir);
+    let mut monitors: BTreeMap<u32, Vec<Frame>> = BTreeMap::new();
+    let time_policy = TimePolicy::new(config.time.local_time);
+    collect_frames(&cache_dir, &args, time_policy, &mut monitors)?;
+
+    if monitors.is_empty() {
+        warn!(path = %cache_dir.display(), "No cached frames found in range");
+        return Ok(());
+    }
+
+    fs::create_dir_all(&args.output)?;
+    for (monitor_id, mut frames) in monitors {
+        frames.sort_by_key(|f| f.timestamp);
+        let output = args.output.join(format!(
+            "timelapse_{}_{}_{}.{}",
+            monitor_id,
+            args.from.format("%Y%m%d_%H%M%S"),
+            args.to.format("%Y%m%d_%H%M%S"),
+            args.format.extension()
+        ));
+        info!(
+            monitor_id,
+            frames = frames.len(),
+            path = %output.display(),
+            "Encoding timelapse"
+        );
+        encode(&frames, &output, &args)
+            .with_context(|| format!("Failed to encode timelapse for monitor {}", monitor_id))?;
+    }
+    Ok(())
+}
+
+/// Recursively collect cached frames within the requested range, grouped by monitor.
+fn collect_frames(
+    dir: &Path,
+    args: &TimelapseArgs,
+    time_policy: TimePolicy,
+    monitors: &mut BTreeMap<u32, Vec<Frame>>,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            // Spooled uploads are other encodings of cached frames
+            if !path.ends_with(UPLOAD_SPOOL_DIR) {
+                collect_frames(&path, args, time_policy, monitors)?;
+            }
+            continue;
+        }
+
+        let Some((timestamp, monitor_id)) = parse_frame_name(&path, time_policy) else {
+            continue;
+        };
+        if timestamp < args.from || timestamp > args.to {
+            continue;
+        }
+        monitors
+            .entry(monitor_id)
+            .or_default()
+            .push(Frame { timestamp, path });
+    }
+    Ok(())
+}
+
+/// Parse `{%Y%m%d_%H%M%S%3f}_{monitor_id}[_flagged].{ext}` into its
+/// timestamp and monitor id, for the extension of any encoder.
+///
+/// The name is read in the time zone the cache was written with.
+fn parse_frame_name(path: &Path, time_policy: TimePolicy) -> Option<(DateTime<Utc>, u32)> {
+    let extension = path.extension()?.to_str()?;
+    if !FRAME_EXTENSIONS.contains(&extension) {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let stem = stem.strip_suffix(FLAGGED_MARKER).unwrap_or(stem);
+    let (time, monitor_id) = stem.rsplit_once('_')?;
+    let timestamp = time_policy.parse(time, "%Y%m%d_%H%M%S%3f")?;
+    Some((timestamp, monitor_id.parse().ok()?))
+}
+
+/// Number of output frames each input frame is held for.
+fn frame_repeats(frames: &[Frame], args: &TimelapseArgs) -> Vec<u32> {
+    let Some(speed) = args.speed.filter(|s| *s > 0.0) else {
+        return vec![1; frames.len()];
+    };
+
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let Some(next) = frames.get(i + 1) else {
+                return 1;
+            };
+            let gap = (next.timestamp - frame.timestamp).num_milliseconds() as f64 / 1000.0;
+            if gap > args.idle_secs as f64 {
+                // Ramp through idle periods instead of holding the frame
+                return 1;
+            }
+            ((gap / speed) * args.fps as f64).round().max(1.0) as u32
+        })
+        .collect()
+}
+
+/// Decode a cached frame, through ffmpeg for formats the image crate cannot
+/// read (AVIF, JPEG XL).
+fn open_frame(path: &Path, ffmpeg: &str) -> Result<RgbaImage, Error> {
+    if let Ok(image) = image::open(path) {
+        return Ok(image.to_rgba8());
+    }
+    let output = Command::new(ffmpeg)
+        .args(["-loglevel", "error", "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to start {}", ffmpeg))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(image::load_from_memory(&output.stdout)?.to_rgba8())
+}
+
+fn encode(frames: &[Frame], output: &Path, args: &TimelapseArgs) -> Result<(), Error> {
+    let first = open_frame(&frames[0].path, &args.ffmpeg)?;
+    let (width, height) = first.dimensions();
+
+    let mut child = Command::new(&args.ffmpeg)
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgba",
+        ])
+        .args(["-s", &format!("{}x{}", width, height)])
+        .args(["-r", &args.fps.to_string(), "-i", "-"])
+        // yuv420p requires even dimensions
+        .args(["-vf", "scale=trunc(iw/2)*2:trunc(ih/2)*2"])
+        .args(args.format.codec_args())
+        .arg(output)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start {}", args.ffmpeg))?;
+    let mut stdin = child.stdin.take().context("ffmpeg stdin unavailable")?;
+
+    for (frame, repeats) in frames.iter().zip(frame_repeats(frames, args)) {
+        let mut image = match open_frame(&frame.path, &args.ffmpeg) {
+            Ok(image) => image,
+            Err(e) => {
+                warn!(path = %frame.path.display(), error = %e, "Skipping unreadable frame");
+                continue;
+            }
+        };
+        if image.dimensions() != (width, height) {
+            image = imageops::resize(&image, width, height, imageops::FilterType::Triangle);
+        }
+        if args.timestamp {
+            draw_timestamp(
+                &mut image,
+                &frame.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+            );
+        }
+        for _ in 0..repeats {
+            stdin.write_all(image.as_raw())?;
+        }
+    }
+    drop(stdin);
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("ffmpeg exited with {}", status));
+    }
+    Ok(())
+}
+
+/// 3x5 bitmap glyphs for the characters used in timestamps.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        _ => [0; 5],
+    }
+}
+
+/// Draw white text on a black box in the top-left corner of the image.
+fn draw_timestamp(image: &mut RgbaImage, text: &str) {
+    let scale = (image.height() / 200).max(2);
+    let advance = 4 * scale;
+    let box_width = (advance * text.len() as u32 + 2 * scale).min(image.width());
+    let box_height = (7 * scale).min(image.height());
+
+    for y in 0..box_height {
+        for x in 0..box_width {
+            image.put_pixel(x, y, image::Rgba([0, 0, 0, 255]));
+        }
+    }
+
+    for (i, c) in text.chars().enumerate() {
+        let origin_x = scale + i as u32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3u32 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = origin_x + col * scale + dx;
+                        let y = scale + row as u32 * scale + dy;
+                        if x < image.width() && y < image.height() {
+                            image.put_pixel(x, y, image::Rgba([255, 255, 255, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_frame_name() {
+        let path = Path::new("cache/2024/05/01/09/20240501_093015123_42.webp");
+        let (timestamp, monitor_id) = parse_frame_name(path, TimePolicy::default()).unwrap();
+        assert_eq!(monitor_id, 42);
+        assert_eq!(
+            timestamp,
+            "2024-05-01T09:30:15.123Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert!(parse_frame_name(Path::new("summary.json"), TimePolicy::default()).is_none());
+        let flagged = Path::new("20240501_093015123_42_flagged.avif");
+        assert_eq!(
+            parse_frame_name(flagged, TimePolicy::default()),
+            Some((timestamp, 42))
+        );
+        let thumbnail = Path::new("20240501_093015123_42_thumb.webp");
+        assert!(parse_frame_name(thumbnail, TimePolicy::default()).is_none());
+    }
+}
@@ -0,0 +1,130 @@
+//! Stitching the monitors of one capture into a single image.
+//!
+//! Monitors are placed by their desktop geometry. Captures can be in physical
+//! pixels while the geometry is logical (e.g. on macOS), so the canvas uses
+//! the highest pixel density among the monitors and scales the others up to
+//! it. Gaps between monitors stay transparent.
+
+use crate::event::Rect;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use std::sync::Arc;
+
+/// Monitor id of composite images in a `CaptureEvent`.
+pub const COMPOSITE_ID: u32 = u32::MAX;
+
+/// One captured monitor (or region of it) and where it lies on the desktop.
+pub struct Tile {
+    pub image: Arc<DynamicImage>,
+    /// Desktop geometry of the captured area.
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Changed regions in `image` pixels, when known.
+    pub damage: Option<Vec<Rect>>,
+}
+
+impl Tile {
+    fn scale(&self) -> f64 {
+        self.image.width() as f64 / self.width.max(1) as f64
+    }
+}
+
+/// Stitch `tiles` into one image, with their damage mapped onto it when all
+/// of them report it.
+pub fn stitch(tiles: &[Tile]) -> Option<(DynamicImage, Option<Vec<Rect>>)> {
+    let min_x = tiles.iter().map(|tile| tile.x).min()?;
+    let min_y = tiles.iter().map(|tile| tile.y).min()?;
+    let max_x = tiles.iter().map(|tile| tile.x + tile.width as i32).max()?;
+    let max_y = tiles.iter().map(|tile| tile.y + tile.height as i32).max()?;
+    let scale = tiles.iter().map(Tile::scale).fold(1.0, f64::max);
+    let to_canvas = |v: i32| (v as f64 * scale).round() as u32;
+
+    let mut canvas = RgbaImage::new(to_canvas(max_x - min_x), to_canvas(max_y - min_y));
+    let mut damage = Some(Vec::new());
+    for tile in tiles {
+        let (left, top) = (to_canvas(tile.x - min_x), to_canvas(tile.y - min_y));
+        let (width, height) = (
+            to_canvas(tile.width as i32).max(1),
+            to_canvas(tile.height as i32).max(1),
+        );
+        let image = tile.image.to_rgba8();
+        let image = if image.dimensions() == (width, height) {
+            image
+        } else {
+            imageops::resize(&image, width, height, FilterType::Triangle)
+        };
+        imageops::replace(&mut canvas, &image, left as i64, top as i64);
+
+        let factor = scale / tile.scale();
+        damage = match (damage, &tile.damage) {
+            (Some(mut all), Some(rects)) => {
+                all.extend(rects.iter().map(|rect| Rect {
+                    x: left + (rect.x as f64 * factor) as u32,
+                    y: top + (rect.y as f64 * factor) as u32,
+                    width: (rect.width as f64 * factor).ceil() as u32,
+                    height: (rect.height as f64 * factor).ceil() as u32,
+                }));
+                Some(all)
+            }
+            _ => None,
+        };
+    }
+    Some((DynamicImage::ImageRgba8(canvas), damage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn tile(x: i32, width: u32, pixels: u32, color: u8, damage: Option<Vec<Rect>>) -> Tile {
+        Tile {
+            image: Arc::new(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                pixels,
+                pixels / 2,
+                Rgba([color, color, color, 255]),
+            ))),
+            x,
+            y: 0,
+            width,
+            height: width / 2,
+            damage,
+        }
+    }
+
+    #[test]
+    fn test_stitch() {
+        let damage = Rect {
+            x: 2,
+            y: 2,
+            width: 4,
+            height: 4,
+        };
+        // A 2x density monitor left of a 1x one of the same logical size
+        let tiles = [
+            tile(-20, 20, 40, 10, Some(vec![damage])),
+            tile(0, 20, 20, 200, Some(vec![damage])),
+        ];
+        let (image, rects) = stitch(&tiles).unwrap();
+        let image = image.to_rgba8();
+        assert_eq!(image.dimensions(), (80, 20));
+        assert_eq!(image.get_pixel(10, 10), &Rgba([10, 10, 10, 255]));
+        assert_eq!(image.get_pixel(70, 10), &Rgba([200, 200, 200, 255]));
+        let rects = rects.unwrap();
+        assert_eq!(rects[0], damage);
+        assert_eq!(
+            rects[1],
+            Rect {
+                x: 44,
+                y: 4,
+                width: 8,
+                height: 8
+            }
+        );
+
+        let tiles = [tile(0, 20, 20, 0, None), tile(20, 20, 20, 0, Some(vec![]))];
+        assert!(stitch(&tiles).unwrap().1.is_none());
+    }
+}
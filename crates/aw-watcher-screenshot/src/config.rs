@@ -1,27 +1,419 @@
+use crate::event::Rect;
+use crate::hash::HashAlgorithm;
 use anyhow::{Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Log file in the executable directory in portable mode.
+pub const PORTABLE_LOG_FILE: &str = "aw-watcher-screenshot.log";
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
+    /// Keep every path relative to the executable directory, see `make_portable`.
+    #[serde(default)]
+    pub portable: bool,
     pub trigger: TriggerConfig,
     pub capture: CaptureConfig,
     pub cache: CacheConfig,
     pub s3: S3Config,
     pub aw_server: AwServerConfig,
+    #[serde(default)]
+    pub qr_code: QrCodeConfig,
+    #[serde(default)]
+    pub analytics: AnalyticsConfig,
+    #[serde(default)]
+    pub summary: SummaryConfig,
+    #[serde(default)]
+    pub ocr: OcrConfig,
+    #[serde(default)]
+    pub redact: RedactConfig,
+    #[serde(default)]
+    pub face_blur: FaceBlurConfig,
+    #[serde(default)]
+    pub low_fidelity: LowFidelityConfig,
+    #[serde(default)]
+    pub focus_blur: FocusBlurConfig,
+    #[serde(default)]
+    pub nsfw: NsfwConfig,
+    /// Local inference models shared by the pipeline stages, keyed by name.
+    #[serde(default)]
+    pub models: HashMap<String, ModelConfig>,
+    #[serde(default)]
+    pub load_shedding: LoadSheddingConfig,
+    #[serde(default)]
+    pub spill: SpillConfig,
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub time: TimeConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub power: PowerConfig,
+    #[serde(default)]
+    pub meeting: MeetingConfig,
+    #[serde(default)]
+    pub screen_share: ScreenShareConfig,
+    #[serde(default)]
+    pub cpu_load: CpuLoadConfig,
+    #[serde(default)]
+    pub dnd: DndConfig,
+    #[serde(default)]
+    pub fullscreen: FullscreenConfig,
+    #[serde(default)]
+    pub incognito: IncognitoConfig,
+    #[serde(default)]
+    pub remote_desktop: RemoteDesktopConfig,
+    #[serde(default)]
+    pub control: ControlConfig,
+    #[serde(default)]
+    pub hotkeys: HotkeyConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub clip: ClipConfig,
+    #[serde(default)]
+    pub remote: RemoteConfig,
+    /// Per-monitor overrides, `[[monitor]]` tables.
+    #[serde(default, rename = "monitor")]
+    pub monitors: Vec<MonitorConfig>,
+    /// Policies for the focused application, `[[app]]` tables.
+    #[serde(default, rename = "app")]
+    pub apps: Vec<AppPolicy>,
+    /// Reduced fidelity at certain times, `[[privacy_rule]]` tables.
+    #[serde(default, rename = "privacy_rule")]
+    pub privacy_rules: Vec<PrivacyRule>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerSource {
+    /// Capture every `interval_secs`.
+    #[default]
+    Timer,
+    /// Capture shortly after bursts of keyboard/mouse input.
+    Input,
+    /// Capture when the focused window title changes.
+    Title,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct TriggerConfig {
+    /// Timer interval; with the input source, the longest delay before a
+    /// capture during continuous input.
     pub interval_secs: u64,
     pub timeout_secs: Option<u64>,
+    /// Stop after emitting this many capture events.
+    #[serde(default)]
+    pub max_captures: Option<u64>,
+    /// Per-monitor capture deadline; monitors that take longer are left out
+    /// of the tick. Defaults to the interval.
+    pub monitor_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub source: TriggerSource,
+    /// With the input source, capture once input has paused this long.
+    #[serde(default = "default_input_debounce_ms")]
+    pub input_debounce_ms: u64,
+    /// With the title source, how often to check the focused window title.
+    #[serde(default = "default_title_poll_ms")]
+    pub title_poll_ms: u64,
+    /// Pause capture after this long without keyboard/mouse input.
+    #[serde(default)]
+    pub afk_timeout_secs: Option<u64>,
+    /// With the timer source, delay each capture by a random amount up to
+    /// this (at most `interval_secs`), so captures are not predictable.
+    #[serde(default)]
+    pub jitter_secs: u64,
+    /// Take a few rapid follow-up captures when the filter sees a hash
+    /// distance of at least this, to document the transition.
+    #[serde(default)]
+    pub burst_threshold: Option<u32>,
+    /// Number of follow-up captures in a burst.
+    #[serde(default = "default_burst_count")]
+    pub burst_count: u32,
+    /// Delay between the captures of a burst.
+    #[serde(default = "default_burst_interval_ms")]
+    pub burst_interval_ms: u64,
+}
+
+fn default_input_debounce_ms() -> u64 {
+    500
+}
+
+fn default_title_poll_ms() -> u64 {
+    500
+}
+
+fn default_burst_count() -> u32 {
+    3
+}
+
+fn default_burst_interval_ms() -> u64 {
+    500
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureBackendKind {
+    /// DXGI on Windows, ScreenCaptureKit on macOS, xcap elsewhere; falls
+    /// back to xcap, or to the portal on Wayland, when the first choice fails.
+    #[default]
+    Auto,
+    Xcap,
+    /// xdg-desktop-portal Screenshot (Linux, Wayland).
+    Portal,
+    /// DXGI Desktop Duplication (Windows).
+    Dxgi,
+    /// ScreenCaptureKit (macOS 13+).
+    #[serde(rename = "screencapturekit")]
+    ScreenCaptureKit,
+}
+
+/// A step of the filter chain, `capture.filters`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    /// Drop monitors with `exclude` set in their `[[monitor]]` entry.
+    Exclude,
+    /// Drop frames captured while a window matching `blocked_apps` or
+    /// `blocked_titles` had focus, e.g. a window focused right before the
+    /// capture.
+    Blocklist,
+    /// Leave `ignore_regions` and notifications out of the comparison; must
+    /// come before `Dhash`. Blackout regions are painted right after capture
+    /// regardless of this step.
+    Masks,
+    /// Drop frames arriving within `debounce_ms` of the last accepted one.
+    Ratelimit,
+    /// Drop frames whose perceptual hash (and color histogram) is close to
+    /// the last accepted one, unless the force interval elapsed.
+    Dhash,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CaptureConfig {
     pub force_interval_secs: u64,
     pub dhash_threshold: u32,
+    /// Drop timer and input captures of a monitor this soon after its last
+    /// accepted frame. Explicit requests and burst captures are not debounced.
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+    /// Log and attach the filter's per-monitor decision counts to the event
+    /// data this often, to help tune `dhash_threshold`.
+    #[serde(default)]
+    pub filter_stats_secs: Option<u64>,
+    /// Steps the filter runs each frame through, in order. A frame is
+    /// dropped by the first step that rejects it.
+    #[serde(default = "default_filters")]
+    pub filters: Vec<FilterKind>,
+    /// Capture only the primary display.
+    #[serde(default)]
+    pub primary_only: bool,
+    /// Capture only the monitor containing the mouse cursor (or the focused
+    /// window when the cursor position is unavailable).
+    #[serde(default)]
+    pub active_monitor_only: bool,
+    /// Draw the mouse cursor onto captured images.
+    #[serde(default)]
+    pub include_cursor: bool,
+    /// With `include_cursor`, ring the cursor while a mouse button is held down.
+    #[serde(default)]
+    pub highlight_clicks: bool,
+    /// Stitch all monitors of a capture into one image.
+    #[serde(default)]
+    pub composite: bool,
+    /// Resize captures right away so their longer side is at most this many
+    /// pixels, before hashing and encoding.
+    #[serde(default)]
+    pub max_dimension: Option<u32>,
+    /// Grab a few small patches of each monitor first and skip the full
+    /// capture when they did not change (xcap backend). Changes between the
+    /// patches are picked up by the next forced capture.
+    #[serde(default)]
+    pub probe: bool,
+    /// Replace monitor names and fingerprints in event data with keyed
+    /// hashes, so monitor models do not fingerprint the hardware on a shared
+    /// aw-server. A remote server's `[[monitor]]` entries can then match the
+    /// agent's monitors by geometry only.
+    #[serde(default)]
+    pub anonymize_monitor_names: bool,
+    /// Salt for `anonymize_monitor_names`. Without one a random salt is
+    /// picked on every start, so the hashes change across restarts.
+    #[serde(default)]
+    pub monitor_name_salt: Option<String>,
+    /// Capture only monitors whose name matches one of these names or
+    /// regular expressions (all monitors when empty).
+    #[serde(default)]
+    pub include_monitors: Vec<String>,
+    /// Never capture monitors whose name matches one of these.
+    #[serde(default)]
+    pub exclude_monitors: Vec<String>,
+    /// Skip capture while the focused app's name contains one of these
+    /// (case-insensitive), e.g. password managers.
+    #[serde(default)]
+    pub blocked_apps: Vec<String>,
+    /// Skip capture while the focused window title matches one of these
+    /// regular expressions (case-insensitive), e.g. private chats.
+    #[serde(default)]
+    pub blocked_titles: Vec<String>,
+    /// When set, capture only while the focused app's name contains one of
+    /// these, or its title matches one of `allowed_titles`.
+    #[serde(default)]
+    pub allowed_apps: Vec<String>,
+    /// Process names; capture is suspended while any of them runs, focused
+    /// or not.
+    #[serde(default)]
+    pub blocked_processes: Vec<String>,
+    /// Window title regular expressions (case-insensitive) allowing capture.
+    #[serde(default)]
+    pub allowed_titles: Vec<String>,
+    #[serde(default)]
+    pub backend: CaptureBackendKind,
+    /// Perceptual hash compared against `dhash_threshold`.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    /// Split frames into this many rows and columns and hash each tile, so
+    /// that small changes (clock, cursor) do not count as a change; 0 hashes
+    /// whole frames.
+    #[serde(default)]
+    pub tile_grid: u32,
+    /// With `tile_grid`, a frame changed only when more than this many tiles
+    /// differ by at least the dhash threshold.
+    #[serde(default = "default_tile_tolerance")]
+    pub tile_tolerance: u32,
+    /// Leave the notification popup area of this desktop out of the frame
+    /// comparison, so toasts do not count as a change.
+    #[serde(default)]
+    pub ignore_notifications: Option<NotificationArea>,
+    /// Second-stage check of frames the hash found similar: accept them
+    /// anyway when their color histograms differ by at least this share of
+    /// pixels (0-1). Off when unset.
+    #[serde(default)]
+    pub histogram_threshold: Option<f32>,
+    /// Levels per RGB channel of the color histograms.
+    #[serde(default = "default_histogram_bins")]
+    pub histogram_bins: u32,
+    /// Attach a heatmap of this many rows and columns (at most 8) to each
+    /// accepted frame: the mean luma change of every tile since the
+    /// monitor's previous accepted frame. Off when 0.
+    #[serde(default)]
+    pub heatmap_grid: u32,
+    /// Store one image for monitors showing the same picture (mirrored
+    /// displays): same geometry, and final images whose hashes are at most
+    /// `mirror_distance` apart.
+    #[serde(default)]
+    pub dedup_mirrored: bool,
+    #[serde(default = "default_mirror_distance")]
+    pub mirror_distance: u32,
+    /// Tone map captures of displays in HDR mode to SDR (DXGI backend).
+    #[serde(default = "default_hdr_tone_mapping")]
+    pub hdr_tone_mapping: bool,
+    /// Brightness of SDR white on HDR displays (Windows "SDR content
+    /// brightness"), mapped to white in captures.
+    #[serde(default = "default_sdr_white_nits")]
+    pub sdr_white_nits: f32,
+}
+
+fn default_filters() -> Vec<FilterKind> {
+    vec![
+        FilterKind::Exclude,
+        FilterKind::Blocklist,
+        FilterKind::Masks,
+        FilterKind::Ratelimit,
+        FilterKind::Dhash,
+    ]
+}
+
+fn default_debounce_ms() -> u64 {
+    100
+}
+
+fn default_mirror_distance() -> u32 {
+    2
+}
+
+fn default_histogram_bins() -> u32 {
+    4
+}
+
+fn default_tile_tolerance() -> u32 {
+    1
+}
+
+fn default_hdr_tone_mapping() -> bool {
+    true
+}
+
+fn default_sdr_white_nits() -> f32 {
+    80.0
+}
+
+/// Settings for the monitors matching all given selectors (name, geometry,
+/// fingerprint). The first matching entry applies; unset settings fall back
+/// to the global ones.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct MonitorConfig {
+    /// Monitor name as reported by the OS, case-insensitive.
+    pub name: Option<String>,
+    /// Stable monitor fingerprint as recorded in event data.
+    pub fingerprint: Option<String>,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Never capture this monitor.
+    pub exclude: bool,
+    /// Capture this monitor at most this often; effectively rounded up to a
+    /// multiple of `trigger.interval_secs`.
+    pub interval_secs: Option<u64>,
+    pub force_interval_secs: Option<u64>,
+    pub dhash_threshold: Option<u32>,
+    /// Quality (1-100) of this monitor's stored frames, in place of the
+    /// encoder's own: `webp_quality`, `avif.quality` or `jxl.quality`. The
+    /// png and ffmpeg encoders have none and reject it.
+    pub webp_quality: Option<u8>,
+    /// Capture only this part of the monitor, in monitor pixel coordinates,
+    /// e.g. `region = { x = 0, y = 0, width = 1280, height = 720 }`.
+    pub region: Option<Rect>,
+    /// Areas ignored when comparing frames, in monitor pixel coordinates,
+    /// e.g. the taskbar clock or a CPU meter.
+    pub ignore_regions: Vec<Rect>,
+    /// Also black out `ignore_regions` in the stored images.
+    pub black_out_ignored: bool,
+    /// Areas painted black right after capture, in monitor pixel
+    /// coordinates, e.g. where a ticket system shows customer data. No later
+    /// stage, composite image or remote server sees what they cover.
+    pub blackout_regions: Vec<Rect>,
+}
+
+/// Capture policy applied while an application is focused. The first
+/// matching `[[app]]` entry applies; its settings take precedence over the
+/// monitor's.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AppPolicy {
+    /// App name, matched case-insensitively against part of the focused
+    /// window's app name.
+    pub name: String,
+    /// Also require the window title to match this case-insensitive regular
+    /// expression.
+    pub title: Option<String>,
+    /// Never capture while the app is focused.
+    pub skip: bool,
+    pub interval_secs: Option<u64>,
+    pub force_interval_secs: Option<u64>,
+    pub dhash_threshold: Option<u32>,
+    /// Quality in place of the encoder's own, see
+    /// `MonitorConfig::webp_quality`.
+    pub webp_quality: Option<u8>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -31,6 +423,36 @@ pub struct CacheConfig {
     /// WebP quality (1-100). Use 100 for lossless, lower values for lossy compression.
     /// Default is 75 which provides good balance between quality and file size.
     pub webp_quality: u8,
+    /// WebP encoder effort (0 fastest - 6 smallest files); libwebp's
+    /// default (4) when unset.
+    pub webp_method: Option<i32>,
+    /// Let libwebp encode each frame on several threads, so large frames
+    /// do not saturate a single core.
+    pub webp_multithread: bool,
+    /// Encoder used for stored frames.
+    #[serde(alias = "format")]
+    pub encoder: EncoderKind,
+    /// Settings for `encoder = "ffmpeg"`.
+    pub ffmpeg: FfmpegEncoderConfig,
+    /// Settings for `encoder = "avif"`.
+    pub avif: AvifEncoderConfig,
+    /// Settings for `encoder = "jxl"`.
+    pub jxl: JxlEncoderConfig,
+    /// Width of the WebP thumbnail stored next to every frame; 0 disables
+    /// thumbnails.
+    pub thumbnail_width: u32,
+    /// WebP quality of thumbnails (1-100).
+    pub thumbnail_quality: u8,
+    /// Downscale frames wider than this before encoding; 0 for no limit.
+    pub max_width: u32,
+    /// Downscale frames taller than this before encoding; 0 for no limit.
+    pub max_height: u32,
+    /// Store frames in grayscale, for much smaller files.
+    pub grayscale: bool,
+    /// Frames encoded at the same time; 0 for one per CPU core.
+    pub max_parallel_encodes: usize,
+    /// Quality picked from how much a frame changed.
+    pub adaptive: AdaptiveQualityConfig,
 }
 
 impl Default for CacheConfig {
@@ -38,6 +460,160 @@ impl Default for CacheConfig {
         Self {
             cache_dir: "cache".to_string(),
             webp_quality: 75,
+            webp_method: None,
+            webp_multithread: false,
+            encoder: EncoderKind::default(),
+            ffmpeg: FfmpegEncoderConfig::default(),
+            avif: AvifEncoderConfig::default(),
+            jxl: JxlEncoderConfig::default(),
+            thumbnail_width: 0,
+            thumbnail_quality: 60,
+            max_width: 0,
+            max_height: 0,
+            grayscale: false,
+            max_parallel_encodes: 0,
+            adaptive: AdaptiveQualityConfig::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncoderKind {
+    /// libwebp, using `webp_quality`.
+    #[default]
+    Webp,
+    /// An ffmpeg subprocess, e.g. with a hardware encoder (requires the `hw-encode` feature).
+    Ffmpeg,
+    /// AVIF through rav1e, using `cache.avif`.
+    Avif,
+    /// JPEG XL through libjxl's `cjxl`.
+    Jxl,
+    /// Lossless PNG, for pixel-perfect archival.
+    Png,
+}
+
+impl EncoderKind {
+    /// Name as written in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            EncoderKind::Webp => "webp",
+            EncoderKind::Ffmpeg => "ffmpeg",
+            EncoderKind::Avif => "avif",
+            EncoderKind::Jxl => "jxl",
+            EncoderKind::Png => "png",
+        }
+    }
+
+    /// Whether the encoder has a quality setting that per-monitor, per-app
+    /// and adaptive qualities can replace. ffmpeg's is part of its arguments.
+    pub fn has_quality(self) -> bool {
+        matches!(
+            self,
+            EncoderKind::Webp | EncoderKind::Avif | EncoderKind::Jxl
+        )
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FfmpegOutputFormat {
+    #[default]
+    Avif,
+    Jpeg,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+#[cfg_attr(not(feature = "hw-encode"), allow(dead_code))]
+pub struct FfmpegEncoderConfig {
+    /// Path to the ffmpeg executable.
+    pub command: String,
+    /// ffmpeg video codec, e.g. "av1_vaapi", "av1_nvenc", "mjpeg_qsv" or "libsvtav1".
+    pub codec: String,
+    /// Container of the encoded frame; must match the codec (AV1 or MJPEG).
+    pub format: FfmpegOutputFormat,
+    /// Extra ffmpeg output arguments (device setup, filters, quality).
+    pub args: Vec<String>,
+}
+
+impl Default for FfmpegEncoderConfig {
+    fn default() -> Self {
+        Self {
+            command: "ffmpeg".to_string(),
+            codec: "libsvtav1".to_string(),
+            format: FfmpegOutputFormat::Avif,
+            args: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AdaptiveQualityConfig {
+    pub enabled: bool,
+    /// Change score (0-1) from which a frame is a major change, such as a
+    /// new page or app. A monitor's first frame is always one.
+    pub major_score: f32,
+    /// Quality of major changes, in place of the encoder's own (see
+    /// `MonitorConfig::webp_quality`).
+    pub major_quality: u8,
+    /// Change score below which a frame is a minor change.
+    pub minor_score: f32,
+    /// Quality of minor changes.
+    pub minor_quality: u8,
+    /// Store only the thumbnail of minor changes (needs `thumbnail_width`).
+    pub minor_thumbnail_only: bool,
+}
+
+impl Default for AdaptiveQualityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            major_score: 0.25,
+            major_quality: 90,
+            minor_score: 0.05,
+            minor_quality: 50,
+            minor_thumbnail_only: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AvifEncoderConfig {
+    /// Quality (1-100).
+    pub quality: u8,
+    /// Encoder speed (1 slowest, smallest files - 10 fastest).
+    pub speed: u8,
+}
+
+impl Default for AvifEncoderConfig {
+    fn default() -> Self {
+        Self {
+            quality: 60,
+            speed: 8,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct JxlEncoderConfig {
+    /// Path to libjxl's cjxl executable.
+    pub command: String,
+    /// Quality (1-100). 100 is lossless, which suits text-heavy screens best.
+    pub quality: u8,
+    /// Encoder effort (1 fastest - 9 smallest files).
+    pub effort: u8,
+}
+
+impl Default for JxlEncoderConfig {
+    fn default() -> Self {
+        Self {
+            command: "cjxl".to_string(),
+            quality: 100,
+            effort: 7,
         }
     }
 }
@@ -52,6 +628,13 @@ pub struct S3Config {
     pub secret_key: String,
     pub region: String,
     pub key_prefix: Option<String>,
+    /// Encoder for uploaded frames, when it should differ from
+    /// `cache.encoder`. Its other settings are taken from `[cache]`.
+    #[serde(alias = "format")]
+    pub encoder: Option<EncoderKind>,
+    /// WebP quality for uploaded frames, when it should differ from
+    /// `cache.webp_quality`.
+    pub webp_quality: Option<u8>,
 }
 
 impl Default for S3Config {
@@ -64,10 +647,950 @@ impl Default for S3Config {
             secret_key: "".to_string(),
             region: "".to_string(),
             key_prefix: None,
+            encoder: None,
+            webp_quality: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LoadSheddingConfig {
+    pub enabled: bool,
+    /// Consecutive ticks with a full queue before the interval is doubled,
+    /// and ticks without backlog before it is halved again.
+    pub backlog_ticks: u32,
+    /// Longest effective interval as a multiple of `trigger.interval_secs`.
+    pub max_interval_factor: u32,
+    /// While the queue is full, frames whose hash distance is below this are
+    /// dropped instead of waited on.
+    pub low_change_distance: u32,
+    /// Bucket receiving status events (hostname is appended).
+    pub bucket_id: String,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backlog_ticks: 3,
+            max_interval_factor: 8,
+            low_change_distance: 20,
+            bucket_id: "aw-watcher-screenshot-status".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Queue S3 uploads locally while the connection is metered and upload
+    /// them once it is not. Set to false to upload regardless.
+    pub defer_uploads_when_metered: bool,
+    /// Hold heartbeats and status events for an aw-server on another machine
+    /// back in memory while the connection is metered.
+    pub defer_reports_when_metered: bool,
+    /// How often the metered state is re-checked.
+    pub metered_check_secs: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            defer_uploads_when_metered: true,
+            defer_reports_when_metered: true,
+            metered_check_secs: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PowerConfig {
+    /// Switch to the battery-saver profile on low battery.
+    pub enabled: bool,
+    /// Battery charge (percent) at or below which the profile applies.
+    pub battery_threshold: u8,
+    /// Capture interval multiplier while on battery.
+    pub interval_factor: u32,
+    /// WebP quality while on battery.
+    pub webp_quality: u8,
+    /// WebP encoder effort while on battery (0 fastest - 6 slowest).
+    pub webp_method: i32,
+    /// Queue S3 uploads while on battery.
+    pub defer_uploads: bool,
+    /// How often the power source is polled.
+    pub check_secs: u64,
+    /// Bucket receiving profile changes (hostname is appended).
+    pub bucket_id: String,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            battery_threshold: 30,
+            interval_factor: 3,
+            webp_quality: 50,
+            webp_method: 0,
+            defer_uploads: true,
+            check_secs: 60,
+            bucket_id: "aw-watcher-screenshot-status".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScreenShareConfig {
+    /// Pause capture while the screen is being shared or recorded.
+    pub enabled: bool,
+    /// Processes that only run while sharing, checked when the platform
+    /// cannot report screen sharing itself. Matched case-insensitively
+    /// against the whole process name, without a `.exe` suffix.
+    pub processes: Vec<String>,
+}
+
+impl Default for ScreenShareConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // Zoom's screen share host, macOS Screen Sharing server
+            processes: vec!["CptHost".to_string(), "screensharingd".to_string()],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CpuLoadConfig {
+    /// Skip ticks while the system CPU load is high.
+    pub enabled: bool,
+    /// Load limit in percent of all cores, averaged since the previous tick.
+    pub max_load_percent: u8,
+    /// Capture anyway after deferring this long.
+    pub max_defer_secs: u64,
+}
+
+impl Default for CpuLoadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_load_percent: 80,
+            max_defer_secs: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DndConfig {
+    /// Apps (e.g. "zoom.exe", "teams") whose foreground use pauses capture.
+    /// Matched case-insensitively against part of the app name.
+    pub apps: Vec<String>,
+    /// Keep pausing this long after the app lost focus.
+    pub cooldown_secs: u64,
+}
+
+impl Default for DndConfig {
+    fn default() -> Self {
+        Self {
+            apps: Vec::new(),
+            cooldown_secs: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct IncognitoConfig {
+    /// Skip capture while a private browsing window is focused.
+    pub enabled: bool,
+    /// Title patterns (case-insensitive regular expressions) marking private
+    /// windows, keyed by browser app name (matched against part of it).
+    pub browsers: HashMap<String, Vec<String>>,
+}
+
+impl Default for IncognitoConfig {
+    fn default() -> Self {
+        let browsers = [
+            ("chrome", &[r"\(Incognito\)", r"^New Incognito tab"][..]),
+            ("chromium", &[r"\(Incognito\)", r"^New Incognito tab"]),
+            ("msedge", &[r"\bInPrivate\b"]),
+            ("microsoft edge", &[r"\bInPrivate\b"]),
+            (
+                "brave",
+                &[r"\(Private\)", r"^New Private Window", r"\bTor\b"],
+            ),
+            ("vivaldi", &[r"\(Private\)", r"^Private Tab"]),
+            ("opera", &[r"\(Private\)", r"^Private Browsing"]),
+            ("firefox", &[r"Private Browsing$"]),
+            ("librewolf", &[r"Private Browsing$"]),
+            ("safari", &[r"^Private Browsing"]),
+        ];
+        Self {
+            enabled: false,
+            browsers: browsers
+                .iter()
+                .map(|(app, patterns)| {
+                    (
+                        app.to_string(),
+                        patterns.iter().map(|p| p.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Global hotkeys in `global-hotkey` syntax, e.g. "ctrl+alt+shift+KeyP".
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    /// Toggle between paused and running.
+    pub pause: Option<String>,
+    /// Pause for `timed_pause_minutes`, or resume when paused.
+    pub timed_pause: Option<String>,
+    pub timed_pause_minutes: u32,
+    /// Capture all monitors now.
+    pub capture: Option<String>,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        Self {
+            pause: None,
+            timed_pause: None,
+            timed_pause_minutes: 15,
+            capture: None,
+        }
+    }
+}
+
+/// Where a desktop shows notification popups, see `mask::notification_rect`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationArea {
+    /// The preset of the desktop the watcher runs on.
+    #[default]
+    Auto,
+    /// Bottom right, above the taskbar.
+    Windows,
+    /// Top right, below the menu bar.
+    Macos,
+    /// Top center, below the top bar.
+    Gnome,
+    /// Bottom right, above the panel.
+    Kde,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FullscreenAction {
+    /// Capture fullscreen apps like any other window.
+    #[default]
+    Capture,
+    /// Skip capture ticks while a fullscreen app is focused.
+    Skip,
+    /// Capture only every `throttle_factor`-th tick.
+    Throttle,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RemoteDesktopConfig {
+    /// Skip capture while a remote desktop or VM window is focused.
+    pub enabled: bool,
+    /// Remote desktop and VM viewer app names, matched case-insensitively
+    /// against part of the app name.
+    pub apps: Vec<String>,
+    /// Also skip while such a window covers at least this fraction of a
+    /// monitor without being focused; 0 disables the check.
+    pub min_coverage: f32,
+}
+
+impl Default for RemoteDesktopConfig {
+    fn default() -> Self {
+        let apps = [
+            "mstsc",
+            "msrdc",
+            "remote desktop",
+            "remmina",
+            "freerdp",
+            "vnc",
+            "virtualbox",
+            "vmware",
+            "vmplayer",
+            "parallels",
+            "qemu",
+            "virt-viewer",
+            "remote-viewer",
+            "citrix",
+            "wfica",
+        ];
+        Self {
+            enabled: false,
+            apps: apps.iter().map(|app| app.to_string()).collect(),
+            min_coverage: 0.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FullscreenConfig {
+    pub action: FullscreenAction,
+    pub throttle_factor: u32,
+    /// Apply the action only to these apps, matched case-insensitively
+    /// against part of the app name. Empty applies it to every fullscreen app.
+    pub apps: Vec<String>,
+}
+
+impl Default for FullscreenConfig {
+    fn default() -> Self {
+        Self {
+            action: FullscreenAction::Capture,
+            throttle_factor: 5,
+            apps: Vec::new(),
+        }
+    }
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            http_enabled: false,
+            http_bind: "127.0.0.1".to_string(),
+            http_port: 5680,
+            http_token: None,
+            bucket_id: "aw-watcher-screenshot-status".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipFormat {
+    /// VP9 in WebM.
+    #[default]
+    Webm,
+    /// H.264 in fragmented MP4.
+    Mp4,
+}
+
+impl ClipFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ClipFormat::Webm => "webm",
+            ClipFormat::Mp4 => "mp4",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ClipFormat::Webm => "video/webm",
+            ClipFormat::Mp4 => "video/mp4",
+        }
+    }
+}
+
+/// Short video clips after large changes. The producer captures the frames,
+/// which pass the guards and privacy stages like any other, and ffmpeg
+/// encodes them.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClipConfig {
+    pub enabled: bool,
+    /// Record a clip of a monitor when its hash distance to the previous
+    /// accepted frame is at least this.
+    pub change_threshold: u32,
+    pub duration_secs: u64,
+    pub fps: u32,
+    /// Minimum time between the starts of two clips.
+    pub cooldown_secs: u64,
+    pub format: ClipFormat,
+    /// Path to the ffmpeg executable.
+    pub command: String,
+}
+
+impl ClipConfig {
+    /// Frames of one clip.
+    pub fn frames(&self) -> u32 {
+        (self.duration_secs as u32 * self.fps.max(1)).max(1)
+    }
+
+    /// Time between two frames of a clip.
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.fps.max(1) as f64)
+    }
+}
+
+impl Default for ClipConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            change_threshold: 20,
+            duration_secs: 4,
+            fps: 2,
+            cooldown_secs: 60,
+            format: ClipFormat::Webm,
+            command: "ffmpeg".to_string(),
+        }
+    }
+}
+
+/// Working hours; outside them the producer sleeps.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    pub enabled: bool,
+    /// Weekdays to capture on, e.g. "mon" or "monday".
+    pub days: Vec<String>,
+    /// Start and end of the working period, "HH:MM". An end not after the
+    /// start runs past midnight.
+    pub start: String,
+    pub end: String,
+    /// IANA time zone name, e.g. "Europe/Berlin". Defaults to the machine's
+    /// local time zone.
+    pub timezone: Option<String>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            days: ["mon", "tue", "wed", "thu", "fri"]
+                .map(String::from)
+                .to_vec(),
+            start: "09:00".to_string(),
+            end: "17:00".to_string(),
+            timezone: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivacyAction {
+    /// Store no image, only the metadata event.
+    #[default]
+    Metadata,
+    /// Store a thumbnail of `max_dimension` on its longer side.
+    Thumbnail,
+    /// Store the image pixelated in blocks of `pixel_size`.
+    Pixelate,
+}
+
+/// Reduced fidelity during a period of the week, e.g. only metadata in the
+/// evening. The first rule whose period contains a capture applies.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PrivacyRule {
+    /// Weekdays the period starts on, e.g. "sat" or "saturday".
+    pub days: Vec<String>,
+    /// Start and end of the period, "HH:MM". An end not after the start
+    /// runs past midnight, e.g. 18:00 to 09:00.
+    pub start: String,
+    pub end: String,
+    /// IANA time zone name. Defaults to the machine's local time zone.
+    pub timezone: Option<String>,
+    pub action: PrivacyAction,
+    pub max_dimension: u32,
+    pub pixel_size: u32,
+}
+
+impl Default for PrivacyRule {
+    fn default() -> Self {
+        Self {
+            days: ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+                .map(String::from)
+                .to_vec(),
+            start: "00:00".to_string(),
+            end: "00:00".to_string(),
+            timezone: None,
+            action: PrivacyAction::default(),
+            max_dimension: 240,
+            pixel_size: 16,
+        }
+    }
+}
+
+impl PrivacyRule {
+    /// The rule's period as a schedule.
+    pub fn schedule(&self) -> ScheduleConfig {
+        ScheduleConfig {
+            enabled: true,
+            days: self.days.clone(),
+            start: self.start.clone(),
+            end: self.end.clone(),
+            timezone: self.timezone.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ControlConfig {
+    /// Accept `pause`, `resume`, `capture`, `status` and fidelity commands.
+    pub enabled: bool,
+    /// Unix socket path, or named pipe name on Windows. Defaults to
+    /// `$XDG_RUNTIME_DIR/aw-watcher-screenshot.sock` (falling back to the
+    /// temp dir) and `\\.\pipe\aw-watcher-screenshot`.
+    pub path: Option<String>,
+    /// Also accept the commands over HTTP, e.g. `POST /capture`.
+    pub http_enabled: bool,
+    /// IP address of the HTTP endpoint, v4 or v6. Anything but a loopback
+    /// address exposes it to the network and requires `http_token`.
+    pub http_bind: String,
+    pub http_port: u16,
+    /// Token HTTP requests must send as `Authorization: Bearer <token>`.
+    pub http_token: Option<String>,
+    /// Status bucket receiving a `paused` event for every user pause.
+    pub bucket_id: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MeetingAction {
+    /// Skip capture ticks for the duration of the meeting.
+    #[default]
+    Pause,
+    /// Keep capturing but pixelate every frame.
+    Redact,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MeetingConfig {
+    /// Detect meetings from camera/microphone use.
+    pub enabled: bool,
+    pub action: MeetingAction,
+    /// Treat camera use as a meeting.
+    pub camera: bool,
+    /// Treat microphone use as a meeting.
+    pub microphone: bool,
+    /// Block size of the pixelation in redact mode.
+    pub pixel_size: u32,
+    /// Bucket receiving meeting marker events (hostname is appended).
+    pub bucket_id: String,
+}
+
+impl Default for MeetingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            action: MeetingAction::Pause,
+            camera: true,
+            microphone: true,
+            pixel_size: 32,
+            bucket_id: "aw-watcher-screenshot-status".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TimeConfig {
+    /// Use local time instead of UTC for cache paths, object keys and daily
+    /// report boundaries. Event timestamps stay UTC.
+    pub local_time: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Delete stored frames on a schedule.
+    pub enabled: bool,
+    /// Delete cached frames older than this many days; kept when unset.
+    pub max_age_days: Option<u64>,
+    /// Delete frames flagged as sensitive (by the NSFW classifier or
+    /// matching redaction rules) after this many hours, from the cache and
+    /// from S3.
+    pub flagged_max_age_hours: u64,
+    /// How often to look for expired frames.
+    pub interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age_days: None,
+            flagged_max_age_hours: 24,
+            interval_secs: 3600,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Pause capturing while the watcher's login session is switched away
+    /// from (fast user switching, disconnected remote sessions).
+    pub pause_when_inactive: bool,
+    /// Pause capturing while the screen is locked or the screensaver is active.
+    pub pause_when_locked: bool,
+    /// Keep pausing this long after the screen unlocks.
+    pub unlock_grace_secs: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            pause_when_inactive: true,
+            pause_when_locked: true,
+            unlock_grace_secs: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Run encoding, OCR and inference at background OS priority.
+    pub low_priority: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SpillConfig {
+    pub enabled: bool,
+    /// Directory in which a dedicated spill subdirectory, private to the
+    /// current user, is created; defaults to the cache dir.
+    pub dir: Option<PathBuf>,
+    /// Frames kept in memory while the encoder is busy before spilling to disk.
+    pub max_memory_events: usize,
+    /// Events kept on disk; further frames are dropped until the encoder
+    /// catches up.
+    pub max_spilled_events: usize,
+    /// zstd compression level for spilled frames.
+    pub compression_level: i32,
+}
+
+impl Default for SpillConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            max_memory_events: 2,
+            max_spilled_events: 100,
+            compression_level: 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct QrCodeConfig {
+    pub enabled: bool,
+    /// Black out detected codes in the image and keep only a placeholder
+    /// in the metadata instead of the decoded payload.
+    pub redact: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AnalyticsConfig {
+    pub enabled: bool,
+    /// Bucket receiving the hourly context-switch events (hostname is appended).
+    pub bucket_id: String,
+    /// Hamming distance at or above which a frame counts as a scene change.
+    pub scene_change_threshold: u32,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bucket_id: "aw-watcher-screenshot-analytics".to_string(),
+            scene_change_threshold: 20,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SummaryConfig {
+    pub enabled: bool,
+    /// Bucket receiving the daily summary events (hostname is appended).
+    pub bucket_id: String,
+    /// Most frequent OCR words listed in the summary; 0 leaves them out.
+    pub keywords: usize,
+    /// Command writing a natural-language digest of the day, e.g. a script
+    /// around a local vision-language model. It gets the Markdown summary on
+    /// stdin and runs in the day's cache directory; its output is stored as
+    /// `digest`.
+    pub digest_command: Option<String>,
+    pub digest_args: Vec<String>,
+    pub digest_timeout_secs: u64,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bucket_id: "aw-watcher-screenshot-summary".to_string(),
+            keywords: 20,
+            digest_command: None,
+            digest_args: Vec::new(),
+            digest_timeout_secs: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OcrConfig {
+    pub enabled: bool,
+    /// Path to the tesseract executable.
+    pub command: String,
+    /// Tesseract language codes to recognize, e.g. ["eng", "deu"].
+    pub languages: Vec<String>,
+    /// Recognize the next frame of a monitor with the language detected in
+    /// its previous frame, when that language is in `languages`.
+    pub auto_language: bool,
+    /// Include the recognized text in the aw-server event data.
+    pub include_text: bool,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            command: "tesseract".to_string(),
+            languages: vec!["eng".to_string()],
+            auto_language: true,
+            include_text: false,
+        }
+    }
+}
+
+/// What happens to a frame with text matching a redaction rule.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactAction {
+    /// Blur the matched words.
+    #[default]
+    Blur,
+    /// Withhold the whole frame, keeping only its metadata.
+    Drop,
+    /// Keep the frame as is and only record the match.
+    Flag,
+}
+
+/// A named regular expression and its action, `[[redact.rules]]`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RedactRule {
+    /// Name recorded in the event data; defaults to the pattern.
+    pub name: Option<String>,
+    /// Regular expression matched against each OCR text line.
+    pub pattern: String,
+    #[serde(default)]
+    pub action: RedactAction,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RedactConfig {
+    /// Blur text matching sensitive patterns before frames are stored.
+    /// Recognizes text with the `[ocr]` command and languages.
+    pub enabled: bool,
+    /// Built-in patterns: "credit_card", "email" and "api_key" are blurred,
+    /// "private_key" withholds the whole frame.
+    pub builtin: Vec<String>,
+    /// Additional regular expressions, matched against each OCR text line
+    /// and blurred.
+    pub patterns: Vec<String>,
+    /// Rules with their own action, applied along with the patterns above.
+    pub rules: Vec<RedactRule>,
+    /// Strength of the blur over matched text.
+    pub blur_sigma: f32,
+}
+
+impl Default for RedactConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            builtin: vec![
+                "credit_card".to_string(),
+                "email".to_string(),
+                "api_key".to_string(),
+            ],
+            patterns: Vec::new(),
+            rules: Vec::new(),
+            blur_sigma: 12.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LowFidelityMode {
+    /// Replace the image by blocks of `pixel_size` pixels.
+    #[default]
+    Pixelate,
+    /// Shrink the image to `max_dimension` on its longer side.
+    Thumbnail,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LowFidelityConfig {
+    /// Start in low-fidelity mode; the control channel switches it at runtime.
+    pub enabled: bool,
+    pub mode: LowFidelityMode,
+    pub pixel_size: u32,
+    pub max_dimension: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FocusBlurConfig {
+    /// Blur everything but the focused window.
+    pub enabled: bool,
+    pub blur_sigma: f32,
+}
+
+impl Default for FocusBlurConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            blur_sigma: 12.0,
+        }
+    }
+}
+
+impl Default for LowFidelityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: LowFidelityMode::default(),
+            pixel_size: 16,
+            max_dimension: 240,
         }
     }
 }
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FaceBlurConfig {
+    /// Name of the face detection model in `[models]`; blurring is enabled
+    /// when set.
+    pub model: Option<String>,
+    /// Detections scoring below this are ignored.
+    pub min_score: f32,
+    /// Strength of the blur over faces.
+    pub blur_sigma: f32,
+}
+
+impl Default for FaceBlurConfig {
+    fn default() -> Self {
+        Self {
+            model: None,
+            min_score: 0.6,
+            blur_sigma: 16.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NsfwAction {
+    /// Keep the frame and flag it in the event data.
+    Flag,
+    /// Drop the image and keep only its metadata, flagged.
+    #[default]
+    Drop,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NsfwConfig {
+    /// Name of the image classifier in `[models]`; the gate is enabled when
+    /// set.
+    pub model: Option<String>,
+    /// Indices of the classifier outputs counted as unsafe.
+    pub unsafe_classes: Vec<usize>,
+    /// Frames whose unsafe classes sum to at least this probability are flagged.
+    pub threshold: f32,
+    /// Turn the model outputs into probabilities; disable for models that
+    /// already end in a softmax.
+    pub softmax: bool,
+    pub action: NsfwAction,
+}
+
+impl Default for NsfwConfig {
+    fn default() -> Self {
+        Self {
+            model: None,
+            unsafe_classes: vec![1],
+            threshold: 0.8,
+            softmax: true,
+            action: NsfwAction::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelRuntime {
+    /// candle with safetensors weights (requires the `candle` feature).
+    Candle,
+    /// ONNX Runtime (requires the `onnx` feature).
+    Onnx,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(not(any(feature = "candle", feature = "onnx")), allow(dead_code))]
+pub struct ModelConfig {
+    pub runtime: ModelRuntime,
+    pub path: PathBuf,
+    /// "cpu", "cuda:N" or "metal:N".
+    #[serde(default = "default_model_device")]
+    pub device: String,
+    /// Number of inputs per inference call.
+    #[serde(default = "default_model_batch_size")]
+    pub batch_size: usize,
+    /// Image input size the model expects.
+    #[serde(default = "default_model_input_size")]
+    pub input_width: u32,
+    #[serde(default = "default_model_input_size")]
+    pub input_height: u32,
+    /// Per-channel normalization applied to [0, 1] RGB input.
+    #[serde(default = "default_model_mean")]
+    pub mean: [f32; 3],
+    #[serde(default = "default_model_std")]
+    pub std: [f32; 3],
+    /// Network architecture for candle models, e.g. "resnet50".
+    #[cfg_attr(not(feature = "candle"), allow(dead_code))]
+    pub architecture: Option<String>,
+    /// Output classes for candle classifiers; omit for a feature extractor.
+    #[cfg_attr(not(feature = "candle"), allow(dead_code))]
+    pub num_classes: Option<usize>,
+}
+
+fn default_model_device() -> String {
+    "cpu".to_string()
+}
+
+fn default_model_batch_size() -> usize {
+    8
+}
+
+fn default_model_input_size() -> u32 {
+    224
+}
+
+fn default_model_mean() -> [f32; 3] {
+    [0.485, 0.456, 0.406]
+}
+
+fn default_model_std() -> [f32; 3] {
+    [0.229, 0.224, 0.225]
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct AwServerConfig {
@@ -95,6 +1618,43 @@ impl Default for AwServerConfig {
     }
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteMode {
+    /// Run the whole pipeline locally.
+    #[default]
+    Off,
+    /// Capture and encode only, and send events to a server.
+    Agent,
+    /// Receive events from agents instead of capturing, and run filtering,
+    /// S3 upload and aw-server reporting for them.
+    Server,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RemoteConfig {
+    pub mode: RemoteMode,
+    /// Server address: where agents connect to, or the server listens on.
+    pub address: String,
+    /// Shared secret agents must present; set it whenever the server listens
+    /// on a non-loopback address.
+    pub token: Option<String>,
+    /// Name the agent reports itself as; `aw_server.hostname` when unset.
+    pub host: Option<String>,
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            mode: RemoteMode::Off,
+            address: "127.0.0.1:5681".to_string(),
+            token: None,
+            host: None,
+        }
+    }
+}
+
 impl Config {
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let content = fs::read_to_string(path).context("Failed to read config file")?;
@@ -111,28 +1671,172 @@ impl Config {
         } else {
             config.aw_server.pulse_time = Some(config.trigger.interval_secs as f64 * 4.0);
         }
+        // Agents send lossless WebP or PNG, the formats the server can decode
+        if config.remote.mode == RemoteMode::Agent
+            && matches!(config.cache.encoder, EncoderKind::Avif | EncoderKind::Jxl)
+        {
+            return Err(anyhow::anyhow!(
+                "cache.encoder = \"{}\" is not supported in agent mode, set the stored format in the server's cache.encoder",
+                format!("{:?}", config.cache.encoder).to_lowercase()
+            ));
+        }
+        // Agents leave encoding to the server
+        if !config.cache.encoder.has_quality() && config.remote.mode != RemoteMode::Agent {
+            let setting = if config.cache.adaptive.enabled {
+                Some("cache.adaptive")
+            } else if config.monitors.iter().any(|m| m.webp_quality.is_some()) {
+                Some("webp_quality in [[monitor]]")
+            } else if config.apps.iter().any(|a| a.webp_quality.is_some()) {
+                Some("webp_quality in [[app]]")
+            } else {
+                None
+            };
+            if let Some(setting) = setting {
+                return Err(anyhow::anyhow!(
+                    "{} needs an encoder with a quality setting, not cache.encoder = \"{}\"",
+                    setting,
+                    config.cache.encoder.name()
+                ));
+            }
+        }
         Ok(config)
     }
 
+    /// Resolve every relative path against `base` and keep all state that
+    /// would otherwise go to system directories (spilled frames, the control
+    /// socket) below it, so the watcher can run self-contained from a USB
+    /// stick or project folder. The log goes to `PORTABLE_LOG_FILE` there.
+    pub fn make_portable(&mut self, base: &Path) {
+        let resolve = |path: &Path| {
+            if path.is_absolute() {
+                path.to_path_buf()
+            } else {
+                base.join(path)
+            }
+        };
+
+        self.portable = true;
+        self.cache.cache_dir = resolve(Path::new(&self.cache.cache_dir))
+            .to_string_lossy()
+            .into_owned();
+        self.spill.dir = Some(resolve(
+            self.spill.dir.as_deref().unwrap_or(Path::new("spill")),
+        ));
+        for model in self.models.values_mut() {
+            model.path = resolve(&model.path);
+        }
+        // Named pipes are not files, there is nothing to relocate on Windows
+        if cfg!(not(windows)) {
+            let socket = self
+                .control
+                .path
+                .as_deref()
+                .unwrap_or("aw-watcher-screenshot.sock");
+            self.control.path = Some(resolve(Path::new(socket)).to_string_lossy().into_owned());
+        }
+    }
+
     pub fn default_config() -> Self {
-        let exe_path = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("."));
-        let exe_dir = exe_path.parent().unwrap_or_else(|| Path::new("."));
+        let exe_dir = exe_dir();
 
         Self {
+            portable: false,
             trigger: TriggerConfig {
                 interval_secs: 2,
                 timeout_secs: Some(20),
+                monitor_timeout_ms: None,
+                source: TriggerSource::Timer,
+                input_debounce_ms: default_input_debounce_ms(),
+                title_poll_ms: default_title_poll_ms(),
+                afk_timeout_secs: None,
+                jitter_secs: 0,
+                max_captures: None,
+                burst_threshold: None,
+                burst_count: default_burst_count(),
+                burst_interval_ms: default_burst_interval_ms(),
             },
             capture: CaptureConfig {
                 force_interval_secs: 60,
                 dhash_threshold: 10,
+                debounce_ms: default_debounce_ms(),
+                filter_stats_secs: None,
+                filters: default_filters(),
+                primary_only: false,
+                active_monitor_only: false,
+                include_cursor: false,
+                highlight_clicks: false,
+                composite: false,
+                max_dimension: None,
+                anonymize_monitor_names: false,
+                monitor_name_salt: None,
+                probe: false,
+                include_monitors: Vec::new(),
+                exclude_monitors: Vec::new(),
+                blocked_apps: Vec::new(),
+                blocked_titles: Vec::new(),
+                blocked_processes: Vec::new(),
+                allowed_apps: Vec::new(),
+                allowed_titles: Vec::new(),
+                backend: CaptureBackendKind::Auto,
+                hash_algorithm: HashAlgorithm::Dhash,
+                tile_grid: 0,
+                tile_tolerance: default_tile_tolerance(),
+                ignore_notifications: None,
+                histogram_threshold: None,
+                histogram_bins: default_histogram_bins(),
+                heatmap_grid: 0,
+                dedup_mirrored: false,
+                mirror_distance: default_mirror_distance(),
+                hdr_tone_mapping: default_hdr_tone_mapping(),
+                sdr_white_nits: default_sdr_white_nits(),
             },
             cache: CacheConfig {
                 cache_dir: exe_dir.join("cache").to_string_lossy().into_owned(),
-                webp_quality: 75,
+                ..CacheConfig::default()
             },
             s3: S3Config::default(),
             aw_server: AwServerConfig::default(),
+            qr_code: QrCodeConfig::default(),
+            analytics: AnalyticsConfig::default(),
+            summary: SummaryConfig::default(),
+            ocr: OcrConfig::default(),
+            redact: RedactConfig::default(),
+            face_blur: FaceBlurConfig::default(),
+            low_fidelity: LowFidelityConfig::default(),
+            focus_blur: FocusBlurConfig::default(),
+            nsfw: NsfwConfig::default(),
+            models: HashMap::new(),
+            load_shedding: LoadSheddingConfig::default(),
+            spill: SpillConfig::default(),
+            pipeline: PipelineConfig::default(),
+            session: SessionConfig::default(),
+            time: TimeConfig::default(),
+            network: NetworkConfig::default(),
+            retention: RetentionConfig::default(),
+            power: PowerConfig::default(),
+            meeting: MeetingConfig::default(),
+            screen_share: ScreenShareConfig::default(),
+            cpu_load: CpuLoadConfig::default(),
+            dnd: DndConfig::default(),
+            fullscreen: FullscreenConfig::default(),
+            incognito: IncognitoConfig::default(),
+            remote_desktop: RemoteDesktopConfig::default(),
+            control: ControlConfig::default(),
+            hotkeys: HotkeyConfig::default(),
+            schedule: ScheduleConfig::default(),
+            clip: ClipConfig::default(),
+            remote: RemoteConfig::default(),
+            monitors: Vec::new(),
+            apps: Vec::new(),
+            privacy_rules: Vec::new(),
         }
     }
 }
+
+/// Directory containing the running executable.
+pub fn exe_dir() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
@@ -0,0 +1,549 @@
+//! Local control channel.
+//!
+//! A running watcher accepts line-based commands on a unix socket (Linux,
+//! macOS) or a named pipe (Windows) and answers each with one line:
+//!
+//! * `pause` - skip captures until `resume`
+//! * `pause <minutes>` - skip captures and resume automatically afterwards
+//! * `resume` - capture again
+//! * `capture` - capture all monitors now, outside the regular schedule
+//! * `status` - `paused` or `running`
+//! * `low-fidelity` / `full-fidelity` - switch the low-fidelity privacy mode
+//! * `fidelity` - `low` or `full`
+//!
+//! The same commands are available over HTTP for tools that cannot talk to a
+//! socket: `POST /pause` (`?minutes=N` for a timed pause), `POST /resume`,
+//! `POST /capture`, `GET /status`, `POST /low-fidelity`, `POST /full-fidelity`
+//! and `GET /fidelity`. Off loopback, requests must carry
+//! `control.http_token` as a bearer token.
+//!
+//! Pausing goes through the producer's `CaptureGuard`s, so a paused watcher
+//! also ignores `capture`. Global hotkeys share the same `ControlHandle`.
+//! Every pause is posted as a `paused` status event when it starts and
+//! replaced by one of its full length when it ends, so gaps in the captures
+//! can be told apart from outages, even when the watcher stops while paused.
+
+use crate::config::ControlConfig;
+use crate::guard::CaptureGuard;
+use crate::remote::token_matches;
+use crate::status::StatusReporter;
+use anyhow::{Error, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::{Map, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// A pause requested by the user.
+#[derive(Clone, Copy)]
+struct Pause {
+    since: DateTime<Utc>,
+    /// Automatic resume time of a timed pause.
+    until: Option<DateTime<Utc>>,
+    /// Whether the pause started as a timed one, as recorded in its marker.
+    timed: bool,
+}
+
+/// State shared between the control channel and the capture producer.
+#[derive(Clone, Default)]
+pub struct ControlHandle {
+    pause: Arc<Mutex<Option<Pause>>>,
+    capture_requests: Arc<Notify>,
+    low_fidelity: Arc<AtomicBool>,
+    reporter: Option<StatusReporter>,
+}
+
+impl ControlHandle {
+    /// Post `paused` events to `reporter`.
+    pub fn with_reporter(mut self, reporter: StatusReporter) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    /// Switch `low_fidelity`, shared with the `LowFidelityProcessor`.
+    pub fn with_low_fidelity(mut self, low_fidelity: Arc<AtomicBool>) -> Self {
+        self.low_fidelity = low_fidelity;
+        self
+    }
+
+    /// Notified for every `capture` command.
+    pub fn capture_requests(&self) -> Arc<Notify> {
+        self.capture_requests.clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        let mut pause = self.pause.lock().unwrap_or_else(|e| e.into_inner());
+        if pause.is_some_and(|p| p.until.is_some_and(|until| Utc::now() >= until)) {
+            info!("Timed pause is over, resuming capture");
+            self.end_pause(pause.take());
+        }
+        pause.is_some()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        let mut pause = self.pause.lock().unwrap_or_else(|e| e.into_inner());
+        match (pause.as_mut(), paused) {
+            // A plain pause lasts until resumed, even during a timed one
+            (Some(current), true) => current.until = None,
+            (None, true) => {
+                info!(paused, "Capture pause toggled by user");
+                *pause = Some(self.start_pause(None));
+            }
+            (Some(_), false) => {
+                info!(paused, "Capture pause toggled by user");
+                self.end_pause(pause.take());
+            }
+            (None, false) => {}
+        }
+    }
+
+    /// Pause for `minutes`, or extend or shorten the current pause to end
+    /// `minutes` from now.
+    pub fn pause_for(&self, minutes: u32) {
+        let mut pause = self.pause.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Utc::now();
+        let until = now + chrono::Duration::minutes(minutes as i64);
+        info!(minutes, until = %until, "Capture paused by user");
+        match pause.as_mut() {
+            Some(current) => current.until = Some(until),
+            None => *pause = Some(self.start_pause(Some(until))),
+        }
+    }
+
+    /// A pause starting now, with its marker event posted.
+    fn start_pause(&self, until: Option<DateTime<Utc>>) -> Pause {
+        let pause = Pause {
+            since: Utc::now(),
+            until,
+            timed: until.is_some(),
+        };
+        if let Some(reporter) = &self.reporter {
+            reporter.report_span("paused", pause.since, TimeDelta::zero(), pause_data(&pause));
+        }
+        pause
+    }
+
+    /// Extend the marker event of a pause that just ended to its end.
+    fn end_pause(&self, pause: Option<Pause>) {
+        let (Some(pause), Some(reporter)) = (pause, &self.reporter) else {
+            return;
+        };
+        let duration = Utc::now() - pause.since;
+        reporter.replace_span("paused", pause.since, duration, pause_data(&pause));
+    }
+
+    pub fn set_low_fidelity(&self, low_fidelity: bool) {
+        if self.low_fidelity.swap(low_fidelity, Ordering::Relaxed) != low_fidelity {
+            info!(low_fidelity, "Low-fidelity mode toggled by user");
+        }
+    }
+
+    /// Request an immediate capture; refused while paused.
+    pub fn request_capture(&self) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+        self.capture_requests.notify_one();
+        true
+    }
+
+    /// Apply one command and return the reply.
+    fn execute(&self, command: &str) -> String {
+        let command = command.trim().to_ascii_lowercase();
+        if let Some(minutes) = command.strip_prefix("pause ") {
+            return match minutes.trim().parse::<u32>() {
+                Ok(minutes) if minutes > 0 => {
+                    self.pause_for(minutes);
+                    format!("ok paused for {} minutes", minutes)
+                }
+                _ => format!("error invalid pause duration: {}", minutes.trim()),
+            };
+        }
+        match command.as_str() {
+            "pause" => {
+                self.set_paused(true);
+                "ok paused".to_string()
+            }
+            "resume" => {
+                self.set_paused(false);
+                "ok running".to_string()
+            }
+            "capture" if self.request_capture() => "ok capture requested".to_string(),
+            "capture" => "error paused".to_string(),
+            "status" if self.is_paused() => "paused".to_string(),
+            "status" => "running".to_string(),
+            "low-fidelity" => {
+                self.set_low_fidelity(true);
+                "ok low fidelity".to_string()
+            }
+            "full-fidelity" => {
+                self.set_low_fidelity(false);
+                "ok full fidelity".to_string()
+            }
+            "fidelity" if self.low_fidelity.load(Ordering::Relaxed) => "low".to_string(),
+            "fidelity" => "full".to_string(),
+            other => format!("error unknown command: {}", other),
+        }
+    }
+
+    /// Answer an HTTP request with a status code and body. Requests sent by
+    /// browsers (with an `Origin` header) are refused, so web pages cannot
+    /// drive the watcher.
+    fn route_http(&self, method: &str, path: &str, from_browser: bool) -> (u16, String) {
+        if from_browser {
+            return (
+                403,
+                "error cross-origin requests are not allowed".to_string(),
+            );
+        }
+        let (command, query) = path.split_once('?').unwrap_or((path, ""));
+        let command = command.trim_start_matches('/');
+        let expected = match command {
+            "pause" | "resume" | "capture" | "low-fidelity" | "full-fidelity" => "POST",
+            "status" | "fidelity" => "GET",
+            _ => return (404, format!("error unknown command: {}", command)),
+        };
+        if method != expected {
+            return (405, format!("error use {} /{}", expected, command));
+        }
+        let minutes = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("minutes="));
+        let reply = match minutes {
+            Some(minutes) if command == "pause" => self.execute(&format!("pause {}", minutes)),
+            _ => self.execute(command),
+        };
+        let status = if reply.starts_with("error") { 409 } else { 200 };
+        (status, reply)
+    }
+}
+
+impl CaptureGuard for ControlHandle {
+    fn pause_reason(&self) -> Option<String> {
+        self.is_paused().then(|| "paused by user".to_string())
+    }
+}
+
+/// Data of a pause's marker event.
+fn pause_data(pause: &Pause) -> Map<String, Value> {
+    let mut data = Map::new();
+    data.insert("timed".to_string(), Value::from(pause.timed));
+    data
+}
+
+/// The control endpoint used when none is configured.
+pub fn default_path() -> String {
+    #[cfg(windows)]
+    {
+        r"\\.\pipe\aw-watcher-screenshot".to_string()
+    }
+    #[cfg(not(windows))]
+    {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        dir.join("aw-watcher-screenshot.sock")
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Answer commands from one client until it disconnects.
+async fn handle_connection<S: AsyncRead + AsyncWrite>(stream: S, handle: ControlHandle) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        debug!(command = %line.trim(), "Control command");
+        let reply = handle.execute(&line);
+        if writer
+            .write_all(format!("{}\n", reply).as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Listen for control commands on `path` until `token` is cancelled.
+#[cfg(unix)]
+pub fn serve(
+    path: String,
+    handle: ControlHandle,
+    token: CancellationToken,
+) -> Result<JoinHandle<()>, Error> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::net::UnixListener;
+
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+        anyhow::bail!("Control socket {} is in use by another instance", path);
+    }
+    // Left behind by an unclean shutdown; binding fails while it exists
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    info!(path, "Control socket listening");
+
+    Ok(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(handle_connection(stream, handle.clone()));
+                    }
+                    Err(e) => error!(error = %e, "Failed to accept control connection"),
+                },
+            }
+        }
+        let _ = std::fs::remove_file(&path);
+    }))
+}
+
+/// Listen for control commands on the named pipe `path` until `token` is cancelled.
+#[cfg(windows)]
+pub fn serve(
+    path: String,
+    handle: ControlHandle,
+    token: CancellationToken,
+) -> Result<JoinHandle<()>, Error> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&path)?;
+    info!(path, "Control pipe listening");
+
+    Ok(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                connected = server.connect() => {
+                    if let Err(e) = connected {
+                        error!(error = %e, "Failed to accept control connection");
+                        continue;
+                    }
+                    // Create the next instance before handing this one off so
+                    // clients never find the pipe missing
+                    let next = match ServerOptions::new().create(&path) {
+                        Ok(next) => next,
+                        Err(e) => {
+                            error!(error = %e, "Failed to create control pipe");
+                            break;
+                        }
+                    };
+                    let client = std::mem::replace(&mut server, next);
+                    tokio::spawn(handle_connection(client, handle.clone()));
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn serve(
+    _path: String,
+    _handle: ControlHandle,
+    _token: CancellationToken,
+) -> Result<JoinHandle<()>, Error> {
+    anyhow::bail!("The control channel is not supported on this platform")
+}
+
+/// How long a client may take to send its request headers.
+const HTTP_READ_TIMEOUT: Duration = Duration::from_secs(5);
+const HTTP_MAX_HEADERS: usize = 100;
+
+/// Read one HTTP request and answer it, when it carries `token`.
+async fn handle_http(
+    stream: tokio::net::TcpStream,
+    handle: ControlHandle,
+    token: Arc<Option<String>>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let request = async {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await.ok()?;
+        let mut from_browser = false;
+        let mut bearer = None;
+        for _ in 0..HTTP_MAX_HEADERS {
+            let mut header = String::new();
+            if reader.read_line(&mut header).await.ok()? == 0 || header.trim().is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').unwrap_or((&header, ""));
+            let name = name.trim();
+            from_browser |= name.eq_ignore_ascii_case("origin");
+            if name.eq_ignore_ascii_case("authorization") {
+                bearer = value.trim().strip_prefix("Bearer ").map(str::to_string);
+            }
+        }
+        let mut parts = request_line.split_whitespace();
+        Some((
+            parts.next()?.to_string(),
+            parts.next()?.to_string(),
+            from_browser,
+            bearer,
+        ))
+    };
+    let (status, body) = match tokio::time::timeout(HTTP_READ_TIMEOUT, request).await {
+        Ok(Some((_, _, _, bearer))) if !token_matches(token.as_deref(), bearer.as_deref()) => {
+            (401, "error missing or wrong token".to_string())
+        }
+        Ok(Some((method, path, from_browser, _))) => {
+            debug!(method, path, "HTTP control request");
+            handle.route_http(&method, &path, from_browser)
+        }
+        _ => (400, "error bad request".to_string()),
+    };
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Conflict",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        reason,
+        body.len() + 1,
+        body
+    );
+    let _ = writer.write_all(response.as_bytes()).await;
+}
+
+/// Listen for HTTP control requests on `control.http_bind` and
+/// `control.http_port` until `token` is cancelled.
+pub fn serve_http(
+    control: &ControlConfig,
+    handle: ControlHandle,
+    token: CancellationToken,
+) -> Result<JoinHandle<()>, Error> {
+    let bind = control
+        .http_bind
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let ip: IpAddr = bind
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid control.http_bind {:?}: {}", control.http_bind, e))?;
+    let listener = std::net::TcpListener::bind(SocketAddr::new(ip, control.http_port))?;
+    let local_addr = listener.local_addr()?;
+    if !local_addr.ip().is_loopback() {
+        if control.http_token.is_none() {
+            anyhow::bail!(
+                "Refusing to serve HTTP control on {} without control.http_token, set one to accept requests from other machines",
+                local_addr
+            );
+        }
+        warn!(%local_addr, "HTTP control endpoint is reachable from other machines");
+    }
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    info!(%local_addr, "HTTP control endpoint listening");
+    let http_token = Arc::new(control.http_token.clone());
+
+    Ok(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        tokio::spawn(handle_http(stream, handle.clone(), http_token.clone()));
+                    }
+                    Err(e) => error!(error = %e, "Failed to accept HTTP control connection"),
+                },
+            }
+        }
+    }))
+}
+
+/// Send one command to a running watcher and return its reply.
+pub async fn send(path: &str, command: &str) -> Result<String, Error> {
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(path).await?;
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(path)?;
+    #[cfg(not(any(unix, windows)))]
+    anyhow::bail!("The control channel is not supported on this platform");
+
+    let (reader, mut writer) = tokio::io::split(stream);
+    writer
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    let reply = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Watcher closed the control connection"))?;
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_execute() {
+        let handle = ControlHandle::default();
+        assert_eq!(handle.execute("status"), "running");
+        assert_eq!(handle.execute("PAUSE\n"), "ok paused");
+        assert!(handle.pause_reason().is_some());
+        assert_eq!(handle.execute("capture"), "error paused");
+        assert_eq!(handle.execute("resume"), "ok running");
+        assert!(handle.pause_reason().is_none());
+        assert!(handle.execute("reboot").starts_with("error"));
+
+        assert_eq!(handle.execute("pause 15"), "ok paused for 15 minutes");
+        assert!(handle.is_paused());
+        assert!(handle.execute("pause soon").starts_with("error"));
+        // Resumes on its own once the time is up
+        handle.pause.lock().unwrap().as_mut().unwrap().until = Some(Utc::now());
+        assert!(!handle.is_paused());
+
+        let low_fidelity = Arc::new(AtomicBool::new(false));
+        let handle = handle.with_low_fidelity(low_fidelity.clone());
+        assert_eq!(handle.execute("low-fidelity"), "ok low fidelity");
+        assert!(low_fidelity.load(Ordering::Relaxed));
+        assert_eq!(handle.execute("fidelity"), "low");
+        assert_eq!(handle.execute("full-fidelity"), "ok full fidelity");
+        assert_eq!(handle.execute("fidelity"), "full");
+    }
+
+    #[test]
+    fn test_route_http() {
+        let handle = ControlHandle::default();
+        assert_eq!(handle.route_http("POST", "/pause", false).0, 200);
+        assert_eq!(handle.route_http("POST", "/capture?now=1", false).0, 409);
+        assert_eq!(
+            handle.route_http("GET", "/status", false),
+            (200, "paused".to_string())
+        );
+        assert_eq!(handle.route_http("GET", "/resume", false).0, 405);
+        assert_eq!(handle.route_http("POST", "/low-fidelity", false).0, 200);
+        assert_eq!(
+            handle.route_http("GET", "/fidelity", false),
+            (200, "low".to_string())
+        );
+        assert_eq!(handle.route_http("POST", "/resume", true).0, 403);
+        assert_eq!(handle.route_http("POST", "/reboot", false).0, 404);
+        assert_eq!(handle.route_http("POST", "/resume", false).0, 200);
+        assert!(!handle.is_paused());
+        assert_eq!(
+            handle.route_http("POST", "/pause?minutes=5", false),
+            (200, "ok paused for 5 minutes".to_string())
+        );
+        assert_eq!(handle.route_http("POST", "/pause?minutes=0", false).0, 409);
+    }
+}
@@ -0,0 +1,129 @@
+//! System-wide CPU load.
+//!
+//! The OS reports cumulative CPU times; `CpuSampler` turns them into the
+//! share of time all cores were busy between two samples.
+
+use anyhow::{Error, Result};
+
+/// Cumulative CPU times in platform ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+/// CPU load between consecutive calls to `sample`.
+pub struct CpuSampler {
+    last: CpuTimes,
+}
+
+impl CpuSampler {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self { last: cpu_times()? })
+    }
+
+    /// Busy share of all cores (0.0-1.0) since the previous sample, `None`
+    /// if no time passed.
+    pub fn sample(&mut self) -> Result<Option<f32>, Error> {
+        let now = cpu_times()?;
+        let load = load_between(self.last, now);
+        self.last = now;
+        Ok(load)
+    }
+}
+
+fn load_between(previous: CpuTimes, current: CpuTimes) -> Option<f32> {
+    let total = current.total.checked_sub(previous.total)?;
+    let busy = current.busy.saturating_sub(previous.busy);
+    (total > 0).then(|| (busy as f32 / total as f32).min(1.0))
+}
+
+/// Sum of the aggregate `cpu` line of /proc/stat; idle and iowait count as idle.
+#[cfg(target_os = "linux")]
+fn cpu_times() -> Result<CpuTimes, Error> {
+    let stat = std::fs::read_to_string("/proc/stat")?;
+    let line = stat
+        .lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or_else(|| anyhow::anyhow!("No cpu line in /proc/stat"))?;
+    let fields: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|field| field.parse().unwrap_or(0))
+        .collect();
+    // user nice system idle iowait irq softirq steal (guest is part of user)
+    let total: u64 = fields.iter().take(8).sum();
+    let idle = fields.get(3).copied().unwrap_or(0) + fields.get(4).copied().unwrap_or(0);
+    Ok(CpuTimes {
+        busy: total.saturating_sub(idle),
+        total,
+    })
+}
+
+#[cfg(windows)]
+fn cpu_times() -> Result<CpuTimes, Error> {
+    use windows::Win32::Foundation::FILETIME;
+    use windows::Win32::System::Threading::GetSystemTimes;
+
+    let ticks = |time: FILETIME| ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64;
+    let (mut idle, mut kernel, mut user) = (
+        FILETIME::default(),
+        FILETIME::default(),
+        FILETIME::default(),
+    );
+    unsafe { GetSystemTimes(Some(&mut idle), Some(&mut kernel), Some(&mut user))? };
+    // Kernel time includes idle time
+    let total = ticks(kernel) + ticks(user);
+    Ok(CpuTimes {
+        busy: total.saturating_sub(ticks(idle)),
+        total,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn cpu_times() -> Result<CpuTimes, Error> {
+    let mut info = libc::host_cpu_load_info {
+        cpu_ticks: [0; libc::CPU_STATE_MAX as usize],
+    };
+    let mut count = libc::HOST_CPU_LOAD_INFO_COUNT;
+    #[allow(deprecated)]
+    let status = unsafe {
+        libc::host_statistics(
+            libc::mach_host_self(),
+            libc::HOST_CPU_LOAD_INFO,
+            (&mut info as *mut libc::host_cpu_load_info).cast(),
+            &mut count,
+        )
+    };
+    if status != libc::KERN_SUCCESS {
+        anyhow::bail!("host_statistics failed: {}", status);
+    }
+    let total: u64 = info.cpu_ticks.iter().map(|&t| t as u64).sum();
+    let idle = info.cpu_ticks[libc::CPU_STATE_IDLE as usize] as u64;
+    Ok(CpuTimes {
+        busy: total - idle,
+        total,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn cpu_times() -> Result<CpuTimes, Error> {
+    Err(anyhow::anyhow!(
+        "CPU load is not supported on this platform"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_between() {
+        let times = |busy, total| CpuTimes { busy, total };
+        assert_eq!(load_between(times(100, 400), times(175, 500)), Some(0.75));
+        assert_eq!(load_between(times(100, 400), times(100, 400)), None);
+        // Counters reset (e.g. wrapped) since the previous sample
+        assert_eq!(load_between(times(100, 400), times(10, 40)), None);
+        assert!(CpuSampler::new().is_ok());
+    }
+}
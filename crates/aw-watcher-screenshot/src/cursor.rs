@@ -0,0 +1,286 @@
+//! Mouse cursor overlay.
+//!
+//! Capture APIs leave the cursor out of the image. When enabled, its position
+//! is queried as the capture starts and a plain arrow is drawn back in,
+//! optionally ringed while a mouse button is held down.
+
+use crate::backend::CaptureTarget;
+use crate::event::Rect;
+use anyhow::{Error, Result};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Outline of the arrow in unscaled pixels, tip at the origin.
+const ARROW: [(f32, f32); 7] = [
+    (0.0, 0.0),
+    (0.0, 17.0),
+    (4.0, 13.0),
+    (7.0, 19.0),
+    (10.0, 18.0),
+    (7.0, 12.0),
+    (12.0, 12.0),
+];
+const ARROW_OUTLINE: f32 = 1.2;
+const RING_RADIUS: f32 = 12.0;
+const RING_WIDTH: f32 = 2.0;
+const RING_COLOR: Rgba<u8> = Rgba([255, 196, 0, 255]);
+
+/// Pointer position in desktop coordinates, as used for monitor positions.
+#[derive(Clone, Copy, Debug)]
+pub struct CursorState {
+    pub x: i32,
+    pub y: i32,
+    /// A mouse button is held down.
+    pub pressed: bool,
+}
+
+/// Query the current pointer position and button state.
+#[cfg(target_os = "linux")]
+pub fn query() -> Result<CursorState, Error> {
+    use x11_dl::xlib::{Button1Mask, Button2Mask, Button3Mask, Xlib};
+
+    let xlib = Xlib::open().map_err(|e| anyhow::anyhow!("Failed to load Xlib: {}", e))?;
+    unsafe {
+        let display = (xlib.XOpenDisplay)(std::ptr::null());
+        if display.is_null() {
+            anyhow::bail!("Failed to open X display (the cursor requires X11 or XWayland)");
+        }
+        let (mut root, mut child) = (0, 0);
+        let (mut x, mut y, mut win_x, mut win_y, mut mask) = (0, 0, 0, 0, 0);
+        let found = (xlib.XQueryPointer)(
+            display,
+            (xlib.XDefaultRootWindow)(display),
+            &mut root,
+            &mut child,
+            &mut x,
+            &mut y,
+            &mut win_x,
+            &mut win_y,
+            &mut mask,
+        );
+        (xlib.XCloseDisplay)(display);
+        if found == 0 {
+            anyhow::bail!("The pointer is on another screen");
+        }
+        Ok(CursorState {
+            x,
+            y,
+            pressed: mask & (Button1Mask | Button2Mask | Button3Mask) != 0,
+        })
+    }
+}
+
+#[cfg(windows)]
+pub fn query() -> Result<CursorState, Error> {
+    use windows::Win32::Foundation::POINT;
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        GetAsyncKeyState, VK_LBUTTON, VK_MBUTTON, VK_RBUTTON,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::GetCursorPos;
+
+    let mut point = POINT::default();
+    unsafe { GetCursorPos(&mut point) }?;
+    // The most significant bit is set while the key is down
+    let pressed = [VK_LBUTTON, VK_RBUTTON, VK_MBUTTON]
+        .iter()
+        .any(|key| unsafe { GetAsyncKeyState(key.0 as i32) } < 0);
+    Ok(CursorState {
+        x: point.x,
+        y: point.y,
+        pressed,
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn query() -> Result<CursorState, Error> {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    struct CGPoint {
+        x: f64,
+        y: f64,
+    }
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGEventCreate(source: *const c_void) -> *mut c_void;
+        fn CGEventGetLocation(event: *mut c_void) -> CGPoint;
+        fn CGEventSourceButtonState(state: i32, button: u32) -> bool;
+    }
+    #[link(name = "CoreFoundation", kind = "framework")]
+    unsafe extern "C" {
+        fn CFRelease(object: *const c_void);
+    }
+    const COMBINED_SESSION_STATE: i32 = 0;
+
+    unsafe {
+        let event = CGEventCreate(std::ptr::null());
+        if event.is_null() {
+            anyhow::bail!("CGEventCreate failed");
+        }
+        let location = CGEventGetLocation(event);
+        CFRelease(event);
+        let pressed = (0..3).any(|button| CGEventSourceButtonState(COMBINED_SESSION_STATE, button));
+        Ok(CursorState {
+            x: location.x.round() as i32,
+            y: location.y.round() as i32,
+            pressed,
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn query() -> Result<CursorState, Error> {
+    anyhow::bail!("The cursor position is not supported on this platform")
+}
+
+/// Draw `cursor` onto `image`, a capture of `target` or of `region` of it.
+///
+/// Does nothing when the cursor is on another monitor.
+pub fn draw(
+    image: &mut DynamicImage,
+    cursor: &CursorState,
+    target: &CaptureTarget,
+    region: Option<Rect>,
+    highlight_clicks: bool,
+) {
+    let (origin_x, origin_y, width) = match region {
+        Some(region) => (region.x as i32, region.y as i32, region.width),
+        None => (0, 0, target.width),
+    };
+    // Captures may be in physical pixels while monitor geometry is logical
+    let scale = image.width() as f32 / width.max(1) as f32;
+    let x = (cursor.x - target.x - origin_x) as f32 * scale;
+    let y = (cursor.y - target.y - origin_y) as f32 * scale;
+    let reach = (RING_RADIUS + RING_WIDTH + ARROW[3].1) * scale;
+    if x < -reach
+        || y < -reach
+        || x > image.width() as f32 + reach
+        || y > image.height() as f32 + reach
+    {
+        return;
+    }
+
+    if !matches!(image, DynamicImage::ImageRgba8(_)) {
+        *image = DynamicImage::ImageRgba8(image.to_rgba8());
+    }
+    let DynamicImage::ImageRgba8(pixels) = image else {
+        return;
+    };
+    if highlight_clicks && cursor.pressed {
+        draw_ring(pixels, x, y, scale);
+    }
+    draw_arrow(pixels, x, y, scale);
+}
+
+/// Visit the pixels of `image` in the `width` x `height` box at (`x`, `y`),
+/// passing their centers.
+fn for_each_in_box(
+    image: &mut RgbaImage,
+    (x, y): (f32, f32),
+    (width, height): (f32, f32),
+    mut f: impl FnMut(f32, f32, &mut Rgba<u8>),
+) {
+    let clamp_x = |v: f32| (v.max(0.0) as u32).min(image.width());
+    let clamp_y = |v: f32| (v.max(0.0) as u32).min(image.height());
+    let (x0, x1) = (clamp_x(x.floor()), clamp_x((x + width).ceil()));
+    let (y0, y1) = (clamp_y(y.floor()), clamp_y((y + height).ceil()));
+    for py in y0..y1 {
+        for px in x0..x1 {
+            f(
+                px as f32 + 0.5,
+                py as f32 + 0.5,
+                image.get_pixel_mut(px, py),
+            );
+        }
+    }
+}
+
+fn draw_ring(image: &mut RgbaImage, x: f32, y: f32, scale: f32) {
+    let (radius, half_width) = (RING_RADIUS * scale, RING_WIDTH * scale / 2.0);
+    let outer = radius + half_width;
+    for_each_in_box(
+        image,
+        (x - outer, y - outer),
+        (outer * 2.0, outer * 2.0),
+        |px, py, pixel| {
+            let distance = ((px - x).powi(2) + (py - y).powi(2)).sqrt();
+            if (distance - radius).abs() <= half_width {
+                *pixel = RING_COLOR;
+            }
+        },
+    );
+}
+
+fn draw_arrow(image: &mut RgbaImage, x: f32, y: f32, scale: f32) {
+    let (width, height) = (ARROW[6].0 * scale, ARROW[3].1 * scale);
+    for_each_in_box(image, (x, y), (width, height), |px, py, pixel| {
+        let point = ((px - x) / scale, (py - y) / scale);
+        if !inside(point) {
+            return;
+        }
+        *pixel = if edge_distance(point) < ARROW_OUTLINE {
+            Rgba([0, 0, 0, 255])
+        } else {
+            Rgba([255, 255, 255, 255])
+        };
+    });
+}
+
+/// Whether `point` lies inside the arrow (even-odd rule).
+fn inside((x, y): (f32, f32)) -> bool {
+    let mut inside = false;
+    for (i, &(x1, y1)) in ARROW.iter().enumerate() {
+        let (x2, y2) = ARROW[(i + 1) % ARROW.len()];
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Distance from `point` to the nearest edge of the arrow.
+fn edge_distance((x, y): (f32, f32)) -> f32 {
+    (0..ARROW.len())
+        .map(|i| {
+            let (x1, y1) = ARROW[i];
+            let (x2, y2) = ARROW[(i + 1) % ARROW.len()];
+            let (dx, dy) = (x2 - x1, y2 - y1);
+            let t = (((x - x1) * dx + (y - y1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+            ((x - x1 - t * dx).powi(2) + (y - y1 - t * dy).powi(2)).sqrt()
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw() {
+        let target = CaptureTarget {
+            id: 1,
+            x: 100,
+            y: 0,
+            width: 50,
+            height: 50,
+        };
+        let cursor = CursorState {
+            x: 110,
+            y: 20,
+            pressed: true,
+        };
+        // Physical pixels at twice the logical monitor size
+        let mut image = DynamicImage::ImageRgba8(RgbaImage::new(100, 100));
+        draw(&mut image, &cursor, &target, None, true);
+        let pixels = image.to_rgba8();
+        assert_eq!(pixels.get_pixel(21, 43), &Rgba([0, 0, 0, 255]));
+        assert_eq!(pixels.get_pixel(26, 56), &Rgba([255, 255, 255, 255]));
+        assert_eq!(pixels.get_pixel(20 + 24, 40), &RING_COLOR);
+
+        // On another monitor
+        let elsewhere = CursorState { x: 500, ..cursor };
+        let mut image = DynamicImage::ImageRgba8(RgbaImage::new(100, 100));
+        draw(&mut image, &elsewhere, &target, None, true);
+        assert!(image.to_rgba8().pixels().all(|p| p.0 == [0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,54 @@
+//! AVIF encoding through the image crate's rav1e-based encoder.
+
+use super::FrameEncoder;
+use anyhow::{Error, Result};
+use image::codecs::avif::AvifEncoder as ImageAvifEncoder;
+use image::{DynamicImage, ExtendedColorType, ImageEncoder};
+
+/// Lossy AVIF at the configured quality and speed.
+pub struct AvifEncoder {
+    quality: u8,
+    /// Encoder speed (1 slowest - 10 fastest).
+    speed: u8,
+}
+
+impl AvifEncoder {
+    pub fn new(quality: u8, speed: u8) -> Self {
+        Self {
+            quality: quality.clamp(1, 100),
+            speed: speed.clamp(1, 10),
+        }
+    }
+}
+
+impl FrameEncoder for AvifEncoder {
+    fn encode(&self, image: &DynamicImage, output: &mut Vec<u8>) -> Result<(), Error> {
+        let encoder = ImageAvifEncoder::new_with_speed_quality(output, self.speed, self.quality);
+        match image {
+            DynamicImage::ImageRgba8(buffer) => encoder.write_image(
+                buffer.as_raw(),
+                buffer.width(),
+                buffer.height(),
+                ExtendedColorType::Rgba8,
+            )?,
+            other => {
+                let converted = other.to_rgba8();
+                encoder.write_image(
+                    converted.as_raw(),
+                    converted.width(),
+                    converted.height(),
+                    ExtendedColorType::Rgba8,
+                )?
+            }
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &'static str {
+        "avif"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/avif"
+    }
+}
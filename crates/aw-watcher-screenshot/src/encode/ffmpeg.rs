@@ -0,0 +1,88 @@
+//! Hardware-capable encoding through an ffmpeg subprocess.
+//!
+//! Frames are piped to ffmpeg as raw RGBA and encoded with the configured
+//! codec, so GPU encoders (`av1_vaapi`, `av1_qsv`, `av1_nvenc`,
+//! `mjpeg_vaapi`, ...) or multi-threaded software encoders (`libsvtav1`)
+//! take the load off the CPU. Device setup such as
+//! `-vaapi_device /dev/dri/renderD128 -vf format=nv12,hwupload` goes into
+//! `args`.
+
+use super::FrameEncoder;
+use crate::config::{FfmpegEncoderConfig, FfmpegOutputFormat};
+use anyhow::{Context, Error, Result};
+use image::DynamicImage;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+pub struct FfmpegEncoder {
+    config: FfmpegEncoderConfig,
+}
+
+impl FfmpegEncoder {
+    pub fn new(config: FfmpegEncoderConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl FrameEncoder for FfmpegEncoder {
+    fn encode(&self, image: &DynamicImage, output: &mut Vec<u8>) -> Result<(), Error> {
+        let converted;
+        let rgba = match image {
+            DynamicImage::ImageRgba8(buffer) => buffer,
+            other => {
+                converted = other.to_rgba8();
+                &converted
+            }
+        };
+
+        let muxer = match self.config.format {
+            FfmpegOutputFormat::Avif => "avif",
+            FfmpegOutputFormat::Jpeg => "mjpeg",
+        };
+        let mut child = Command::new(&self.config.command)
+            .args(["-hide_banner", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", rgba.width(), rgba.height())])
+            .args(["-i", "-", "-frames:v", "1"])
+            .args(&self.config.args)
+            .args(["-c:v", &self.config.codec, "-f", muxer, "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.config.command))?;
+
+        // Feed stdin from a second thread so a full stdout pipe cannot deadlock us
+        let mut stdin = child.stdin.take().context("ffmpeg stdin not captured")?;
+        let result = std::thread::scope(|scope| {
+            let writer = scope.spawn(move || stdin.write_all(rgba.as_raw()));
+            let result = child.wait_with_output();
+            let _ = writer.join();
+            result
+        })?;
+
+        if !result.status.success() {
+            return Err(anyhow::anyhow!(
+                "ffmpeg exited with {}: {}",
+                result.status,
+                String::from_utf8_lossy(&result.stderr).trim()
+            ));
+        }
+        output.extend_from_slice(&result.stdout);
+        Ok(())
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.config.format {
+            FfmpegOutputFormat::Avif => "avif",
+            FfmpegOutputFormat::Jpeg => "jpg",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self.config.format {
+            FfmpegOutputFormat::Avif => "image/avif",
+            FfmpegOutputFormat::Jpeg => "image/jpeg",
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! JPEG XL encoding through libjxl's `cjxl` tool.
+//!
+//! Lossless JPEG XL is far smaller than lossless WebP on text-heavy
+//! screenshots. There is no JPEG XL encoder crate that builds without a
+//! native libjxl, so frames are written to private temp files in the cache
+//! directory as PAM files and encoded by `cjxl`, which ships with libjxl on
+//! every platform.
+
+use super::FrameEncoder;
+use crate::config::JxlEncoderConfig;
+use anyhow::{Context, Error, Result};
+use image::DynamicImage;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+/// Lossy JPEG XL at the configured quality, lossless at quality 100.
+pub struct JxlEncoder {
+    config: JxlEncoderConfig,
+    /// Where the PAM input and encoded output of `cjxl` are written.
+    temp_dir: PathBuf,
+}
+
+impl JxlEncoder {
+    pub fn new(config: JxlEncoderConfig, temp_dir: PathBuf) -> Self {
+        Self { config, temp_dir }
+    }
+
+    /// Create a temp file only this user can read, removed when dropped.
+    fn temp_file(&self, suffix: &str) -> Result<NamedTempFile, Error> {
+        fs::create_dir_all(&self.temp_dir)?;
+        tempfile::Builder::new()
+            .prefix(".jxl-")
+            .suffix(suffix)
+            .tempfile_in(&self.temp_dir)
+            .with_context(|| {
+                format!(
+                    "Failed to create a temp file in {}",
+                    self.temp_dir.display()
+                )
+            })
+    }
+}
+
+/// Write `image` as a PAM file, the uncompressed format `cjxl` reads.
+/// Grayscale frames stay single-channel.
+fn write_pam<W: Write>(writer: &mut W, image: &DynamicImage) -> Result<(), Error> {
+    let converted;
+    let (depth, tuple_type, width, height, pixels) = match image {
+        DynamicImage::ImageLuma8(buffer) => (
+            1,
+            "GRAYSCALE",
+            buffer.width(),
+            buffer.height(),
+            buffer.as_raw(),
+        ),
+        DynamicImage::ImageRgba8(buffer) => (
+            4,
+            "RGB_ALPHA",
+            buffer.width(),
+            buffer.height(),
+            buffer.as_raw(),
+        ),
+        other => {
+            converted = other.to_rgba8();
+            (
+                4,
+                "RGB_ALPHA",
+                converted.width(),
+                converted.height(),
+                converted.as_raw(),
+            )
+        }
+    };
+    write!(
+        writer,
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n",
+        width, height, depth, tuple_type
+    )?;
+    writer.write_all(pixels)?;
+    Ok(())
+}
+
+impl FrameEncoder for JxlEncoder {
+    fn encode(&self, image: &DynamicImage, output: &mut Vec<u8>) -> Result<(), Error> {
+        let mut input = self.temp_file(".pam")?;
+        let encoded = self.temp_file(".jxl")?;
+        write_pam(input.as_file_mut(), image)?;
+        input.as_file_mut().flush()?;
+
+        let result = Command::new(&self.config.command)
+            .arg(input.path())
+            .arg(encoded.path())
+            .args(["-q", &self.config.quality.clamp(1, 100).to_string()])
+            .args(["-e", &self.config.effort.clamp(1, 9).to_string()])
+            .arg("--quiet")
+            .output()
+            .with_context(|| format!("Failed to run {}", self.config.command))?;
+        if !result.status.success() {
+            return Err(anyhow::anyhow!(
+                "cjxl exited with {}: {}",
+                result.status,
+                String::from_utf8_lossy(&result.stderr).trim()
+            ));
+        }
+        output.extend_from_slice(&fs::read(encoded.path())?);
+        Ok(())
+    }
+
+    fn extension(&self) -> &'static str {
+        "jxl"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/jxl"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, RgbImage};
+
+    #[test]
+    fn test_write_pam() {
+        let mut pam = Vec::new();
+        write_pam(&mut pam, &DynamicImage::ImageLuma8(GrayImage::new(3, 2))).unwrap();
+        let header = "P7\nWIDTH 3\nHEIGHT 2\nDEPTH 1\nMAXVAL 255\nTUPLTYPE GRAYSCALE\nENDHDR\n";
+        assert_eq!(&pam[..header.len()], header.as_bytes());
+        assert_eq!(pam.len(), header.len() + 6);
+
+        let mut pam = Vec::new();
+        write_pam(&mut pam, &DynamicImage::ImageRgb8(RgbImage::new(2, 2))).unwrap();
+        assert!(pam.starts_with(b"P7\nWIDTH 2\nHEIGHT 2\nDEPTH 4\n"));
+        assert!(pam.ends_with(&[0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_encode_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let encoder = JxlEncoder::new(
+            JxlEncoderConfig {
+                command: "aw-watcher-screenshot-missing-cjxl".to_string(),
+                ..JxlEncoderConfig::default()
+            },
+            dir.path().to_path_buf(),
+        );
+        let image = DynamicImage::new_rgba8(4, 4);
+        let mut output = Vec::new();
+        assert!(encoder.encode(&image, &mut output).is_err());
+        assert!(output.is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,145 @@
+//! Image encoders for stored frames.
+//!
+//! The cache stage encodes every accepted frame through a `FrameEncoder`
+//! chosen by `cache.encoder`. WebP (libwebp), AVIF (rav1e) and lossless PNG
+//! are always available, JPEG XL needs libjxl's `cjxl` at runtime; the
+//! `ffmpeg` encoder, which can use hardware encoders such as VAAPI, QSV or
+//! NVENC, is compiled in with the `hw-encode` feature.
+
+mod avif;
+#[cfg(feature = "hw-encode")]
+mod ffmpeg;
+mod jxl;
+mod png;
+mod webp;
+
+pub use self::webp::encode_webp;
+
+use crate::config::{CacheConfig, EncoderKind, JxlEncoderConfig, S3Config};
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// File extensions of the frames any encoder writes.
+pub const FRAME_EXTENSIONS: &[&str] = &["webp", "avif", "jxl", "png", "jpg"];
+
+/// Encodes a frame into a file format.
+pub trait FrameEncoder: Send + Sync {
+    /// Encode an image, appending the result to `output`.
+    fn encode(&self, image: &DynamicImage, output: &mut Vec<u8>) -> Result<(), Error>;
+    /// File extension of the encoded format, without the dot.
+    fn extension(&self) -> &'static str;
+    /// MIME type of the encoded format.
+    fn content_type(&self) -> &'static str;
+}
+
+/// Create a cheaper variant of the configured encoder for battery power.
+///
+/// WebP gets the given quality and effort, AVIF its fastest speed and JPEG XL
+/// its lowest effort; other encoders are used as is.
+pub fn low_power_from_config(
+    config: &CacheConfig,
+    quality: u8,
+    method: i32,
+) -> Result<Arc<dyn FrameEncoder>, Error> {
+    match config.encoder {
+        EncoderKind::Webp => Ok(Arc::new(
+            webp::WebpEncoder::new(quality as f32)
+                .with_method(method)
+                .with_multithreading(config.webp_multithread),
+        )),
+        EncoderKind::Avif => Ok(Arc::new(avif::AvifEncoder::new(config.avif.quality, 10))),
+        EncoderKind::Jxl => Ok(Arc::new(jxl::JxlEncoder::new(
+            JxlEncoderConfig {
+                effort: 1,
+                ..config.jxl.clone()
+            },
+            PathBuf::from(&config.cache_dir),
+        ))),
+        _ => from_config(config),
+    }
+}
+
+/// Create the encoder selected in the cache configuration.
+pub fn from_config(config: &CacheConfig) -> Result<Arc<dyn FrameEncoder>, Error> {
+    match config.encoder {
+        EncoderKind::Webp => {
+            let mut encoder = webp::WebpEncoder::new(config.webp_quality as f32)
+                .with_multithreading(config.webp_multithread);
+            if let Some(method) = config.webp_method {
+                encoder = encoder.with_method(method);
+            }
+            Ok(Arc::new(encoder))
+        }
+        EncoderKind::Avif => Ok(Arc::new(avif::AvifEncoder::new(
+            config.avif.quality,
+            config.avif.speed,
+        ))),
+        EncoderKind::Jxl => Ok(Arc::new(jxl::JxlEncoder::new(
+            config.jxl.clone(),
+            PathBuf::from(&config.cache_dir),
+        ))),
+        EncoderKind::Png => Ok(Arc::new(png::PngEncoder)),
+        #[cfg(feature = "hw-encode")]
+        EncoderKind::Ffmpeg => Ok(Arc::new(ffmpeg::FfmpegEncoder::new(config.ffmpeg.clone()))),
+        #[cfg(not(feature = "hw-encode"))]
+        EncoderKind::Ffmpeg => Err(anyhow::anyhow!(
+            "The ffmpeg encoder is not compiled in, rebuild with --features hw-encode"
+        )),
+    }
+}
+
+/// Create the configured encoder with `quality` (1-100) in place of its own
+/// quality setting: `webp_quality`, `avif.quality` or `jxl.quality`. Fails
+/// for encoders without one, see `EncoderKind::has_quality`.
+pub fn with_quality(config: &CacheConfig, quality: u8) -> Result<Arc<dyn FrameEncoder>, Error> {
+    let mut config = config.clone();
+    match config.encoder {
+        EncoderKind::Webp => config.webp_quality = quality,
+        EncoderKind::Avif => config.avif.quality = quality,
+        EncoderKind::Jxl => config.jxl.quality = quality,
+        EncoderKind::Png | EncoderKind::Ffmpeg => anyhow::bail!(
+            "cache.encoder = \"{}\" has no quality setting",
+            config.encoder.name()
+        ),
+    }
+    from_config(&config)
+}
+
+/// Create the lossless encoder agents send frames to a remote server with,
+/// so that the server's own encoding is the only lossy step: PNG with
+/// `encoder = "png"`, lossless WebP otherwise.
+pub fn lossless_from_config(config: &CacheConfig) -> Arc<dyn FrameEncoder> {
+    match config.encoder {
+        EncoderKind::Png => Arc::new(png::PngEncoder),
+        _ => {
+            let mut encoder =
+                webp::WebpEncoder::new(100.0).with_multithreading(config.webp_multithread);
+            if let Some(method) = config.webp_method {
+                encoder = encoder.with_method(method);
+            }
+            Arc::new(encoder)
+        }
+    }
+}
+
+/// Create the encoder for uploaded frames, if `s3.encoder` or
+/// `s3.webp_quality` ask for a different encoding than the cache's.
+pub fn upload_from_config(
+    cache: &CacheConfig,
+    s3: &S3Config,
+) -> Result<Option<Arc<dyn FrameEncoder>>, Error> {
+    let config = CacheConfig {
+        encoder: s3.encoder.unwrap_or(cache.encoder),
+        webp_quality: s3.webp_quality.unwrap_or(cache.webp_quality),
+        ..cache.clone()
+    };
+    // Only WebP reads `webp_quality`
+    let same = config.encoder == cache.encoder
+        && (config.encoder != EncoderKind::Webp || config.webp_quality == cache.webp_quality);
+    if same {
+        return Ok(None);
+    }
+    from_config(&config).map(Some)
+}
@@ -0,0 +1,54 @@
+//! Lossless PNG encoding, for pixel-perfect archival.
+
+use super::FrameEncoder;
+use anyhow::{Error, Result};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder as ImagePngEncoder};
+use image::{DynamicImage, ExtendedColorType, ImageEncoder};
+
+/// PNG with fast compression; files are large, but exact.
+pub struct PngEncoder;
+
+impl FrameEncoder for PngEncoder {
+    fn encode(&self, image: &DynamicImage, output: &mut Vec<u8>) -> Result<(), Error> {
+        let encoder =
+            ImagePngEncoder::new_with_quality(output, CompressionType::Fast, FilterType::Adaptive);
+        match image {
+            DynamicImage::ImageRgba8(buffer) => encoder.write_image(
+                buffer.as_raw(),
+                buffer.width(),
+                buffer.height(),
+                ExtendedColorType::Rgba8,
+            )?,
+            DynamicImage::ImageRgb8(buffer) => encoder.write_image(
+                buffer.as_raw(),
+                buffer.width(),
+                buffer.height(),
+                ExtendedColorType::Rgb8,
+            )?,
+            DynamicImage::ImageLuma8(buffer) => encoder.write_image(
+                buffer.as_raw(),
+                buffer.width(),
+                buffer.height(),
+                ExtendedColorType::L8,
+            )?,
+            other => {
+                let converted = other.to_rgba8();
+                encoder.write_image(
+                    converted.as_raw(),
+                    converted.width(),
+                    converted.height(),
+                    ExtendedColorType::Rgba8,
+                )?
+            }
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &'static str {
+        "png"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/png"
+    }
+}
@@ -0,0 +1,103 @@
+//! WebP encoding through libwebp.
+
+use super::FrameEncoder;
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use webp::{Encoder, WebPConfig};
+
+/// Lossy WebP at the configured quality, lossless at quality 100.
+pub struct WebpEncoder {
+    quality: f32,
+    /// Encoder effort (0 fastest - 6 slowest), libwebp's default when unset.
+    method: Option<i32>,
+    /// Let libwebp split the work of one frame across threads.
+    multithread: bool,
+}
+
+/// libwebp's default encoder effort.
+const DEFAULT_METHOD: i32 = 4;
+
+impl WebpEncoder {
+    pub fn new(quality: f32) -> Self {
+        Self {
+            quality,
+            method: None,
+            multithread: false,
+        }
+    }
+
+    pub fn with_method(mut self, method: i32) -> Self {
+        self.method = Some(method.clamp(0, 6));
+        self
+    }
+
+    pub fn with_multithreading(mut self, enabled: bool) -> Self {
+        self.multithread = enabled;
+        self
+    }
+}
+
+impl FrameEncoder for WebpEncoder {
+    fn encode(&self, image: &DynamicImage, output: &mut Vec<u8>) -> Result<(), Error> {
+        match (self.method, self.multithread) {
+            (None, false) => encode_webp(image, self.quality, output),
+            (method, multithread) => {
+                let mut config = WebPConfig::new()
+                    .map_err(|_| anyhow::anyhow!("Failed to initialize WebP config"))?;
+                let lossless = self.quality >= 100.0;
+                config.lossless = lossless as i32;
+                config.alpha_compression = !lossless as i32;
+                config.quality = if lossless { 75.0 } else { self.quality };
+                config.method = method.unwrap_or(DEFAULT_METHOD);
+                config.thread_level = multithread as i32;
+                let webp_data = with_encoder(image, |encoder| encoder.encode_advanced(&config))
+                    .map_err(|e| anyhow::anyhow!("WebP encoding failed: {:?}", e))?;
+                output.extend_from_slice(&webp_data);
+            }
+        }
+        Ok(())
+    }
+
+    fn extension(&self) -> &'static str {
+        "webp"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "image/webp"
+    }
+}
+
+/// Encode an image as WebP, appending the result to `output`.
+pub fn encode_webp(image: &DynamicImage, quality: f32, output: &mut Vec<u8>) {
+    let webp_data = with_encoder(image, |encoder| {
+        if quality >= 100.0 {
+            encoder.encode_lossless()
+        } else {
+            encoder.encode(quality)
+        }
+    });
+    output.extend_from_slice(&webp_data);
+}
+
+/// Run `f` with an encoder for the image, converting formats libwebp cannot
+/// take to RGB(A) first.
+fn with_encoder<R>(image: &DynamicImage, f: impl FnOnce(Encoder) -> R) -> R {
+    if let Ok(encoder) = Encoder::from_image(image) {
+        return f(encoder);
+    }
+    match image {
+        // Grayscale has no alpha plane to keep
+        DynamicImage::ImageLuma8(_) => {
+            let rgb = image.to_rgb8();
+            f(Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height()))
+        }
+        other => {
+            let rgba = other.to_rgba8();
+            f(Encoder::from_rgba(
+                rgba.as_raw(),
+                rgba.width(),
+                rgba.height(),
+            ))
+        }
+    }
+}
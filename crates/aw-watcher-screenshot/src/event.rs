@@ -1,15 +1,65 @@
+use crate::backend::CaptureTarget;
+use crate::config::{MonitorConfig, RedactAction};
+use crate::pool::PooledBuffer;
 use chrono::{DateTime, Utc};
 use image::DynamicImage;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+/// What caused a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CaptureTrigger {
+    /// A timer or input tick.
+    #[default]
+    Tick,
+    /// An explicit request (hotkey, control command).
+    Requested,
+    /// A follow-up capture of a burst after a large change.
+    Burst,
+    /// A frame of a clip recorded after a large change, see
+    /// `worker_impl::clip`.
+    Clip(ClipFrame),
+}
+
+/// Position of a frame in a clip recording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClipFrame {
+    /// Recording the frame belongs to, counted up by the producer.
+    pub clip: u64,
+    pub index: u32,
+    /// Frames in the recording.
+    pub count: u32,
+}
+
 pub struct CaptureEvent {
     pub images: HashMap<u32, Arc<DynamicImage>>,
     pub monitors: HashMap<u32, UploadImageInfo>,
     pub timestamp: DateTime<Utc>,
+    pub trigger: CaptureTrigger,
+    pub focused_window: Option<FocusedWindow>,
+    /// Hamming distance of each monitor's hash to its last accepted hash,
+    /// including monitors that were filtered out.
+    pub change_distances: HashMap<u32, u32>,
+    /// Text recognized in each monitor's image, when OCR is enabled.
+    pub ocr: HashMap<u32, OcrResult>,
+    /// Regions reported as changed by the capture backend since the
+    /// monitor's previous frame. Monitors without an entry have unknown damage.
+    pub damage: HashMap<u32, Vec<Rect>>,
+    /// Whether a meeting was going on when the frame was captured, see
+    /// `guard::meeting`.
+    pub in_meeting: bool,
+    /// An encoded video clip, sent without images; see `ClipProcessor`.
+    pub clip: Option<Clip>,
+}
+
+/// An encoded video clip of one monitor.
+pub struct Clip {
+    pub monitor_id: u32,
+    pub data: Vec<u8>,
+    pub content_type: &'static str,
 }
 
 impl CaptureEvent {
@@ -18,6 +68,13 @@ impl CaptureEvent {
             images: HashMap::new(),
             monitors: HashMap::new(),
             timestamp: Utc::now(),
+            trigger: CaptureTrigger::Tick,
+            focused_window: None,
+            change_distances: HashMap::new(),
+            ocr: HashMap::new(),
+            damage: HashMap::new(),
+            in_meeting: false,
+            clip: None,
         }
     }
 
@@ -30,15 +87,196 @@ impl CaptureEvent {
         self.images.insert(monitor_id, Arc::new(image_info));
         self.monitors.insert(monitor_id, monitor_info);
     }
+
+    /// Whether the event is a frame of a clip recording, which stages that
+    /// only inspect the screen pass on untouched.
+    pub fn is_clip_frame(&self) -> bool {
+        matches!(self.trigger, CaptureTrigger::Clip(_))
+    }
+}
+
+/// An axis-aligned rectangle in image pixel coordinates.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
 }
 
-pub type WebpImage = Vec<u8>;
+impl Rect {
+    /// End of the rectangle along x, clamped to `u32::MAX` for untrusted rects.
+    fn right(&self) -> u32 {
+        self.x.saturating_add(self.width)
+    }
+
+    /// End of the rectangle along y, clamped like `right`.
+    fn bottom(&self) -> u32 {
+        self.y.saturating_add(self.height)
+    }
+
+    /// The smallest rectangle containing all of `rects`.
+    pub fn bounding(rects: &[Rect]) -> Option<Rect> {
+        let min_x = rects.iter().map(|r| r.x).min()?;
+        let min_y = rects.iter().map(|r| r.y).min()?;
+        let max_x = rects.iter().map(Rect::right).max()?;
+        let max_y = rects.iter().map(Rect::bottom).max()?;
+        Some(Rect {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x,
+            height: max_y - min_y,
+        })
+    }
+
+    /// The overlap of two rectangles, if it is not empty.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (right > x && bottom > y).then(|| Rect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+/// Filter decisions for one monitor over a reporting window, see
+/// `capture.filter_stats_secs`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FilterStats {
+    /// Length of the window the counts cover.
+    pub window_secs: i64,
+    /// Frames the filter looked at.
+    pub captured: u32,
+    /// Frames passed on, including forced ones.
+    pub accepted: u32,
+    /// Frames passed on because the force interval elapsed.
+    pub forced: u32,
+    /// Frames dropped as too similar to the last accepted one (or undamaged).
+    pub skipped_similar: u32,
+    /// Frames dropped by the debounce.
+    pub skipped_ratelimit: u32,
+    /// Frames dropped because a blocklisted window had focus.
+    #[serde(default)]
+    pub skipped_blocked: u32,
+}
+
+/// How much a frame changed since the monitor's previous accepted frame.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ChangeScore {
+    /// Hamming distance between the perceptual hashes.
+    pub distance: u32,
+    /// `distance` over the hash size, from 0 (identical) to 1.
+    pub score: f32,
+    /// Fraction of tiles that changed, with `capture.tile_grid`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiles_changed: Option<f32>,
+}
+
+impl ChangeScore {
+    pub fn new(distance: u32, tiles_changed: Option<f32>) -> Self {
+        Self {
+            distance,
+            score: distance as f32 / u64::BITS as f32,
+            tiles_changed,
+        }
+    }
+}
+
+/// A stored thumbnail of a frame.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub object_key: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A redaction rule that matched text in a frame.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Redaction {
+    pub rule: String,
+    pub action: RedactAction,
+    /// Number of matches in the frame.
+    pub matches: u32,
+}
+
+/// The window that had input focus when a capture was taken.
+#[derive(Serialize, Clone, Debug)]
+pub struct FocusedWindow {
+    pub app_name: String,
+    pub title: String,
+    pub pid: u32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A word recognized by OCR.
+#[derive(Clone, Debug)]
+pub struct OcrWord {
+    pub text: String,
+    /// Index of the text line the word belongs to.
+    pub line: u32,
+    /// Position of the word in the image.
+    pub bounds: Rect,
+}
+
+/// OCR output for one image.
+#[derive(Clone, Debug, Default)]
+pub struct OcrResult {
+    pub words: Vec<OcrWord>,
+    /// ISO 639-3 code of the detected language.
+    pub language: Option<String>,
+}
+
+impl OcrResult {
+    /// The recognized text, one line per OCR text line.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        let mut line = None;
+        for word in &self.words {
+            if line.is_some() {
+                text.push(if line == Some(word.line) { ' ' } else { '\n' });
+            }
+            text.push_str(&word.text);
+            line = Some(word.line);
+        }
+        text
+    }
+}
+
+/// Encoded image data, recycled into the encoder's buffer pool when dropped.
+pub type WebpImage = PooledBuffer;
 
 pub struct ImageEvent {
     pub datas: HashMap<u32, Arc<WebpImage>>,
     pub monitors: HashMap<u32, UploadImageInfo>,
     pub timestamp: DateTime<Utc>,
     pub local_dir: PathBuf,
+    /// MIME type of the encoded images.
+    pub content_type: &'static str,
+    /// The images encoded for upload, when `s3.encoder` or `s3.webp_quality`
+    /// differ from the cache's; `datas` is then only what was cached.
+    pub upload: Option<UploadVariant>,
+    /// WebP thumbnails of the images, with `cache.thumbnail_width`.
+    pub thumbnails: HashMap<u32, Arc<WebpImage>>,
+    /// Paths the images of `datas` were cached at.
+    pub files: HashMap<u32, PathBuf>,
+    /// Paths the thumbnails were cached at.
+    pub thumbnail_files: HashMap<u32, PathBuf>,
+}
+
+/// Images encoded for upload with a different format or quality than the
+/// cached ones.
+pub struct UploadVariant {
+    pub datas: HashMap<u32, Arc<WebpImage>>,
+    /// MIME type of the encoded images.
+    pub content_type: &'static str,
 }
 
 impl ImageEvent {
@@ -52,6 +290,11 @@ impl ImageEvent {
             timestamp,
             local_dir,
             monitors,
+            content_type: "image/webp",
+            upload: None,
+            thumbnails: HashMap::new(),
+            files: HashMap::new(),
+            thumbnail_files: HashMap::new(),
         }
     }
 
@@ -60,23 +303,134 @@ impl ImageEvent {
     }
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UploadImageInfo {
     pub monitor_name: String,
     pub monitor_id: u32,
+    /// Stable monitor identity derived from name and geometry; the backend id
+    /// may change across reboots.
+    pub monitor_fingerprint: String,
     pub object_key: String,
+    /// Name of the cached file in `local_dir`, when it was encoded
+    /// differently than the uploaded object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_file: Option<String>,
     pub uploaded: bool,
+    /// Size of the captured image, when it was downscaled for storage with
+    /// `cache.max_width` or `cache.max_height`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_height: Option<u32>,
+    /// Display scale factor of the monitor (1.0 = 96 DPI on Windows).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale_factor: Option<f32>,
+    /// Perceptual hash of the image as 16 hex digits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dhash: Option<String>,
+    /// Algorithm `dhash` was computed with (`capture.hash_algorithm`).
+    /// Hashes recorded without it are difference hashes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_algorithm: Option<String>,
+    /// ISO 639-3 code of the language detected in the image text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Text recognized in the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// UTC offset the object key and cache path were formatted in, when local time is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<String>,
+    /// Bounding box of the regions the capture backend reported as changed
+    /// since the previous accepted frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed_region: Option<Rect>,
+    /// Change since the previous accepted frame; absent for a monitor's
+    /// first frame.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<ChangeScore>,
+    /// Mean luma change per tile since the previous accepted frame, as rows
+    /// of values from 0 to 255, with `capture.heatmap_grid`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heatmap: Option<Vec<Vec<u8>>>,
+    /// Small preview of the image, see `cache.thumbnail_width`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<Thumbnail>,
+    /// Decoded QR code payloads found in the image.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub qr_codes: Vec<String>,
+    /// Monitor whose image this monitor mirrors; `object_key` then refers to
+    /// that monitor's image and no image of its own is stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror_of: Option<u32>,
+    /// Filter decisions since the previous report, attached every
+    /// `capture.filter_stats_secs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_stats: Option<FilterStats>,
+    /// Part of the monitor that was captured, when not the whole screen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<Rect>,
+    /// Redaction rules that matched the image text, see `RedactProcessor`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<Redaction>,
+    /// Content policy flag, e.g. "nsfw". Withheld frames keep only their
+    /// metadata, with an empty `object_key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flag: Option<String>,
+    /// Remote agent machine that captured the image, see `crate::remote`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Per-monitor settings matched at capture time.
+    #[serde(skip)]
+    pub settings: Option<Arc<MonitorConfig>>,
+    /// The monitor as passed to the capture backend, to capture it again.
+    #[serde(skip)]
+    pub target: Option<CaptureTarget>,
 }
 
 impl UploadImageInfo {
-    pub fn new(monitor_name: String, monitor_id: u32, object_key: String) -> Self {
+    pub fn new(
+        monitor_name: String,
+        monitor_id: u32,
+        monitor_fingerprint: String,
+        object_key: String,
+    ) -> Self {
         Self {
             monitor_name,
             monitor_id,
+            monitor_fingerprint,
             object_key,
+            cache_file: None,
             uploaded: false,
+            original_width: None,
+            original_height: None,
+            scale_factor: None,
+            dhash: None,
+            hash_algorithm: None,
+            language: None,
+            text: None,
+            utc_offset: None,
+            changed_region: None,
+            change: None,
+            heatmap: None,
+            thumbnail: None,
+            qr_codes: Vec::new(),
+            mirror_of: None,
+            filter_stats: None,
+            region: None,
+            redactions: Vec::new(),
+            flag: None,
+            host: None,
+            settings: None,
+            target: None,
         }
     }
+
+    /// Whether a content filter flagged the image or its text matched a
+    /// redaction rule; such frames expire sooner, see `crate::retention`.
+    pub fn is_sensitive(&self) -> bool {
+        self.flag.is_some() || !self.redactions.is_empty()
+    }
 }
 
 impl From<UploadImageInfo> for Value {
@@ -144,3 +498,25 @@ impl AwEvent {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rect_overflow() {
+        let rect = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let huge = rect(u32::MAX - 10, 0, 100, u32::MAX);
+        let small = rect(u32::MAX - 20, 5, 15, 10);
+        assert_eq!(huge.intersect(&small), Some(rect(u32::MAX - 10, 5, 5, 10)));
+        assert_eq!(
+            Rect::bounding(&[huge, small]),
+            Some(rect(u32::MAX - 20, 0, 20, u32::MAX))
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! AFK detection.
+//!
+//! Like aw-watcher-afk, treats the user as away after a period without
+//! keyboard or mouse input. The `AfkGuard` pauses capture while away, so idle
+//! and lock screens are not captured over and over, and resumes on the first
+//! tick after input returns.
+
+use super::CaptureGuard;
+use crate::idle::IdleTime;
+use anyhow::{Error, Result};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::debug;
+
+/// Pauses capture after `timeout` without input.
+pub struct AfkGuard {
+    idle: Mutex<IdleTime>,
+    timeout: Duration,
+}
+
+impl AfkGuard {
+    pub fn new(timeout: Duration) -> Result<Self, Error> {
+        Ok(Self {
+            idle: Mutex::new(IdleTime::new()?),
+            timeout,
+        })
+    }
+}
+
+impl CaptureGuard for AfkGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        match idle.get() {
+            Ok(idle_time) if idle_time >= self.timeout => {
+                Some(format!("no input for {}s (AFK)", idle_time.as_secs()))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                // Unknown state: keep capturing rather than silently stopping
+                debug!(error = %e, "Failed to query idle time");
+                None
+            }
+        }
+    }
+}
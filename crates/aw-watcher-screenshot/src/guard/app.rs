@@ -0,0 +1,123 @@
+//! Per-application capture policies.
+//!
+//! `[[app]]` tables tune capturing to the focused application: capture a
+//! browser less often and at a lower quality, an editor more often, and
+//! never a password manager. `AppPolicies` pauses capture while an app with
+//! `skip` is focused; for the others the capture producer lays the policy
+//! over each monitor's `[[monitor]]` settings, which the filter and encoder
+//! then follow like any per-monitor override.
+
+use super::CaptureGuard;
+use super::window::WindowRules;
+use crate::config::{AppPolicy, MonitorConfig};
+use crate::event::FocusedWindow;
+use crate::window::focused_window;
+use anyhow::{Error, Result};
+use std::sync::Arc;
+use tracing::debug;
+
+/// Policies with their compiled window rules, in configuration order.
+pub struct AppPolicies {
+    policies: Vec<(WindowRules, AppPolicy)>,
+}
+
+impl AppPolicies {
+    pub fn new(policies: &[AppPolicy]) -> Result<Self, Error> {
+        let policies = policies
+            .iter()
+            .map(|policy| {
+                let titles: Vec<String> = policy.title.iter().cloned().collect();
+                let rules = WindowRules::new(std::slice::from_ref(&policy.name), &titles)?;
+                Ok((rules, policy.clone()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { policies })
+    }
+
+    /// The first policy matching `window`.
+    pub fn find(&self, window: &FocusedWindow) -> Option<&AppPolicy> {
+        self.policies
+            .iter()
+            .find(|(rules, _)| rules.matches(window))
+            .map(|(_, policy)| policy)
+    }
+}
+
+/// `settings` with the overrides of `policy` on top.
+pub fn apply(policy: &AppPolicy, settings: Option<&MonitorConfig>) -> MonitorConfig {
+    let mut settings = settings.cloned().unwrap_or_default();
+    settings.interval_secs = policy.interval_secs.or(settings.interval_secs);
+    settings.force_interval_secs = policy.force_interval_secs.or(settings.force_interval_secs);
+    settings.dhash_threshold = policy.dhash_threshold.or(settings.dhash_threshold);
+    settings.webp_quality = policy.webp_quality.or(settings.webp_quality);
+    settings
+}
+
+/// `apply` for the policy of the focused window, if any.
+pub fn apply_focused(
+    policies: &AppPolicies,
+    window: Option<&FocusedWindow>,
+    settings: Option<Arc<MonitorConfig>>,
+) -> Option<Arc<MonitorConfig>> {
+    match window.and_then(|window| policies.find(window)) {
+        Some(policy) => Some(Arc::new(apply(policy, settings.as_deref()))),
+        None => settings,
+    }
+}
+
+impl CaptureGuard for AppPolicies {
+    fn pause_reason(&self) -> Option<String> {
+        let window = focused_window()
+            .inspect_err(|e| debug!(error = %e, "Failed to get focused window"))
+            .ok()??;
+        self.find(&window)
+            .filter(|policy| policy.skip)
+            .map(|policy| format!("{} is focused (app policy)", policy.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let policies = AppPolicies::new(&[
+            AppPolicy {
+                name: "firefox".to_string(),
+                webp_quality: Some(60),
+                interval_secs: Some(10),
+                ..Default::default()
+            },
+            AppPolicy {
+                name: "keepass".to_string(),
+                skip: true,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        let window = |app_name: &str| FocusedWindow {
+            app_name: app_name.to_string(),
+            title: String::new(),
+            pid: 1,
+            x: 0,
+            y: 0,
+            width: 800,
+            height: 600,
+        };
+        let monitor = MonitorConfig {
+            webp_quality: Some(90),
+            dhash_threshold: Some(4),
+            ..Default::default()
+        };
+
+        let firefox = policies.find(&window("Firefox")).unwrap();
+        let settings = apply(firefox, Some(&monitor));
+        assert_eq!(settings.webp_quality, Some(60));
+        assert_eq!(settings.interval_secs, Some(10));
+        // The monitor's other overrides stay
+        assert_eq!(settings.dhash_threshold, Some(4));
+        assert!(policies.find(&window("KeePassXC")).unwrap().skip);
+        assert!(policies.find(&window("code")).is_none());
+    }
+}
@@ -0,0 +1,60 @@
+//! CPU load deferral.
+//!
+//! Capturing, hashing and encoding compete with compile jobs and renders.
+//! The `CpuLoadGuard` samples the system CPU load at every tick and skips
+//! ticks while it is above the limit, but never for longer than the maximum
+//! deferral, so a machine that stays busy is still captured now and then.
+
+use super::CaptureGuard;
+use crate::config::CpuLoadConfig;
+use crate::cpu::CpuSampler;
+use anyhow::{Error, Result};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Defers capture while the CPU is busy.
+pub struct CpuLoadGuard {
+    /// Load limit as a share of all cores.
+    max_load: f32,
+    max_defer: Duration,
+    /// Sampler and the start of the current deferral.
+    state: Mutex<(CpuSampler, Option<Instant>)>,
+}
+
+impl CpuLoadGuard {
+    pub fn new(config: &CpuLoadConfig) -> Result<Self, Error> {
+        Ok(Self {
+            max_load: config.max_load_percent as f32 / 100.0,
+            max_defer: Duration::from_secs(config.max_defer_secs),
+            state: Mutex::new((CpuSampler::new()?, None)),
+        })
+    }
+}
+
+impl CaptureGuard for CpuLoadGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (sampler, deferred_since) = &mut *state;
+        // Load since the previous tick
+        let load = match sampler.sample() {
+            Ok(Some(load)) => load,
+            Ok(None) => return None,
+            Err(e) => {
+                debug!(error = %e, "Failed to sample CPU load");
+                return None;
+            }
+        };
+        if load <= self.max_load {
+            *deferred_since = None;
+            return None;
+        }
+        let since = *deferred_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= self.max_defer {
+            debug!(load, "CPU still busy, capturing after the maximum deferral");
+            *deferred_since = None;
+            return None;
+        }
+        Some(format!("CPU load {:.0}% (busy)", load * 100.0))
+    }
+}
@@ -0,0 +1,120 @@
+//! Password prompts.
+//!
+//! OS credential dialogs and password manager unlock windows are never worth
+//! capturing, so the `CredentialPromptGuard` is always registered and skips
+//! capture while one is focused, whatever the block- and allowlists say.
+//! Prompts are recognized by built-in app name and title heuristics.
+
+use super::CaptureGuard;
+use crate::event::FocusedWindow;
+use crate::window::focused_window;
+use tracing::debug;
+
+/// Apps that only ever show credential prompts, matched against part of the
+/// lowercased app name.
+const PROMPT_APPS: &[&str] = &[
+    "credentialuibroker",
+    "credwiz",
+    "consent.exe",
+    "securityagent",
+    "coreautha",
+    "pinentry",
+    "gcr-prompter",
+    "polkit-gnome-authentication-agent",
+    "polkit-kde-authentication-agent",
+    "polkit-mate-authentication-agent",
+    "lxpolkit",
+    "ssh-askpass",
+    "ksshaskpass",
+    "x11-ssh-askpass",
+];
+
+/// Password managers, which are skipped only while their unlock window is
+/// focused.
+const PASSWORD_MANAGERS: &[&str] = &[
+    "1password",
+    "bitwarden",
+    "keepass",
+    "lastpass",
+    "dashlane",
+    "enpass",
+    "proton pass",
+];
+
+/// Title fragments of unlock windows and credential dialogs, lowercased.
+const PROMPT_TITLES: &[&str] = &[
+    "windows security",
+    "enter password",
+    "enter your password",
+    "password required",
+    "authentication required",
+    "authenticate",
+    "unlock database",
+    "unlock keyring",
+    "unlock login keyring",
+    "master password",
+    "vault is locked",
+    "locked",
+];
+
+/// Skips capture while a password prompt is focused.
+pub struct CredentialPromptGuard;
+
+impl CaptureGuard for CredentialPromptGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let window = focused_window()
+            .inspect_err(|e| debug!(error = %e, "Failed to get focused window"))
+            .ok()??;
+        is_credential_prompt(&window).then(|| "a password prompt is focused".to_string())
+    }
+}
+
+/// Whether `window` looks like a credential dialog or password manager
+/// unlock window.
+fn is_credential_prompt(window: &FocusedWindow) -> bool {
+    let app_name = window.app_name.to_lowercase();
+    if PROMPT_APPS.iter().any(|app| app_name.contains(app)) {
+        return true;
+    }
+    let title = window.title.to_lowercase();
+    let prompt_title = || {
+        PROMPT_TITLES
+            .iter()
+            .any(|fragment| title.contains(fragment))
+    };
+    if PASSWORD_MANAGERS.iter().any(|app| app_name.contains(app)) {
+        return prompt_title();
+    }
+    // Other apps' windows only count with an unambiguous dialog title, not
+    // e.g. a document mentioning "locked"
+    matches!(
+        title.trim(),
+        "windows security" | "authentication required" | "password required" | "enter password"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_credential_prompt() {
+        let window = |app_name: &str, title: &str| FocusedWindow {
+            app_name: app_name.to_string(),
+            title: title.to_string(),
+            pid: 1,
+            x: 0,
+            y: 0,
+            width: 400,
+            height: 300,
+        };
+        let prompt = |app_name, title| is_credential_prompt(&window(app_name, title));
+        assert!(prompt("CredentialUIBroker.exe", "Windows Security"));
+        assert!(prompt("pinentry-gnome3", ""));
+        assert!(prompt("KeePassXC", "Unlock Database - KeePassXC"));
+        assert!(prompt("1Password", "1Password is locked"));
+        assert!(prompt("explorer.exe", "Windows Security"));
+        assert!(!prompt("1Password", "All Items - 1Password"));
+        assert!(!prompt("code", "locked-accounts.md - Visual Studio Code"));
+    }
+}
@@ -0,0 +1,85 @@
+//! Do-not-disturb apps.
+//!
+//! Presentations and screen sharing are not always visible to share
+//! detection. The `DndAppGuard` pauses capture while one of the configured
+//! apps is in the foreground, and for a cooldown after it loses focus so
+//! briefly switching windows mid-presentation is not captured either.
+
+use super::CaptureGuard;
+use crate::config::DndConfig;
+use crate::process::normalize;
+use crate::window::focused_window;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Pauses capture around foreground use of do-not-disturb apps.
+pub struct DndAppGuard {
+    /// Normalized app names, see `normalize`.
+    apps: Vec<String>,
+    cooldown: Duration,
+    /// The last matching app and when it was last seen focused.
+    last_seen: Mutex<Option<(String, Instant)>>,
+}
+
+impl DndAppGuard {
+    pub fn new(config: &DndConfig) -> Self {
+        Self {
+            apps: config.apps.iter().map(|app| normalize(app)).collect(),
+            cooldown: Duration::from_secs(config.cooldown_secs),
+            last_seen: Mutex::new(None),
+        }
+    }
+
+    /// The configured app matching `app_name`, if any.
+    fn matching_app(&self, app_name: &str) -> Option<&str> {
+        let app_name = normalize(app_name);
+        self.apps
+            .iter()
+            .find(|app| !app.is_empty() && app_name.contains(app.as_str()))
+            .map(String::as_str)
+    }
+}
+
+impl CaptureGuard for DndAppGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let focused = focused_window().unwrap_or_else(|e| {
+            debug!(error = %e, "Failed to get focused window");
+            None
+        });
+        let mut last_seen = self.last_seen.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(app) = focused.and_then(|w| self.matching_app(&w.app_name).map(str::to_string))
+        {
+            let reason = format!("{} is in the foreground", app);
+            *last_seen = Some((app, Instant::now()));
+            return Some(reason);
+        }
+
+        match &*last_seen {
+            Some((app, seen)) if seen.elapsed() < self.cooldown => {
+                Some(format!("{} was in the foreground recently", app))
+            }
+            _ => {
+                *last_seen = None;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_app() {
+        let guard = DndAppGuard::new(&DndConfig {
+            apps: vec!["zoom.exe".to_string(), "Teams".to_string()],
+            cooldown_secs: 60,
+        });
+        assert_eq!(guard.matching_app("Zoom.exe"), Some("zoom"));
+        assert_eq!(guard.matching_app("Microsoft Teams"), Some("teams"));
+        assert_eq!(guard.matching_app("firefox"), None);
+    }
+}
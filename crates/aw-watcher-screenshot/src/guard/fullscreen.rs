@@ -0,0 +1,108 @@
+//! Fullscreen app detection.
+//!
+//! Grabbing the screen every few seconds makes high refresh rate games and
+//! video players stutter on some systems. The `FullscreenGuard` skips or
+//! thins out captures while the focused window covers a whole monitor.
+
+use super::CaptureGuard;
+use crate::config::{FullscreenAction, FullscreenConfig};
+use crate::event::FocusedWindow;
+use crate::window::focused_window;
+use std::sync::Mutex;
+use tracing::debug;
+use xcap::Monitor;
+
+/// Skips or throttles capture while a fullscreen app is focused.
+pub struct FullscreenGuard {
+    action: FullscreenAction,
+    throttle_factor: u32,
+    /// Lowercased app names the action is limited to.
+    apps: Vec<String>,
+    /// Ticks seen while throttling.
+    ticks: Mutex<u32>,
+}
+
+impl FullscreenGuard {
+    pub fn new(config: &FullscreenConfig) -> Self {
+        Self {
+            action: config.action,
+            throttle_factor: config.throttle_factor.max(1),
+            apps: config.apps.iter().map(|app| app.to_lowercase()).collect(),
+            ticks: Mutex::new(0),
+        }
+    }
+
+    /// The focused app, if it is fullscreen and the action applies to it.
+    fn fullscreen_app(&self) -> Option<String> {
+        let window = focused_window()
+            .inspect_err(|e| debug!(error = %e, "Failed to get focused window"))
+            .ok()??;
+        let app_name = window.app_name.to_lowercase();
+        if !self.apps.is_empty() && !self.apps.iter().any(|app| app_name.contains(app.as_str())) {
+            return None;
+        }
+        let monitors: Vec<(i32, i32, u32, u32)> = Monitor::all()
+            .ok()?
+            .iter()
+            .filter_map(|m| Some((m.x().ok()?, m.y().ok()?, m.width().ok()?, m.height().ok()?)))
+            .collect();
+        covers_monitor(&window, &monitors).then_some(window.app_name)
+    }
+}
+
+impl CaptureGuard for FullscreenGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let mut ticks = self.ticks.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(app) = self.fullscreen_app() else {
+            *ticks = 0;
+            return None;
+        };
+        match self.action {
+            FullscreenAction::Capture => None,
+            FullscreenAction::Skip => Some(format!("{} is fullscreen", app)),
+            FullscreenAction::Throttle => {
+                // Capture on the first fullscreen tick, then every n-th one
+                let skip = !ticks.is_multiple_of(self.throttle_factor);
+                *ticks = ticks.wrapping_add(1);
+                skip.then(|| format!("{} is fullscreen (throttled)", app))
+            }
+        }
+    }
+}
+
+/// Whether `window` covers at least one of the monitor rectangles
+/// `(x, y, width, height)` entirely.
+fn covers_monitor(window: &FocusedWindow, monitors: &[(i32, i32, u32, u32)]) -> bool {
+    let right = window.x as i64 + window.width as i64;
+    let bottom = window.y as i64 + window.height as i64;
+    monitors.iter().any(|&(x, y, width, height)| {
+        window.x <= x
+            && window.y <= y
+            && right >= x as i64 + width as i64
+            && bottom >= y as i64 + height as i64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_covers_monitor() {
+        let window = |x, y, width, height| FocusedWindow {
+            app_name: "game".to_string(),
+            title: String::new(),
+            pid: 1,
+            x,
+            y,
+            width,
+            height,
+        };
+        let monitors = [(0, 0, 2560, 1440), (2560, 0, 1920, 1080)];
+
+        assert!(covers_monitor(&window(2560, 0, 1920, 1080), &monitors));
+        // Borderless windows often overhang by the invisible frame
+        assert!(covers_monitor(&window(-8, -8, 2576, 1456), &monitors));
+        assert!(!covers_monitor(&window(0, 0, 2560, 1400), &monitors));
+    }
+}
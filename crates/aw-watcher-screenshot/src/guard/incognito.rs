@@ -0,0 +1,102 @@
+//! Private browsing windows.
+//!
+//! Browsers do not report private windows to the OS, but all major ones mark
+//! them in the window title. The `IncognitoGuard` skips capture while a
+//! browser window whose title matches one of that browser's patterns is
+//! focused.
+
+use super::CaptureGuard;
+use crate::config::IncognitoConfig;
+use crate::event::FocusedWindow;
+use crate::window::focused_window;
+use anyhow::{Error, Result};
+use regex::{Regex, RegexBuilder};
+use tracing::debug;
+
+/// Skips capture while a private browsing window is focused.
+pub struct IncognitoGuard {
+    /// Lowercase browser app names and their private window title patterns.
+    browsers: Vec<(String, Vec<Regex>)>,
+}
+
+impl IncognitoGuard {
+    pub fn new(config: &IncognitoConfig) -> Result<Self, Error> {
+        let browsers = config
+            .browsers
+            .iter()
+            .map(|(app, patterns)| {
+                let patterns = patterns
+                    .iter()
+                    .map(|pattern| {
+                        RegexBuilder::new(pattern)
+                            .case_insensitive(true)
+                            .build()
+                            .map_err(|e| {
+                                anyhow::anyhow!(
+                                    "Invalid private window pattern {:?} for {}: {}",
+                                    pattern,
+                                    app,
+                                    e
+                                )
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((app.trim().to_lowercase(), patterns))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { browsers })
+    }
+
+    /// The browser whose private window `window` is, if any.
+    fn private_browser(&self, window: &FocusedWindow) -> Option<&str> {
+        let app_name = window.app_name.to_lowercase();
+        self.browsers
+            .iter()
+            .find(|(app, patterns)| {
+                !app.is_empty()
+                    && app_name.contains(app.as_str())
+                    && patterns.iter().any(|re| re.is_match(&window.title))
+            })
+            .map(|(app, _)| app.as_str())
+    }
+}
+
+impl CaptureGuard for IncognitoGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let window = focused_window()
+            .inspect_err(|e| debug!(error = %e, "Failed to get focused window"))
+            .ok()??;
+        self.private_browser(&window)
+            .map(|browser| format!("a private {} window is focused", browser))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_private_browser() {
+        let guard = IncognitoGuard::new(&IncognitoConfig::default()).unwrap();
+        let window = |app_name: &str, title: &str| FocusedWindow {
+            app_name: app_name.to_string(),
+            title: title.to_string(),
+            pid: 1,
+            x: 0,
+            y: 0,
+            width: 800,
+            height: 600,
+        };
+        let private = |app_name, title| guard.private_browser(&window(app_name, title)).is_some();
+        assert!(private("chrome.exe", "News - Google Chrome (Incognito)"));
+        assert!(private("msedge.exe", "[InPrivate] Bank - Microsoft Edge"));
+        assert!(private(
+            "firefox",
+            "Search — Mozilla Firefox Private Browsing"
+        ));
+        assert!(private("Brave Browser", "Mail - Brave (Private)"));
+        assert!(!private("chrome.exe", "News - Google Chrome"));
+        // Only browsers' titles count
+        assert!(!private("code", "incognito.rs - Visual Studio Code"));
+    }
+}
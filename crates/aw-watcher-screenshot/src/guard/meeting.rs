@@ -0,0 +1,330 @@
+//! Meeting detection.
+//!
+//! Video calls are privacy-sensitive and make poor screenshots. While the
+//! camera or microphone is in use, the `MeetingGuard` either pauses capture
+//! or flags frames for heavy redaction, and posts a "meeting" marker event
+//! spanning the call to the status bucket instead.
+
+use super::CaptureGuard;
+use crate::config::{MeetingAction, MeetingConfig};
+use crate::status::StatusReporter;
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info};
+
+/// Pauses or redacts capture while a meeting is detected.
+pub struct MeetingGuard {
+    config: MeetingConfig,
+    reporter: Option<StatusReporter>,
+    /// Set while a meeting is detected, read by the redaction stage.
+    active: Arc<AtomicBool>,
+    started: Mutex<Option<(DateTime<Utc>, &'static str)>>,
+}
+
+impl MeetingGuard {
+    pub fn new(config: MeetingConfig, reporter: Option<StatusReporter>) -> Self {
+        Self {
+            config,
+            reporter,
+            active: Arc::new(AtomicBool::new(false)),
+            started: Mutex::new(None),
+        }
+    }
+
+    /// Flag that is set while a meeting is detected.
+    pub fn active_flag(&self) -> Arc<AtomicBool> {
+        self.active.clone()
+    }
+
+    /// Track meeting start and end, posting a marker event when one ends.
+    fn update(&self, device: Option<&'static str>) {
+        let mut started = self.started.lock().unwrap_or_else(|e| e.into_inner());
+        match (*started, device) {
+            (None, Some(device)) => {
+                info!(device, "Meeting detected");
+                *started = Some((Utc::now(), device));
+            }
+            (Some((start, device)), None) => {
+                info!(device, "Meeting ended");
+                *started = None;
+                if let Some(reporter) = &self.reporter {
+                    let mut data = Map::new();
+                    data.insert("device".to_string(), Value::from(device));
+                    reporter.report_span("meeting", start, Utc::now() - start, data);
+                }
+            }
+            _ => {}
+        }
+        self.active.store(device.is_some(), Ordering::Relaxed);
+    }
+}
+
+impl CaptureGuard for MeetingGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let device = device_in_use(&self.config).unwrap_or_else(|e| {
+            debug!(error = %e, "Failed to query camera/microphone state");
+            None
+        });
+        self.update(device);
+
+        match (device, self.config.action) {
+            (Some(device), MeetingAction::Pause) => Some(format!("{} in use (meeting)", device)),
+            _ => None,
+        }
+    }
+}
+
+/// The first watched device that another application is using.
+fn device_in_use(config: &MeetingConfig) -> Result<Option<&'static str>, Error> {
+    if config.camera && camera_in_use()? {
+        return Ok(Some("camera"));
+    }
+    if config.microphone && microphone_in_use()? {
+        return Ok(Some("microphone"));
+    }
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+fn camera_in_use() -> Result<bool, Error> {
+    // Any other process holding a V4L2 device open
+    let own_pid = std::process::id().to_string();
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(pid) = name.to_str() else { continue };
+        if pid == own_pid || !pid.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        // Processes of other users are not readable, skip them
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if let Ok(target) = std::fs::read_link(fd.path())
+                && target.to_string_lossy().starts_with("/dev/video")
+            {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(target_os = "linux")]
+fn microphone_in_use() -> Result<bool, Error> {
+    // Capture substreams are "pcm*c"; their status is "closed" when idle
+    for card in std::fs::read_dir("/proc/asound")?.flatten() {
+        let Ok(pcms) = std::fs::read_dir(card.path()) else {
+            continue;
+        };
+        for pcm in pcms.flatten() {
+            let name = pcm.file_name();
+            let name = name.to_string_lossy();
+            if !(name.starts_with("pcm") && name.ends_with('c')) {
+                continue;
+            }
+            let Ok(subs) = std::fs::read_dir(pcm.path()) else {
+                continue;
+            };
+            for sub in subs.flatten() {
+                let status = std::fs::read_to_string(sub.path().join("status")).unwrap_or_default();
+                if status.contains("RUNNING") {
+                    return Ok(true);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Whether any app currently uses a capability, according to the privacy
+/// consent store (an in-use entry has `LastUsedTimeStop` of 0).
+#[cfg(windows)]
+fn capability_in_use(capability: &str) -> Result<bool, Error> {
+    let key = format!(
+        r"HKCU\Software\Microsoft\Windows\CurrentVersion\CapabilityAccessManager\ConsentStore\{}",
+        capability
+    );
+    let output = std::process::Command::new("reg")
+        .args(["query", &key, "/s", "/v", "LastUsedTimeStop"])
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        fields.next() == Some("LastUsedTimeStop") && fields.nth(1) == Some("0x0")
+    }))
+}
+
+#[cfg(windows)]
+fn camera_in_use() -> Result<bool, Error> {
+    capability_in_use("webcam")
+}
+
+#[cfg(windows)]
+fn microphone_in_use() -> Result<bool, Error> {
+    capability_in_use("microphone")
+}
+
+#[cfg(target_os = "macos")]
+mod mac {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    pub struct PropertyAddress {
+        pub selector: u32,
+        pub scope: u32,
+        pub element: u32,
+    }
+
+    pub const fn fourcc(code: &[u8; 4]) -> u32 {
+        u32::from_be_bytes(*code)
+    }
+
+    pub const SYSTEM_OBJECT: u32 = 1;
+    pub const SCOPE_GLOBAL: u32 = fourcc(b"glob");
+    pub const ELEMENT_MAIN: u32 = 0;
+    pub const DEFAULT_INPUT_DEVICE: u32 = fourcc(b"dIn ");
+    pub const DEVICES: u32 = fourcc(b"dev#");
+    pub const IS_RUNNING_SOMEWHERE: u32 = fourcc(b"gone");
+
+    #[link(name = "CoreAudio", kind = "framework")]
+    unsafe extern "C" {
+        pub fn AudioObjectGetPropertyData(
+            object: u32,
+            address: *const PropertyAddress,
+            qualifier_size: u32,
+            qualifier: *const c_void,
+            data_size: *mut u32,
+            data: *mut c_void,
+        ) -> i32;
+    }
+
+    #[link(name = "CoreMediaIO", kind = "framework")]
+    unsafe extern "C" {
+        pub fn CMIOObjectGetPropertyDataSize(
+            object: u32,
+            address: *const PropertyAddress,
+            qualifier_size: u32,
+            qualifier: *const c_void,
+            data_size: *mut u32,
+        ) -> i32;
+        pub fn CMIOObjectGetPropertyData(
+            object: u32,
+            address: *const PropertyAddress,
+            qualifier_size: u32,
+            qualifier: *const c_void,
+            data_size: u32,
+            data_used: *mut u32,
+            data: *mut c_void,
+        ) -> i32;
+    }
+
+    pub fn address(selector: u32) -> PropertyAddress {
+        PropertyAddress {
+            selector,
+            scope: SCOPE_GLOBAL,
+            element: ELEMENT_MAIN,
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn camera_in_use() -> Result<bool, Error> {
+    use mac::*;
+    use std::ptr::null;
+
+    unsafe {
+        let mut size = 0u32;
+        let status =
+            CMIOObjectGetPropertyDataSize(SYSTEM_OBJECT, &address(DEVICES), 0, null(), &mut size);
+        if status != 0 {
+            return Err(anyhow::anyhow!("CMIO device list query failed: {}", status));
+        }
+        let mut devices = vec![0u32; size as usize / std::mem::size_of::<u32>()];
+        let mut used = 0u32;
+        let status = CMIOObjectGetPropertyData(
+            SYSTEM_OBJECT,
+            &address(DEVICES),
+            0,
+            null(),
+            size,
+            &mut used,
+            devices.as_mut_ptr().cast(),
+        );
+        if status != 0 {
+            return Err(anyhow::anyhow!("CMIO device list query failed: {}", status));
+        }
+
+        for device in devices {
+            let mut running = 0u32;
+            let status = CMIOObjectGetPropertyData(
+                device,
+                &address(IS_RUNNING_SOMEWHERE),
+                0,
+                null(),
+                std::mem::size_of::<u32>() as u32,
+                &mut used,
+                (&mut running as *mut u32).cast(),
+            );
+            if status == 0 && running != 0 {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(target_os = "macos")]
+fn microphone_in_use() -> Result<bool, Error> {
+    use mac::*;
+    use std::ptr::null;
+
+    unsafe {
+        let mut device = 0u32;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        let status = AudioObjectGetPropertyData(
+            SYSTEM_OBJECT,
+            &address(DEFAULT_INPUT_DEVICE),
+            0,
+            null(),
+            &mut size,
+            (&mut device as *mut u32).cast(),
+        );
+        if status != 0 || device == 0 {
+            return Ok(false);
+        }
+
+        let mut running = 0u32;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        let status = AudioObjectGetPropertyData(
+            device,
+            &address(IS_RUNNING_SOMEWHERE),
+            0,
+            null(),
+            &mut size,
+            (&mut running as *mut u32).cast(),
+        );
+        if status != 0 {
+            return Err(anyhow::anyhow!("Audio device query failed: {}", status));
+        }
+        Ok(running != 0)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn camera_in_use() -> Result<bool, Error> {
+    Err(anyhow::anyhow!(
+        "Camera detection is not supported on this platform"
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn microphone_in_use() -> Result<bool, Error> {
+    Err(anyhow::anyhow!(
+        "Microphone detection is not supported on this platform"
+    ))
+}
@@ -0,0 +1,32 @@
+//! Conditions under which capturing must pause.
+//!
+//! The capture producer consults every registered `CaptureGuard` at each
+//! tick, on a blocking thread, and skips the tick while any of them reports a
+//! reason to pause.
+
+pub mod afk;
+pub mod app;
+pub mod cpu;
+pub mod credential;
+pub mod dnd;
+pub mod fullscreen;
+pub mod incognito;
+pub mod meeting;
+pub mod process;
+pub mod remote_desktop;
+pub mod session;
+pub mod sharing;
+pub mod window;
+
+/// A condition that can pause capturing.
+pub trait CaptureGuard: Send + Sync {
+    /// Why capture must be paused right now, or `None` to allow it.
+    fn pause_reason(&self) -> Option<String>;
+}
+
+/// A guard shared with another part of the pipeline.
+impl<T: CaptureGuard + ?Sized> CaptureGuard for std::sync::Arc<T> {
+    fn pause_reason(&self) -> Option<String> {
+        (**self).pause_reason()
+    }
+}
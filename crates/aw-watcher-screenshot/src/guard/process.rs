@@ -0,0 +1,61 @@
+//! Process blocklist.
+//!
+//! Some programs hold data that must never be recorded, such as tax
+//! software or a hypervisor with client machines. The `ProcessGuard`
+//! suspends capture while any process in `capture.blocked_processes` runs,
+//! whether or not one of its windows is focused.
+//!
+//! Where processes cannot be listed at all the blocklist cannot be enforced;
+//! this is warned about at startup. Once they could be listed, a failed
+//! listing skips capture.
+
+use super::CaptureGuard;
+use crate::process::{is_process, normalize, process_names};
+use tracing::{debug, warn};
+
+/// Suspends capture while a blocklisted process runs.
+pub struct ProcessGuard {
+    /// Normalized process names, see `normalize`.
+    processes: Vec<String>,
+    /// Whether processes could be listed at startup.
+    enforceable: bool,
+}
+
+impl ProcessGuard {
+    pub fn new(processes: &[String]) -> Self {
+        let enforceable = process_names()
+            .inspect_err(|e| {
+                warn!(error = %e, "Processes cannot be listed, the process blocklist is not enforced")
+            })
+            .is_ok();
+        Self {
+            processes: processes
+                .iter()
+                .map(|name| normalize(name))
+                .filter(|name| !name.is_empty())
+                .collect(),
+            enforceable,
+        }
+    }
+}
+
+impl CaptureGuard for ProcessGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let running = match process_names() {
+            Ok(running) => running,
+            Err(e) => {
+                debug!(error = %e, "Failed to list processes");
+                return self
+                    .enforceable
+                    .then(|| "running processes are unknown".to_string());
+            }
+        };
+        running.iter().find_map(|name| {
+            let name = normalize(name);
+            self.processes
+                .iter()
+                .find(|wanted| is_process(&name, wanted))
+                .map(|wanted| format!("{} is running", wanted))
+        })
+    }
+}
@@ -0,0 +1,129 @@
+//! Remote desktop and virtual machine windows.
+//!
+//! RDP, VNC and VM viewer windows often show another organization's
+//! machine. The `RemoteDesktopGuard` skips capture while such a window is
+//! focused and, optionally, while one covers most of a monitor even without
+//! focus.
+
+use super::CaptureGuard;
+use crate::config::RemoteDesktopConfig;
+use crate::event::FocusedWindow;
+use crate::window::focused_window;
+use tracing::debug;
+use xcap::{Monitor, Window};
+
+/// Skips capture while a remote desktop or VM window is focused or covers
+/// most of a monitor.
+pub struct RemoteDesktopGuard {
+    /// Lowercased app names of remote desktop and VM viewers.
+    apps: Vec<String>,
+    min_coverage: f32,
+}
+
+impl RemoteDesktopGuard {
+    pub fn new(config: &RemoteDesktopConfig) -> Self {
+        Self {
+            apps: config
+                .apps
+                .iter()
+                .map(|app| app.trim().to_lowercase())
+                .filter(|app| !app.is_empty())
+                .collect(),
+            min_coverage: config.min_coverage,
+        }
+    }
+
+    fn is_remote(&self, app_name: &str) -> bool {
+        let app_name = app_name.to_lowercase();
+        self.apps.iter().any(|app| app_name.contains(app.as_str()))
+    }
+
+    /// A visible remote window covering at least `min_coverage` of a monitor.
+    fn covering_app(&self) -> Option<String> {
+        let monitors: Vec<(i32, i32, u32, u32)> = Monitor::all()
+            .ok()?
+            .iter()
+            .filter_map(|m| Some((m.x().ok()?, m.y().ok()?, m.width().ok()?, m.height().ok()?)))
+            .collect();
+        Window::all()
+            .inspect_err(|e| debug!(error = %e, "Failed to list windows"))
+            .ok()?
+            .into_iter()
+            .filter(|window| !window.is_minimized().unwrap_or(true))
+            .find_map(|window| {
+                let app_name = window.app_name().ok()?;
+                if !self.is_remote(&app_name) {
+                    return None;
+                }
+                let window = FocusedWindow {
+                    app_name,
+                    title: String::new(),
+                    pid: window.pid().ok()?,
+                    x: window.x().ok()?,
+                    y: window.y().ok()?,
+                    width: window.width().ok()?,
+                    height: window.height().ok()?,
+                };
+                (coverage(&window, &monitors) >= self.min_coverage).then_some(window.app_name)
+            })
+    }
+}
+
+impl CaptureGuard for RemoteDesktopGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let focused = focused_window()
+            .inspect_err(|e| debug!(error = %e, "Failed to get focused window"))
+            .ok()
+            .flatten();
+        if let Some(window) = focused.filter(|window| self.is_remote(&window.app_name)) {
+            return Some(format!("{} is focused", window.app_name));
+        }
+        if self.min_coverage <= 0.0 {
+            return None;
+        }
+        self.covering_app()
+            .map(|app| format!("{} covers a monitor", app))
+    }
+}
+
+/// The largest fraction of one of the monitor rectangles
+/// `(x, y, width, height)` covered by `window`.
+fn coverage(window: &FocusedWindow, monitors: &[(i32, i32, u32, u32)]) -> f32 {
+    let right = window.x as i64 + window.width as i64;
+    let bottom = window.y as i64 + window.height as i64;
+    monitors
+        .iter()
+        .filter(|&&(_, _, width, height)| width > 0 && height > 0)
+        .map(|&(x, y, width, height)| {
+            let overlap_x = right.min(x as i64 + width as i64) - (window.x as i64).max(x as i64);
+            let overlap_y = bottom.min(y as i64 + height as i64) - (window.y as i64).max(y as i64);
+            let overlap = overlap_x.max(0) as f64 * overlap_y.max(0) as f64;
+            (overlap / (width as f64 * height as f64)) as f32
+        })
+        .fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        let window = |x, y, width, height| FocusedWindow {
+            app_name: "mstsc.exe".to_string(),
+            title: String::new(),
+            pid: 1,
+            x,
+            y,
+            width,
+            height,
+        };
+        let monitors = [(0, 0, 2000, 1000), (2000, 0, 1000, 1000)];
+
+        assert_eq!(coverage(&window(2000, 0, 1000, 1000), &monitors), 1.0);
+        assert_eq!(coverage(&window(0, 0, 1000, 1000), &monitors), 0.5);
+        // Straddling both monitors counts the better covered one
+        assert_eq!(coverage(&window(1500, 0, 1000, 1000), &monitors), 0.5);
+        assert_eq!(coverage(&window(-500, -500, 100, 100), &monitors), 0.0);
+    }
+}
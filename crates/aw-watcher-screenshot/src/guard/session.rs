@@ -0,0 +1,342 @@
+//! Login session detection.
+//!
+//! On machines with fast user switching or several logged-in users, a
+//! watcher keeps running in sessions that are switched away from. The
+//! `SessionGuard` pauses capturing while the watcher's own session is not the
+//! active one, the `LockGuard` while it is locked or the screensaver runs
+//! (and for a grace period after unlocking), and `SessionInfo` identifies
+//! the session in event data.
+
+use super::CaptureGuard;
+use anyhow::{Error, Result};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// The login session the watcher runs in.
+#[derive(Serialize, Clone, Debug)]
+pub struct SessionInfo {
+    /// Platform session id (logind session, Windows session number or uid).
+    pub id: String,
+    pub user: String,
+}
+
+impl SessionInfo {
+    pub fn current() -> Self {
+        let user = std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_default();
+        Self {
+            id: session_id(),
+            user,
+        }
+    }
+}
+
+/// Pauses capture while the watcher's session is inactive.
+pub struct SessionGuard {
+    session: SessionInfo,
+}
+
+impl SessionGuard {
+    pub fn new(session: SessionInfo) -> Self {
+        Self { session }
+    }
+}
+
+impl CaptureGuard for SessionGuard {
+    fn pause_reason(&self) -> Option<String> {
+        match is_active(&self.session) {
+            Ok(true) => None,
+            Ok(false) => Some(format!("session {} is not active", self.session.id)),
+            Err(e) => {
+                // Unknown state: keep capturing rather than silently stopping
+                debug!(error = %e, "Failed to query session state");
+                None
+            }
+        }
+    }
+}
+
+/// Pauses capture while the session is locked, so the lock screen is not
+/// captured over and over each time the force interval fires, and for
+/// `grace` after it unlocks: the first frames after unlocking often show
+/// password prompts, 2FA codes or a backlog of notifications.
+pub struct LockGuard {
+    session: SessionInfo,
+    grace: Duration,
+    state: Mutex<LockState>,
+}
+
+#[derive(Default)]
+struct LockState {
+    locked: bool,
+    unlocked_at: Option<Instant>,
+}
+
+impl LockState {
+    /// Record the lock state seen at `now` and return why to pause, if at all.
+    fn update(&mut self, locked: bool, now: Instant, grace: Duration) -> Option<String> {
+        if locked {
+            self.locked = true;
+            self.unlocked_at = None;
+            return Some("screen is locked".to_string());
+        }
+        if std::mem::take(&mut self.locked) && !grace.is_zero() {
+            info!(
+                grace_secs = grace.as_secs(),
+                "Screen unlocked, waiting before capturing"
+            );
+            self.unlocked_at = Some(now);
+        }
+        match self.unlocked_at {
+            Some(unlocked_at) if now < unlocked_at + grace => {
+                Some("screen was just unlocked".to_string())
+            }
+            _ => {
+                self.unlocked_at = None;
+                None
+            }
+        }
+    }
+}
+
+impl LockGuard {
+    pub fn new(session: SessionInfo, grace: Duration) -> Self {
+        Self {
+            session,
+            grace,
+            state: Mutex::new(LockState::default()),
+        }
+    }
+}
+
+impl CaptureGuard for LockGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let locked = match is_locked(&self.session) {
+            Ok(locked) => locked,
+            Err(e) => {
+                debug!(error = %e, "Failed to query lock state");
+                false
+            }
+        };
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.update(locked, Instant::now(), self.grace)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn session_id() -> String {
+    std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn is_active(session: &SessionInfo) -> Result<bool, Error> {
+    let output = std::process::Command::new("loginctl")
+        .args(["show-session", &session.id, "--property=Active", "--value"])
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "loginctl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim() == "yes")
+}
+
+#[cfg(target_os = "linux")]
+fn is_locked(session: &SessionInfo) -> Result<bool, Error> {
+    use std::process::Command;
+
+    let locked_hint = Command::new("loginctl")
+        .args([
+            "show-session",
+            &session.id,
+            "--property=LockedHint",
+            "--value",
+        ])
+        .output();
+    if let Ok(output) = &locked_hint
+        && output.status.success()
+        && String::from_utf8_lossy(&output.stdout).trim() == "yes"
+    {
+        return Ok(true);
+    }
+    // Screensavers that lock without setting the logind hint
+    let screensaver = Command::new("busctl")
+        .args([
+            "--user",
+            "call",
+            "org.freedesktop.ScreenSaver",
+            "/org/freedesktop/ScreenSaver",
+            "org.freedesktop.ScreenSaver",
+            "GetActive",
+        ])
+        .output();
+    match (locked_hint, screensaver) {
+        (_, Ok(output)) if output.status.success() => {
+            Ok(String::from_utf8_lossy(&output.stdout).trim() == "b true")
+        }
+        (Ok(output), _) if output.status.success() => Ok(false),
+        _ => Err(anyhow::anyhow!(
+            "Neither logind nor the screensaver report the lock state"
+        )),
+    }
+}
+
+#[cfg(windows)]
+fn session_id() -> String {
+    use windows::Win32::System::RemoteDesktop::ProcessIdToSessionId;
+    use windows::Win32::System::Threading::GetCurrentProcessId;
+
+    let mut session = 0;
+    match unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session) } {
+        Ok(()) => session.to_string(),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+#[cfg(windows)]
+fn is_active(session: &SessionInfo) -> Result<bool, Error> {
+    use windows::Win32::System::RemoteDesktop::{
+        WTS_CONNECTSTATE_CLASS, WTSActive, WTSConnectState, WTSFreeMemory,
+        WTSQuerySessionInformationW,
+    };
+    use windows::core::PWSTR;
+
+    let id: u32 = session.id.parse()?;
+    let mut buffer = PWSTR::null();
+    let mut bytes = 0;
+    // Covers both the console session and remote desktop sessions
+    let state = unsafe {
+        WTSQuerySessionInformationW(None, id, WTSConnectState, &mut buffer, &mut bytes)?;
+        let state = *(buffer.0 as *const WTS_CONNECTSTATE_CLASS);
+        WTSFreeMemory(buffer.0 as _);
+        state
+    };
+    Ok(state == WTSActive)
+}
+
+/// Polled at each tick, like the other guards, instead of registering for
+/// session notifications, which would need a window and a message loop.
+#[cfg(windows)]
+fn is_locked(session: &SessionInfo) -> Result<bool, Error> {
+    use windows::Win32::System::RemoteDesktop::{
+        WTS_SESSIONSTATE_LOCK, WTSFreeMemory, WTSINFOEXW, WTSQuerySessionInformationW,
+        WTSSessionInfoEx,
+    };
+    use windows::core::PWSTR;
+
+    let id: u32 = session.id.parse()?;
+    let mut buffer = PWSTR::null();
+    let mut bytes = 0;
+    let flags = unsafe {
+        WTSQuerySessionInformationW(None, id, WTSSessionInfoEx, &mut buffer, &mut bytes)?;
+        let info = &*(buffer.0 as *const WTSINFOEXW);
+        let flags = info.Data.WTSInfoExLevel1.SessionFlags;
+        WTSFreeMemory(buffer.0 as _);
+        flags
+    };
+    Ok(flags as u32 == WTS_SESSIONSTATE_LOCK)
+}
+
+#[cfg(target_os = "macos")]
+fn session_id() -> String {
+    unsafe { libc::getuid() }.to_string()
+}
+
+#[cfg(target_os = "macos")]
+fn is_active(_session: &SessionInfo) -> Result<bool, Error> {
+    // No window server session at all, e.g. started over SSH, counts as inactive
+    Ok(session_flag(c"kCGSSessionOnConsoleKey"))
+}
+
+#[cfg(target_os = "macos")]
+fn is_locked(_session: &SessionInfo) -> Result<bool, Error> {
+    Ok(session_flag(c"CGSSessionScreenIsLocked"))
+}
+
+/// A boolean from the current window server session dictionary, false when
+/// missing.
+#[cfg(target_os = "macos")]
+fn session_flag(key: &std::ffi::CStr) -> bool {
+    use std::ffi::c_void;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGSessionCopyCurrentDictionary() -> *const c_void;
+    }
+    #[link(name = "CoreFoundation", kind = "framework")]
+    unsafe extern "C" {
+        fn CFStringCreateWithCString(
+            alloc: *const c_void,
+            c_str: *const std::ffi::c_char,
+            encoding: u32,
+        ) -> *const c_void;
+        fn CFDictionaryGetValue(dict: *const c_void, key: *const c_void) -> *const c_void;
+        fn CFBooleanGetValue(boolean: *const c_void) -> bool;
+        fn CFRelease(object: *const c_void);
+    }
+    const UTF8: u32 = 0x0800_0100;
+
+    unsafe {
+        let dict = CGSessionCopyCurrentDictionary();
+        if dict.is_null() {
+            return false;
+        }
+        let key = CFStringCreateWithCString(std::ptr::null(), key.as_ptr(), UTF8);
+        let value = CFDictionaryGetValue(dict, key);
+        let flag = !value.is_null() && CFBooleanGetValue(value);
+        CFRelease(key);
+        CFRelease(dict);
+        flag
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn session_id() -> String {
+    "unknown".to_string()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn is_active(_session: &SessionInfo) -> Result<bool, Error> {
+    Err(anyhow::anyhow!(
+        "Session state is not supported on this platform"
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn is_locked(_session: &SessionInfo) -> Result<bool, Error> {
+    Err(anyhow::anyhow!(
+        "Lock state is not supported on this platform"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlock_grace() {
+        let grace = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut state = LockState::default();
+
+        assert!(state.update(false, at(0), grace).is_none());
+        assert_eq!(
+            state.update(true, at(1), grace).as_deref(),
+            Some("screen is locked")
+        );
+        assert_eq!(
+            state.update(false, at(5), grace).as_deref(),
+            Some("screen was just unlocked")
+        );
+        assert!(state.update(false, at(14), grace).is_some());
+        assert!(state.update(false, at(15), grace).is_none());
+        // No grace period configured
+        state.update(true, at(20), Duration::ZERO);
+        assert!(state.update(false, at(21), Duration::ZERO).is_none());
+    }
+}
@@ -0,0 +1,154 @@
+//! Screen sharing detection.
+//!
+//! Captures taken while the screen is shared would record meeting content,
+//! and captures taken while it is recorded end up inside the recording. The
+//! `ScreenShareGuard` pauses capture while the platform reports a screen
+//! cast or screen watcher, and falls back to looking for processes that only
+//! run while sharing. Both checks spawn or scan processes, so their result is
+//! reused for `CHECK_INTERVAL`.
+
+use super::CaptureGuard;
+use crate::config::ScreenShareConfig;
+use crate::process::{normalize, process_names};
+use anyhow::{Error, Result};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// How long a detection result is reused before checking again.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Pauses capture while the screen is being shared.
+pub struct ScreenShareGuard {
+    /// Lowercase process names, see `normalize`.
+    processes: Vec<String>,
+    /// Skip the platform check because our own capture stream would count
+    /// as screen sharing (ScreenCaptureKit backend).
+    process_only: bool,
+    /// When the screen was last checked and how it was being shared.
+    last: Mutex<Option<(Instant, Option<String>)>>,
+}
+
+impl ScreenShareGuard {
+    pub fn new(config: &ScreenShareConfig, process_only: bool) -> Self {
+        Self {
+            processes: config.processes.iter().map(|p| normalize(p)).collect(),
+            process_only,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// How the screen is being shared, if it is.
+    fn detect(&self) -> Option<String> {
+        if !self.process_only {
+            match platform_sharing() {
+                Ok(Some(source)) => return Some(source.to_string()),
+                Ok(None) => {}
+                Err(e) => debug!(error = %e, "Screen sharing query failed"),
+            }
+        }
+        if self.processes.is_empty() {
+            return None;
+        }
+        let running = process_names().unwrap_or_else(|e| {
+            debug!(error = %e, "Failed to list processes");
+            Vec::new()
+        });
+        running
+            .into_iter()
+            .find(|name| self.processes.contains(&normalize(name)))
+    }
+}
+
+impl CaptureGuard for ScreenShareGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let source = match &*last {
+            Some((checked, source)) if checked.elapsed() < CHECK_INTERVAL => source.clone(),
+            _ => {
+                let source = self.detect();
+                let sharing = last.as_ref().is_some_and(|(_, last)| last.is_some());
+                match (sharing, &source) {
+                    (false, Some(source)) => info!(source, "Screen sharing detected"),
+                    (true, None) => info!("Screen sharing ended"),
+                    _ => {}
+                }
+                *last = Some((Instant::now(), source.clone()));
+                source
+            }
+        };
+        source.map(|source| format!("screen is being shared ({})", source))
+    }
+}
+
+/// A running PipeWire screen cast: a video source node that is not backed by
+/// a device (cameras are) and is being consumed.
+#[cfg(target_os = "linux")]
+fn platform_sharing() -> Result<Option<&'static str>, Error> {
+    let output = std::process::Command::new("pw-dump").output()?;
+    if !output.status.success() {
+        anyhow::bail!("pw-dump exited with {}", output.status);
+    }
+    let objects: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+    Ok(pipewire_screen_cast(&objects).then_some("PipeWire screen cast"))
+}
+
+#[cfg(target_os = "linux")]
+fn pipewire_screen_cast(objects: &[serde_json::Value]) -> bool {
+    objects.iter().any(|object| {
+        let info = &object["info"];
+        let props = &info["props"];
+        object["type"] == "PipeWire:Interface:Node"
+            && props["media.class"] == "Video/Source"
+            && props.get("device.id").is_none()
+            && info["state"] == "running"
+    })
+}
+
+/// Whether any process watches the screen through `CGDisplayStream` or
+/// ScreenCaptureKit.
+#[cfg(target_os = "macos")]
+fn platform_sharing() -> Result<Option<&'static str>, Error> {
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGSIsScreenWatcherPresent() -> bool;
+    }
+    let present = unsafe { CGSIsScreenWatcherPresent() };
+    Ok(present.then_some("screen watcher"))
+}
+
+/// Windows has no public API reporting screen sharing; only processes are checked.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn platform_sharing() -> Result<Option<&'static str>, Error> {
+    Ok(None)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pipewire_screen_cast() {
+        let node = |class: &str, device: bool, state: &str| {
+            let mut props = json!({ "media.class": class });
+            if device {
+                props["device.id"] = json!(42);
+            }
+            json!({
+                "type": "PipeWire:Interface:Node",
+                "info": { "state": state, "props": props },
+            })
+        };
+        assert!(!pipewire_screen_cast(&[
+            node("Video/Source", true, "running"),
+            node("Video/Source", false, "suspended"),
+            node("Stream/Input/Video", false, "running"),
+        ]));
+        assert!(pipewire_screen_cast(&[node(
+            "Video/Source",
+            false,
+            "running"
+        )]));
+    }
+}
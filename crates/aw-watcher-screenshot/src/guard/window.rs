@@ -0,0 +1,171 @@
+//! Focused window blocklist and allowlist.
+//!
+//! Password managers, banking sites and private chats must never end up in
+//! a screenshot. The `BlocklistGuard` skips capture while the focused
+//! window's app name or title matches the blocklist in `[capture]`. For
+//! work-only tracking the `AllowlistGuard` does the inverse and captures
+//! only while an allowlisted window is focused.
+//!
+//! Where the focused window cannot be queried at all (some Wayland
+//! compositors) the blocklist cannot be enforced; this is warned about at
+//! startup. Once it could be queried, a failed query skips capture.
+
+use super::CaptureGuard;
+use crate::config::CaptureConfig;
+use crate::event::FocusedWindow;
+use crate::process::normalize;
+use crate::window::focused_window;
+use anyhow::{Error, Result};
+use regex::{Regex, RegexBuilder};
+use tracing::{debug, warn};
+
+/// App names and title patterns a window can match.
+pub struct WindowRules {
+    /// Normalized app names, see `normalize`.
+    apps: Vec<String>,
+    titles: Vec<Regex>,
+}
+
+impl WindowRules {
+    /// App names match case-insensitively against part of the app name,
+    /// titles are case-insensitive regular expressions searched in the title.
+    pub fn new(apps: &[String], titles: &[String]) -> Result<Self, Error> {
+        let titles = titles
+            .iter()
+            .map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| anyhow::anyhow!("Invalid title pattern {:?}: {}", pattern, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            apps: apps
+                .iter()
+                .map(|app| normalize(app))
+                .filter(|app| !app.is_empty())
+                .collect(),
+            titles,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.apps.is_empty() && self.titles.is_empty()
+    }
+
+    pub fn matches(&self, window: &FocusedWindow) -> bool {
+        let app_name = normalize(&window.app_name);
+        self.apps.iter().any(|app| app_name.contains(app.as_str()))
+            || self
+                .titles
+                .iter()
+                .any(|title| title.is_match(&window.title))
+    }
+}
+
+/// Skips capture while a blocklisted window is focused.
+pub struct BlocklistGuard {
+    rules: WindowRules,
+    /// Whether the focused window could be queried at startup.
+    enforceable: bool,
+}
+
+impl BlocklistGuard {
+    pub fn new(config: &CaptureConfig) -> Result<Self, Error> {
+        let rules = WindowRules::new(&config.blocked_apps, &config.blocked_titles)?;
+        let enforceable = match focused_window() {
+            Ok(_) => true,
+            Err(e) if !rules.is_empty() => {
+                warn!(error = %e, "The focused window cannot be queried, the window blocklist is not enforced");
+                false
+            }
+            Err(_) => false,
+        };
+        Ok(Self { rules, enforceable })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl CaptureGuard for BlocklistGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let window = match focused_window() {
+            Ok(window) => window?,
+            Err(e) => {
+                debug!(error = %e, "Failed to get focused window");
+                return self
+                    .enforceable
+                    .then(|| "the focused window is unknown".to_string());
+            }
+        };
+        // The title is left out of the reason, it is what must stay private
+        self.rules
+            .matches(&window)
+            .then(|| format!("{} is blocklisted", window.app_name))
+    }
+}
+
+/// Skips capture unless an allowlisted window is focused.
+pub struct AllowlistGuard {
+    rules: WindowRules,
+}
+
+impl AllowlistGuard {
+    pub fn new(config: &CaptureConfig) -> Result<Self, Error> {
+        Ok(Self {
+            rules: WindowRules::new(&config.allowed_apps, &config.allowed_titles)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl CaptureGuard for AllowlistGuard {
+    fn pause_reason(&self) -> Option<String> {
+        let window = focused_window().unwrap_or_else(|e| {
+            debug!(error = %e, "Failed to get focused window");
+            None
+        });
+        // Without a known focused window nothing is known to be work
+        match window {
+            Some(window) if self.rules.matches(&window) => None,
+            Some(window) => Some(format!("{} is not allowlisted", window.app_name)),
+            None => Some("no allowlisted window is focused".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_rules() {
+        let window = |app_name: &str, title: &str| FocusedWindow {
+            app_name: app_name.to_string(),
+            title: title.to_string(),
+            pid: 1,
+            x: 0,
+            y: 0,
+            width: 800,
+            height: 600,
+        };
+        let rules = WindowRules::new(
+            &["KeePassXC.exe".to_string(), "1password".to_string()],
+            &[r"online banking".to_string(), r"^Signal\b".to_string()],
+        )
+        .unwrap();
+        assert!(rules.matches(&window("keepassxc", "Passwords.kdbx")));
+        assert!(rules.matches(&window("1Password 8", "Vault")));
+        assert!(rules.matches(&window("firefox", "My Bank - Online Banking")));
+        assert!(rules.matches(&window("signal-desktop", "Signal - Alice")));
+        assert!(!rules.matches(&window("firefox", "Docs - Signal processing")));
+        assert!(!rules.matches(&window("code", "main.rs")));
+        assert!(WindowRules::new(&[], &["(".to_string()]).is_err());
+        assert!(WindowRules::new(&[String::new()], &[]).unwrap().is_empty());
+    }
+}
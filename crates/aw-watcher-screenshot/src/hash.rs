@@ -0,0 +1,530 @@
+//! Perceptual hashing of screenshots.
+//!
+//! Every hash starts from a small grid of luma values computed straight from
+//! the frame buffer: each grid cell is the mean luma of a fixed 4x4 grid of
+//! sample points, using integer math only. This avoids resizing and
+//! grayscale converting the full frame, which dominated the filter cost on
+//! large monitors.
+//!
+//! The difference hash (dhash) compares neighbouring cells and is the
+//! cheapest; it misses changes that keep the brightness gradients, such as
+//! text edits on text-heavy screens. The DCT hash (pHash) and the wavelet
+//! hash (wHash) look at the frequency content instead and catch those at
+//! the same threshold.
+
+use image::{DynamicImage, GenericImageView};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// Sample points per grid cell along each axis.
+const SAMPLES: usize = 4;
+/// Largest grid side used by any hash.
+const MAX_GRID: usize = 32;
+/// Grid side of the pHash and wHash inputs.
+const DETAIL_GRID: usize = 32;
+/// Side of the 64-bit hashes.
+const HASH_SIZE: usize = 8;
+/// Sample points along each axis of a color histogram.
+const HISTOGRAM_SAMPLES: usize = 64;
+/// Luma grid cells per change heatmap tile along each axis.
+const HEATMAP_CELLS: usize = 4;
+
+/// A 64-bit perceptual hash; similar images have hashes with a small
+/// Hamming distance.
+pub trait PerceptualHash: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn hash(&self, image: &DynamicImage) -> u64;
+}
+
+/// Selects a `PerceptualHash` in the capture configuration.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// Difference hash: gradients between neighbouring cells.
+    #[default]
+    Dhash,
+    /// Average hash: cells brighter than the mean.
+    Ahash,
+    /// DCT hash: low frequencies above their median.
+    Phash,
+    /// Haar wavelet hash: the low band above its median.
+    Whash,
+}
+
+impl HashAlgorithm {
+    /// Name of the algorithm, as recorded next to its hashes.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Dhash => "dhash",
+            HashAlgorithm::Ahash => "ahash",
+            HashAlgorithm::Phash => "phash",
+            HashAlgorithm::Whash => "whash",
+        }
+    }
+
+    pub fn hasher(self) -> Arc<dyn PerceptualHash> {
+        match self {
+            HashAlgorithm::Dhash => Arc::new(DHash),
+            HashAlgorithm::Ahash => Arc::new(AHash),
+            HashAlgorithm::Phash => Arc::new(PHash),
+            HashAlgorithm::Whash => Arc::new(WHash),
+        }
+    }
+}
+
+pub struct DHash;
+
+impl PerceptualHash for DHash {
+    fn name(&self) -> &'static str {
+        "dhash"
+    }
+
+    fn hash(&self, image: &DynamicImage) -> u64 {
+        dhash(image)
+    }
+}
+
+pub struct AHash;
+
+impl PerceptualHash for AHash {
+    fn name(&self) -> &'static str {
+        "ahash"
+    }
+
+    fn hash(&self, image: &DynamicImage) -> u64 {
+        let mut grid = [0u16; HASH_SIZE * HASH_SIZE];
+        if !luma_grid(image, HASH_SIZE, HASH_SIZE, &mut grid) {
+            return 0;
+        }
+        let mean = grid.iter().map(|&v| v as u32).sum::<u32>() / grid.len() as u32;
+        bits(grid.iter().map(|&v| v as u32 > mean))
+    }
+}
+
+/// The 8x8 lowest frequencies of a 32x32 DCT-II.
+pub struct PHash;
+
+impl PerceptualHash for PHash {
+    fn name(&self) -> &'static str {
+        "phash"
+    }
+
+    fn hash(&self, image: &DynamicImage) -> u64 {
+        let mut grid = [0u16; DETAIL_GRID * DETAIL_GRID];
+        if !luma_grid(image, DETAIL_GRID, DETAIL_GRID, &mut grid) {
+            return 0;
+        }
+        let cosines: [[f32; DETAIL_GRID]; HASH_SIZE] = std::array::from_fn(|k| {
+            std::array::from_fn(|n| {
+                (std::f32::consts::PI / DETAIL_GRID as f32 * (n as f32 + 0.5) * k as f32).cos()
+            })
+        });
+        // Rows first, then the columns of the row coefficients
+        let mut rows = [[0f32; HASH_SIZE]; DETAIL_GRID];
+        for (y, row) in rows.iter_mut().enumerate() {
+            let cells = &grid[y * DETAIL_GRID..(y + 1) * DETAIL_GRID];
+            for (k, coefficient) in row.iter_mut().enumerate() {
+                *coefficient = cells
+                    .iter()
+                    .zip(&cosines[k])
+                    .map(|(&v, c)| v as f32 * c)
+                    .sum();
+            }
+        }
+        let mut low = [0f32; HASH_SIZE * HASH_SIZE];
+        for (i, coefficient) in low.iter_mut().enumerate() {
+            let (k, column) = (i / HASH_SIZE, i % HASH_SIZE);
+            *coefficient = rows
+                .iter()
+                .zip(&cosines[k])
+                .map(|(row, c)| row[column] * c)
+                .sum();
+        }
+        let median = median(&low);
+        bits(low.iter().map(|&v| v > median))
+    }
+}
+
+/// The 8x8 low band of a two-level Haar decomposition of a 32x32 grid,
+/// with the mean (the coarsest low band) removed.
+pub struct WHash;
+
+impl PerceptualHash for WHash {
+    fn name(&self) -> &'static str {
+        "whash"
+    }
+
+    fn hash(&self, image: &DynamicImage) -> u64 {
+        let mut grid = [0u16; DETAIL_GRID * DETAIL_GRID];
+        if !luma_grid(image, DETAIL_GRID, DETAIL_GRID, &mut grid) {
+            return 0;
+        }
+        let mut band: Vec<f32> = grid.iter().map(|&v| v as f32).collect();
+        let mut size = DETAIL_GRID;
+        while size > HASH_SIZE {
+            band = haar_low_band(&band, size);
+            size /= 2;
+        }
+        let mean = band.iter().sum::<f32>() / band.len() as f32;
+        band.iter_mut().for_each(|v| *v -= mean);
+        let median = median(&band);
+        bits(band.iter().map(|&v| v > median))
+    }
+}
+
+/// Compute perceptual hash (difference hash) for an image.
+///
+/// The dhash algorithm:
+/// 1. Downscale image to a 9x8 grayscale grid
+/// 2. Compare adjacent cells horizontally
+/// 3. Generate 64-bit hash based on comparisons
+pub fn dhash(image: &DynamicImage) -> u64 {
+    const GRID_WIDTH: usize = HASH_SIZE + 1;
+    let mut grid = [0u16; GRID_WIDTH * HASH_SIZE];
+    if !luma_grid(image, GRID_WIDTH, HASH_SIZE, &mut grid) {
+        return 0;
+    }
+
+    let mut hash = 0u64;
+    for y in 0..HASH_SIZE {
+        let row = &grid[y * GRID_WIDTH..(y + 1) * GRID_WIDTH];
+        for x in 0..GRID_WIDTH - 1 {
+            hash |= ((row[x] < row[x + 1]) as u64) << (y * (GRID_WIDTH - 1) + x);
+        }
+    }
+    hash
+}
+
+/// Compute Hamming distance between two hashes.
+///
+/// Returns the number of bits that differ between the two hashes.
+pub fn hamming_distance(hash1: u64, hash2: u64) -> u32 {
+    (hash1 ^ hash2).count_ones()
+}
+
+/// Hash each tile of the image split into a `grid` x `grid` grid, row by
+/// row. Edge tiles take the remainder pixels.
+pub fn tile_hashes(hasher: &dyn PerceptualHash, image: &DynamicImage, grid: u32) -> Vec<u64> {
+    let (width, height) = image.dimensions();
+    let grid = grid.clamp(1, width.min(height).max(1));
+    let mut hashes = Vec::with_capacity((grid * grid) as usize);
+    for row in 0..grid {
+        let (y, y_end) = (height * row / grid, height * (row + 1) / grid);
+        for column in 0..grid {
+            let (x, x_end) = (width * column / grid, width * (column + 1) / grid);
+            hashes.push(hasher.hash(&image.crop_imm(x, y, x_end - x, y_end - y)));
+        }
+    }
+    hashes
+}
+
+/// Coarse color histogram of the image with `bins` levels per RGB channel,
+/// normalized to sum to 1. Built from a grid of sample points, like the
+/// hashes. It tells apart frames whose luma structure matches but whose
+/// colors differ, which hashes of dark-theme screens often miss.
+pub fn color_histogram(image: &DynamicImage, bins: u32) -> Vec<f32> {
+    let bins = bins.clamp(1, 16) as usize;
+    let mut histogram = vec![0.0; bins * bins * bins];
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return histogram;
+    }
+    let mut xs = [0usize; HISTOGRAM_SAMPLES];
+    let mut ys = [0usize; HISTOGRAM_SAMPLES];
+    let xs = sample_offsets(width, &mut xs);
+    let ys = sample_offsets(height, &mut ys);
+    let bin = |value: u8| value as usize * bins / 256;
+    for &y in ys.iter() {
+        for &x in xs.iter() {
+            let [r, g, b, _] = image.get_pixel(x as u32, y as u32).0;
+            histogram[(bin(r) * bins + bin(g)) * bins + bin(b)] += 1.0;
+        }
+    }
+    let samples = (xs.len() * ys.len()) as f32;
+    histogram.iter_mut().for_each(|count| *count /= samples);
+    histogram
+}
+
+/// Share of the samples that would have to move to another bin to turn one
+/// histogram into the other, from 0 (same colors) to 1.
+pub fn histogram_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f32>() / 2.0
+}
+
+/// Mean luma of a square grid of cells, `grid` heatmap tiles of 4x4 cells
+/// per side (`grid` is at most 8), compared by `heatmap`.
+pub fn heatmap_luma(image: &DynamicImage, grid: u32) -> Vec<u8> {
+    let side = (grid as usize).clamp(1, MAX_GRID / HEATMAP_CELLS) * HEATMAP_CELLS;
+    let mut cells = vec![0u16; side * side];
+    if !luma_grid(image, side, side, &mut cells) {
+        return Vec::new();
+    }
+    let samples = (SAMPLES * SAMPLES) as u16;
+    cells.iter().map(|sum| (sum / samples) as u8).collect()
+}
+
+/// Where two frames differ: the mean absolute luma difference of each tile
+/// between two `heatmap_luma` grids, as rows of values from 0 (unchanged)
+/// to 255. Empty when the grids do not match.
+pub fn heatmap(previous: &[u8], current: &[u8]) -> Vec<Vec<u8>> {
+    let side = current.len().isqrt();
+    if previous.len() != current.len() || side == 0 || !side.is_multiple_of(HEATMAP_CELLS) {
+        return Vec::new();
+    }
+    let tiles = side / HEATMAP_CELLS;
+    (0..tiles)
+        .map(|row| {
+            (0..tiles)
+                .map(|column| {
+                    let mut total = 0u32;
+                    for y in row * HEATMAP_CELLS..(row + 1) * HEATMAP_CELLS {
+                        for x in column * HEATMAP_CELLS..(column + 1) * HEATMAP_CELLS {
+                            let i = y * side + x;
+                            total += previous[i].abs_diff(current[i]) as u32;
+                        }
+                    }
+                    (total / (HEATMAP_CELLS * HEATMAP_CELLS) as u32) as u8
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Pack 64 bits, the first one lowest.
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values
+        .take(64)
+        .enumerate()
+        .fold(0, |hash, (i, bit)| hash | ((bit as u64) << i))
+}
+
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let middle = sorted.len() / 2;
+    (sorted[middle - 1] + sorted[middle]) / 2.0
+}
+
+/// Averages of the 2x2 blocks of a `size` x `size` grid: the low band of
+/// one Haar wavelet step (up to a constant factor).
+fn haar_low_band(grid: &[f32], size: usize) -> Vec<f32> {
+    let half = size / 2;
+    (0..half * half)
+        .map(|i| {
+            let (y, x) = (2 * (i / half), 2 * (i % half));
+            (grid[y * size + x]
+                + grid[y * size + x + 1]
+                + grid[(y + 1) * size + x]
+                + grid[(y + 1) * size + x + 1])
+                / 4.0
+        })
+        .collect()
+}
+
+/// Fill the row-major `grid` of `grid_width` x `grid_height` cells with the
+/// summed luma of each cell's sample points; false for an empty image.
+fn luma_grid(
+    image: &DynamicImage,
+    grid_width: usize,
+    grid_height: usize,
+    grid: &mut [u16],
+) -> bool {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return false;
+    }
+    let mut xs = [0usize; MAX_GRID * SAMPLES];
+    let mut ys = [0usize; MAX_GRID * SAMPLES];
+    let xs = sample_offsets(width, &mut xs[..grid_width * SAMPLES]);
+    let ys = sample_offsets(height, &mut ys[..grid_height * SAMPLES]);
+
+    match image {
+        DynamicImage::ImageRgba8(buffer) => {
+            downscale(buffer.as_raw(), width, 4, xs, ys, grid_width, grid)
+        }
+        DynamicImage::ImageRgb8(buffer) => {
+            downscale(buffer.as_raw(), width, 3, xs, ys, grid_width, grid)
+        }
+        // Uncommon formats: sample through the generic accessor
+        _ => {
+            for (i, y) in ys.iter().enumerate() {
+                for (j, x) in xs.iter().enumerate() {
+                    let [r, g, b, _] = image.get_pixel(*x as u32, *y as u32).0;
+                    grid[(i / SAMPLES) * grid_width + j / SAMPLES] += luma(r, g, b);
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Accumulate sampled luma of an 8-bit interleaved RGB(A) buffer into the grid.
+fn downscale(
+    data: &[u8],
+    width: u32,
+    channels: usize,
+    xs: &[usize],
+    ys: &[usize],
+    grid_width: usize,
+    grid: &mut [u16],
+) {
+    let stride = width as usize * channels;
+    for (i, y) in ys.iter().enumerate() {
+        let row = &data[y * stride..(y + 1) * stride];
+        let cells = &mut grid[(i / SAMPLES) * grid_width..(i / SAMPLES + 1) * grid_width];
+        for (cell, offsets) in cells.iter_mut().zip(xs.chunks_exact(SAMPLES)) {
+            *cell += offsets
+                .iter()
+                .map(|&x| {
+                    let o = x * channels;
+                    luma(row[o], row[o + 1], row[o + 2])
+                })
+                .sum::<u16>();
+        }
+    }
+}
+
+/// Evenly spaced sample coordinates at the center of each sub-cell.
+fn sample_offsets(size: u32, offsets: &mut [usize]) -> &[usize] {
+    let (size, n) = (size as usize, offsets.len());
+    for (i, offset) in offsets.iter_mut().enumerate() {
+        *offset = ((2 * i + 1) * size) / (2 * n);
+    }
+    offsets
+}
+
+/// Integer BT.601 luma, 0-255.
+fn luma(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, _| {
+            let v = (x * 255 / width) as u8;
+            Rgb([v, v, v])
+        })
+    }
+
+    #[test]
+    fn test_dhash_identical() {
+        // Two identical images should have hamming distance of 0
+        let img = DynamicImage::new_rgba8(100, 100);
+        let hash1 = dhash(&img);
+        let hash2 = dhash(&img);
+        assert_eq!(hamming_distance(hash1, hash2), 0);
+    }
+
+    #[test]
+    fn test_dhash_gradient() {
+        let img = DynamicImage::ImageRgb8(gradient(640, 480));
+        assert_eq!(dhash(&img), u64::MAX);
+        // Fast paths and the generic fallback agree
+        assert_eq!(dhash(&DynamicImage::ImageRgba8(img.to_rgba8())), u64::MAX);
+        assert_eq!(dhash(&DynamicImage::ImageRgb16(img.to_rgb16())), u64::MAX);
+    }
+
+    #[test]
+    fn test_hash_algorithms() {
+        let img = DynamicImage::ImageRgb8(gradient(640, 480));
+        // Text-like detail: a few dark lines in one corner
+        let mut text = gradient(640, 480);
+        for y in (20..120).step_by(12) {
+            for x in 20..300 {
+                text.put_pixel(x, y, Rgb([0, 0, 0]));
+                text.put_pixel(x, y + 1, Rgb([0, 0, 0]));
+            }
+        }
+        let text = DynamicImage::ImageRgb8(text);
+        for algorithm in [
+            HashAlgorithm::Dhash,
+            HashAlgorithm::Ahash,
+            HashAlgorithm::Phash,
+            HashAlgorithm::Whash,
+        ] {
+            let hasher = algorithm.hasher();
+            assert_eq!(hasher.hash(&img), hasher.hash(&img), "{}", hasher.name());
+            assert_eq!(hasher.hash(&DynamicImage::new_rgb8(0, 0)), 0);
+        }
+        // The DCT hash sees the lines the gradient-based dhash misses
+        let distance = |algorithm: HashAlgorithm| {
+            let hasher = algorithm.hasher();
+            hamming_distance(hasher.hash(&img), hasher.hash(&text))
+        };
+        assert!(distance(HashAlgorithm::Phash) > distance(HashAlgorithm::Dhash));
+    }
+
+    #[test]
+    fn test_tile_hashes() {
+        let img = DynamicImage::ImageRgb8(gradient(640, 480));
+        // A clock-sized change in the bottom right tile
+        let mut clock = gradient(640, 480);
+        for y in 440..470 {
+            for x in 560..630 {
+                clock.put_pixel(x, y, Rgb([255, 255, 255]));
+            }
+        }
+        let clock = DynamicImage::ImageRgb8(clock);
+        let hasher = HashAlgorithm::Dhash.hasher();
+        let before = tile_hashes(hasher.as_ref(), &img, 4);
+        let after = tile_hashes(hasher.as_ref(), &clock, 4);
+        assert_eq!(before.len(), 16);
+        let changed: Vec<usize> = (0..16).filter(|&i| before[i] != after[i]).collect();
+        assert_eq!(changed, vec![15]);
+        assert_eq!(
+            tile_hashes(hasher.as_ref(), &DynamicImage::new_rgb8(0, 0), 4).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_color_histogram() {
+        let dark = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 64, Rgb([20, 20, 20])));
+        let mut red = RgbImage::from_pixel(64, 64, Rgb([20, 20, 20]));
+        for x in 0..16 {
+            for y in 0..64 {
+                red.put_pixel(x, y, Rgb([200, 20, 20]));
+            }
+        }
+        let red = DynamicImage::ImageRgb8(red);
+
+        let histogram = color_histogram(&dark, 4);
+        assert_eq!(histogram.len(), 64);
+        assert_eq!(histogram[0], 1.0);
+        assert_eq!(histogram_distance(&histogram, &histogram), 0.0);
+        // A quarter of the frame turned red
+        let distance = histogram_distance(&histogram, &color_histogram(&red, 4));
+        assert!((distance - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let dark = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 64, Rgb([0, 0, 0])));
+        let mut corner = RgbImage::from_pixel(64, 64, Rgb([0, 0, 0]));
+        for x in 32..64 {
+            for y in 0..32 {
+                corner.put_pixel(x, y, Rgb([255, 255, 255]));
+            }
+        }
+        let corner = DynamicImage::ImageRgb8(corner);
+
+        let previous = heatmap_luma(&dark, 2);
+        assert_eq!(previous.len(), 64);
+        let map = heatmap(&previous, &heatmap_luma(&corner, 2));
+        assert_eq!(map, vec![vec![0, 255], vec![0, 0]]);
+        assert!(heatmap(&previous, &heatmap_luma(&corner, 4)).is_empty());
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(0b0000, 0b0000), 0);
+        assert_eq!(hamming_distance(0b0001, 0b0000), 1);
+        assert_eq!(hamming_distance(0b1111, 0b0000), 4);
+        assert_eq!(hamming_distance(0xFF, 0x00), 8);
+    }
+}
@@ -0,0 +1,86 @@
+//! HDR tone mapping.
+//!
+//! On a display in HDR mode, Windows composes the desktop in scRGB: linear
+//! light with sRGB primaries where 1.0 is 80 nits. SDR content sits at the
+//! user's SDR white level and highlights reach the display's peak. Read as
+//! 8-bit, the desktop comes out washed out and highlights clip, so HDR
+//! captures are read in floating point and tone mapped to sRGB instead.
+
+/// Linear level (relative to SDR white) above which highlights are compressed.
+const KNEE: f32 = 0.8;
+
+/// scRGB to 8-bit sRGB tone mapping for one display.
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMap {
+    /// Factor from scRGB to linear light relative to SDR white.
+    scale: f32,
+    /// Display peak relative to SDR white, above `KNEE`.
+    peak: f32,
+}
+
+impl ToneMap {
+    /// Tone map for a display showing SDR white at `sdr_white_nits` and
+    /// reaching `peak_nits`.
+    pub fn new(sdr_white_nits: f32, peak_nits: f32) -> Self {
+        let sdr_white_nits = sdr_white_nits.max(1.0);
+        Self {
+            scale: 80.0 / sdr_white_nits,
+            peak: (peak_nits / sdr_white_nits).max(1.0),
+        }
+    }
+
+    /// Map one scRGB pixel to sRGB.
+    ///
+    /// Levels up to `KNEE` pass through; above it the brightest channel is
+    /// compressed with an extended Reinhard curve that reaches 1.0 at the
+    /// display peak, and the other channels are scaled along to keep the hue.
+    /// Colors outside the sRGB gamut (negative channels) are clamped.
+    pub fn map(&self, rgb: [f32; 3]) -> [u8; 3] {
+        let linear = rgb.map(|v| (v * self.scale).max(0.0));
+        let max = linear[0].max(linear[1]).max(linear[2]);
+        let factor = if max > KNEE {
+            let x = (max - KNEE) / (1.0 - KNEE);
+            let white = (self.peak - KNEE) / (1.0 - KNEE);
+            let compressed = x * (1.0 + x / (white * white)) / (1.0 + x);
+            (KNEE + (1.0 - KNEE) * compressed.min(1.0)) / max
+        } else {
+            1.0
+        };
+        linear.map(|v| (srgb_encode(v * factor) * 255.0).round() as u8)
+    }
+}
+
+/// The sRGB transfer function for a linear value, clamped to 0..=1.
+fn srgb_encode(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_map() {
+        let tone_map = ToneMap::new(200.0, 1000.0);
+        let white = 200.0 / 80.0;
+        assert_eq!(tone_map.map([0.0, 0.0, 0.0]), [0, 0, 0]);
+        assert_eq!(tone_map.map([-0.5, 0.0, 0.0]), [0, 0, 0]);
+        // SDR mid-grey passes through unchanged
+        assert_eq!(tone_map.map([0.216 * white; 3]), [128, 128, 128]);
+        // SDR white stays near white, highlights stay distinct up to the peak
+        let sdr = tone_map.map([white; 3])[0];
+        let highlight = tone_map.map([2.0 * white; 3])[0];
+        assert!(sdr >= 235);
+        assert!(highlight > sdr && highlight < 255);
+        assert_eq!(tone_map.map([5.0 * white; 3]), [255, 255, 255]);
+        assert_eq!(tone_map.map([50.0 * white; 3]), [255, 255, 255]);
+        // Hue is kept for a bright red
+        let [r, g, b] = tone_map.map([4.0 * white, 0.0, 0.0]);
+        assert!(r > 200 && g == 0 && b == 0);
+    }
+}
@@ -0,0 +1,125 @@
+//! Global hotkeys.
+//!
+//! Registers system-wide shortcuts that toggle the pause state, pause for a
+//! while or request an immediate capture through the same `ControlHandle` as
+//! the control channel.
+//! Requires the `hotkeys` feature; supported on Windows and on Linux under X11.
+
+use crate::config::HotkeyConfig;
+use crate::control::ControlHandle;
+use anyhow::{Error, Result};
+
+/// Register the configured hotkeys on a background thread.
+#[cfg(feature = "hotkeys")]
+pub fn start(config: &HotkeyConfig, handle: ControlHandle) -> Result<(), Error> {
+    use global_hotkey::hotkey::HotKey;
+    use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+    use std::sync::mpsc;
+    use tracing::info;
+
+    if cfg!(target_os = "macos") {
+        // The manager needs the main thread's run loop, which tokio occupies
+        anyhow::bail!("Global hotkeys are not supported on macOS");
+    }
+
+    let parse = |hotkey: &Option<String>| {
+        hotkey
+            .as_deref()
+            .map(|s| {
+                s.parse::<HotKey>()
+                    .map_err(|e| anyhow::anyhow!("Invalid hotkey {:?}: {}", s, e))
+            })
+            .transpose()
+    };
+    let pause = parse(&config.pause)?;
+    let timed_pause = parse(&config.timed_pause)?;
+    let capture = parse(&config.capture)?;
+    let hotkeys: Vec<HotKey> = pause
+        .iter()
+        .chain(timed_pause.iter())
+        .chain(capture.iter())
+        .copied()
+        .collect();
+    if hotkeys.is_empty() {
+        return Ok(());
+    }
+
+    let (pause_id, capture_id) = (pause.map(|h| h.id()), capture.map(|h| h.id()));
+    let timed_pause_id = timed_pause.map(|h| h.id());
+    let minutes = config.timed_pause_minutes.max(1);
+    GlobalHotKeyEvent::set_event_handler(Some(move |event: GlobalHotKeyEvent| {
+        if event.state() != HotKeyState::Pressed {
+            return;
+        }
+        if Some(event.id()) == pause_id {
+            handle.set_paused(!handle.is_paused());
+        } else if Some(event.id()) == timed_pause_id {
+            if handle.is_paused() {
+                handle.set_paused(false);
+            } else {
+                handle.pause_for(minutes);
+            }
+        } else if Some(event.id()) == capture_id && !handle.request_capture() {
+            info!("Capture hotkey ignored while paused");
+        }
+    }));
+
+    // On Windows the manager must live on a thread running a message loop
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("hotkeys".to_string())
+        .spawn(move || {
+            let registered = GlobalHotKeyManager::new()
+                .and_then(|manager| manager.register_all(&hotkeys).map(|()| manager));
+            let manager = match registered {
+                Ok(manager) => manager,
+                Err(e) => {
+                    let _ = tx.send(Err(anyhow::anyhow!("Failed to register hotkeys: {}", e)));
+                    return;
+                }
+            };
+            let _ = tx.send(Ok(()));
+            run_event_loop();
+            drop(manager);
+        })?;
+    rx.recv()??;
+    info!(
+        pause = ?config.pause,
+        timed_pause = ?config.timed_pause,
+        capture = ?config.capture,
+        "Global hotkeys registered"
+    );
+    Ok(())
+}
+
+#[cfg(not(feature = "hotkeys"))]
+pub fn start(_config: &HotkeyConfig, _handle: ControlHandle) -> Result<(), Error> {
+    anyhow::bail!("Global hotkeys are not compiled in, rebuild with --features hotkeys")
+}
+
+/// Keep the thread owning the manager alive, pumping window messages where needed.
+#[cfg(all(feature = "hotkeys", windows))]
+fn run_event_loop() {
+    use windows::Win32::UI::WindowsAndMessaging::{
+        DispatchMessageW, GetMessageW, MSG, TranslateMessage,
+    };
+
+    let mut msg = MSG::default();
+    // SAFETY: msg is a valid out pointer for the duration of each call
+    unsafe {
+        // GetMessageW returns 0 on WM_QUIT and -1 on error
+        while GetMessageW(&mut msg, None, 0, 0).0 > 0 {
+            let _ = TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+}
+
+/// Keep the thread owning the manager alive; X11 events are read on the
+/// crate's own thread.
+#[cfg(all(feature = "hotkeys", not(windows)))]
+fn run_event_loop() {
+    loop {
+        std::thread::park();
+    }
+}
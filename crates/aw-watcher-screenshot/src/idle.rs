@@ -0,0 +1,134 @@
+//! Time since the last keyboard/mouse input.
+//!
+//! Queried from the OS rather than by hooking input events: XScreenSaver on
+//! X11 (loaded at runtime, so Wayland-only systems still start), the last
+//! input tick on Windows and the combined session event source on macOS.
+
+use anyhow::{Error, Result};
+use std::time::Duration;
+
+/// Handle for querying the user's idle time.
+///
+/// Can be moved between threads but not shared; wrap it in a `Mutex` to
+/// query it from several.
+pub struct IdleTime {
+    #[cfg(target_os = "linux")]
+    x11: x11::Connection,
+}
+
+impl IdleTime {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            #[cfg(target_os = "linux")]
+            x11: x11::Connection::open()?,
+        })
+    }
+
+    /// Time since the last keyboard or mouse input.
+    pub fn get(&self) -> Result<Duration, Error> {
+        self.query()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn query(&self) -> Result<Duration, Error> {
+        self.x11.idle()
+    }
+
+    #[cfg(windows)]
+    fn query(&self) -> Result<Duration, Error> {
+        use windows::Win32::System::SystemInformation::GetTickCount;
+        use windows::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+        let mut info = LASTINPUTINFO {
+            cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        if !unsafe { GetLastInputInfo(&mut info) }.as_bool() {
+            return Err(anyhow::anyhow!("GetLastInputInfo failed"));
+        }
+        // Both are milliseconds since boot and wrap together
+        let idle_ms = unsafe { GetTickCount() }.wrapping_sub(info.dwTime);
+        Ok(Duration::from_millis(idle_ms as u64))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn query(&self) -> Result<Duration, Error> {
+        #[link(name = "CoreGraphics", kind = "framework")]
+        unsafe extern "C" {
+            fn CGEventSourceSecondsSinceLastEventType(state: i32, event_type: u32) -> f64;
+        }
+        const COMBINED_SESSION_STATE: i32 = 0;
+        const ANY_INPUT_EVENT: u32 = u32::MAX;
+
+        let seconds = unsafe {
+            CGEventSourceSecondsSinceLastEventType(COMBINED_SESSION_STATE, ANY_INPUT_EVENT)
+        };
+        Ok(Duration::from_secs_f64(seconds.max(0.0)))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    fn query(&self) -> Result<Duration, Error> {
+        Err(anyhow::anyhow!(
+            "Idle time is not supported on this platform"
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use anyhow::{Error, Result};
+    use std::time::Duration;
+    use x11_dl::xlib::{Display, Xlib};
+    use x11_dl::xss::Xss;
+
+    pub struct Connection {
+        xlib: Xlib,
+        xss: Xss,
+        display: *mut Display,
+    }
+
+    impl Connection {
+        pub fn open() -> Result<Self, Error> {
+            let xlib = Xlib::open().map_err(|e| anyhow::anyhow!("Failed to load Xlib: {}", e))?;
+            let xss =
+                Xss::open().map_err(|e| anyhow::anyhow!("Failed to load XScreenSaver: {}", e))?;
+            let display = unsafe { (xlib.XOpenDisplay)(std::ptr::null()) };
+            if display.is_null() {
+                return Err(anyhow::anyhow!(
+                    "Failed to open X display (idle time requires X11 or XWayland)"
+                ));
+            }
+            Ok(Self { xlib, xss, display })
+        }
+
+        pub fn idle(&self) -> Result<Duration, Error> {
+            unsafe {
+                let info = (self.xss.XScreenSaverAllocInfo)();
+                if info.is_null() {
+                    return Err(anyhow::anyhow!("XScreenSaverAllocInfo failed"));
+                }
+                let root = (self.xlib.XDefaultRootWindow)(self.display);
+                let status = (self.xss.XScreenSaverQueryInfo)(self.display, root, info);
+                let idle = (*info).idle;
+                (self.xlib.XFree)(info.cast());
+                if status == 0 {
+                    return Err(anyhow::anyhow!("XScreenSaverQueryInfo failed"));
+                }
+                // c_ulong is 32 bits on 32-bit targets
+                #[allow(clippy::unnecessary_cast)]
+                Ok(Duration::from_millis(idle as u64))
+            }
+        }
+    }
+
+    // Xlib calls on a display are safe from any thread as long as they are
+    // not concurrent, which `&mut`/`Mutex` access to `IdleTime` guarantees
+    // since `Connection` is not `Sync`.
+    unsafe impl Send for Connection {}
+
+    impl Drop for Connection {
+        fn drop(&mut self) {
+            unsafe { (self.xlib.XCloseDisplay)(self.display) };
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! candle runtime for image models stored as safetensors.
+
+use super::{Device, ModelInput, ModelRunner, image_to_chw};
+use crate::config::ModelConfig;
+use anyhow::{Context, Error, Result};
+use candle_core::{DType, Module, Tensor};
+use candle_nn::{Func, VarBuilder};
+use candle_transformers::models::resnet;
+
+/// Runs a ResNet classifier or feature extractor loaded from safetensors.
+pub struct CandleRunner {
+    config: ModelConfig,
+    device: candle_core::Device,
+    model: Func<'static>,
+}
+
+impl CandleRunner {
+    pub fn load(config: &ModelConfig, device: Device) -> Result<Self, Error> {
+        let device = match device {
+            Device::Cpu => candle_core::Device::Cpu,
+            Device::Cuda(ordinal) => candle_core::Device::new_cuda(ordinal)?,
+            Device::Metal(ordinal) => candle_core::Device::new_metal(ordinal)?,
+        };
+
+        // Safety: the weights file must not be modified while it is mapped.
+        let vb =
+            unsafe { VarBuilder::from_mmaped_safetensors(&[&config.path], DType::F32, &device) }
+                .with_context(|| {
+                    format!("Failed to load weights from {}", config.path.display())
+                })?;
+
+        let architecture = config
+            .architecture
+            .as_deref()
+            .context("candle models require an architecture")?;
+        let model = match (architecture, config.num_classes) {
+            ("resnet18", Some(classes)) => resnet::resnet18(classes, vb)?,
+            ("resnet18", None) => resnet::resnet18_no_final_layer(vb)?,
+            ("resnet34", Some(classes)) => resnet::resnet34(classes, vb)?,
+            ("resnet34", None) => resnet::resnet34_no_final_layer(vb)?,
+            ("resnet50", Some(classes)) => resnet::resnet50(classes, vb)?,
+            ("resnet50", None) => resnet::resnet50_no_final_layer(vb)?,
+            (other, _) => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported candle architecture {:?}",
+                    other
+                ));
+            }
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            device,
+            model,
+        })
+    }
+}
+
+impl ModelRunner for CandleRunner {
+    fn run(&self, inputs: &[ModelInput]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut data = Vec::new();
+        for input in inputs {
+            match input {
+                ModelInput::Image(image) => data.extend(image_to_chw(image, &self.config)),
+                ModelInput::Text(_) => {
+                    return Err(anyhow::anyhow!("candle models only accept image input"));
+                }
+            }
+        }
+
+        let shape = (
+            inputs.len(),
+            3,
+            self.config.input_height as usize,
+            self.config.input_width as usize,
+        );
+        let batch = Tensor::from_vec(data, shape, &self.device)?;
+        let output = self.model.forward(&batch)?.flatten_from(1)?;
+        Ok(output.to_vec2::<f32>()?)
+    }
+}
@@ -0,0 +1,164 @@
+//! Local model inference.
+//!
+//! Stages that need a neural network (classification, detection, ...) go
+//! through the `ModelRunner` trait instead of embedding their own ML stack.
+//! Models are declared once under `[models.<name>]` and loaded lazily by the
+//! shared `ModelRegistry`, which also takes care of batching and device
+//! selection. Runtimes are compiled in through the `candle` and `onnx` features.
+
+#[cfg(feature = "candle")]
+mod candle;
+#[cfg(feature = "onnx")]
+mod onnx;
+
+use crate::config::ModelConfig;
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Device a model runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Cpu,
+    Cuda(usize),
+    Metal(usize),
+}
+
+impl Device {
+    /// Parse "cpu", "cuda", "cuda:N", "metal" or "metal:N".
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let (kind, ordinal) = s.split_once(':').unwrap_or((s, "0"));
+        let ordinal = ordinal
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid device ordinal in {:?}", s))?;
+        match kind {
+            "cpu" => Ok(Device::Cpu),
+            "cuda" => Ok(Device::Cuda(ordinal)),
+            "metal" => Ok(Device::Metal(ordinal)),
+            _ => Err(anyhow::anyhow!("Unknown device {:?}", s)),
+        }
+    }
+}
+
+/// A single model input.
+#[cfg_attr(not(any(feature = "candle", feature = "onnx")), allow(dead_code))]
+pub enum ModelInput<'a> {
+    Image(&'a DynamicImage),
+    /// Supported by the runtimes, though no stage feeds text yet.
+    #[allow(dead_code)]
+    Text(&'a str),
+}
+
+/// A loaded model that maps a batch of inputs to one output vector each.
+pub trait ModelRunner: Send + Sync {
+    fn run(&self, inputs: &[ModelInput]) -> Result<Vec<Vec<f32>>, Error>;
+}
+
+/// A loaded model together with its batching settings.
+pub struct Model {
+    name: String,
+    runner: Box<dyn ModelRunner>,
+    batch_size: usize,
+}
+
+impl Model {
+    /// Run the model on any number of inputs, split into batches.
+    pub fn run(&self, inputs: &[ModelInput]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut outputs = Vec::with_capacity(inputs.len());
+        for batch in inputs.chunks(self.batch_size.max(1)) {
+            let batch_outputs = self.runner.run(batch)?;
+            if batch_outputs.len() != batch.len() {
+                return Err(anyhow::anyhow!(
+                    "Model {} returned {} outputs for {} inputs",
+                    self.name,
+                    batch_outputs.len(),
+                    batch.len()
+                ));
+            }
+            outputs.extend(batch_outputs);
+        }
+        Ok(outputs)
+    }
+}
+
+/// Lazily loads the configured models and shares them between stages.
+pub struct ModelRegistry {
+    configs: HashMap<String, ModelConfig>,
+    loaded: Mutex<HashMap<String, Arc<Model>>>,
+}
+
+impl ModelRegistry {
+    pub fn new(configs: HashMap<String, ModelConfig>) -> Self {
+        Self {
+            configs,
+            loaded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get a model by name, loading it on first use.
+    pub fn get(&self, name: &str) -> Result<Arc<Model>, Error> {
+        let mut loaded = self
+            .loaded
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Model registry poisoned"))?;
+        if let Some(model) = loaded.get(name) {
+            return Ok(model.clone());
+        }
+
+        let config = self
+            .configs
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Model {:?} is not configured", name))?;
+        let device = Device::parse(&config.device)?;
+        info!(model = name, runtime = ?config.runtime, ?device, "Loading model");
+
+        let model = Arc::new(Model {
+            name: name.to_string(),
+            runner: load_runner(config, device)?,
+            batch_size: config.batch_size,
+        });
+        loaded.insert(name.to_string(), model.clone());
+        Ok(model)
+    }
+}
+
+#[allow(unused_variables)]
+fn load_runner(config: &ModelConfig, device: Device) -> Result<Box<dyn ModelRunner>, Error> {
+    match config.runtime {
+        #[cfg(feature = "candle")]
+        crate::config::ModelRuntime::Candle => {
+            Ok(Box::new(candle::CandleRunner::load(config, device)?))
+        }
+        #[cfg(feature = "onnx")]
+        crate::config::ModelRuntime::Onnx => Ok(Box::new(onnx::OnnxRunner::load(config, device)?)),
+        #[allow(unreachable_patterns)]
+        runtime => Err(anyhow::anyhow!(
+            "Model runtime {:?} is not compiled in, rebuild with the matching feature",
+            runtime
+        )),
+    }
+}
+
+/// Resize an image to the model input size and convert it to normalized
+/// CHW float data.
+#[cfg(any(feature = "candle", feature = "onnx"))]
+fn image_to_chw(image: &DynamicImage, config: &ModelConfig) -> Vec<f32> {
+    let resized = image
+        .resize_exact(
+            config.input_width,
+            config.input_height,
+            image::imageops::FilterType::Triangle,
+        )
+        .to_rgb8();
+
+    let plane = (config.input_width * config.input_height) as usize;
+    let mut data = vec![0.0; 3 * plane];
+    for (i, pixel) in resized.pixels().enumerate() {
+        for c in 0..3 {
+            data[c * plane + i] = (pixel[c] as f32 / 255.0 - config.mean[c]) / config.std[c];
+        }
+    }
+    data
+}
@@ -0,0 +1,104 @@
+//! ONNX Runtime backend.
+//!
+//! The ONNX Runtime shared library is loaded at runtime (set `ORT_DYLIB_PATH`
+//! if it is not on the library search path).
+
+use super::{Device, ModelInput, ModelRunner, image_to_chw};
+use crate::config::ModelConfig;
+use anyhow::{Error, Result};
+use ort::execution_providers::{CUDAExecutionProvider, CoreMLExecutionProvider};
+use ort::session::Session;
+use ort::value::Tensor;
+use std::sync::Mutex;
+
+/// Runs an ONNX model with a single input and uses its first output.
+///
+/// Image batches are fed as float `[N, 3, H, W]`; text is fed as byte-level
+/// token ids `[N, L]` (i64, zero padded).
+pub struct OnnxRunner {
+    config: ModelConfig,
+    session: Mutex<Session>,
+}
+
+impl OnnxRunner {
+    pub fn load(config: &ModelConfig, device: Device) -> Result<Self, Error> {
+        let mut builder = Session::builder()?;
+        builder = match device {
+            Device::Cpu => builder,
+            Device::Cuda(ordinal) => {
+                builder.with_execution_providers([CUDAExecutionProvider::default()
+                    .with_device_id(ordinal as i32)
+                    .build()])?
+            }
+            Device::Metal(_) => {
+                builder.with_execution_providers([CoreMLExecutionProvider::default().build()])?
+            }
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            session: Mutex::new(builder.commit_from_file(&config.path)?),
+        })
+    }
+
+    fn image_tensor(&self, inputs: &[ModelInput]) -> Result<Tensor<f32>, Error> {
+        let mut data = Vec::new();
+        for input in inputs {
+            let ModelInput::Image(image) = input else {
+                return Err(anyhow::anyhow!(
+                    "Cannot mix image and text inputs in a batch"
+                ));
+            };
+            data.extend(image_to_chw(image, &self.config));
+        }
+        let shape = [
+            inputs.len(),
+            3,
+            self.config.input_height as usize,
+            self.config.input_width as usize,
+        ];
+        Ok(Tensor::from_array((shape, data))?)
+    }
+
+    fn text_tensor(&self, inputs: &[ModelInput]) -> Result<Tensor<i64>, Error> {
+        let mut texts = Vec::new();
+        for input in inputs {
+            let ModelInput::Text(text) = input else {
+                return Err(anyhow::anyhow!(
+                    "Cannot mix image and text inputs in a batch"
+                ));
+            };
+            texts.push(text.as_bytes());
+        }
+
+        let len = texts.iter().map(|t| t.len()).max().unwrap_or(0).max(1);
+        let mut data = vec![0i64; texts.len() * len];
+        for (row, text) in texts.iter().enumerate() {
+            for (col, byte) in text.iter().enumerate() {
+                data[row * len + col] = *byte as i64;
+            }
+        }
+        Ok(Tensor::from_array(([texts.len(), len], data))?)
+    }
+}
+
+impl ModelRunner for OnnxRunner {
+    fn run(&self, inputs: &[ModelInput]) -> Result<Vec<Vec<f32>>, Error> {
+        if inputs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut session = self
+            .session
+            .lock()
+            .map_err(|_| anyhow::anyhow!("ONNX session poisoned"))?;
+        let outputs = match inputs[0] {
+            ModelInput::Image(_) => session.run(ort::inputs![self.image_tensor(inputs)?])?,
+            ModelInput::Text(_) => session.run(ort::inputs![self.text_tensor(inputs)?])?,
+        };
+
+        let (_, data) = outputs[0].try_extract_tensor::<f32>()?;
+        let width = data.len() / inputs.len();
+        Ok(data.chunks(width.max(1)).map(|c| c.to_vec()).collect())
+    }
+}
@@ -1,63 +1,166 @@
+mod backend;
+mod command;
+mod composite;
 mod config;
+mod control;
+mod cpu;
+mod cursor;
+mod encode;
 mod event;
+mod guard;
+mod hash;
+// Only the DXGI backend reads HDR surfaces
+#[cfg_attr(not(windows), allow(dead_code))]
+mod hdr;
+mod hotkey;
+mod idle;
+mod inference;
+mod mask;
+mod monitor;
+mod network;
+mod pool;
+mod power;
+mod priority;
+mod process;
+mod remote;
+mod retention;
+mod schedule;
+mod shedding;
+mod status;
+mod timezone;
+mod window;
 mod worker;
 mod worker_impl;
 
 use crate::event::{AwEvent, CaptureEvent, ImageEvent};
 use crate::worker::{Consumer, Processor, Producer};
-use anyhow::{Error, Result};
+use anyhow::{Context, Error, Result};
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Path to configuration file
-    #[arg(short, long, default_value = "config.toml")]
+    #[arg(short, long, default_value = "config.toml", global = true)]
     config: PathBuf,
+
+    /// Resolve the config file and all paths relative to the executable
+    /// directory and write nothing outside it
+    #[arg(long, global = true)]
+    portable: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Assemble cached frames into a timelapse video per monitor
+    Timelapse(command::timelapse::TimelapseArgs),
+    /// Cluster recurring screens and label events with cluster ids
+    Cluster(command::cluster::ClusterArgs),
+    /// Measure capture, hashing, encoding and upload cost on this machine
+    Bench(command::bench::BenchArgs),
+    /// Pause, resume or trigger capture in a running watcher
+    Control(command::control::ControlArgs),
+    /// Delete cached files, S3 objects and aw-server events in a time range
+    Purge(command::purge::PurgeArgs),
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let args = Args::parse();
 
+    let exe_dir = config::exe_dir();
+    let config_path = if args.portable {
+        exe_dir.join(&args.config)
+    } else {
+        args.config.clone()
+    };
+    // Loaded first: in portable mode the log goes next to the executable
+    let loaded = crate::config::Config::load_from_file(&config_path);
+    let portable = args.portable || loaded.as_ref().is_ok_and(|config| config.portable);
+
     // Initialize tracing with EnvFilter
     // Default: show info level, but filter out noisy xcap platform errors
     // Override with RUST_LOG env var, e.g.: RUST_LOG=debug,xcap=off
     use tracing_subscriber::EnvFilter;
+    use tracing_subscriber::fmt::writer::MakeWriterExt;
 
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("info,xcap::platform=off"));
 
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_env_filter(filter)
         .with_thread_ids(true)
         .with_thread_names(true)
         .with_file(true)
-        .with_line_number(true)
-        .init();
+        .with_line_number(true);
+    if portable {
+        let log_path = exe_dir.join(config::PORTABLE_LOG_FILE);
+        let log_file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .with_context(|| format!("Failed to open log file {}", log_path.display()))?;
+        subscriber
+            .with_ansi(false)
+            .with_writer(std::io::stdout.and(std::sync::Mutex::new(log_file)))
+            .init();
+    } else {
+        subscriber.init();
+    }
 
     info!("Starting capture service...");
 
-    let config = match crate::config::Config::load_from_file(&args.config) {
+    let mut config = match loaded {
         Ok(c) => c,
         Err(e) => {
             info!(
                 "Failed to load config from {:?}: {}. Using defaults.",
-                args.config, e
+                config_path, e
             );
             crate::config::Config::default_config()
         }
     };
+    if portable {
+        info!(dir = %exe_dir.display(), "Portable mode, keeping all files next to the executable");
+        config.make_portable(&exe_dir);
+    }
 
     info!("Config loaded, aw_server: {:?}", config.aw_server);
 
+    if let Some(command) = args.command {
+        return match command {
+            Command::Timelapse(timelapse_args) => {
+                tokio::task::spawn_blocking(move || {
+                    command::timelapse::run(&config, timelapse_args)
+                })
+                .await?
+            }
+            Command::Cluster(cluster_args) => command::cluster::run(&config, cluster_args).await,
+            Command::Bench(bench_args) => command::bench::run(&config, bench_args).await,
+            Command::Control(control_args) => command::control::run(&config, control_args).await,
+            Command::Purge(purge_args) => command::purge::run(&config, purge_args).await,
+        };
+    }
+
+    if config.pipeline.low_priority {
+        priority::init_low_priority_pool()?;
+    }
+
     // Create channels for the worker pipeline
     // Flow: Capture -> Filter -> Cache (ToWebp) -> S3 -> AwServer
+    // Remote agent: Capture -> Filter -> RemoteSender
+    // Remote server: RemoteReceiver -> Cache (ToWebp) -> S3 -> AwServer
     let cancel_token = CancellationToken::new();
 
     // Setup Ctrl-C handler to trigger graceful shutdown
@@ -71,65 +174,556 @@ async fn main() -> Result<(), Error> {
     });
 
     let (tx_capture, rx_capture) = mpsc::channel::<CaptureEvent>(10);
-    let (tx_filter, rx_filter) = mpsc::channel::<CaptureEvent>(10);
     let (tx_cache, rx_cache) = mpsc::channel::<ImageEvent>(10);
     let (tx_s3, rx_s3) = mpsc::channel::<AwEvent>(10);
 
     // Create processors
-    let capture_producer =
-        worker_impl::capture::TimerCaptureProducer::new(config.trigger, cancel_token.clone())?;
-    let filter_processor = worker_impl::filter::FilterProcessor::new(config.capture.clone());
-    let cache_processor = worker_impl::cache::ToWebpProcessor::new(config.cache.clone())?;
-    let aw_processor =
-        worker_impl::awserver::AwServerProcessor::new(config.aw_server.clone()).await?;
+    let time_policy = timezone::TimePolicy::new(config.time.local_time);
+    let capture_backend = backend::from_kind(
+        config.capture.backend,
+        config
+            .capture
+            .hdr_tone_mapping
+            .then_some(config.capture.sdr_white_nits),
+    )?;
+    let mut capture_producer = worker_impl::capture::TimerCaptureProducer::new(
+        config.trigger.clone(),
+        cancel_token.clone(),
+    )?
+    .with_time_policy(time_policy)
+    .with_monitor_settings(config.monitors.clone())
+    .with_monitor_filter(monitor::MonitorFilter::new(
+        &config.capture.include_monitors,
+        &config.capture.exclude_monitors,
+    )?)
+    .with_primary_only(config.capture.primary_only)
+    .with_active_monitor_only(config.capture.active_monitor_only)
+    .with_composite(config.capture.composite)
+    .with_max_dimension(config.capture.max_dimension)
+    .with_backend(capture_backend.clone());
+    if config.capture.anonymize_monitor_names {
+        let key = monitor::salt_key(config.capture.monitor_name_salt.as_deref())?;
+        capture_producer = capture_producer.with_anonymized_names(key);
+    }
+    if config.capture.include_cursor {
+        capture_producer = capture_producer.with_cursor(config.capture.highlight_clicks);
+    }
+    if config.capture.probe {
+        info!("Probing monitors for changes before capturing them");
+        capture_producer =
+            capture_producer.with_probe(Duration::from_secs(config.capture.force_interval_secs));
+    }
+    let mut filter_processor = worker_impl::filter::FilterProcessor::new(config.capture.clone())?;
+    if let Some(threshold) = config.trigger.burst_threshold {
+        info!(threshold, "Capture bursts enabled");
+        let requests = Arc::new(tokio::sync::Notify::new());
+        capture_producer = capture_producer.with_burst(
+            requests.clone(),
+            config.trigger.burst_count,
+            Duration::from_millis(config.trigger.burst_interval_ms),
+        );
+        filter_processor = filter_processor.with_burst(threshold, requests);
+    }
+    if config.load_shedding.enabled {
+        info!("Load shedding enabled");
+        let reporter =
+            status::StatusReporter::new(&config.load_shedding.bucket_id, &config.aw_server)
+                .await?
+                .with_metered_deferral(&config.network);
+        let shedder = shedding::LoadShedder::new(
+            config.load_shedding.clone(),
+            config.trigger.interval_secs,
+            Some(reporter),
+        );
+        capture_producer = capture_producer.with_load_shedding(shedder);
+        filter_processor = filter_processor.with_load_shedding(&config.load_shedding);
+    }
+    let mut cache_processor = worker_impl::cache::ToWebpProcessor::new(config.cache.clone())?
+        .with_time_policy(time_policy);
+    if config.capture.dedup_mirrored {
+        cache_processor = cache_processor.with_mirror_dedup(config.capture.mirror_distance);
+    }
+    if config.s3.enabled
+        && let Some(encoder) = encode::upload_from_config(&config.cache, &config.s3)?
+    {
+        info!(format = encoder.extension(), "Encoding uploads separately");
+        cache_processor = cache_processor.with_upload_encoder(encoder);
+    }
+    let session = guard::session::SessionInfo::current();
+    info!(session = ?session, "Running in login session");
+    // Agents report nothing to aw-server themselves
+    let agent = config.remote.mode == config::RemoteMode::Agent;
+    let server = config.remote.mode == config::RemoteMode::Server;
+    let record_clips = config.clip.enabled && !agent && !server;
+    if config.clip.enabled && !record_clips {
+        warn!("Clips are not recorded in remote mode");
+    } else if record_clips {
+        info!(
+            threshold = config.clip.change_threshold,
+            "Clip recording enabled"
+        );
+        let requests = Arc::new(tokio::sync::Notify::new());
+        capture_producer = capture_producer.with_clips(
+            requests.clone(),
+            config.clip.frames(),
+            config.clip.frame_interval(),
+            Duration::from_secs(config.clip.cooldown_secs),
+        );
+        filter_processor = filter_processor.with_clips(config.clip.change_threshold, requests);
+    }
+    let mut aw_processor = if agent {
+        None
+    } else {
+        Some(
+            worker_impl::awserver::AwServerProcessor::new(config.aw_server.clone())
+                .await?
+                .with_session(session.clone())
+                .with_metered_deferral(&config.network),
+        )
+    };
+    let power_profile = if config.power.enabled {
+        info!("Battery-aware power profile enabled");
+        let reporter = status::StatusReporter::new(&config.power.bucket_id, &config.aw_server)
+            .await?
+            .with_metered_deferral(&config.network);
+        let profile = power::watch_profile(&config.power, Some(reporter));
+        capture_producer =
+            capture_producer.with_power_profile(profile.clone(), config.power.interval_factor);
+        cache_processor =
+            cache_processor.with_power_profile(profile.clone(), &config.cache, &config.power)?;
+        aw_processor = aw_processor.map(|processor| processor.with_power_profile(profile.clone()));
+        Some(profile)
+    } else {
+        None
+    };
+    if config.session.pause_when_inactive {
+        capture_producer =
+            capture_producer.with_guard(guard::session::SessionGuard::new(session.clone()));
+    }
+    if config.session.pause_when_locked {
+        capture_producer = capture_producer.with_guard(guard::session::LockGuard::new(
+            session,
+            Duration::from_secs(config.session.unlock_grace_secs),
+        ));
+    }
+    if let Some(afk_timeout) = config.trigger.afk_timeout_secs {
+        match guard::afk::AfkGuard::new(Duration::from_secs(afk_timeout)) {
+            Ok(afk_guard) => {
+                info!(afk_timeout, "Pausing capture while AFK");
+                capture_producer = capture_producer.with_guard(afk_guard);
+            }
+            Err(e) => error!(error = %e, "AFK detection unavailable, capturing while idle"),
+        }
+    }
+    if config.screen_share.enabled {
+        info!("Screen sharing detection enabled");
+        // ScreenCaptureKit's own stream would count as a screen watcher
+        let process_only = capture_backend.name() == "screencapturekit";
+        capture_producer = capture_producer.with_guard(guard::sharing::ScreenShareGuard::new(
+            &config.screen_share,
+            process_only,
+        ));
+    }
+    if config.cpu_load.enabled {
+        match guard::cpu::CpuLoadGuard::new(&config.cpu_load) {
+            Ok(cpu_guard) => {
+                info!(
+                    max_load_percent = config.cpu_load.max_load_percent,
+                    "Deferring capture under high CPU load"
+                );
+                capture_producer = capture_producer.with_guard(cpu_guard);
+            }
+            Err(e) => error!(error = %e, "CPU load unavailable, capturing regardless of load"),
+        }
+    }
+    let blocklist = guard::window::BlocklistGuard::new(&config.capture)?;
+    if !blocklist.is_empty() {
+        info!(
+            apps = config.capture.blocked_apps.len(),
+            titles = config.capture.blocked_titles.len(),
+            "Window blocklist configured"
+        );
+        capture_producer = capture_producer.with_guard(blocklist);
+    }
+    // Always on: password prompts are skipped whatever the other settings say
+    capture_producer = capture_producer.with_guard(guard::credential::CredentialPromptGuard);
+    if !config.apps.is_empty() {
+        info!(
+            apps = config.apps.len(),
+            "Per-application policies configured"
+        );
+        capture_producer =
+            capture_producer.with_app_policies(guard::app::AppPolicies::new(&config.apps)?);
+    }
+    let allowlist = guard::window::AllowlistGuard::new(&config.capture)?;
+    if !allowlist.is_empty() {
+        info!(
+            apps = config.capture.allowed_apps.len(),
+            titles = config.capture.allowed_titles.len(),
+            "Capturing only allowlisted windows"
+        );
+        capture_producer = capture_producer.with_guard(allowlist);
+    }
+    if !config.capture.blocked_processes.is_empty() {
+        info!(processes = ?config.capture.blocked_processes, "Process blocklist configured");
+        capture_producer = capture_producer.with_guard(guard::process::ProcessGuard::new(
+            &config.capture.blocked_processes,
+        ));
+    }
+    if config.incognito.enabled {
+        info!(
+            browsers = config.incognito.browsers.len(),
+            "Private browsing detection enabled"
+        );
+        capture_producer =
+            capture_producer.with_guard(guard::incognito::IncognitoGuard::new(&config.incognito)?);
+    }
+    if config.remote_desktop.enabled {
+        info!(
+            min_coverage = config.remote_desktop.min_coverage,
+            "Remote desktop detection enabled"
+        );
+        capture_producer = capture_producer.with_guard(
+            guard::remote_desktop::RemoteDesktopGuard::new(&config.remote_desktop),
+        );
+    }
+    if !config.dnd.apps.is_empty() {
+        info!(apps = ?config.dnd.apps, "Do-not-disturb apps configured");
+        capture_producer = capture_producer.with_guard(guard::dnd::DndAppGuard::new(&config.dnd));
+    }
+    if config.fullscreen.action != config::FullscreenAction::Capture {
+        info!(action = ?config.fullscreen.action, "Fullscreen app detection enabled");
+        capture_producer = capture_producer
+            .with_guard(guard::fullscreen::FullscreenGuard::new(&config.fullscreen));
+    }
+    if config.schedule.enabled {
+        info!(
+            days = ?config.schedule.days,
+            start = %config.schedule.start,
+            end = %config.schedule.end,
+            "Capturing only within working hours"
+        );
+        capture_producer =
+            capture_producer.with_schedule(schedule::Schedule::new(&config.schedule)?);
+    }
+    let hotkeys_enabled = config.hotkeys.pause.is_some()
+        || config.hotkeys.timed_pause.is_some()
+        || config.hotkeys.capture.is_some();
+    let control_enabled = config.control.enabled || config.control.http_enabled;
+    let low_fidelity = Arc::new(AtomicBool::new(config.low_fidelity.enabled));
+    if control_enabled || hotkeys_enabled {
+        let mut handle = control::ControlHandle::default().with_low_fidelity(low_fidelity.clone());
+        match status::StatusReporter::new(&config.control.bucket_id, &config.aw_server).await {
+            Ok(reporter) => {
+                handle = handle.with_reporter(reporter.with_metered_deferral(&config.network))
+            }
+            Err(e) => warn!(error = %e, "Pauses will not be reported to aw-server"),
+        }
+        if config.control.enabled {
+            let path = config
+                .control
+                .path
+                .clone()
+                .unwrap_or_else(control::default_path);
+            // Not joined with the workers: it only stops on cancellation
+            control::serve(path, handle.clone(), cancel_token.clone())?;
+        }
+        if config.control.http_enabled {
+            control::serve_http(&config.control, handle.clone(), cancel_token.clone())?;
+        }
+        if hotkeys_enabled && let Err(e) = hotkey::start(&config.hotkeys, handle.clone()) {
+            error!(error = %e, "Global hotkeys unavailable");
+        }
+        capture_producer = capture_producer
+            .with_capture_requests(handle.capture_requests())
+            .with_guard(handle);
+    }
+    if config.retention.enabled {
+        // Not joined with the workers: it only stops on cancellation
+        retention::serve(
+            config.retention.clone(),
+            Path::new(&config.cache.cache_dir),
+            config.s3.enabled.then_some(&config.s3),
+            time_policy,
+            cancel_token.clone(),
+        )?;
+    }
+    let meeting_redaction = if config.meeting.enabled {
+        info!(action = ?config.meeting.action, "Meeting detection enabled");
+        let reporter = status::StatusReporter::new(&config.meeting.bucket_id, &config.aw_server)
+            .await?
+            .with_metered_deferral(&config.network);
+        let meeting_guard =
+            guard::meeting::MeetingGuard::new(config.meeting.clone(), Some(reporter));
+        let redact = config.meeting.action == config::MeetingAction::Redact;
+        if redact {
+            capture_producer = capture_producer.with_meeting_state(meeting_guard.active_flag());
+        }
+        capture_producer = capture_producer.with_guard(meeting_guard);
+        redact
+    } else {
+        false
+    };
 
     // Start all workers with proper channel wiring
-    // Producer: TimerCaptureProducer -> tx_capture
-    let capture_handle = capture_producer.produce(tx_capture)?;
-    // Processor: rx_capture -> FilterProcessor -> tx_filter
-    let filter_handle = filter_processor.process(rx_capture, tx_filter)?;
-    // Processor: rx_filter -> ToWebpProcessor -> tx_cache
-    let cache_handle = cache_processor.process(rx_filter, tx_cache)?;
+    let mut handles = Vec::new();
+    // Producer: TimerCaptureProducer/RemoteReceiver -> tx_capture
+    if server {
+        info!("Remote server mode, receiving events from agents instead of capturing");
+        let receiver = worker_impl::remote::RemoteReceiver::new(
+            config.remote.address.clone(),
+            config.remote.token.clone(),
+            cancel_token.clone(),
+        )
+        .with_time_policy(time_policy)
+        .with_monitor_settings(config.monitors.clone());
+        handles.push(("Remote", receiver.produce(tx_capture)?));
+    } else {
+        handles.push(("Capture", capture_producer.produce(tx_capture)?));
+    }
+    // Processor: rx_capture -> FilterProcessor -> tx_filter (agents filter before sending)
+    let rx_filter = if server {
+        rx_capture
+    } else {
+        let (tx_filter, rx_filter) = mpsc::channel::<CaptureEvent>(10);
+        handles.push(("Filter", filter_processor.process(rx_capture, tx_filter)?));
+        rx_filter
+    };
 
-    // Processor: rx_cache -> S3Processor/Passthrough -> tx_s3
-    // Use PassthroughProcessor when S3 is disabled
-    let s3_handle = if config.s3.enabled {
-        info!("S3 upload enabled, using S3Processor");
-        let s3_processor = worker_impl::s3::S3Processor::new(config.s3.clone())?;
-        s3_processor.process(rx_cache, tx_s3)?
+    // Processor: rx_filter -> PrivacyRuleProcessor -> tx_privacy (optional)
+    let rx_filter = if !config.privacy_rules.is_empty() {
+        info!(
+            rules = config.privacy_rules.len(),
+            "Privacy rules configured, using PrivacyRuleProcessor"
+        );
+        let (tx_privacy, rx_privacy) = mpsc::channel::<CaptureEvent>(10);
+        let privacy_processor =
+            worker_impl::privacy::PrivacyRuleProcessor::new(&config.privacy_rules)?;
+        handles.push((
+            "PrivacyRules",
+            privacy_processor.process(rx_filter, tx_privacy)?,
+        ));
+        rx_privacy
     } else {
-        info!("S3 upload disabled, using PassthroughProcessor");
-        let passthrough = worker_impl::passthrough::PassthroughProcessor::new();
-        passthrough.process(rx_cache, tx_s3)?
+        rx_filter
     };
 
-    // Consumer: rx_s3 -> AwServerProcessor
-    let aw_handle = aw_processor.consume(rx_s3)?;
+    // Processor: rx_filter -> LowFidelityProcessor -> tx_fidelity (optional)
+    let rx_filter = if config.low_fidelity.enabled || control_enabled {
+        info!(
+            active = config.low_fidelity.enabled,
+            mode = ?config.low_fidelity.mode,
+            "Low-fidelity mode available, using LowFidelityProcessor"
+        );
+        let (tx_fidelity, rx_fidelity) = mpsc::channel::<CaptureEvent>(10);
+        let fidelity_processor =
+            worker_impl::fidelity::LowFidelityProcessor::new(&config.low_fidelity, low_fidelity);
+        handles.push((
+            "LowFidelity",
+            fidelity_processor.process(rx_filter, tx_fidelity)?,
+        ));
+        rx_fidelity
+    } else {
+        rx_filter
+    };
 
-    // Wait for all tasks to complete, with graceful shutdown timeout
-    let all_workers = async {
-        let (capture_result, filter_result, cache_result, s3_result, aw_result) = tokio::join!(
-            capture_handle,
-            filter_handle,
-            cache_handle,
-            s3_handle,
-            aw_handle
+    // Processor: rx_filter -> FocusBlurProcessor -> tx_focus (optional)
+    let rx_filter = if config.focus_blur.enabled {
+        info!("Focus blur enabled, using FocusBlurProcessor");
+        let (tx_focus, rx_focus) = mpsc::channel::<CaptureEvent>(10);
+        let focus_processor = worker_impl::focus::FocusBlurProcessor::new(&config.focus_blur);
+        handles.push(("FocusBlur", focus_processor.process(rx_filter, tx_focus)?));
+        rx_focus
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> MeetingRedactProcessor -> tx_meeting (optional)
+    let rx_filter = if meeting_redaction {
+        info!("Meeting redaction enabled, using MeetingRedactProcessor");
+        let (tx_meeting, rx_meeting) = mpsc::channel::<CaptureEvent>(10);
+        let meeting_processor =
+            worker_impl::meeting::MeetingRedactProcessor::new(config.meeting.pixel_size);
+        handles.push(("Meeting", meeting_processor.process(rx_filter, tx_meeting)?));
+        rx_meeting
+    } else {
+        rx_filter
+    };
+
+    let registry = Arc::new(inference::ModelRegistry::new(config.models.clone()));
+
+    // Processor: rx_filter -> RedactProcessor -> tx_redact (optional)
+    let rx_filter = if config.redact.enabled {
+        info!("Sensitive text redaction enabled, using RedactProcessor");
+        let (tx_redact, rx_redact) = mpsc::channel::<CaptureEvent>(10);
+        let redact_processor =
+            worker_impl::redact::RedactProcessor::new(&config.redact, &config.ocr)?;
+        handles.push(("Redact", redact_processor.process(rx_filter, tx_redact)?));
+        rx_redact
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> FaceBlurProcessor -> tx_face (optional)
+    let rx_filter = if config.face_blur.model.is_some() {
+        info!("Face blurring enabled, using FaceBlurProcessor");
+        let (tx_face, rx_face) = mpsc::channel::<CaptureEvent>(10);
+        let face_processor =
+            worker_impl::face::FaceBlurProcessor::new(config.face_blur.clone(), registry.clone());
+        handles.push(("FaceBlur", face_processor.process(rx_filter, tx_face)?));
+        rx_face
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> NsfwProcessor -> tx_nsfw (optional)
+    let rx_filter = if config.nsfw.model.is_some() {
+        info!(action = ?config.nsfw.action, "Unsafe content gate enabled, using NsfwProcessor");
+        let (tx_nsfw, rx_nsfw) = mpsc::channel::<CaptureEvent>(10);
+        let nsfw_processor =
+            worker_impl::nsfw::NsfwProcessor::new(config.nsfw.clone(), registry.clone());
+        handles.push(("Nsfw", nsfw_processor.process(rx_filter, tx_nsfw)?));
+        rx_nsfw
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> ContextSwitchProcessor -> tx_analytics (optional)
+    let rx_filter = if config.analytics.enabled {
+        info!("Context switch analytics enabled, using ContextSwitchProcessor");
+        let (tx_analytics, rx_analytics) = mpsc::channel::<CaptureEvent>(10);
+        let analytics_processor = worker_impl::analytics::ContextSwitchProcessor::new(
+            config.analytics.clone(),
+            &config.aw_server,
+        )
+        .await?
+        .with_time_policy(time_policy);
+        handles.push((
+            "Analytics",
+            analytics_processor.process(rx_filter, tx_analytics)?,
+        ));
+        rx_analytics
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> OcrProcessor -> tx_ocr (optional)
+    let rx_filter = if config.ocr.enabled {
+        info!("OCR enabled, using OcrProcessor");
+        let (tx_ocr, rx_ocr) = mpsc::channel::<CaptureEvent>(10);
+        let ocr_processor = worker_impl::ocr::OcrProcessor::new(config.ocr.clone());
+        handles.push(("Ocr", ocr_processor.process(rx_filter, tx_ocr)?));
+        rx_ocr
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> QrCodeProcessor -> tx_qr (optional)
+    let rx_filter = if config.qr_code.enabled {
+        info!("QR code detection enabled, using QrCodeProcessor");
+        let (tx_qr, rx_qr) = mpsc::channel::<CaptureEvent>(10);
+        let qr_processor = worker_impl::qrcode::QrCodeProcessor::new(config.qr_code.clone());
+        handles.push(("QrCode", qr_processor.process(rx_filter, tx_qr)?));
+        rx_qr
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> DailySummaryProcessor -> tx_summary (optional)
+    let rx_filter = if config.summary.enabled {
+        info!("Daily summary enabled, using DailySummaryProcessor");
+        let (tx_summary, rx_summary) = mpsc::channel::<CaptureEvent>(10);
+        let summary_processor = worker_impl::summary::DailySummaryProcessor::new(
+            config.summary.clone(),
+            &config.cache,
+            &config.aw_server,
+        )
+        .await?
+        .with_time_policy(time_policy);
+        handles.push(("Summary", summary_processor.process(rx_filter, tx_summary)?));
+        rx_summary
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> ClipProcessor -> tx_clip (optional)
+    let rx_filter = if record_clips {
+        info!("Clip recording enabled, using ClipProcessor");
+        let (tx_clip, rx_clip) = mpsc::channel::<CaptureEvent>(10);
+        let clip_processor = worker_impl::clip::ClipProcessor::new(config.clip.clone())
+            .with_time_policy(time_policy);
+        handles.push(("Clip", clip_processor.process(rx_filter, tx_clip)?));
+        rx_clip
+    } else {
+        rx_filter
+    };
+
+    // Processor: rx_filter -> SpillProcessor -> tx_spill (optional)
+    let rx_filter = if config.spill.enabled {
+        info!("Spill to disk enabled, using SpillProcessor");
+        // Keep the encoder queue short, waiting frames are held by the spill stage
+        let (tx_spill, rx_spill) = mpsc::channel::<CaptureEvent>(1);
+        let spill_processor = worker_impl::spill::SpillProcessor::new(
+            config.spill.clone(),
+            Path::new(&config.cache.cache_dir),
+        )?;
+        handles.push(("Spill", spill_processor.process(rx_filter, tx_spill)?));
+        rx_spill
+    } else {
+        rx_filter
+    };
+
+    if let Some(aw_processor) = aw_processor {
+        // Processor: rx_filter -> ToWebpProcessor -> tx_cache
+        handles.push(("Cache", cache_processor.process(rx_filter, tx_cache)?));
+
+        // Processor: rx_cache -> S3Processor/Passthrough -> tx_s3
+        // Use PassthroughProcessor when S3 is disabled
+        let s3_handle = if config.s3.enabled {
+            info!("S3 upload enabled, using S3Processor");
+            let mut s3_processor = worker_impl::s3::S3Processor::new(config.s3.clone())?;
+            if config.network.defer_uploads_when_metered {
+                s3_processor = s3_processor
+                    .with_metered_deferral(&config.network, Path::new(&config.cache.cache_dir));
+            }
+            if let Some(profile) = power_profile.filter(|_| config.power.defer_uploads) {
+                s3_processor =
+                    s3_processor.with_power_profile(profile, Path::new(&config.cache.cache_dir));
+            }
+            s3_processor.process(rx_cache, tx_s3)?
+        } else {
+            info!("S3 upload disabled, using PassthroughProcessor");
+            let passthrough = worker_impl::passthrough::PassthroughProcessor::new();
+            passthrough.process(rx_cache, tx_s3)?
+        };
+        handles.push(("S3", s3_handle));
+
+        // Consumer: rx_s3 -> AwServerProcessor
+        handles.push(("AwServer", aw_processor.consume(rx_s3)?));
+    } else {
+        // Consumer: rx_filter -> RemoteSender (agent mode)
+        let host = config
+            .remote
+            .host
+            .clone()
+            .unwrap_or_else(|| config.aw_server.hostname.clone());
+        info!(address = %config.remote.address, host, "Remote agent mode, sending events to the server");
+        let sender = worker_impl::remote::RemoteSender::new(
+            config.remote.address.clone(),
+            config.remote.token.clone(),
+            host,
+            encode::lossless_from_config(&config.cache),
         );
+        handles.push(("Remote", sender.consume(rx_filter)?));
+    }
 
-        if let Err(e) = capture_result {
-            error!("Capture worker joined with error: {}", e);
-        }
-        if let Err(e) = filter_result {
-            error!("Filter worker joined with error: {}", e);
-        }
-        if let Err(e) = cache_result {
-            error!("Cache worker joined with error: {}", e);
-        }
-        if let Err(e) = s3_result {
-            error!("S3 worker joined with error: {}", e);
-        }
-        if let Err(e) = aw_result {
-            error!("AwServer worker joined with error: {}", e);
+    // Wait for all tasks to complete, with graceful shutdown timeout
+    let all_workers = async {
+        // Workers are already spawned, so awaiting them in order is equivalent to joining
+        for (name, handle) in handles {
+            if let Err(e) = handle.await {
+                error!("{} worker joined with error: {}", name, e);
+            }
         }
     };
 
@@ -140,7 +734,7 @@ async fn main() -> Result<(), Error> {
         }
         _ = cancel_token.cancelled() => {
             info!("Shutdown initiated, waiting up to 5 seconds for workers to finish...");
-            let shutdown_timeout = tokio::time::sleep(std::time::Duration::from_secs(5));
+            let shutdown_timeout = tokio::time::sleep(Duration::from_secs(5));
             tokio::select! {
                 _ = shutdown_timeout => {
                     info!("Shutdown timeout reached, forcing exit.");
@@ -0,0 +1,217 @@
+//! Rectangles of a monitor masked out of captures.
+//!
+//! Masks are configured in monitor pixel coordinates, while the captured
+//! image may cover only a region of the monitor and may have been
+//! downscaled, so they are mapped into image coordinates first. Areas are
+//! either painted black or blurred.
+
+use crate::config::{MonitorConfig, NotificationArea};
+use crate::event::{Rect, UploadImageInfo};
+use crate::monitor::MonitorIdentity;
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+
+/// Map `masks` given in monitor pixels into the pixels of `image`, the
+/// capture described by `info`. Masks outside the captured area are dropped.
+pub fn image_rects(info: &UploadImageInfo, image: &DynamicImage, masks: &[Rect]) -> Vec<Rect> {
+    let (width, height) = image.dimensions();
+    // The captured part of the monitor; the image itself when the monitor
+    // geometry is unknown
+    let area = info.region.unwrap_or_else(|| {
+        MonitorIdentity::from_event_name(&info.monitor_name, &info.monitor_fingerprint).map_or(
+            Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+            |monitor| Rect {
+                x: 0,
+                y: 0,
+                width: monitor.width,
+                height: monitor.height,
+            },
+        )
+    });
+    area_rects(area, image, masks)
+}
+
+/// Map `masks` given in monitor pixels into the pixels of `image`, which
+/// shows `area` of the monitor. Masks outside the area are dropped.
+pub fn area_rects(area: Rect, image: &DynamicImage, masks: &[Rect]) -> Vec<Rect> {
+    let (width, height) = image.dimensions();
+    if area.width == 0 || area.height == 0 {
+        return Vec::new();
+    }
+    let scale_x = width as f64 / area.width as f64;
+    let scale_y = height as f64 / area.height as f64;
+    masks
+        .iter()
+        .filter_map(|mask| mask.intersect(&area))
+        .filter_map(|mask| {
+            let x = ((mask.x - area.x) as f64 * scale_x).floor() as u32;
+            let y = ((mask.y - area.y) as f64 * scale_y).floor() as u32;
+            let right = ((mask.x + mask.width - area.x) as f64 * scale_x).ceil() as u32;
+            let bottom = ((mask.y + mask.height - area.y) as f64 * scale_y).ceil() as u32;
+            Rect {
+                x,
+                y,
+                width: right.min(width).saturating_sub(x),
+                height: bottom.min(height).saturating_sub(y),
+            }
+            .intersect(&Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            })
+        })
+        .collect()
+}
+
+/// Regions painted black in the stored images of a monitor:
+/// `blackout_regions`, and `ignore_regions` with `black_out_ignored`.
+pub fn blackout_regions(settings: &MonitorConfig) -> Vec<Rect> {
+    let mut regions = settings.blackout_regions.clone();
+    if settings.black_out_ignored {
+        regions.extend_from_slice(&settings.ignore_regions);
+    }
+    regions
+}
+
+/// The area where `area`'s desktop shows notification popups on a monitor
+/// of `width` x `height` pixels with the given scale factor, in monitor
+/// pixels. Sized for a stack of about three popups.
+pub fn notification_rect(area: NotificationArea, width: u32, height: u32, scale: f32) -> Rect {
+    let area = match area {
+        NotificationArea::Auto if cfg!(windows) => NotificationArea::Windows,
+        NotificationArea::Auto if cfg!(target_os = "macos") => NotificationArea::Macos,
+        NotificationArea::Auto => {
+            let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+            if desktop.to_uppercase().contains("KDE") {
+                NotificationArea::Kde
+            } else {
+                NotificationArea::Gnome
+            }
+        }
+        area => area,
+    };
+    // Logical size of the area
+    let (area_width, area_height) = match area {
+        NotificationArea::Windows => (400, 720),
+        NotificationArea::Macos => (400, 440),
+        NotificationArea::Gnome => (460, 200),
+        NotificationArea::Auto | NotificationArea::Kde => (420, 560),
+    };
+    let scale = if scale > 0.0 { scale } else { 1.0 };
+    let area_width = ((area_width as f32 * scale) as u32).min(width);
+    let area_height = ((area_height as f32 * scale) as u32).min(height);
+    let (x, y) = match area {
+        NotificationArea::Macos => (width - area_width, 0),
+        NotificationArea::Gnome => ((width - area_width) / 2, 0),
+        _ => (width - area_width, height - area_height),
+    };
+    Rect {
+        x,
+        y,
+        width: area_width,
+        height: area_height,
+    }
+}
+
+/// Gaussian-blur the area of the image within `rect`.
+pub fn blur(image: &mut DynamicImage, rect: Rect, sigma: f32) {
+    let Some(rect) = rect.intersect(&Rect {
+        x: 0,
+        y: 0,
+        width: image.width(),
+        height: image.height(),
+    }) else {
+        return;
+    };
+    let blurred = image
+        .crop_imm(rect.x, rect.y, rect.width, rect.height)
+        .blur(sigma);
+    image::imageops::replace(image, &blurred, rect.x as i64, rect.y as i64);
+}
+
+/// Paint `rects` of the image black.
+pub fn fill(image: &mut DynamicImage, rects: &[Rect]) {
+    for rect in rects {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_rects() {
+        let mut info = UploadImageInfo::new(
+            "DELL_3840_2160_0_0".to_string(),
+            1,
+            "0".to_string(),
+            String::new(),
+        );
+        let clock = Rect {
+            x: 3640,
+            y: 2100,
+            width: 200,
+            height: 60,
+        };
+        // Downscaled to half size
+        let image = DynamicImage::new_rgb8(1920, 1080);
+        let expected = Rect {
+            x: 1820,
+            y: 1050,
+            width: 100,
+            height: 30,
+        };
+        assert_eq!(image_rects(&info, &image, &[clock]), vec![expected]);
+
+        // Only the top left quarter was captured
+        info.region = Some(Rect {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        });
+        assert!(image_rects(&info, &image, &[clock]).is_empty());
+    }
+
+    #[test]
+    fn test_notification_rect() {
+        assert_eq!(
+            notification_rect(NotificationArea::Windows, 1920, 1080, 1.0),
+            Rect {
+                x: 1520,
+                y: 360,
+                width: 400,
+                height: 720,
+            }
+        );
+        assert_eq!(
+            notification_rect(NotificationArea::Gnome, 3840, 2160, 2.0),
+            Rect {
+                x: 1460,
+                y: 0,
+                width: 920,
+                height: 400,
+            }
+        );
+        // Never larger than the monitor
+        assert_eq!(
+            notification_rect(NotificationArea::Macos, 600, 400, 2.0),
+            Rect {
+                x: 0,
+                y: 0,
+                width: 600,
+                height: 400,
+            }
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Monitor naming and identity.
+//!
+//! Display names come from the OS and may contain any Unicode text. They are
+//! sanitized for use in metadata and paths without dropping non-ASCII
+//! characters, and each monitor gets a fingerprint derived from its name and
+//! geometry that, unlike the backend id, stays the same across reboots.
+
+use crate::config::MonitorConfig;
+use anyhow::{Error, Result};
+use hmac::{Hmac, Mac};
+use regex::{Regex, RegexBuilder};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Make a monitor name safe for file names and object keys.
+///
+/// Unicode letters and digits (including CJK and accented characters) and
+/// `-`, `_`, `.` are kept, whitespace becomes `_`, and anything else is
+/// percent-encoded as UTF-8.
+pub fn sanitize_name(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    for c in name.trim().chars() {
+        if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+            sanitized.push(c);
+        } else if c.is_whitespace() {
+            sanitized.push('_');
+        } else {
+            let mut buf = [0u8; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                let _ = write!(sanitized, "%{:02X}", byte);
+            }
+        }
+    }
+    sanitized
+}
+
+/// Stable identity of a monitor as 16 hex digits.
+///
+/// xcap does not expose EDID data, so the fingerprint hashes the reported
+/// name and geometry with FNV-1a, which unlike `DefaultHasher` is fixed
+/// across Rust releases.
+pub fn fingerprint(name: &str, width: u32, height: u32, x: i32, y: i32) -> String {
+    let mut hash = Fnv::default();
+    hash.feed(name.as_bytes());
+    hash.feed(&width.to_le_bytes());
+    hash.feed(&height.to_le_bytes());
+    hash.feed(&x.to_le_bytes());
+    hash.feed(&y.to_le_bytes());
+    format!("{:016x}", hash.0)
+}
+
+/// Key for `salted_hash`: the SHA-256 of a user-chosen salt, or random
+/// bytes from the OS when there is none.
+pub fn salt_key(salt: Option<&str>) -> Result<[u8; 32], Error> {
+    match salt {
+        Some(salt) => Ok(Sha256::digest(salt.as_bytes()).into()),
+        None => {
+            let mut key = [0; 32];
+            getrandom::fill(&mut key)
+                .map_err(|e| anyhow::anyhow!("Failed to generate a salt: {}", e))?;
+            Ok(key)
+        }
+    }
+}
+
+/// Keyed hash (HMAC-SHA256) of a monitor name or fingerprint as 16 hex
+/// digits, so model strings do not identify the hardware when events are
+/// shared; without the key, guessed model strings cannot be checked against
+/// it. The same value and key always give the same hash.
+pub fn salted_hash(value: &str, key: &[u8; 32]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(value.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    digest[..8]
+        .iter()
+        .fold(String::with_capacity(16), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+/// 64-bit FNV-1a.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// A monitor as matched against `[[monitor]]` entries.
+pub struct MonitorIdentity<'a> {
+    /// Sanitized name, see `sanitize_name`.
+    pub name: &'a str,
+    pub fingerprint: &'a str,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl<'a> MonitorIdentity<'a> {
+    /// Identity of a monitor from an event's `name_width_height_x_y` monitor
+    /// name, for events not captured locally (remote agents).
+    pub fn from_event_name(event_name: &'a str, fingerprint: &'a str) -> Option<Self> {
+        let mut parts = event_name.rsplitn(5, '_');
+        let y = parts.next()?.parse().ok()?;
+        let x = parts.next()?.parse().ok()?;
+        let height = parts.next()?.parse().ok()?;
+        let width = parts.next()?.parse().ok()?;
+        Some(Self {
+            name: parts.next()?,
+            fingerprint,
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+/// The first per-monitor entry whose selectors all match the monitor.
+pub fn find_settings<'a>(
+    configs: &'a [Arc<MonitorConfig>],
+    monitor: &MonitorIdentity,
+) -> Option<&'a Arc<MonitorConfig>> {
+    configs.iter().find(|config| matches(config, monitor))
+}
+
+fn matches(config: &MonitorConfig, monitor: &MonitorIdentity) -> bool {
+    config
+        .name
+        .as_ref()
+        .is_none_or(|name| sanitize_name(name).to_lowercase() == monitor.name.to_lowercase())
+        && config
+            .fingerprint
+            .as_ref()
+            .is_none_or(|fingerprint| fingerprint == monitor.fingerprint)
+        && config.x.is_none_or(|x| x == monitor.x)
+        && config.y.is_none_or(|y| y == monitor.y)
+        && config.width.is_none_or(|width| width == monitor.width)
+        && config.height.is_none_or(|height| height == monitor.height)
+}
+
+/// `capture.include_monitors` / `capture.exclude_monitors` patterns.
+#[derive(Clone, Debug, Default)]
+pub struct MonitorFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl MonitorFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, Error> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    RegexBuilder::new(&format!("^(?:{})$", pattern))
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|e| {
+                            anyhow::anyhow!("Invalid monitor pattern {:?}: {}", pattern, e)
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Whether the monitor named `name` (as reported by the OS) is captured.
+    /// Patterns may match either the reported or the sanitized name.
+    pub fn allows(&self, name: &str) -> bool {
+        let sanitized = sanitize_name(name);
+        let matches = |patterns: &[Regex]| {
+            patterns
+                .iter()
+                .any(|re| re.is_match(name) || re.is_match(&sanitized))
+        };
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_settings() {
+        let configs = vec![
+            Arc::new(MonitorConfig {
+                name: Some("dell u2720q".to_string()),
+                exclude: true,
+                ..Default::default()
+            }),
+            Arc::new(MonitorConfig {
+                width: Some(2560),
+                dhash_threshold: Some(4),
+                ..Default::default()
+            }),
+        ];
+        let monitor = |name, width| MonitorIdentity {
+            name,
+            fingerprint: "0",
+            x: 0,
+            y: 0,
+            width,
+            height: 1440,
+        };
+
+        assert!(find_settings(&configs, &monitor("DELL_U2720Q", 3840)).is_some_and(|c| c.exclude));
+        let settings = find_settings(&configs, &monitor("Built-in", 2560));
+        assert_eq!(settings.and_then(|c| c.dhash_threshold), Some(4));
+        assert!(find_settings(&configs, &monitor("Built-in", 1920)).is_none());
+
+        let identity = MonitorIdentity::from_event_name("HP_24_1920_1080_-1920_0", "0").unwrap();
+        assert_eq!(
+            (identity.name, identity.width, identity.x),
+            ("HP_24", 1920, -1920)
+        );
+        assert!(MonitorIdentity::from_event_name("composite", "0").is_none());
+    }
+
+    #[test]
+    fn test_monitor_filter() {
+        let patterns = |list: &[&str]| list.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let filter = MonitorFilter::new(&[], &patterns(&["dell u2720q", ".*TV.*"])).unwrap();
+        assert!(!filter.allows("DELL U2720Q"));
+        assert!(!filter.allows("LG TV SSCR2"));
+        assert!(filter.allows("DELL U2720QM"));
+        assert!(MonitorFilter::default().allows("anything"));
+
+        let filter = MonitorFilter::new(&patterns(&["HDMI-\\d"]), &[]).unwrap();
+        assert!(filter.allows("HDMI-1"));
+        assert!(!filter.allows("eDP-1"));
+        assert!(MonitorFilter::new(&patterns(&["("]), &[]).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_name_keeps_unicode() {
+        assert_eq!(sanitize_name("DELL U2720Q"), "DELL_U2720Q");
+        assert_eq!(sanitize_name("内蔵ディスプレイ"), "内蔵ディスプレイ");
+        assert_eq!(sanitize_name("Écran/2"), "Écran%2F2");
+    }
+
+    #[test]
+    fn test_fingerprint_is_stable() {
+        let a = fingerprint("内蔵ディスプレイ", 2560, 1600, 0, 0);
+        assert_eq!(a, fingerprint("内蔵ディスプレイ", 2560, 1600, 0, 0));
+        assert_ne!(a, fingerprint("内蔵ディスプレイ", 2560, 1600, 2560, 0));
+        assert_eq!(a.len(), 16);
+
+        let key = salt_key(Some("salt")).unwrap();
+        let hash = salted_hash("DELL_U2720Q", &key);
+        assert_eq!(hash, salted_hash("DELL_U2720Q", &key));
+        assert_eq!(hash.len(), 16);
+        assert_ne!(
+            hash,
+            salted_hash("DELL_U2720Q", &salt_key(Some("other")).unwrap())
+        );
+        assert_ne!(salt_key(None).unwrap(), salt_key(None).unwrap());
+    }
+}
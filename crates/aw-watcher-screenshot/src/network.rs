@@ -0,0 +1,163 @@
+//! Metered network detection.
+//!
+//! On metered connections (mobile hotspots, capped plans) uploads are
+//! deferred to a local queue instead of spending the user's data, and events
+//! for an aw-server on another machine are held back in memory. Detection
+//! uses the Windows connection cost API and the NetworkManager `Metered`
+//! property on Linux; other platforms are treated as unmetered.
+
+use crate::config::NetworkConfig;
+use anyhow::{Error, Result};
+use aw_models::Event;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Most aw-server events held back while metered; older ones are dropped.
+const MAX_DEFERRED_EVENTS: usize = 10_000;
+
+/// Caches the metered state so the OS is queried at most once per interval.
+pub struct MeteredCheck {
+    interval: Duration,
+    last: Option<(Instant, bool)>,
+}
+
+impl MeteredCheck {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    /// Whether the current connection is metered.
+    pub async fn is_metered(&mut self) -> bool {
+        if let Some((checked_at, metered)) = self.last
+            && checked_at.elapsed() < self.interval
+        {
+            return metered;
+        }
+
+        let metered = match tokio::task::spawn_blocking(is_metered).await {
+            Ok(Ok(metered)) => metered,
+            Ok(Err(e)) => {
+                // Unknown state: behave as before detection existed
+                debug!(error = %e, "Failed to query metered state");
+                false
+            }
+            Err(e) => {
+                debug!(error = %e, "Metered state query panicked");
+                false
+            }
+        };
+        if self.last.map(|(_, last)| last) != Some(metered) {
+            info!(metered, "Network metered state changed");
+        }
+        self.last = Some((Instant::now(), metered));
+        metered
+    }
+}
+
+/// Whether `host` is this machine, so talking to it costs no data.
+pub fn is_local_host(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Holds aw-server events back while the connection is metered, along with
+/// whatever is needed to post them.
+pub struct DeferredEvents<T = Event> {
+    check: MeteredCheck,
+    events: VecDeque<T>,
+    dropped: usize,
+}
+
+impl<T> DeferredEvents<T> {
+    /// Deferral of events for the aw-server at `host`, unless it is local or
+    /// `network.defer_reports_when_metered` is off.
+    pub fn for_host(config: &NetworkConfig, host: &str) -> Option<Self> {
+        if !config.defer_reports_when_metered || is_local_host(host) {
+            return None;
+        }
+        Some(Self {
+            check: MeteredCheck::new(Duration::from_secs(config.metered_check_secs)),
+            events: VecDeque::new(),
+            dropped: 0,
+        })
+    }
+
+    /// The events to post now: none while metered, otherwise the held back
+    /// ones followed by `event`.
+    pub async fn gate(&mut self, event: T) -> Vec<T> {
+        if self.check.is_metered().await {
+            if self.events.len() >= MAX_DEFERRED_EVENTS {
+                self.events.pop_front();
+                self.dropped += 1;
+            }
+            self.events.push_back(event);
+            return Vec::new();
+        }
+        if self.dropped > 0 {
+            warn!(
+                dropped = self.dropped,
+                "Dropped the oldest aw-server events held back on the metered connection"
+            );
+            self.dropped = 0;
+        }
+        if !self.events.is_empty() {
+            info!(
+                count = self.events.len(),
+                "Posting deferred aw-server events"
+            );
+        }
+        let mut events: Vec<T> = self.events.drain(..).collect();
+        events.push(event);
+        events
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_metered() -> Result<bool, Error> {
+    // NMMetered: 0 unknown, 1 yes, 2 no, 3 guess-yes, 4 guess-no
+    let output = std::process::Command::new("busctl")
+        .args([
+            "get-property",
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "Metered",
+        ])
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "busctl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // Output looks like "u 1"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let value = stdout.split_whitespace().last().unwrap_or_default();
+    Ok(matches!(value, "1" | "3"))
+}
+
+#[cfg(windows)]
+fn is_metered() -> Result<bool, Error> {
+    use windows::Networking::Connectivity::{NetworkCostType, NetworkInformation};
+
+    let profile = NetworkInformation::GetInternetConnectionProfile()?;
+    let cost = profile.GetConnectionCost()?;
+    let cost_type = cost.NetworkCostType()?;
+    Ok(cost.Roaming()?
+        || cost.OverDataLimit()?
+        || (cost_type != NetworkCostType::Unrestricted && cost_type != NetworkCostType::Unknown))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn is_metered() -> Result<bool, Error> {
+    Ok(false)
+}
@@ -0,0 +1,98 @@
+//! Recycling of encoded image buffers.
+//!
+//! Every stored frame produces a multi-megabyte output buffer that lives until
+//! the upload and heartbeat stages are done with it. Instead of freeing those
+//! buffers and allocating new ones on the next tick, `PooledBuffer` hands its
+//! allocation back to the `BufferPool` it came from when dropped.
+//!
+//! Only these output buffers are pooled. Encoders still allocate their own
+//! working memory, libwebp's output is copied into the pooled buffer, and
+//! captured frames are allocated by the capture backend on every tick.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// A bounded pool of reusable byte buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    /// Create a pool that keeps at most `max_buffers` idle buffers.
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            free: Arc::new(Mutex::new(Vec::new())),
+            max_buffers,
+        }
+    }
+
+    /// Take an empty buffer, reusing an idle allocation when available.
+    pub fn take(&self) -> PooledBuffer {
+        let data = self
+            .free
+            .lock()
+            .ok()
+            .and_then(|mut free| free.pop())
+            .unwrap_or_default();
+        PooledBuffer {
+            data,
+            pool: self.clone(),
+        }
+    }
+
+    fn give(&self, mut data: Vec<u8>) {
+        data.clear();
+        if let Ok(mut free) = self.free.lock()
+            && free.len() < self.max_buffers
+        {
+            free.push(data);
+        }
+    }
+}
+
+/// A byte buffer that returns its allocation to its pool when dropped.
+pub struct PooledBuffer {
+    data: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.give(std::mem::take(&mut self.data));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_is_reused() {
+        let pool = BufferPool::new(1);
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[0; 1024]);
+        let ptr = buffer.as_ptr();
+        drop(buffer);
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 1024);
+        assert_eq!(buffer.as_ptr(), ptr);
+    }
+}
@@ -0,0 +1,166 @@
+//! Battery-aware power profile.
+//!
+//! A background task polls the power source and publishes the active
+//! `PowerProfile`. While running on battery below the configured charge,
+//! stages switch to their battery-saver behavior: capture runs less often,
+//! frames are encoded cheaper and uploads are queued until AC power returns.
+
+use crate::config::PowerConfig;
+use crate::status::StatusReporter;
+use anyhow::{Error, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerProfile {
+    Normal,
+    BatterySaver,
+}
+
+impl PowerProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerProfile::Normal => "normal",
+            PowerProfile::BatterySaver => "battery_saver",
+        }
+    }
+}
+
+/// Power source as reported by the OS.
+#[derive(Clone, Copy, Debug, Default)]
+struct PowerState {
+    on_battery: bool,
+    /// Remaining battery charge, when known.
+    percent: Option<u8>,
+}
+
+/// Profile for a power state; unknown charge levels are not throttled.
+fn profile_for(state: PowerState, threshold: u8) -> PowerProfile {
+    if state.on_battery && state.percent.is_some_and(|p| p <= threshold) {
+        PowerProfile::BatterySaver
+    } else {
+        PowerProfile::Normal
+    }
+}
+
+/// Start polling the power source and return the active profile.
+///
+/// Profile changes are logged and posted to `reporter` when given.
+pub fn watch_profile(
+    config: &PowerConfig,
+    reporter: Option<StatusReporter>,
+) -> watch::Receiver<PowerProfile> {
+    let (tx, rx) = watch::channel(PowerProfile::Normal);
+    let threshold = config.battery_threshold;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.check_secs.max(1)));
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            let state = match tokio::task::spawn_blocking(power_state).await {
+                Ok(Ok(state)) => state,
+                Ok(Err(e)) => {
+                    debug!(error = %e, "Failed to query power state");
+                    PowerState::default()
+                }
+                Err(e) => {
+                    debug!(error = %e, "Power state query panicked");
+                    PowerState::default()
+                }
+            };
+
+            let profile = profile_for(state, threshold);
+            if profile == *tx.borrow() {
+                continue;
+            }
+            info!(profile = profile.as_str(), battery = ?state.percent, "Power profile changed");
+            if let Some(reporter) = &reporter {
+                let mut data = Map::new();
+                data.insert("power_profile".to_string(), Value::from(profile.as_str()));
+                data.insert("battery_percent".to_string(), Value::from(state.percent));
+                reporter.report("power_profile", data);
+            }
+            if tx.send(profile).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(target_os = "linux")]
+fn power_state() -> Result<PowerState, Error> {
+    let mut state = PowerState::default();
+    for entry in std::fs::read_dir("/sys/class/power_supply")? {
+        let path = entry?.path();
+        let read = |name: &str| std::fs::read_to_string(path.join(name)).unwrap_or_default();
+        if read("type").trim() != "Battery" {
+            continue;
+        }
+        if read("status").trim() == "Discharging" {
+            state.on_battery = true;
+            state.percent = read("capacity").trim().parse().ok();
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(windows)]
+fn power_state() -> Result<PowerState, Error> {
+    use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    let mut status = SYSTEM_POWER_STATUS::default();
+    unsafe { GetSystemPowerStatus(&mut status)? };
+    Ok(PowerState {
+        on_battery: status.ACLineStatus == 0,
+        // 255 means the charge is unknown
+        percent: (status.BatteryLifePercent <= 100).then_some(status.BatteryLifePercent),
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn power_state() -> Result<PowerState, Error> {
+    // Now drawing from 'Battery Power'
+    //  -InternalBattery-0 (id=1234)	85%; discharging; 4:10 remaining present: true
+    let output = std::process::Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let percent = stdout
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .find_map(|word| word.strip_suffix('%')?.parse().ok());
+    Ok(PowerState {
+        on_battery: stdout.contains("'Battery Power'"),
+        percent,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn power_state() -> Result<PowerState, Error> {
+    Ok(PowerState::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_for() {
+        let battery = |percent| PowerState {
+            on_battery: true,
+            percent,
+        };
+        assert_eq!(
+            profile_for(battery(Some(20)), 30),
+            PowerProfile::BatterySaver
+        );
+        assert_eq!(profile_for(battery(Some(80)), 30), PowerProfile::Normal);
+        assert_eq!(profile_for(battery(None), 30), PowerProfile::Normal);
+        assert_eq!(profile_for(PowerState::default(), 30), PowerProfile::Normal);
+    }
+}
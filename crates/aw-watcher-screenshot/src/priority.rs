@@ -0,0 +1,82 @@
+//! Reduced-priority execution of CPU-heavy pipeline work.
+//!
+//! Encoding, OCR and model inference run through `spawn_heavy`. With
+//! `pipeline.low_priority` enabled they go to a dedicated blocking pool whose
+//! threads run at background priority (nice 19 on Linux, background QoS on
+//! macOS, background mode on Windows), so processing never competes with
+//! foreground work. Otherwise they use the regular tokio blocking pool.
+
+use anyhow::{Error, Result};
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+static LOW_PRIORITY_POOL: OnceLock<Runtime> = OnceLock::new();
+
+/// Start the background-priority pool used by `spawn_heavy`.
+pub fn init_low_priority_pool() -> Result<(), Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("low-priority")
+        .on_thread_start(|| {
+            if let Err(e) = lower_current_thread() {
+                warn!(error = %e, "Failed to lower thread priority");
+            }
+        })
+        .build()?;
+
+    if LOW_PRIORITY_POOL.set(runtime).is_ok() {
+        info!("Heavy pipeline stages run at background priority");
+    }
+    Ok(())
+}
+
+/// Run CPU-heavy blocking work, at background priority when enabled.
+pub fn spawn_heavy<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match LOW_PRIORITY_POOL.get() {
+        Some(runtime) => runtime.spawn_blocking(f),
+        None => tokio::task::spawn_blocking(f),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lower_current_thread() -> Result<(), Error> {
+    // On Linux the nice value is per thread; `who = 0` targets the calling thread.
+    // Child processes (e.g. tesseract) inherit it.
+    let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn lower_current_thread() -> Result<(), Error> {
+    let ret =
+        unsafe { libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0) };
+    if ret != 0 {
+        return Err(std::io::Error::from_raw_os_error(ret).into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn lower_current_thread() -> Result<(), Error> {
+    use windows::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN,
+    };
+    unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN)? };
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn lower_current_thread() -> Result<(), Error> {
+    Err(anyhow::anyhow!(
+        "Thread priority is not supported on this platform"
+    ))
+}
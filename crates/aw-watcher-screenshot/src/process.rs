@@ -0,0 +1,86 @@
+//! Running process lookup.
+//!
+//! Lists the names of all running processes, for guards that react to
+//! programs running anywhere on the machine rather than to the focused window.
+
+use anyhow::{Error, Result};
+
+/// Lowercase a process name and drop a Windows `.exe` suffix.
+pub fn normalize(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    name.strip_suffix(".exe")
+        .map(str::to_string)
+        .unwrap_or(name)
+}
+
+/// Length Linux truncates process names (`comm`) to.
+const COMM_LENGTH: usize = 15;
+
+/// Whether the running process `running` is `wanted`, both normalized. Names
+/// cut short by Linux match the configured name they start.
+pub fn is_process(running: &str, wanted: &str) -> bool {
+    running == wanted || (running.len() == COMM_LENGTH && wanted.starts_with(running))
+}
+
+/// Names of all running processes.
+#[cfg(target_os = "linux")]
+pub fn process_names() -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let name = entry.file_name();
+        if !name.to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        if let Ok(comm) = std::fs::read_to_string(entry.path().join("comm")) {
+            names.push(comm.trim_end().to_string());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(target_os = "macos")]
+pub fn process_names() -> Result<Vec<String>, Error> {
+    let output = std::process::Command::new("ps")
+        .args(["-A", "-c", "-o", "comm="])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect())
+}
+
+#[cfg(windows)]
+pub fn process_names() -> Result<Vec<String>, Error> {
+    let output = std::process::Command::new("tasklist")
+        .args(["/fo", "csv", "/nh"])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split("\",\"").next())
+        .map(|name| name.trim_matches('"').to_string())
+        .collect())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn process_names() -> Result<Vec<String>, Error> {
+    Err(anyhow::anyhow!(
+        "Process listing is not supported on this platform"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_process() {
+        assert!(is_process(
+            &normalize("TurboTax.exe"),
+            &normalize("turbotax")
+        ));
+        assert!(is_process("vmware-vmx", "vmware-vmx"));
+        assert!(!is_process("vmware", "vmware-vmx"));
+        // Truncated by the kernel
+        assert!(is_process("virtualboxvm-bi", "virtualboxvm-bin"));
+    }
+}
@@ -0,0 +1,147 @@
+//! Remote agent protocol.
+//!
+//! In agent mode a watcher captures, filters and masks frames, and ships each
+//! accepted event losslessly encoded over TCP to a central watcher in server
+//! mode, which runs the optional analysis stages, encodes and stores the
+//! frames, and handles S3 upload and aw-server reporting, so that only the
+//! central instance holds those credentials. Agents keep no local cache.
+//!
+//! Each event is one frame: a big-endian `u32` header length, the JSON
+//! `FrameHeader`, then the encoded images back to back with the lengths
+//! listed in the header; monitors without an image have length 0. The
+//! connection is not encrypted; run it over a VPN or SSH tunnel when it
+//! leaves a trusted network.
+
+use crate::event::UploadImageInfo;
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Upper bound for a frame header, far above any real one.
+const MAX_HEADER_BYTES: u32 = 4 << 20;
+
+/// Upper bound for one encoded image.
+const MAX_IMAGE_BYTES: u32 = 512 << 20;
+
+#[derive(Serialize, Deserialize)]
+pub struct FrameHeader {
+    /// Shared secret, compared with the server's `remote.token`.
+    pub token: Option<String>,
+    /// Name of the agent's machine.
+    pub host: String,
+    pub timestamp: DateTime<Utc>,
+    /// MIME type of the encoded data.
+    pub content_type: String,
+    /// Monitor info and encoded length of each image, in data order. The
+    /// server rebuilds object keys rather than trusting the agent's.
+    pub images: Vec<(UploadImageInfo, u32)>,
+}
+
+/// Whether an agent's `given` token matches the server's `expected` one,
+/// compared in constant time. Any token matches when none is expected.
+pub fn token_matches(expected: Option<&str>, given: Option<&str>) -> bool {
+    match expected {
+        None => true,
+        Some(expected) => {
+            given.is_some_and(|given| bool::from(given.as_bytes().ct_eq(expected.as_bytes())))
+        }
+    }
+}
+
+/// Write one frame with `header` and the encoded `images` it lists.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    header: &FrameHeader,
+    images: &[&[u8]],
+) -> Result<(), Error> {
+    let json = serde_json::to_vec(header)?;
+    writer.write_u32(json.len() as u32).await?;
+    writer.write_all(&json).await?;
+    for image in images {
+        writer.write_all(image).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read the next frame, or `None` when the peer closed the connection.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<(FrameHeader, Vec<Vec<u8>>)>, Error> {
+    let header_len = match reader.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if header_len > MAX_HEADER_BYTES {
+        anyhow::bail!("Frame header of {} bytes is too large", header_len);
+    }
+    let mut json = vec![0; header_len as usize];
+    reader.read_exact(&mut json).await?;
+    let header: FrameHeader = serde_json::from_slice(&json)?;
+
+    let mut images = Vec::with_capacity(header.images.len());
+    for (_, len) in &header.images {
+        if *len > MAX_IMAGE_BYTES {
+            anyhow::bail!("Image of {} bytes is too large", len);
+        }
+        let mut data = vec![0; *len as usize];
+        reader.read_exact(&mut data).await?;
+        images.push(data);
+    }
+    Ok(Some((header, images)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let info = UploadImageInfo::new(
+                "DELL_U2720Q".to_string(),
+                7,
+                "0123456789abcdef".to_string(),
+                "2024/05/01/09/20240501_090000000_7.webp".to_string(),
+            );
+            let header = FrameHeader {
+                token: Some("secret".to_string()),
+                host: "workstation".to_string(),
+                timestamp: Utc::now(),
+                content_type: "image/webp".to_string(),
+                images: vec![(info, 3)],
+            };
+            let mut buffer = Vec::new();
+            write_frame(&mut buffer, &header, &[&[1, 2, 3]])
+                .await
+                .unwrap();
+
+            let mut reader = buffer.as_slice();
+            let (read, images) = read_frame(&mut reader).await.unwrap().unwrap();
+            assert_eq!(read.host, "workstation");
+            assert_eq!(read.images[0].0.monitor_id, 7);
+            assert_eq!(images, vec![vec![1, 2, 3]]);
+            assert!(read_frame(&mut reader).await.unwrap().is_none());
+
+            // Truncated image data
+            let mut reader = &buffer[..buffer.len() - 1];
+            assert!(read_frame(&mut reader).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches(None, None));
+        assert!(token_matches(None, Some("anything")));
+        assert!(token_matches(Some("secret"), Some("secret")));
+        assert!(!token_matches(Some("secret"), Some("secreT")));
+        assert!(!token_matches(Some("secret"), Some("secret2")));
+        assert!(!token_matches(Some("secret"), None));
+    }
+}
@@ -0,0 +1,285 @@
+//! Retention of stored frames.
+//!
+//! Frames flagged as sensitive (see `UploadImageInfo::is_sensitive`) are
+//! cached and uploaded with a `_flagged` marker in their name. A background
+//! sweep deletes them after `retention.flagged_max_age_hours`, from the cache
+//! and from S3, and deletes other cached frames after
+//! `retention.max_age_days`. Unflagged S3 objects are left to the bucket's
+//! lifecycle rules.
+//!
+//! The S3 sweep remembers how far it got in `retention_watermark` in the
+//! cache dir, so objects expiring while the watcher was not running are still
+//! found. Without a watermark, on the first sweep, the whole bucket is listed
+//! once. Frames are flagged by content filters and redaction rules only; a
+//! blocklisted window is never captured, so there is no blocklist flag.
+
+use crate::config::{RetentionConfig, S3Config};
+use crate::timezone::TimePolicy;
+use crate::worker_impl::s3::{drop_queued, open_bucket};
+use anyhow::{Error, Result};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use s3::Bucket;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Marker before the extension of flagged frames' file names and object keys.
+pub const FLAGGED_MARKER: &str = "_flagged";
+
+/// File in the cache dir holding the cutoff of the last complete S3 sweep.
+const WATERMARK_FILE: &str = "retention_watermark";
+
+/// `name` with the flagged marker inserted before its extension.
+pub fn flagged_name(name: &str) -> String {
+    let file_start = name.rfind('/').map_or(0, |slash| slash + 1);
+    match name[file_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = name.split_at(file_start + dot);
+            format!("{}{}{}", stem, FLAGGED_MARKER, extension)
+        }
+        _ => format!("{}{}", name, FLAGGED_MARKER),
+    }
+}
+
+/// Whether a file name or object key carries the flagged marker.
+fn is_flagged(name: &str) -> bool {
+    let name = name.rsplit('/').next().unwrap_or(name);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    stem.ends_with(FLAGGED_MARKER)
+}
+
+/// Whether a file name starts with a `%Y%m%d_` capture date.
+fn is_frame(name: &str) -> bool {
+    name.get(..8)
+        .is_some_and(|date| date.bytes().all(|b| b.is_ascii_digit()))
+        && name.as_bytes().get(8) == Some(&b'_')
+}
+
+/// Sweep for expired frames every `retention.interval_secs` until `token` is
+/// cancelled.
+pub fn serve(
+    config: RetentionConfig,
+    cache_dir: &Path,
+    s3: Option<&S3Config>,
+    time_policy: TimePolicy,
+    token: CancellationToken,
+) -> Result<JoinHandle<()>, Error> {
+    let bucket = s3.map(open_bucket).transpose()?;
+    let cache_dir = cache_dir.to_path_buf();
+    let interval = std::time::Duration::from_secs(config.interval_secs.max(60));
+    info!(
+        max_age_days = ?config.max_age_days,
+        flagged_max_age_hours = config.flagged_max_age_hours,
+        "Retention enabled"
+    );
+
+    Ok(tokio::spawn(async move {
+        let watermark_path = cache_dir.join(WATERMARK_FILE);
+        let mut watermark = read_watermark(&watermark_path).await;
+        loop {
+            let dir = cache_dir.clone();
+            let sweep_config = config.clone();
+            match tokio::task::spawn_blocking(move || sweep_cache(&dir, &sweep_config)).await {
+                Ok(0) => {}
+                Ok(deleted) => {
+                    info!(deleted, "Deleted expired cached frames");
+                    // Deferred uploads of the deleted frames
+                    match drop_queued(&cache_dir, |path, _| !path.exists()).await {
+                        Ok(0) => {}
+                        Ok(dropped) => info!(dropped, "Dropped queued uploads of expired frames"),
+                        Err(e) => warn!(error = %e, "Failed to update the upload queue"),
+                    }
+                }
+                Err(e) => warn!(error = %e, "Cache retention sweep failed"),
+            }
+            if let Some(bucket) = &bucket {
+                match sweep_s3(bucket, &config, time_policy, watermark).await {
+                    Ok(cutoff) => {
+                        watermark = Some(cutoff);
+                        if let Err(e) = tokio::fs::write(&watermark_path, cutoff.to_rfc3339()).await
+                        {
+                            warn!(path = %watermark_path.display(), error = %e, "Failed to save the retention watermark");
+                        }
+                    }
+                    // Retried from the same watermark on the next sweep
+                    Err(e) => warn!(error = %e, "S3 retention sweep incomplete"),
+                }
+            }
+
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }))
+}
+
+/// Delete expired files under `dir`, by modification time. Returns how many
+/// were deleted.
+fn sweep_cache(dir: &Path, config: &RetentionConfig) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let flagged_age = std::time::Duration::from_secs(config.flagged_max_age_hours * 3600);
+    let max_age = config
+        .max_age_days
+        .map(|days| std::time::Duration::from_secs(days * 24 * 3600));
+    let now = SystemTime::now();
+
+    let mut deleted = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            deleted += sweep_cache(&path, config);
+            continue;
+        }
+        // Only frames and clips, named after their capture time, including
+        // spooled uploads; the upload queue and spill files are managed
+        // by their stages
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| is_frame(name))
+        else {
+            continue;
+        };
+        let limit = if is_flagged(name) {
+            Some(flagged_age)
+        } else {
+            max_age
+        };
+        let expired = limit.is_some_and(|limit| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > limit)
+        });
+        if !expired {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => deleted += 1,
+            Err(e) => warn!(path = %path.display(), error = %e, "Failed to delete expired frame"),
+        }
+    }
+    deleted
+}
+
+/// The watermark saved by the last complete S3 sweep, if any.
+async fn read_watermark(path: &Path) -> Option<DateTime<Utc>> {
+    let content = tokio::fs::read_to_string(path).await.ok()?;
+    DateTime::parse_from_rfc3339(content.trim())
+        .inspect_err(
+            |e| warn!(path = %path.display(), error = %e, "Ignoring invalid retention watermark"),
+        )
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// Delete flagged objects older than `flagged_max_age_hours`. With a
+/// watermark only the hourly prefixes since then are listed, both the local
+/// `%Y/%m/%d/%H/` ones and those under each remote agent's host; otherwise
+/// the whole bucket is. Returns the cutoff to use as the next watermark.
+async fn sweep_s3(
+    bucket: &Bucket,
+    config: &RetentionConfig,
+    time_policy: TimePolicy,
+    watermark: Option<DateTime<Utc>>,
+) -> Result<DateTime<Utc>, Error> {
+    let cutoff = Utc::now() - Duration::hours(config.flagged_max_age_hours as i64);
+    let mut deleted = 0;
+    let mut failed = 0;
+    match watermark {
+        None => {
+            info!("No retention watermark, listing the whole bucket");
+            sweep_prefix(bucket, String::new(), cutoff, &mut deleted, &mut failed).await?;
+        }
+        Some(watermark) => {
+            // Local frames at the top level, agents' frames under their host
+            let mut roots = vec![String::new()];
+            for page in bucket.list(String::new(), Some("/".to_string())).await? {
+                for common in page.common_prefixes.into_iter().flatten() {
+                    if !is_year(&common.prefix) {
+                        roots.push(common.prefix);
+                    }
+                }
+            }
+            // An hour of slack for local time around DST changes
+            let mut hour = (watermark - Duration::hours(1)).duration_trunc(Duration::hours(1))?;
+            while hour <= cutoff {
+                let hour_prefix = format!("{}/", time_policy.format(hour, "%Y/%m/%d/%H"));
+                for root in &roots {
+                    let prefix = format!("{}{}", root, hour_prefix);
+                    sweep_prefix(bucket, prefix, cutoff, &mut deleted, &mut failed).await?;
+                }
+                hour += Duration::hours(1);
+            }
+        }
+    }
+    if deleted > 0 {
+        info!(deleted, "Deleted expired flagged S3 objects");
+    }
+    anyhow::ensure!(
+        failed == 0,
+        "{} flagged S3 objects could not be deleted",
+        failed
+    );
+    Ok(cutoff)
+}
+
+/// Delete the flagged objects under `prefix` last modified before `cutoff`.
+async fn sweep_prefix(
+    bucket: &Bucket,
+    prefix: String,
+    cutoff: DateTime<Utc>,
+    deleted: &mut usize,
+    failed: &mut usize,
+) -> Result<(), Error> {
+    let pages = bucket.list(prefix, None).await?;
+    for object in pages.iter().flat_map(|page| &page.contents) {
+        let expired = DateTime::parse_from_rfc3339(&object.last_modified)
+            .is_ok_and(|modified| modified < cutoff);
+        if !is_flagged(&object.key) || !expired {
+            continue;
+        }
+        match bucket.delete_object(&object.key).await {
+            Ok(_) => *deleted += 1,
+            Err(e) => {
+                warn!(key = object.key, error = %e, "Failed to delete flagged S3 object");
+                *failed += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a top-level prefix is a `%Y/` one of the local layout rather than
+/// a remote agent's host.
+fn is_year(prefix: &str) -> bool {
+    prefix
+        .strip_suffix('/')
+        .is_some_and(|year| year.len() == 4 && year.bytes().all(|b| b.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flagged_name() {
+        let key = flagged_name("2024/05/01/14/20240501_143005123_1.webp");
+        assert_eq!(key, "2024/05/01/14/20240501_143005123_1_flagged.webp");
+        assert!(is_flagged(&key));
+        assert!(is_flagged("20240501_143005123_1_flagged.avif"));
+        assert!(!is_flagged("20240501_143005123_1.webp"));
+        assert!(is_frame("20240501_143005123_1.webp"));
+        assert!(!is_frame("upload_queue.jsonl"));
+        assert!(is_year("2024/"));
+        assert!(!is_year("laptop/"));
+    }
+}
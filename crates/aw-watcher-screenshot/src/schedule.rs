@@ -0,0 +1,150 @@
+//! Working-hours schedule.
+//!
+//! Captures happen only on the configured weekdays between `start` and
+//! `end`, in the configured time zone or the machine's local one. A window
+//! whose end is not after its start runs past midnight and belongs to the
+//! day it starts on.
+
+use crate::config::ScheduleConfig;
+use anyhow::{Error, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, NaiveTime, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+    /// `None` for the machine's local time zone.
+    timezone: Option<Tz>,
+}
+
+impl Schedule {
+    pub fn new(config: &ScheduleConfig) -> Result<Self, Error> {
+        let days = config
+            .days
+            .iter()
+            .map(|day| {
+                day.parse::<Weekday>()
+                    .map_err(|_| anyhow::anyhow!("Invalid schedule day {:?}", day))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if days.is_empty() {
+            anyhow::bail!("schedule.days must name at least one day");
+        }
+        let time = |s: &str| {
+            NaiveTime::parse_from_str(s, "%H:%M")
+                .map_err(|_| anyhow::anyhow!("Invalid schedule time {:?}, expected HH:MM", s))
+        };
+        let timezone = config
+            .timezone
+            .as_deref()
+            .map(|name| {
+                name.parse::<Tz>()
+                    .map_err(|_| anyhow::anyhow!("Unknown time zone {:?}", name))
+            })
+            .transpose()?;
+        Ok(Self {
+            days,
+            start: time(&config.start)?,
+            end: time(&config.end)?,
+            timezone,
+        })
+    }
+
+    /// Time until the next working period starts, or `None` during one.
+    pub fn until_open(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let local = match &self.timezone {
+            Some(tz) => now.with_timezone(tz).naive_local(),
+            None => now.with_timezone(&Local).naive_local(),
+        };
+        // Wall-clock difference; off by the shift on DST change days, which
+        // the producer absorbs by checking again while it sleeps
+        self.until_open_local(local)
+            .map(|wait| wait.to_std().unwrap_or_default())
+    }
+
+    /// Whether `now` falls into a period of the schedule.
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        self.until_open(now).is_none()
+    }
+
+    fn until_open_local(&self, now: NaiveDateTime) -> Option<TimeDelta> {
+        let today = now.date();
+        let open = [today.pred_opt(), Some(today)]
+            .into_iter()
+            .flatten()
+            .filter(|day| self.days.contains(&day.weekday()))
+            .any(|day| {
+                let start = day.and_time(self.start);
+                let end = if self.end > self.start {
+                    day.and_time(self.end)
+                } else {
+                    day.and_time(self.end) + TimeDelta::days(1)
+                };
+                start <= now && now < end
+            });
+        if open {
+            return None;
+        }
+        (0..=7)
+            .filter_map(|offset| today.checked_add_signed(TimeDelta::days(offset)))
+            .filter(|day| self.days.contains(&day.weekday()))
+            .map(|day| day.and_time(self.start))
+            .find(|start| *start > now)
+            .map(|start| start - now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(days: &[&str], start: &str, end: &str) -> Schedule {
+        Schedule::new(&ScheduleConfig {
+            enabled: true,
+            days: days.iter().map(|d| d.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+            timezone: Some("UTC".to_string()),
+        })
+        .unwrap()
+    }
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_until_open() {
+        let workdays = schedule(&["mon", "tue", "wed", "thu", "fri"], "09:00", "17:00");
+        // 2024-01-05 is a Friday
+        assert_eq!(workdays.until_open_local(at("2024-01-05 12:00")), None);
+        assert_eq!(
+            workdays.until_open_local(at("2024-01-05 08:30")),
+            Some(TimeDelta::minutes(30))
+        );
+        assert_eq!(
+            workdays.until_open_local(at("2024-01-05 17:00")),
+            Some(TimeDelta::hours(64))
+        );
+
+        // Past midnight, belonging to the day it starts on
+        let nights = schedule(&["fri"], "22:00", "02:00");
+        assert_eq!(nights.until_open_local(at("2024-01-06 01:00")), None);
+        assert_eq!(
+            nights.until_open_local(at("2024-01-06 02:00")),
+            Some(TimeDelta::days(6) + TimeDelta::hours(20))
+        );
+
+        assert!(Schedule::new(&ScheduleConfig::default()).is_ok());
+        assert!(
+            Schedule::new(&ScheduleConfig {
+                timezone: Some("Mars/Olympus".to_string()),
+                ..ScheduleConfig::default()
+            })
+            .is_err()
+        );
+    }
+}
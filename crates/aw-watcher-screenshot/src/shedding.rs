@@ -0,0 +1,131 @@
+//! Load shedding under sustained backlog.
+//!
+//! When the downstream queue stays full (slow disk or uplink), the capture
+//! producer would otherwise block on every send and fall further behind.
+//! `LoadShedder` watches the queue at each tick and, once it has been full for
+//! `backlog_ticks` ticks in a row, doubles the effective capture interval (up
+//! to `max_interval_factor`). It steps back down after the same number of
+//! ticks without backlog. Every change is reported as a status event.
+
+use crate::config::LoadSheddingConfig;
+use crate::status::StatusReporter;
+use serde_json::{Map, Value};
+use tracing::warn;
+
+pub struct LoadShedder {
+    config: LoadSheddingConfig,
+    interval_secs: u64,
+    reporter: Option<StatusReporter>,
+
+    /// Effective interval as a multiple of the configured one.
+    factor: u32,
+    full_ticks: u32,
+    clear_ticks: u32,
+    tick: u64,
+}
+
+impl LoadShedder {
+    pub fn new(
+        config: LoadSheddingConfig,
+        interval_secs: u64,
+        reporter: Option<StatusReporter>,
+    ) -> Self {
+        Self {
+            config,
+            interval_secs,
+            reporter,
+            factor: 1,
+            full_ticks: 0,
+            clear_ticks: 0,
+            tick: 0,
+        }
+    }
+
+    /// Account one timer tick and decide whether to capture on it.
+    pub fn on_tick(&mut self, queue_full: bool) -> bool {
+        self.tick += 1;
+
+        if queue_full {
+            self.clear_ticks = 0;
+            self.full_ticks += 1;
+            if self.full_ticks >= self.config.backlog_ticks
+                && self.factor < self.config.max_interval_factor
+            {
+                self.full_ticks = 0;
+                self.set_factor((self.factor * 2).min(self.config.max_interval_factor));
+            }
+        } else {
+            self.full_ticks = 0;
+            self.clear_ticks += 1;
+            if self.clear_ticks >= self.config.backlog_ticks && self.factor > 1 {
+                self.clear_ticks = 0;
+                self.set_factor(self.factor / 2);
+            }
+        }
+
+        self.tick.is_multiple_of(self.factor as u64)
+    }
+
+    /// Whether capture is currently degraded.
+    pub fn is_shedding(&self) -> bool {
+        self.factor > 1
+    }
+
+    fn set_factor(&mut self, factor: u32) {
+        self.factor = factor.max(1);
+        let state = if self.is_shedding() {
+            "degraded"
+        } else {
+            "normal"
+        };
+        warn!(
+            state,
+            interval_factor = self.factor,
+            effective_interval_secs = self.interval_secs * self.factor as u64,
+            "Capture load shedding changed"
+        );
+
+        if let Some(reporter) = &self.reporter {
+            let mut data = Map::new();
+            data.insert("state".to_string(), Value::from(state));
+            data.insert("interval_factor".to_string(), Value::from(self.factor));
+            data.insert(
+                "effective_interval_secs".to_string(),
+                Value::from(self.interval_secs * self.factor as u64),
+            );
+            reporter.report("load_shedding", data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlog_lengthens_interval() {
+        let config = LoadSheddingConfig {
+            backlog_ticks: 2,
+            max_interval_factor: 4,
+            ..Default::default()
+        };
+        let mut shedder = LoadShedder::new(config, 2, None);
+
+        assert!(shedder.on_tick(true));
+        shedder.on_tick(true);
+        assert_eq!(shedder.factor, 2);
+        shedder.on_tick(true);
+        shedder.on_tick(true);
+        shedder.on_tick(true);
+        shedder.on_tick(true);
+        assert_eq!(shedder.factor, 4);
+
+        // Only every fourth tick captures while degraded
+        let captured = (0..4).filter(|_| shedder.on_tick(true)).count();
+        assert_eq!(captured, 1);
+
+        shedder.on_tick(false);
+        shedder.on_tick(false);
+        assert_eq!(shedder.factor, 2);
+    }
+}
@@ -0,0 +1,150 @@
+//! Watcher status events.
+//!
+//! Changes in how the watcher is operating (for example degraded capture
+//! under load) are posted as events to a dedicated aw-server bucket, so they
+//! show up next to the captures they affect.
+
+use crate::config::{AwServerConfig, NetworkConfig};
+use crate::network::DeferredEvents;
+use anyhow::{Error, Result};
+use aw_client_lite::AwClient;
+use aw_models::Event;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// A status event to post.
+enum Posting {
+    Insert(Event),
+    /// Replaces the event of the same kind and start, see `replace_span`.
+    Replace(Event),
+}
+
+/// Posts status events without blocking the caller.
+#[derive(Clone)]
+pub struct StatusReporter {
+    client: Arc<AwClient>,
+    bucket_id: String,
+    /// Runtime the events are posted on, so threads outside of it (hotkey
+    /// handlers) can report too.
+    runtime: tokio::runtime::Handle,
+    host: String,
+    deferred: Option<Arc<tokio::sync::Mutex<DeferredEvents<Posting>>>>,
+}
+
+impl StatusReporter {
+    pub async fn new(bucket_id: &str, aw_config: &AwServerConfig) -> Result<Self, Error> {
+        let client = AwClient::new(&aw_config.host, aw_config.port);
+        let bucket_id = format!("{}_{}", bucket_id, aw_config.hostname);
+
+        let bucket = serde_json::json!({
+            "id": bucket_id,
+            "client": aw_config.bucket_id,
+            "hostname": aw_config.hostname,
+            "type": "uno.guan810.screenshot.status"
+        });
+
+        client.create_bucket(&bucket).await?;
+        info!("Status bucket {} initialized.", bucket_id);
+
+        Ok(Self {
+            client: Arc::new(client),
+            bucket_id,
+            runtime: tokio::runtime::Handle::current(),
+            host: aw_config.host.clone(),
+            deferred: None,
+        })
+    }
+
+    /// Hold events back while the connection is metered, see
+    /// `DeferredEvents::for_host`.
+    pub fn with_metered_deferral(mut self, config: &NetworkConfig) -> Self {
+        self.deferred = DeferredEvents::for_host(config, &self.host).map(|d| Arc::new(d.into()));
+        self
+    }
+
+    /// Post a status event of the given kind with extra data.
+    pub fn report(&self, kind: &str, data: Map<String, Value>) {
+        self.report_span(kind, Utc::now(), Duration::zero(), data);
+    }
+
+    /// Post a status event covering a period of time.
+    pub fn report_span(
+        &self,
+        kind: &str,
+        timestamp: DateTime<Utc>,
+        duration: Duration,
+        mut data: Map<String, Value>,
+    ) {
+        data.insert("status".to_string(), Value::from(kind));
+        let event = Event {
+            id: None,
+            timestamp,
+            duration,
+            data,
+        };
+        self.post(Posting::Insert(event));
+    }
+
+    /// Replace the status event of the given kind posted at `timestamp` with
+    /// one covering `duration`, or post it anew when it is not found.
+    pub fn replace_span(
+        &self,
+        kind: &str,
+        timestamp: DateTime<Utc>,
+        duration: Duration,
+        mut data: Map<String, Value>,
+    ) {
+        data.insert("status".to_string(), Value::from(kind));
+        let event = Event {
+            id: None,
+            timestamp,
+            duration,
+            data,
+        };
+        self.post(Posting::Replace(event));
+    }
+
+    fn post(&self, posting: Posting) {
+        let client = self.client.clone();
+        let bucket_id = self.bucket_id.clone();
+        let deferred = self.deferred.clone();
+        self.runtime.spawn(async move {
+            let postings = match &deferred {
+                Some(deferred) => deferred.lock().await.gate(posting).await,
+                None => vec![posting],
+            };
+            for posting in postings {
+                let event = match posting {
+                    Posting::Insert(event) => event,
+                    Posting::Replace(mut event) => {
+                        event.id = find_event(&client, &bucket_id, &event).await;
+                        event
+                    }
+                };
+                // Inserting an event with an existing id replaces it on the server
+                if let Err(e) = client.insert_event(&bucket_id, &event).await {
+                    error!("Failed to post status event: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Id of the stored event with the status and start of `event`.
+async fn find_event(client: &AwClient, bucket_id: &str, event: &Event) -> Option<i64> {
+    let end = event.timestamp + event.duration;
+    let stored = client
+        .get_events(bucket_id, Some(event.timestamp), Some(end), None)
+        .await
+        .inspect_err(|e| error!("Failed to look up status event: {}", e))
+        .ok()?;
+    stored
+        .into_iter()
+        .find(|stored| {
+            stored.data.get("status") == event.data.get("status")
+                && (stored.timestamp - event.timestamp).abs() < Duration::milliseconds(1)
+        })?
+        .id
+}
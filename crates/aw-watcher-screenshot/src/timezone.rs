@@ -0,0 +1,81 @@
+//! Time zone policy for paths and reports.
+//!
+//! Event timestamps are always UTC. Cache directories, file names, object keys
+//! and day/hour report boundaries follow a `TimePolicy`, which is either UTC
+//! (the default) or the machine's local time zone so that an evening's
+//! captures stay in one day.
+
+use chrono::{
+    DateTime, DurationRound, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Timelike, Utc,
+};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimePolicy {
+    local: bool,
+}
+
+impl TimePolicy {
+    pub fn new(local: bool) -> Self {
+        Self { local }
+    }
+
+    /// Format a timestamp for use in paths and keys.
+    pub fn format(&self, timestamp: DateTime<Utc>, fmt: &str) -> String {
+        if self.local {
+            timestamp.with_timezone(&Local).format(fmt).to_string()
+        } else {
+            timestamp.format(fmt).to_string()
+        }
+    }
+
+    /// The calendar day a timestamp belongs to.
+    pub fn date(&self, timestamp: DateTime<Utc>) -> NaiveDate {
+        if self.local {
+            timestamp.with_timezone(&Local).date_naive()
+        } else {
+            timestamp.date_naive()
+        }
+    }
+
+    /// Hour of the day a timestamp belongs to.
+    pub fn hour(&self, timestamp: DateTime<Utc>) -> u32 {
+        if self.local {
+            timestamp.with_timezone(&Local).hour()
+        } else {
+            timestamp.hour()
+        }
+    }
+
+    /// Start of the hour a timestamp belongs to.
+    pub fn hour_start(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        let hour = TimeDelta::hours(1);
+        if self.local {
+            timestamp
+                .with_timezone(&Local)
+                .duration_trunc(hour)
+                .map(|t| t.with_timezone(&Utc))
+                .unwrap_or(timestamp)
+        } else {
+            timestamp.duration_trunc(hour).unwrap_or(timestamp)
+        }
+    }
+
+    /// UTC offset of path timestamps, e.g. "+02:00"; `None` for UTC paths.
+    pub fn utc_offset(&self, timestamp: DateTime<Utc>) -> Option<String> {
+        self.local
+            .then(|| timestamp.with_timezone(&Local).format("%:z").to_string())
+    }
+
+    /// Parse a timestamp written with `format`.
+    pub fn parse(&self, s: &str, fmt: &str) -> Option<DateTime<Utc>> {
+        let naive = NaiveDateTime::parse_from_str(s, fmt).ok()?;
+        if self.local {
+            Local
+                .from_local_datetime(&naive)
+                .earliest()
+                .map(|t| t.with_timezone(&Utc))
+        } else {
+            Some(naive.and_utc())
+        }
+    }
+}
@@ -0,0 +1,28 @@
+//! Focused window lookup.
+//!
+//! Used by the capture producer to attach the active application to each
+//! `CaptureEvent`, so downstream stages can reason about what the user was doing.
+
+use crate::event::FocusedWindow;
+use anyhow::{Error, Result};
+use xcap::Window;
+
+/// Return the currently focused window, if the platform reports one.
+pub fn focused_window() -> Result<Option<FocusedWindow>, Error> {
+    for window in Window::all()? {
+        if !window.is_focused().unwrap_or(false) {
+            continue;
+        }
+
+        return Ok(Some(FocusedWindow {
+            app_name: window.app_name()?,
+            title: window.title()?,
+            pid: window.pid()?,
+            x: window.x()?,
+            y: window.y()?,
+            width: window.width()?,
+            height: window.height()?,
+        }));
+    }
+    Ok(None)
+}
@@ -0,0 +1,197 @@
+//! Scene-change analytics processor.
+//!
+//! This module provides a `Processor` that derives an hourly "context switch"
+//! metric from dhash distances and focused-app changes, and publishes it to a
+//! separate ActivityWatch bucket. Capture events are passed through unchanged.
+
+use crate::config::{AnalyticsConfig, AwServerConfig};
+use crate::event::CaptureEvent;
+use crate::timezone::TimePolicy;
+use crate::worker::Processor;
+use anyhow::{Error, Result};
+use aw_client_lite::AwClient;
+use aw_models::Event;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Counters accumulated for a single hour.
+struct HourStats {
+    hour_start: DateTime<Utc>,
+    last_timestamp: DateTime<Utc>,
+    samples: u64,
+    app_switches: u64,
+    scene_changes: u64,
+    context_switches: u64,
+    total_distance: u64,
+}
+
+impl HourStats {
+    fn new(hour_start: DateTime<Utc>) -> Self {
+        Self {
+            hour_start,
+            last_timestamp: hour_start,
+            samples: 0,
+            app_switches: 0,
+            scene_changes: 0,
+            context_switches: 0,
+            total_distance: 0,
+        }
+    }
+
+    /// Fraction of samples in which the user switched app or scene (0.0 - 1.0).
+    fn score(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.context_switches as f64 / self.samples as f64
+    }
+
+    fn to_event(&self) -> Event {
+        let mut data = Map::new();
+        data.insert("samples".to_string(), Value::from(self.samples));
+        data.insert("app_switches".to_string(), Value::from(self.app_switches));
+        data.insert("scene_changes".to_string(), Value::from(self.scene_changes));
+        data.insert(
+            "mean_distance".to_string(),
+            Value::from(self.total_distance as f64 / self.samples.max(1) as f64),
+        );
+        data.insert(
+            "context_switch_score".to_string(),
+            Value::from(self.score()),
+        );
+
+        Event {
+            id: None,
+            timestamp: self.hour_start,
+            duration: self.last_timestamp - self.hour_start,
+            data,
+        }
+    }
+}
+
+/// Processor that scores context switching per hour.
+///
+/// A sample counts as a context switch when the focused application changed
+/// or the largest per-monitor hash distance reached `scene_change_threshold`.
+pub struct ContextSwitchProcessor {
+    config: AnalyticsConfig,
+    client: AwClient,
+    bucket_id: String,
+    time_policy: TimePolicy,
+
+    current: Option<HourStats>,
+    last_app: Option<String>,
+}
+
+impl ContextSwitchProcessor {
+    pub async fn new(config: AnalyticsConfig, aw_config: &AwServerConfig) -> Result<Self, Error> {
+        let client = AwClient::new(&aw_config.host, aw_config.port);
+        let bucket_id = format!("{}_{}", config.bucket_id, aw_config.hostname);
+
+        let bucket = serde_json::json!({
+            "id": bucket_id,
+            "client": aw_config.bucket_id,
+            "hostname": aw_config.hostname,
+            "type": "uno.guan810.screenshot.analytics"
+        });
+
+        client.create_bucket(&bucket).await?;
+        info!("Analytics bucket {} initialized.", bucket_id);
+
+        Ok(Self {
+            config,
+            client,
+            bucket_id,
+            time_policy: TimePolicy::default(),
+            current: None,
+            last_app: None,
+        })
+    }
+
+    /// Draw hour boundaries according to `policy`.
+    pub fn with_time_policy(mut self, policy: TimePolicy) -> Self {
+        self.time_policy = policy;
+        self
+    }
+
+    /// Account one capture tick, returning the finished hour if it rolled over.
+    fn record(&mut self, event: &CaptureEvent) -> Option<HourStats> {
+        let hour_start = self.time_policy.hour_start(event.timestamp);
+
+        let finished = match &self.current {
+            Some(stats) if stats.hour_start != hour_start => self.current.take(),
+            _ => None,
+        };
+        let stats = self
+            .current
+            .get_or_insert_with(|| HourStats::new(hour_start));
+
+        let app = event.focused_window.as_ref().map(|w| w.app_name.clone());
+        let app_switched = match (&self.last_app, &app) {
+            (Some(last), Some(current)) => last != current,
+            _ => false,
+        };
+        if app.is_some() {
+            self.last_app = app;
+        }
+
+        let distance = event.change_distances.values().copied().max().unwrap_or(0);
+        let scene_changed = distance >= self.config.scene_change_threshold;
+
+        stats.last_timestamp = event.timestamp;
+        stats.samples += 1;
+        stats.total_distance += distance as u64;
+        stats.app_switches += app_switched as u64;
+        stats.scene_changes += scene_changed as u64;
+        stats.context_switches += (app_switched || scene_changed) as u64;
+
+        finished
+    }
+
+    async fn publish(&self, stats: &HourStats) {
+        info!(
+            hour = %stats.hour_start,
+            score = stats.score(),
+            "Publishing context switch analytics"
+        );
+        if let Err(e) = self
+            .client
+            .insert_event(&self.bucket_id, &stats.to_event())
+            .await
+        {
+            error!("Failed to publish analytics event: {}", e);
+        }
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for ContextSwitchProcessor {
+    fn process(
+        mut self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        Ok(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if !event.is_clip_frame()
+                    && let Some(finished) = self.record(&event)
+                {
+                    self.publish(&finished).await;
+                }
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "ContextSwitchProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+
+            // Publish the partial hour on shutdown
+            if let Some(stats) = self.current.take() {
+                self.publish(&stats).await;
+            }
+            info!("ContextSwitchProcessor finished");
+        }))
+    }
+}
@@ -1,5 +1,9 @@
 use std::collections::HashMap;
 
+use crate::config::NetworkConfig;
+use crate::guard::session::SessionInfo;
+use crate::network::DeferredEvents;
+use crate::power::PowerProfile;
 use crate::worker::Consumer;
 use crate::{config::AwServerConfig, event::AwEvent};
 use anyhow::Error;
@@ -8,6 +12,7 @@ use aw_models::Event;
 use chrono::{DateTime, Duration, Utc};
 use serde_json::{Map, Value};
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
@@ -19,6 +24,9 @@ pub struct AwServerProcessor {
     timeout: Duration,
     last_datas: Option<AwEvent>,
     last_timestamp: HashMap<u32, DateTime<Utc>>,
+    session: Option<SessionInfo>,
+    power: Option<watch::Receiver<PowerProfile>>,
+    deferred: Option<DeferredEvents>,
 }
 
 impl AwServerProcessor {
@@ -45,16 +53,55 @@ impl AwServerProcessor {
             timeout: Duration::seconds(timeout as i64),
             last_datas: None,
             last_timestamp: HashMap::new(),
+            session: None,
+            power: None,
+            deferred: None,
         })
     }
 
-    pub async fn heartbeat(&self, event: &Event, pulse_time: f64) {
-        if let Err(e) = self
-            .client
-            .heartbeat(&self.bucket_id, event, pulse_time)
-            .await
-        {
-            error!("Failed to heartbeat: {}", e);
+    /// Record the login session in every heartbeat.
+    pub fn with_session(mut self, session: SessionInfo) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Record the active power profile in every heartbeat.
+    pub fn with_power_profile(mut self, profile: watch::Receiver<PowerProfile>) -> Self {
+        self.power = Some(profile);
+        self
+    }
+
+    /// Hold heartbeats back while the connection is metered, see
+    /// `DeferredEvents::for_host`.
+    pub fn with_metered_deferral(mut self, config: &NetworkConfig) -> Self {
+        self.deferred = DeferredEvents::for_host(config, &self.config.host);
+        self
+    }
+
+    fn heartbeat_data(&self, event: &AwEvent) -> Map<String, Value> {
+        let mut data = create_heartbeat_data(event, self.session.as_ref());
+        if let Some(profile) = &self.power {
+            data.insert(
+                "power_profile".to_string(),
+                Value::from(profile.borrow().as_str()),
+            );
+        }
+        data
+    }
+
+    pub async fn heartbeat(&mut self, event: Event, pulse_time: f64) {
+        let events = match &mut self.deferred {
+            Some(deferred) => deferred.gate(event).await,
+            None => vec![event],
+        };
+        for event in events {
+            if let Err(e) = self
+                .client
+                .heartbeat(&self.bucket_id, &event, pulse_time)
+                .await
+            {
+                error!("Failed to heartbeat: {}", e);
+            }
         }
     }
 }
@@ -80,10 +127,10 @@ impl Consumer<AwEvent> for AwServerProcessor {
                         id: None,
                         timestamp: last_heartbeat.timestamp,
                         duration: Duration::zero(),
-                        data: create_heartbeat_data(&last_heartbeat),
+                        data: self.heartbeat_data(last_heartbeat),
                     };
 
-                    self.heartbeat(&heart_beat, pulse_time).await;
+                    self.heartbeat(heart_beat, pulse_time).await;
                     continue;
                 }
 
@@ -95,13 +142,12 @@ impl Consumer<AwEvent> for AwServerProcessor {
                 // Check last_datas for missing images and retention
                 if let Some(last_datas) = &self.last_datas {
                     for (key, value) in last_datas.datas.iter() {
-                        if !event.datas.contains_key(key) {
-                            if let Some(last_ts) = self.last_timestamp.get(key) {
-                                if timestamp - *last_ts <= self.timeout {
-                                    // Keep image if within timeout
-                                    event.add_data(*key, value.clone());
-                                }
-                            }
+                        if !event.datas.contains_key(key)
+                            && let Some(last_ts) = self.last_timestamp.get(key)
+                            && timestamp - *last_ts <= self.timeout
+                        {
+                            // Keep image if within timeout
+                            event.add_data(*key, value.clone());
                         }
                     }
                 }
@@ -115,19 +161,19 @@ impl Consumer<AwEvent> for AwServerProcessor {
                         id: None,
                         timestamp: timestamp - Duration::milliseconds(1),
                         duration: Duration::zero(),
-                        data: create_heartbeat_data(&last_datas),
+                        data: self.heartbeat_data(last_datas),
                     };
-                    self.heartbeat(&finish, pulse_time).await;
+                    self.heartbeat(finish, pulse_time).await;
                 }
 
                 let heartbeat = Event {
                     id: None,
                     timestamp,
                     duration: Duration::zero(),
-                    data: create_heartbeat_data(&event),
+                    data: self.heartbeat_data(&event),
                 };
 
-                self.heartbeat(&heartbeat, pulse_time).await;
+                self.heartbeat(heartbeat, pulse_time).await;
 
                 self.last_datas = Some(event);
             }
@@ -135,7 +181,7 @@ impl Consumer<AwEvent> for AwServerProcessor {
     }
 }
 
-fn create_heartbeat_data(event: &AwEvent) -> Map<String, Value> {
+fn create_heartbeat_data(event: &AwEvent, session: Option<&SessionInfo>) -> Map<String, Value> {
     let mut map = Map::new();
     map.insert(
         "local_dir".to_string(),
@@ -143,14 +189,22 @@ fn create_heartbeat_data(event: &AwEvent) -> Map<String, Value> {
     );
     map.insert(
         "s3_info".to_string(),
-        serde_json::to_value(event.s3_info.clone()).unwrap_or(Value::Null),
+        serde_json::to_value(&event.s3_info).unwrap_or(Value::Null),
     );
 
     let mut images = Vec::new();
     for value in event.datas.values() {
-        images.push(serde_json::to_value(value.clone()).unwrap_or(Value::Null));
+        // Serialize by reference, the image info can carry text and embeddings
+        images.push(serde_json::to_value(value).unwrap_or(Value::Null));
     }
     map.insert("images".to_string(), Value::Array(images));
 
+    if let Some(session) = session {
+        map.insert(
+            "session".to_string(),
+            serde_json::to_value(session).unwrap_or(Value::Null),
+        );
+    }
+
     map
 }
@@ -1,18 +1,46 @@
-use crate::event::{CaptureEvent, ImageEvent};
+use crate::event::{
+    CaptureEvent, ChangeScore, Clip, ImageEvent, Thumbnail, UploadImageInfo, UploadVariant,
+};
+use crate::hash::{dhash, hamming_distance};
+use crate::monitor::MonitorIdentity;
+use crate::pool::BufferPool;
+use crate::power::PowerProfile;
+use crate::priority::spawn_heavy;
+use crate::retention;
+use crate::timezone::TimePolicy;
 use crate::worker::Processor;
 use anyhow::{Error, Result};
 use futures::future::join_all;
-use std::path::PathBuf;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{Semaphore, watch};
 use tokio::task::JoinHandle;
-use tracing::{error, info};
-use webp::Encoder;
+use tracing::{debug, error, info};
+
+/// Number of idle output buffers kept for reuse; enough for a few monitors'
+/// frames to be in flight in the upload and heartbeat stages.
+const MAX_POOLED_BUFFERS: usize = 16;
 
 pub struct ToWebpProcessor {
     cache_dir: PathBuf,
-    webp_quality: f32,
+    encoder: Arc<dyn FrameEncoder>,
+    config: CacheConfig,
+    pool: BufferPool,
+    time_policy: TimePolicy,
+    /// Power profile and the encoder used in battery-saver mode.
+    power: Option<(watch::Receiver<PowerProfile>, Arc<dyn FrameEncoder>)>,
+    /// Encoder for a separate upload variant of every frame.
+    upload_encoder: Option<Arc<dyn FrameEncoder>>,
+    /// Bounds the encodes running at once, see `cache.max_parallel_encodes`.
+    encode_permits: Arc<Semaphore>,
+    /// Largest hash distance between the images of mirrored monitors, see
+    /// `capture.dedup_mirrored`.
+    mirror_distance: Option<u32>,
 }
 
 impl Processor<CaptureEvent, ImageEvent> for ToWebpProcessor {
@@ -22,14 +50,59 @@ impl Processor<CaptureEvent, ImageEvent> for ToWebpProcessor {
         tx: Sender<ImageEvent>,
     ) -> Result<JoinHandle<()>, Error> {
         let cache_dir = self.cache_dir.clone();
-        let webp_quality = self.webp_quality;
+        let default_encoder = self.encoder;
+        let power = self.power;
+        let pool = self.pool;
+        let time_policy = self.time_policy;
+        let config = self.config;
+        let upload_encoder = self.upload_encoder;
+        let encode_permits = self.encode_permits;
+        let mirror_distance = self.mirror_distance;
+        // Encoders for per-monitor and adaptive qualities, created on first use
+        let mut quality_encoders: HashMap<u8, Arc<dyn FrameEncoder>> = HashMap::new();
 
         Ok(tokio::spawn(async move {
-            while let Some(event) = rx.recv().await {
+            while let Some(mut event) = rx.recv().await {
+                if let Some(clip) = event.clip.take() {
+                    match save_clip(&cache_dir, time_policy, event, clip, &pool).await {
+                        Ok(image_event) => {
+                            if let Err(e) = tx.send(image_event).await {
+                                error!("Failed to send image event: {}", e);
+                                break;
+                            }
+                        }
+                        Err(e) => error!(error = %e, "Failed to cache clip"),
+                    }
+                    continue;
+                }
+                // After every stage that withholds or alters images, so
+                // mirrors refer to an image that is actually stored
+                if let Some(max_distance) = mirror_distance {
+                    event = match spawn_heavy(move || {
+                        dedup_mirrored(&mut event, max_distance);
+                        event
+                    })
+                    .await
+                    {
+                        Ok(event) => event,
+                        Err(e) => {
+                            error!(error = %e, "Failed to find mirrored monitors");
+                            continue;
+                        }
+                    };
+                }
                 info!("ToWebpProcessor: processing {} images", event.images.len());
 
+                let battery_saver = power
+                    .as_ref()
+                    .is_some_and(|(profile, _)| *profile.borrow() == PowerProfile::BatterySaver);
+                let encoder = match &power {
+                    Some((_, low_power)) if battery_saver => low_power.clone(),
+                    _ => default_encoder.clone(),
+                };
+
                 // Compute cache path based on event timestamp
-                let cache_path = cache_dir.join(event.timestamp.format("%Y/%m/%d/%H").to_string());
+                let cache_path = cache_dir.join(time_policy.format(event.timestamp, "%Y/%m/%d/%H"));
 
                 // Create directory asynchronously
                 if let Err(e) = fs::create_dir_all(&cache_path).await {
@@ -39,54 +112,241 @@ impl Processor<CaptureEvent, ImageEvent> for ToWebpProcessor {
 
                 let cache_path = Arc::new(cache_path);
                 let mut cache_futures = Vec::new();
+                // Sensitive frames get a marker in their name so retention
+                // can delete them sooner
+                let flagged: HashSet<u32> = event
+                    .images
+                    .keys()
+                    .copied()
+                    .filter(|id| event.monitors.get(id).is_some_and(|m| m.is_sensitive()))
+                    .collect();
+                // Frames of which only the thumbnail is kept, see `cache.adaptive`
+                let mut thumbnail_only = HashSet::new();
 
                 for (key, image_data) in event.images.iter() {
                     let cache_path = cache_path.clone();
                     let image_data = image_data.clone();
                     let key = *key;
                     let timestamp = event.timestamp;
+                    let mut buffer = pool.take();
+                    let is_flagged = flagged.contains(&key);
+                    let monitor = event.monitors.get(&key);
+                    let storage = if config.adaptive.enabled && !battery_saver {
+                        adaptive_storage(
+                            &config.adaptive,
+                            monitor.and_then(|m| m.change.as_ref()),
+                            config.thumbnail_width > 0,
+                        )
+                    } else {
+                        Storage::Full(None)
+                    };
+                    if storage == Storage::ThumbnailOnly {
+                        thumbnail_only.insert(key);
+                    }
+                    // A monitor's own quality setting wins over the adaptive one
+                    let quality = monitor
+                        .and_then(|m| m.settings.as_ref()?.webp_quality)
+                        .or(match storage {
+                            Storage::Full(quality) => quality,
+                            Storage::ThumbnailOnly => None,
+                        })
+                        .filter(|_| config.encoder.has_quality());
+                    let full = storage != Storage::ThumbnailOnly;
+                    let encoder = match quality {
+                        Some(quality) if !battery_saver => quality_encoders
+                            .entry(quality)
+                            .or_insert_with(|| {
+                                encode::with_quality(&config, quality).unwrap_or_else(|e| {
+                                    error!(error = %e, "Failed to create per-monitor encoder");
+                                    encoder.clone()
+                                })
+                            })
+                            .clone(),
+                        _ => encoder.clone(),
+                    };
 
-                    // Use spawn_blocking for WebP encoding (Encoder is not Send due to raw pointers)
-                    let cache_task = async move {
-                        let webp_vec = tokio::task::spawn_blocking(move || {
-                            let encoder = Encoder::from_image(&*image_data).map_err(|e| {
-                                anyhow::anyhow!("Failed to create WebP encoder: {}", e)
-                            })?;
+                    let upload_encoder = upload_encoder.clone();
+                    let upload_buffer = upload_encoder.as_ref().map(|_| pool.take());
+                    let thumbnail_width = config.thumbnail_width;
+                    let thumbnail_quality = config.thumbnail_quality as f32;
+                    let thumbnail_buffer = (thumbnail_width > 0).then(|| pool.take());
+                    let (max_width, max_height) = (config.max_width, config.max_height);
+                    let encode_permits = encode_permits.clone();
+                    let grayscale = config.grayscale;
 
-                            let webp_data = if webp_quality >= 100.0 {
-                                encoder.encode_lossless()
+                    // Encode on a blocking thread (encoders may hold raw pointers or spawn processes)
+                    let cache_task = async move {
+                        let extension = encoder.extension();
+                        let permit = encode_permits.acquire_owned().await?;
+                        let (webp_vec, upload_vec, thumbnail) = spawn_heavy(move || {
+                            let _permit = permit;
+                            let mut image_data = match downscale(&image_data, max_width, max_height)
+                            {
+                                Some(scaled) => Arc::new(scaled),
+                                None => image_data,
+                            };
+                            if grayscale {
+                                image_data =
+                                    Arc::new(DynamicImage::ImageLuma8(image_data.to_luma8()));
+                            }
+                            let image = if full {
+                                encoder.encode(&image_data, &mut buffer)?;
+                                Some(buffer)
                             } else {
-                                encoder.encode(webp_quality)
+                                None
                             };
-
-                            Ok::<_, Error>(webp_data.to_vec())
+                            let upload = match (upload_encoder, upload_buffer) {
+                                (Some(upload_encoder), Some(mut upload_buffer)) if full => {
+                                    upload_encoder.encode(&image_data, &mut upload_buffer)?;
+                                    Some(upload_buffer)
+                                }
+                                _ => None,
+                            };
+                            let thumbnail = match thumbnail_buffer {
+                                Some(mut thumbnail_buffer) => {
+                                    let small = image_data.thumbnail(
+                                        thumbnail_width.min(image_data.width()),
+                                        u32::MAX,
+                                    );
+                                    encode_webp(&small, thumbnail_quality, &mut thumbnail_buffer);
+                                    Some((thumbnail_buffer, small.width(), small.height()))
+                                }
+                                None => None,
+                            };
+                            Ok::<_, Error>((image, upload, thumbnail))
                         })
                         .await??;
 
-                        let file_path = cache_path.join(format!(
-                            "{}_{}.webp",
-                            timestamp.format("%Y%m%d_%H%M%S%3f"),
-                            key
-                        ));
+                        let file_name = format!(
+                            "{}_{}.{}",
+                            time_policy.format(timestamp, "%Y%m%d_%H%M%S%3f"),
+                            key,
+                            extension
+                        );
+                        let mut file_names = Vec::new();
+                        if let Some(data) = &webp_vec {
+                            file_names.push((false, file_name.clone(), &**data));
+                        }
+                        if let Some((data, _, _)) = &thumbnail {
+                            file_names.push((true, thumbnail_name(&file_name), &**data));
+                        }
+                        let (mut image_path, mut thumbnail_path) = (None, None);
+                        for (is_thumbnail, mut file_name, data) in file_names {
+                            if is_flagged {
+                                file_name = retention::flagged_name(&file_name);
+                            }
+                            let file_path = cache_path.join(file_name);
 
-                        // Async file write
-                        fs::write(&file_path, &webp_vec).await?;
-                        info!(path = %file_path.display(), size_bytes = webp_vec.len(), "Saved WebP image");
+                            // Async file write
+                            fs::write(&file_path, data).await?;
+                            info!(path = %file_path.display(), size_bytes = data.len(), "Saved image");
+                            if is_thumbnail {
+                                thumbnail_path = Some(file_path);
+                            } else {
+                                image_path = Some(file_path);
+                            }
+                        }
 
-                        Ok::<_, Error>((key, webp_vec))
+                        Ok::<_, Error>((
+                            key,
+                            webp_vec,
+                            upload_vec,
+                            thumbnail,
+                            image_path,
+                            thumbnail_path,
+                        ))
                     };
 
                     cache_futures.push(cache_task);
                 }
 
+                let mut monitors = event.monitors;
+                // Thumbnail keys by monitor, filled in once encoded
+                let mut thumbnail_keys = HashMap::new();
+                for monitor in monitors.values_mut() {
+                    // Withheld frames keep their empty key
+                    if monitor.object_key.is_empty() {
+                        continue;
+                    }
+                    let cache_key = replace_extension(&monitor.object_key, encoder.extension());
+                    if let Some(upload_encoder) = &upload_encoder {
+                        monitor.object_key =
+                            replace_extension(&monitor.object_key, upload_encoder.extension());
+                        let file_name = cache_key.rsplit('/').next().unwrap_or(&cache_key);
+                        monitor.cache_file = Some(file_name.to_string());
+                    } else {
+                        monitor.object_key = cache_key;
+                    }
+                    let mut thumbnail_key = thumbnail_name(&monitor.object_key);
+                    let source = monitor.mirror_of.unwrap_or(monitor.monitor_id);
+                    if let Some(image) = event.images.get(&source)
+                        && exceeds(image, config.max_width, config.max_height)
+                    {
+                        monitor.original_width = Some(image.width());
+                        monitor.original_height = Some(image.height());
+                    }
+                    if flagged.contains(&source) {
+                        monitor.object_key = retention::flagged_name(&monitor.object_key);
+                        monitor.cache_file =
+                            monitor.cache_file.as_deref().map(retention::flagged_name);
+                        thumbnail_key = retention::flagged_name(&thumbnail_key);
+                    }
+                    thumbnail_keys.insert(monitor.monitor_id, (source, thumbnail_key));
+                    if thumbnail_only.contains(&source) {
+                        monitor.object_key.clear();
+                        monitor.cache_file = None;
+                    }
+                }
                 let mut image_event =
-                    ImageEvent::new(event.timestamp, cache_path.to_path_buf(), event.monitors);
+                    ImageEvent::new(event.timestamp, cache_path.to_path_buf(), monitors);
+                image_event.content_type = encoder.content_type();
+                image_event.upload = upload_encoder.as_ref().map(|upload_encoder| UploadVariant {
+                    datas: HashMap::new(),
+                    content_type: upload_encoder.content_type(),
+                });
 
                 let results: Vec<Result<_, Error>> = join_all(cache_futures).await;
 
                 for result in results {
                     match result {
-                        Ok((key, webp_data)) => image_event.add_data(key, webp_data),
+                        Ok((
+                            key,
+                            webp_data,
+                            upload_data,
+                            thumbnail,
+                            image_path,
+                            thumbnail_path,
+                        )) => {
+                            if let Some(webp_data) = webp_data {
+                                image_event.add_data(key, webp_data);
+                            }
+                            if let Some(path) = image_path {
+                                image_event.files.insert(key, path);
+                            }
+                            if let Some(path) = thumbnail_path {
+                                image_event.thumbnail_files.insert(key, path);
+                            }
+                            if let Some((data, width, height)) = thumbnail {
+                                image_event.thumbnails.insert(key, Arc::new(data));
+                                for (id, (source, object_key)) in &thumbnail_keys {
+                                    if *source != key {
+                                        continue;
+                                    }
+                                    if let Some(monitor) = image_event.monitors.get_mut(id) {
+                                        monitor.thumbnail = Some(Thumbnail {
+                                            object_key: object_key.clone(),
+                                            width,
+                                            height,
+                                        });
+                                    }
+                                }
+                            }
+                            if let (Some(upload), Some(data)) =
+                                (&mut image_event.upload, upload_data)
+                            {
+                                upload.datas.insert(key, Arc::new(data));
+                            }
+                        }
                         Err(e) => error!("Failed to cache image: {}", e),
                     }
                 }
@@ -101,17 +361,284 @@ impl Processor<CaptureEvent, ImageEvent> for ToWebpProcessor {
     }
 }
 
-use crate::config::CacheConfig;
+use crate::config::{AdaptiveQualityConfig, CacheConfig, PowerConfig};
+use crate::encode::{self, FrameEncoder, encode_webp};
 
 impl ToWebpProcessor {
     pub fn new(config: CacheConfig) -> Result<Self, Error> {
-        let cache_dir = PathBuf::from(config.cache_dir);
+        let cache_dir = PathBuf::from(&config.cache_dir);
+        let max_parallel_encodes = match config.max_parallel_encodes {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
 
         // Note: Directory creation is done asynchronously during processing
         // Initial directory will be created on first use
         Ok(Self {
             cache_dir,
-            webp_quality: config.webp_quality as f32,
+            encoder: encode::from_config(&config)?,
+            config,
+            pool: BufferPool::new(MAX_POOLED_BUFFERS),
+            time_policy: TimePolicy::default(),
+            power: None,
+            upload_encoder: None,
+            encode_permits: Arc::new(Semaphore::new(max_parallel_encodes)),
+            mirror_distance: None,
         })
     }
+
+    /// Encode with the cheaper battery settings from `power` in battery-saver mode.
+    pub fn with_power_profile(
+        mut self,
+        profile: watch::Receiver<PowerProfile>,
+        config: &CacheConfig,
+        power: &PowerConfig,
+    ) -> Result<Self, Error> {
+        let encoder = encode::low_power_from_config(config, power.webp_quality, power.webp_method)?;
+        self.power = Some((profile, encoder));
+        Ok(self)
+    }
+
+    /// Also encode every frame with `encoder` for upload, see `s3.encoder`.
+    pub fn with_upload_encoder(mut self, encoder: Arc<dyn FrameEncoder>) -> Self {
+        self.upload_encoder = Some(encoder);
+        self
+    }
+
+    /// Store one image for mirrored monitors, see `capture.dedup_mirrored`.
+    pub fn with_mirror_dedup(mut self, max_distance: u32) -> Self {
+        self.mirror_distance = Some(max_distance);
+        self
+    }
+
+    /// Name cache directories and files according to `policy`.
+    pub fn with_time_policy(mut self, policy: TimePolicy) -> Self {
+        self.time_policy = policy;
+        self
+    }
+}
+
+/// Keep only the lowest monitor id's image of monitors mirroring each other:
+/// the same geometry and image size, and images whose hashes are at most
+/// `max_distance` apart. The others refer to it through `mirror_of` and
+/// `object_key`.
+fn dedup_mirrored(event: &mut CaptureEvent, max_distance: u32) {
+    let geometry = |monitor: &UploadImageInfo| {
+        let identity =
+            MonitorIdentity::from_event_name(&monitor.monitor_name, &monitor.monitor_fingerprint)?;
+        Some((identity.x, identity.y, identity.width, identity.height))
+    };
+    let mut ids: Vec<u32> = event.images.keys().copied().collect();
+    ids.sort_unstable();
+    // Kept monitors with their geometry and, once needed, their hash
+    let mut kept: Vec<(u32, _, Option<u64>)> = Vec::new();
+    for id in ids {
+        let Some(own_geometry) = event.monitors.get(&id).and_then(geometry) else {
+            continue;
+        };
+        let image = event.images[&id].clone();
+        let mut own_hash = None;
+        let mut original = None;
+        for (other, other_geometry, other_hash) in &mut kept {
+            if *other_geometry != own_geometry
+                || event.images[other].dimensions() != image.dimensions()
+            {
+                continue;
+            }
+            let other_hash = *other_hash.get_or_insert_with(|| dhash(&event.images[other]));
+            let hash = *own_hash.get_or_insert_with(|| dhash(&image));
+            if hamming_distance(other_hash, hash) <= max_distance {
+                original = Some(*other);
+                break;
+            }
+        }
+        let Some(original) = original else {
+            kept.push((id, own_geometry, own_hash));
+            continue;
+        };
+
+        event.images.remove(&id);
+        let object_key = event.monitors[&original].object_key.clone();
+        if let Some(monitor) = event.monitors.get_mut(&id) {
+            monitor.object_key = object_key;
+            monitor.mirror_of = Some(original);
+        }
+        debug!(
+            monitor_id = id,
+            mirror_of = original,
+            "Dropped mirrored image"
+        );
+    }
+}
+
+/// Write an encoded clip to the cache, named after its object key, and wrap
+/// it for upload.
+async fn save_clip(
+    cache_dir: &Path,
+    time_policy: TimePolicy,
+    event: CaptureEvent,
+    clip: Clip,
+    pool: &BufferPool,
+) -> Result<ImageEvent, Error> {
+    let object_key = event
+        .monitors
+        .get(&clip.monitor_id)
+        .map(|monitor| monitor.object_key.as_str())
+        .unwrap_or_default();
+    let file_name = object_key.rsplit('/').next().unwrap_or_default();
+    if file_name.is_empty() {
+        anyhow::bail!("Clip of monitor {} has no object key", clip.monitor_id);
+    }
+    let cache_path = cache_dir.join(time_policy.format(event.timestamp, "%Y/%m/%d/%H"));
+    fs::create_dir_all(&cache_path).await?;
+    let file_path = cache_path.join(file_name);
+    fs::write(&file_path, &clip.data).await?;
+    info!(path = %file_path.display(), size_bytes = clip.data.len(), "Saved clip");
+
+    let mut buffer = pool.take();
+    buffer.extend_from_slice(&clip.data);
+    let mut image_event = ImageEvent::new(event.timestamp, cache_path, event.monitors);
+    image_event.content_type = clip.content_type;
+    image_event.add_data(clip.monitor_id, buffer);
+    image_event.files.insert(clip.monitor_id, file_path);
+    Ok(image_event)
+}
+
+/// Replace the extension of an object key, e.g. `a/b.webp` -> `a/b.avif`.
+fn replace_extension(key: &str, extension: &str) -> String {
+    match key.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => format!("{}.{}", stem, extension),
+        _ => format!("{}.{}", key, extension),
+    }
+}
+
+/// How a frame is stored, see `cache.adaptive`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Storage {
+    /// The full frame, at this WebP quality if set.
+    Full(Option<u8>),
+    /// Only the frame's thumbnail.
+    ThumbnailOnly,
+}
+
+/// Pick how to store a frame from how much it changed. Major changes get the
+/// higher quality, minor ones the lower quality or only a thumbnail, and
+/// the rest the configured quality.
+fn adaptive_storage(
+    config: &AdaptiveQualityConfig,
+    change: Option<&ChangeScore>,
+    thumbnails: bool,
+) -> Storage {
+    // The tile fraction reflects how much of the screen changed better than
+    // the hash distance, when the filter computes it
+    let score = change.map(|change| change.tiles_changed.unwrap_or(change.score));
+    match score {
+        None => Storage::Full(Some(config.major_quality)),
+        Some(score) if score >= config.major_score => Storage::Full(Some(config.major_quality)),
+        Some(score) if score < config.minor_score => {
+            if config.minor_thumbnail_only && thumbnails {
+                Storage::ThumbnailOnly
+            } else {
+                Storage::Full(Some(config.minor_quality))
+            }
+        }
+        Some(_) => Storage::Full(None),
+    }
+}
+
+/// Whether `image` is larger than the configured maximum size; 0 means no
+/// limit.
+fn exceeds(image: &DynamicImage, max_width: u32, max_height: u32) -> bool {
+    (max_width > 0 && image.width() > max_width) || (max_height > 0 && image.height() > max_height)
+}
+
+/// `image` scaled down to fit the maximum size, keeping its aspect ratio,
+/// or `None` if it already fits.
+fn downscale(image: &DynamicImage, max_width: u32, max_height: u32) -> Option<DynamicImage> {
+    if !exceeds(image, max_width, max_height) {
+        return None;
+    }
+    let width = if max_width > 0 { max_width } else { u32::MAX };
+    let height = if max_height > 0 { max_height } else { u32::MAX };
+    Some(image.resize(width, height, FilterType::Lanczos3))
+}
+
+/// Name of a frame's thumbnail, e.g. `a/b_1.png` -> `a/b_1_thumb.webp`.
+fn thumbnail_name(name: &str) -> String {
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => name,
+    };
+    format!("{}_thumb.webp", stem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_mirrored() {
+        let mut event = CaptureEvent::new();
+        let striped = DynamicImage::ImageLuma8(image::GrayImage::from_fn(64, 48, |x, _| {
+            image::Luma([if x % 16 < 8 { 0 } else { 255 }])
+        }));
+        let monitors = [
+            (0, "HDMI-1_64_48_0_0", striped.clone()),
+            (1, "HDMI-2_64_48_0_0", striped.clone()),
+            // Same picture elsewhere, e.g. the same wallpaper
+            (2, "DP-1_64_48_64_0", striped.clone()),
+            // Same place, other picture
+            (3, "DP-2_64_48_0_0", DynamicImage::new_luma8(64, 48)),
+        ];
+        for (id, name, image) in monitors {
+            let info =
+                UploadImageInfo::new(name.to_string(), id, String::new(), format!("{}.webp", id));
+            event.add_image(id, image, info);
+        }
+
+        dedup_mirrored(&mut event, 2);
+        let mut stored: Vec<u32> = event.images.keys().copied().collect();
+        stored.sort_unstable();
+        assert_eq!(stored, vec![0, 2, 3]);
+        assert_eq!(event.monitors[&1].mirror_of, Some(0));
+        assert_eq!(event.monitors[&1].object_key, "0.webp");
+        assert_eq!(event.monitors[&2].mirror_of, None);
+        assert_eq!(event.monitors[&3].mirror_of, None);
+    }
+
+    #[test]
+    fn test_adaptive_storage() {
+        let config = AdaptiveQualityConfig {
+            enabled: true,
+            minor_thumbnail_only: true,
+            ..AdaptiveQualityConfig::default()
+        };
+        let change = |distance, tiles| ChangeScore::new(distance, tiles);
+
+        assert_eq!(
+            adaptive_storage(&config, None, true),
+            Storage::Full(Some(90))
+        );
+        assert_eq!(
+            adaptive_storage(&config, Some(&change(32, None)), true),
+            Storage::Full(Some(90))
+        );
+        assert_eq!(
+            adaptive_storage(&config, Some(&change(8, None)), true),
+            Storage::Full(None)
+        );
+        assert_eq!(
+            adaptive_storage(&config, Some(&change(1, None)), true),
+            Storage::ThumbnailOnly
+        );
+        assert_eq!(
+            adaptive_storage(&config, Some(&change(1, None)), false),
+            Storage::Full(Some(50))
+        );
+        // Tile coverage takes precedence over the hash distance
+        assert_eq!(
+            adaptive_storage(&config, Some(&change(1, Some(0.5))), true),
+            Storage::Full(Some(90))
+        );
+    }
 }
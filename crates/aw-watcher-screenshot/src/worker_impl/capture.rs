@@ -3,43 +3,106 @@
 //! This module provides a `Producer` that captures screenshots from all monitors
 //! on a regular interval. The captured images are sent downstream for filtering.
 
-use crate::config::TriggerConfig;
-use crate::event::{CaptureEvent, UploadImageInfo};
+use crate::backend::xcap::XcapBackend;
+use crate::backend::{CaptureBackend, CaptureTarget, Frame};
+use crate::composite::{self, COMPOSITE_ID, Tile};
+use crate::config::{MonitorConfig, TriggerConfig, TriggerSource};
+use crate::cursor;
+use crate::event::{CaptureEvent, CaptureTrigger, ClipFrame, Rect, UploadImageInfo};
+use crate::guard::CaptureGuard;
+use crate::guard::app::{self, AppPolicies};
+use crate::idle::IdleTime;
+use crate::mask;
+use crate::monitor::{self, MonitorFilter};
+use crate::power::PowerProfile;
+use crate::schedule::Schedule;
+use crate::shedding::LoadShedder;
+use crate::timezone::TimePolicy;
+use crate::window::focused_window;
 use crate::worker::Producer;
 use anyhow::{Error, Result};
+use chrono::Utc;
+use futures::future::join_all;
 use image::DynamicImage;
+use image::imageops::FilterType;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::{Notify, watch};
 use tokio::task::JoinHandle;
-use tokio::time::{self, Interval, sleep};
+use tokio::time::{self, sleep};
 use tokio_util::sync::CancellationToken;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 use xcap::Monitor;
 
 /// Monitor information for capture.
 struct MonitorInfo {
+    /// Sanitized name, see `monitor::sanitize_name`.
     name: String,
+    /// Name as reported by the OS.
+    reported_name: String,
     id: u32,
     x: i32,
     y: i32,
     width: u32,
     height: u32,
+    scale_factor: f32,
+    fingerprint: String,
+    primary: bool,
 }
 
 impl MonitorInfo {
     fn new(monitor: Monitor) -> Result<Self, Error> {
+        let name = monitor.name()?;
+        let (x, y) = (monitor.x()?, monitor.y()?);
+        let (width, height) = (monitor.width()?, monitor.height()?);
         Ok(Self {
-            name: monitor.name()?,
+            fingerprint: monitor::fingerprint(&name, width, height, x, y),
+            name: monitor::sanitize_name(&name),
+            reported_name: name,
             id: monitor.id()?,
-            x: monitor.x()?,
-            y: monitor.y()?,
-            width: monitor.width()?,
-            height: monitor.height()?,
+            x,
+            y,
+            width,
+            height,
+            scale_factor: monitor.scale_factor().unwrap_or(1.0),
+            primary: monitor.is_primary().unwrap_or(false),
         })
     }
 
+    fn target(&self) -> CaptureTarget {
+        CaptureTarget {
+            id: self.id,
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x
+            && y >= self.y
+            && ((x - self.x) as u32) < self.width
+            && ((y - self.y) as u32) < self.height
+    }
+
+    fn identity(&self) -> monitor::MonitorIdentity<'_> {
+        monitor::MonitorIdentity {
+            name: &self.name,
+            fingerprint: &self.fingerprint,
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
     fn get_friendly_name(&self) -> String {
         format!(
             "{}_{}_{}_{}_{}",
@@ -48,10 +111,353 @@ impl MonitorInfo {
     }
 }
 
-/// Timer-based screenshot producer that captures from all monitors.
+/// Options applied to every capture, set through the producer's builder.
+#[derive(Clone, Copy, Default)]
+struct CaptureOptions {
+    /// Capture only the primary display.
+    primary_only: bool,
+    /// Capture only the monitor with the cursor or the focused window.
+    active_monitor_only: bool,
+    /// Draw the mouse cursor onto the images.
+    include_cursor: bool,
+    /// Ring the cursor while a mouse button is held down.
+    highlight_clicks: bool,
+    /// Stitch all monitors into one image.
+    composite: bool,
+    /// Resize images whose longer side exceeds this.
+    max_dimension: Option<u32>,
+    /// Probe monitors before capturing them, and capture a monitor whose
+    /// probe did not change only when it was not captured for this long.
+    probe: Option<Duration>,
+    /// Key of the hashes replacing monitor names and fingerprints in event
+    /// data.
+    anonymize: Option<[u8; 32]>,
+}
+
+/// `[[monitor]]` overrides and the `[[app]]` policies laid over them.
+#[derive(Default)]
+struct Settings {
+    monitors: Vec<Arc<MonitorConfig>>,
+    apps: Option<Arc<AppPolicies>>,
+}
+
+/// Rapid follow-up captures requested by the filter after a large change,
+/// for a burst or a clip recording.
+struct Burst {
+    requests: Arc<Notify>,
+    count: u32,
+    interval: Duration,
+    /// Minimum time between the starts of two runs.
+    cooldown: Duration,
+    /// Captures left in the running burst.
+    remaining: u32,
+    /// When the next capture of the running burst is due.
+    next: Option<Instant>,
+    /// Start of the last burst.
+    started: Option<Instant>,
+}
+
+impl Burst {
+    fn new(requests: Arc<Notify>, count: u32, interval: Duration, cooldown: Duration) -> Self {
+        Self {
+            requests,
+            count,
+            interval,
+            cooldown,
+            remaining: 0,
+            next: None,
+            started: None,
+        }
+    }
+
+    fn can_start(&self) -> bool {
+        self.remaining == 0
+            && self.count > 0
+            && self
+                .started
+                .is_none_or(|started| started.elapsed() >= self.cooldown)
+    }
+
+    fn start(&mut self) {
+        let now = Instant::now();
+        self.remaining = self.count;
+        self.next = Some(now + self.interval);
+        self.started = Some(now);
+    }
+
+    /// Account for a capture of the running burst and return its index.
+    fn advance(&mut self) -> u32 {
+        self.remaining -= 1;
+        self.next = (self.remaining > 0).then(|| Instant::now() + self.interval);
+        self.count - self.remaining - 1
+    }
+}
+
+/// What woke the producer up.
+#[derive(PartialEq, Eq)]
+enum Wake {
+    Tick,
+    Requested,
+    BurstStart,
+    BurstCapture,
+    ClipStart,
+    ClipCapture,
+}
+
+/// Longest sleep outside working hours before checking the schedule again,
+/// so clock changes and suspend are noticed.
+const SCHEDULE_RECHECK: Duration = Duration::from_secs(60);
+
+/// How often to look for displays while none are available.
+const MONITOR_RETRY: Duration = Duration::from_secs(5);
+
+/// Retry delay after a monitor failed twice in a row, doubled with every
+/// further failure up to `MAX_FAILURE_BACKOFF`.
+const FAILURE_BACKOFF: Duration = Duration::from_secs(4);
+
+const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How often the input watcher polls the idle time.
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Per-monitor capture history, by fingerprint.
+#[derive(Default)]
+struct MonitorHistory {
+    /// Last capture time, for per-monitor intervals.
+    last_captured: HashMap<String, Instant>,
+    /// Consecutive failures and when to try again.
+    failures: HashMap<String, (u32, Instant)>,
+    /// Fingerprint of the last probe, see `CaptureBackend::probe`.
+    probes: HashMap<String, u64>,
+    /// Set while a capture thread of the monitor runs, including one
+    /// abandoned after the deadline.
+    pending: HashMap<String, Arc<AtomicBool>>,
+}
+
+impl MonitorHistory {
+    /// Mark a capture of the monitor as running, unless one still is. The
+    /// mark is cleared when the returned guard is dropped.
+    fn start_capture(&mut self, fingerprint: &str) -> Option<PendingCapture> {
+        let pending = self.pending.entry(fingerprint.to_string()).or_default();
+        if pending.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        Some(PendingCapture(pending.clone()))
+    }
+
+    fn backing_off(&self, fingerprint: &str) -> bool {
+        self.failures
+            .get(fingerprint)
+            .is_some_and(|(_, retry_at)| Instant::now() < *retry_at)
+    }
+
+    /// Record a failure and return the number of consecutive failures and
+    /// the delay before the monitor is tried again.
+    fn failed(&mut self, fingerprint: &str) -> (u32, Duration) {
+        let (failures, retry_at) = self
+            .failures
+            .entry(fingerprint.to_string())
+            .or_insert((0, Instant::now()));
+        *failures += 1;
+        // The first failure may be a one-off, retry it on the next tick
+        let delay = match *failures {
+            1 => Duration::ZERO,
+            n => FAILURE_BACKOFF
+                .saturating_mul(1 << (n - 2).min(16))
+                .min(MAX_FAILURE_BACKOFF),
+        };
+        *retry_at = Instant::now() + delay;
+        (*failures, delay)
+    }
+
+    /// Record a success and return the number of failures it ends.
+    fn succeeded(&mut self, fingerprint: &str) -> Option<u32> {
+        self.last_captured
+            .insert(fingerprint.to_string(), Instant::now());
+        self.failures
+            .remove(fingerprint)
+            .map(|(failures, _)| failures)
+    }
+}
+
+/// Clears a monitor's pending mark when its capture thread finishes.
+struct PendingCapture(Arc<AtomicBool>);
+
+impl Drop for PendingCapture {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// What starts a capture.
+enum TickSource {
+    /// Fires every `period`, each time at a random offset up to `jitter`
+    /// after the regular deadline `next`, so the jitter never delays the
+    /// following ticks.
+    Timer {
+        period: Duration,
+        jitter: Duration,
+        next: Instant,
+    },
+    /// Signals from the input watcher thread, see `watch_input`.
+    Input(mpsc::Receiver<()>),
+    /// Signals from the window title watcher thread, see `watch_title`.
+    Title(mpsc::Receiver<()>),
+}
+
+impl TickSource {
+    fn new(trigger_config: &TriggerConfig, interval: Duration) -> Self {
+        match trigger_config.source {
+            TriggerSource::Input => {
+                let debounce = Duration::from_millis(trigger_config.input_debounce_ms);
+                match spawn_input_watcher(debounce, interval) {
+                    Ok(rx) => return TickSource::Input(rx),
+                    Err(e) => {
+                        warn!(error = %e, "Input trigger unavailable, falling back to the timer")
+                    }
+                }
+            }
+            TriggerSource::Title => {
+                let poll = Duration::from_millis(trigger_config.title_poll_ms.max(1));
+                match spawn_title_watcher(poll) {
+                    Ok(rx) => return TickSource::Title(rx),
+                    Err(e) => {
+                        warn!(error = %e, "Title trigger unavailable, falling back to the timer")
+                    }
+                }
+            }
+            TriggerSource::Timer => {}
+        }
+        let jitter = Duration::from_secs(trigger_config.jitter_secs).min(interval);
+        TickSource::Timer {
+            period: interval,
+            jitter,
+            next: Instant::now(),
+        }
+    }
+
+    async fn tick(&mut self) {
+        match self {
+            TickSource::Timer {
+                period,
+                jitter,
+                next,
+            } => {
+                let deadline = *next + jitter.mul_f64(fastrand::f64());
+                time::sleep_until(deadline.into()).await;
+                *next += *period;
+            }
+            TickSource::Input(rx) | TickSource::Title(rx) => {
+                if rx.recv().await.is_none() {
+                    warn!("Trigger watcher stopped, no further captures will be triggered");
+                    std::future::pending::<()>().await;
+                }
+            }
+        }
+    }
+
+    /// Drop ticks that piled up while the producer was not waiting for them;
+    /// the timer fires right away.
+    fn reset(&mut self) {
+        match self {
+            TickSource::Timer { next, .. } => *next = Instant::now(),
+            TickSource::Input(rx) | TickSource::Title(rx) => while rx.try_recv().is_ok() {},
+        }
+    }
+}
+
+/// Start a thread that signals a capture once input pauses for `debounce`
+/// after activity, or after `max_delay` of continuous activity.
+fn spawn_input_watcher(
+    debounce: Duration,
+    max_delay: Duration,
+) -> Result<mpsc::Receiver<()>, Error> {
+    let idle = IdleTime::new()?;
+    let (tx, rx) = mpsc::channel(1);
+    std::thread::Builder::new()
+        .name("input-trigger".to_string())
+        .spawn(move || watch_input(idle, debounce, max_delay, tx))?;
+    Ok(rx)
+}
+
+fn watch_input(idle: IdleTime, debounce: Duration, max_delay: Duration, tx: mpsc::Sender<()>) {
+    // Set from the first reading, which says nothing about new input
+    let mut last_idle: Option<Duration> = None;
+    let mut active_since: Option<Instant> = None;
+
+    while !tx.is_closed() {
+        std::thread::sleep(INPUT_POLL_INTERVAL);
+        let idle_time = match idle.get() {
+            Ok(idle_time) => idle_time,
+            Err(e) => {
+                debug!(error = %e, "Failed to query idle time");
+                continue;
+            }
+        };
+
+        // The idle time only goes down when there was input since the last poll
+        if last_idle.is_some_and(|last_idle| idle_time < last_idle) {
+            active_since.get_or_insert_with(Instant::now);
+        }
+        last_idle = Some(idle_time);
+
+        if let Some(since) = active_since
+            && (idle_time >= debounce || since.elapsed() >= max_delay)
+        {
+            active_since = None;
+            // A full channel means a capture is already pending
+            if let Err(TrySendError::Closed(_)) = tx.try_send(()) {
+                break;
+            }
+        }
+    }
+}
+
+/// Start a thread that signals a capture whenever the focused window's
+/// title changes, polling it every `poll`.
+fn spawn_title_watcher(poll: Duration) -> Result<mpsc::Receiver<()>, Error> {
+    // Fail early on platforms without focused window lookup
+    focused_window()?;
+    let (tx, rx) = mpsc::channel(1);
+    std::thread::Builder::new()
+        .name("title-trigger".to_string())
+        .spawn(move || watch_title(poll, tx))?;
+    Ok(rx)
+}
+
+fn watch_title(poll: Duration, tx: mpsc::Sender<()>) {
+    let mut last_title: Option<String> = None;
+
+    while !tx.is_closed() {
+        let title = match focused_window() {
+            Ok(window) => window.map(|window| window.title),
+            Err(e) => {
+                debug!(error = %e, "Failed to get focused window");
+                std::thread::sleep(poll);
+                continue;
+            }
+        };
+        // No focused window (e.g. the desktop) is not a new document
+        if let Some(title) = title
+            && last_title.as_ref() != Some(&title)
+        {
+            debug!(title = %title, "Focused window title changed");
+            last_title = Some(title);
+            // A full channel means a capture is already pending
+            if let Err(TrySendError::Closed(_)) = tx.try_send(()) {
+                break;
+            }
+        }
+        std::thread::sleep(poll);
+    }
+}
+
+/// Screenshot producer that captures from all monitors.
 ///
 /// This producer operates in **Source mode**, meaning it has no input channel
-/// and only produces outputs. It captures screenshots at regular intervals
+/// and only produces outputs. It captures screenshots at regular intervals,
+/// after bursts of keyboard/mouse input with `trigger.source = "input"`, or
+/// when the focused window title changes with `trigger.source = "title"`,
 /// without any filtering - filtering is done by a downstream processor.
 ///
 /// # Example
@@ -61,9 +467,31 @@ impl MonitorInfo {
 /// // Wire to filter processor downstream
 /// ```
 pub struct TimerCaptureProducer {
-    interval: Interval,
+    ticks: TickSource,
     timeout: Option<Duration>,
+    /// Stop after emitting this many events.
+    max_captures: Option<u64>,
+    monitor_timeout: Duration,
     token: CancellationToken,
+    shedder: Option<LoadShedder>,
+    guards: Vec<Box<dyn CaptureGuard>>,
+    time_policy: TimePolicy,
+    /// Power profile and the interval multiplier applied in battery-saver mode.
+    power: Option<(watch::Receiver<PowerProfile>, u32)>,
+    settings: Settings,
+    monitor_filter: MonitorFilter,
+    history: MonitorHistory,
+    /// Notified to capture immediately, outside the regular schedule.
+    capture_requests: Option<Arc<Notify>>,
+    options: CaptureOptions,
+    schedule: Option<Schedule>,
+    burst: Option<Burst>,
+    clip: Option<Burst>,
+    /// Clip recordings started so far, numbering their frames.
+    clips: u64,
+    backend: Arc<dyn CaptureBackend>,
+    /// Meeting state stamped on every event, see `with_meeting_state`.
+    meeting: Option<Arc<AtomicBool>>,
 }
 
 impl TimerCaptureProducer {
@@ -74,35 +502,554 @@ impl TimerCaptureProducer {
     /// * `trigger_config` - Configuration for timer interval and timeout
     /// * `token` - Cancellation token for graceful shutdown
     pub fn new(trigger_config: TriggerConfig, token: CancellationToken) -> Result<Self, Error> {
-        let real_monitors = Monitor::all()?;
-        info!(
-            "TimerCaptureProducer: Found {} monitors",
-            real_monitors.len()
-        );
-
-        let mut monitors = Vec::new();
-        for monitor in real_monitors {
-            let monitor_info = MonitorInfo::new(monitor)?;
-            monitors.push(monitor_info);
+        // Headless sessions and disconnected RDP sessions have no displays
+        // yet; the capture loop waits for them instead of failing here
+        match list_monitors() {
+            Ok(monitors) => info!("TimerCaptureProducer: Found {} monitors", monitors.len()),
+            Err(e) => warn!(error = %e, "No monitors available yet, waiting for displays"),
         }
 
         let interval_duration = Duration::from_secs(trigger_config.interval_secs);
         let timeout = trigger_config.timeout_secs.map(Duration::from_secs);
+        let monitor_timeout = trigger_config
+            .monitor_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(interval_duration);
 
         Ok(Self {
-            interval: time::interval(interval_duration),
+            ticks: TickSource::new(&trigger_config, interval_duration),
             timeout,
+            max_captures: trigger_config.max_captures,
+            monitor_timeout,
             token,
+            shedder: None,
+            guards: Vec::new(),
+            time_policy: TimePolicy::default(),
+            power: None,
+            settings: Settings::default(),
+            monitor_filter: MonitorFilter::default(),
+            history: MonitorHistory::default(),
+            capture_requests: None,
+            options: CaptureOptions::default(),
+            schedule: None,
+            burst: None,
+            clip: None,
+            clips: 0,
+            backend: Arc::new(XcapBackend),
+            meeting: None,
         })
     }
+
+    /// Apply `[[monitor]]` overrides: skip excluded monitors, capture
+    /// monitors with their own interval less often, and attach the matched
+    /// settings to each image for the downstream stages.
+    pub fn with_monitor_settings(mut self, settings: Vec<MonitorConfig>) -> Self {
+        self.settings.monitors = settings.into_iter().map(Arc::new).collect();
+        self
+    }
+
+    /// Lay the `[[app]]` policy of the focused application over each
+    /// monitor's settings, and pause while an app with `skip` is focused.
+    pub fn with_app_policies(mut self, policies: AppPolicies) -> Self {
+        let policies = Arc::new(policies);
+        self.settings.apps = Some(policies.clone());
+        self.with_guard(policies)
+    }
+
+    /// Capture only every `interval_factor`-th tick in battery-saver mode.
+    pub fn with_power_profile(
+        mut self,
+        profile: watch::Receiver<PowerProfile>,
+        interval_factor: u32,
+    ) -> Self {
+        self.power = Some((profile, interval_factor.max(1)));
+        self
+    }
+
+    /// Format object keys according to `policy`.
+    pub fn with_time_policy(mut self, policy: TimePolicy) -> Self {
+        self.time_policy = policy;
+        self
+    }
+
+    /// Grab monitor images with `backend` instead of xcap.
+    pub fn with_backend(mut self, backend: Arc<dyn CaptureBackend>) -> Self {
+        info!(backend = backend.name(), "Using capture backend");
+        self.backend = backend;
+        self
+    }
+
+    /// Capture only the monitors whose name passes `filter`.
+    pub fn with_monitor_filter(mut self, filter: MonitorFilter) -> Self {
+        self.monitor_filter = filter;
+        self
+    }
+
+    /// Capture only the monitor containing the mouse cursor, or else the
+    /// center of the focused window.
+    pub fn with_active_monitor_only(mut self, active_monitor_only: bool) -> Self {
+        self.options.active_monitor_only = active_monitor_only;
+        self
+    }
+
+    /// Capture only the primary display.
+    pub fn with_primary_only(mut self, primary_only: bool) -> Self {
+        self.options.primary_only = primary_only;
+        self
+    }
+
+    /// Draw the mouse cursor onto captures, ringed while a button is held
+    /// down if `highlight_clicks` is set.
+    pub fn with_cursor(mut self, highlight_clicks: bool) -> Self {
+        self.options.include_cursor = true;
+        self.options.highlight_clicks = highlight_clicks;
+        self
+    }
+
+    /// Capture only within the working hours of `schedule`, sleeping outside them.
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    /// Stitch the monitors of each capture into one image laid out by their
+    /// desktop geometry.
+    pub fn with_composite(mut self, composite: bool) -> Self {
+        self.options.composite = composite;
+        self
+    }
+
+    /// Resize captures right away so their longer side is at most `max_dimension`.
+    pub fn with_max_dimension(mut self, max_dimension: Option<u32>) -> Self {
+        self.options.max_dimension = max_dimension;
+        self
+    }
+
+    /// Replace monitor names and fingerprints in event data with hashes
+    /// keyed with `key`, see `monitor::salt_key`. Geometry stays in the name
+    /// so per-monitor handling downstream keeps working.
+    pub fn with_anonymized_names(mut self, key: [u8; 32]) -> Self {
+        self.options.anonymize = Some(key);
+        self
+    }
+
+    /// Probe each monitor before capturing it and skip monitors whose probe
+    /// did not change, but capture every monitor at least every `force_interval`
+    /// (or its own `force_interval_secs`).
+    pub fn with_probe(mut self, force_interval: Duration) -> Self {
+        self.options.probe = Some(force_interval);
+        self
+    }
+
+    /// Also capture whenever `requests` is notified.
+    pub fn with_capture_requests(mut self, requests: Arc<Notify>) -> Self {
+        self.capture_requests = Some(requests);
+        self
+    }
+
+    /// Mark events captured while `active` is set as taken during a meeting,
+    /// so later stages act on the state at capture time.
+    pub fn with_meeting_state(mut self, active: Arc<AtomicBool>) -> Self {
+        self.meeting = Some(active);
+        self
+    }
+
+    /// Take `count` follow-up captures `interval` apart whenever `requests`
+    /// is notified; requests during or right after a burst are ignored.
+    pub fn with_burst(mut self, requests: Arc<Notify>, count: u32, interval: Duration) -> Self {
+        // Bursts start at most once per two burst lengths, so continuous change
+        // such as video does not chain them into a high capture rate
+        let cooldown = interval * count * 2;
+        self.burst = Some(Burst::new(requests, count, interval, cooldown));
+        self
+    }
+
+    /// Record a clip of `count` frames `interval` apart whenever `requests`
+    /// is notified, at most once per `cooldown`. The frames pass the guards
+    /// like any capture and are assembled by `ClipProcessor`.
+    pub fn with_clips(
+        mut self,
+        requests: Arc<Notify>,
+        count: u32,
+        interval: Duration,
+        cooldown: Duration,
+    ) -> Self {
+        let cooldown = cooldown.max(interval * count);
+        self.clip = Some(Burst::new(requests, count, interval, cooldown));
+        self
+    }
+
+    /// Skip ticks while `guard` reports a reason to pause.
+    pub fn with_guard(mut self, guard: impl CaptureGuard + 'static) -> Self {
+        self.guards.push(Box::new(guard));
+        self
+    }
+
+    /// Shed load instead of blocking when the downstream queue stays full.
+    pub fn with_load_shedding(mut self, shedder: LoadShedder) -> Self {
+        self.shedder = Some(shedder);
+        self
+    }
+}
+
+/// Wait for an explicit capture request, forever if there is no source.
+async fn capture_requested(requests: Option<&Notify>) {
+    match requests {
+        Some(requests) => requests.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Enumerate the connected monitors, failing if there are none.
+fn list_monitors() -> Result<Vec<MonitorInfo>, Error> {
+    let monitors: Vec<MonitorInfo> = Monitor::all()?
+        .into_iter()
+        .filter_map(|monitor| {
+            MonitorInfo::new(monitor)
+                .inspect_err(|e| error!(error = %e, "Failed to get monitor info"))
+                .ok()
+        })
+        .collect();
+    if monitors.is_empty() {
+        anyhow::bail!("No monitors found");
+    }
+    Ok(monitors)
+}
+
+/// Wait until the next capture of a running burst or clip is due, forever if
+/// none is.
+async fn burst_due(burst: Option<&Burst>) {
+    match burst.and_then(|burst| burst.next) {
+        Some(next) => time::sleep_until(next.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Capture all monitors concurrently.
+///
+/// Each monitor is grabbed on its own blocking thread so one slow display
+/// does not delay the others. Monitors that miss `deadline` are left out of
+/// the event; their capture thread is abandoned and finishes in the
+/// background, and the monitor is skipped until it has.
+async fn capture_all(
+    deadline: Duration,
+    time_policy: TimePolicy,
+    backend: &Arc<dyn CaptureBackend>,
+    settings: &Settings,
+    filter: &MonitorFilter,
+    history: &mut MonitorHistory,
+    options: CaptureOptions,
+) -> Result<CaptureEvent, Error> {
+    let (mut monitors, focused, cursor) = tokio::task::spawn_blocking(move || {
+        let monitors = list_monitors()?;
+        let focused = focused_window().unwrap_or_else(|e| {
+            debug!(error = %e, "Failed to get focused window");
+            None
+        });
+        let cursor = if options.include_cursor || options.active_monitor_only {
+            cursor::query()
+                .inspect_err(|e| debug!(error = %e, "Failed to get cursor position"))
+                .ok()
+        } else {
+            None
+        };
+        Ok::<_, Error>((monitors, focused, cursor))
+    })
+    .await??;
+
+    // Selection by name first, so `primary_only` and `active_monitor_only`
+    // pick among the selected monitors
+    monitors.retain(|monitor| {
+        let allowed = filter.allows(&monitor.reported_name);
+        if !allowed {
+            debug!(monitor_name = %monitor.name, "Skipping monitor not selected by name");
+        }
+        allowed
+    });
+    // Backends that cannot tell the primary display keep capturing all of them
+    if options.primary_only && monitors.iter().any(|monitor| monitor.primary) {
+        monitors.retain(|monitor| monitor.primary);
+    }
+    if options.active_monitor_only {
+        let point = cursor.as_ref().map(|c| (c.x, c.y)).or_else(|| {
+            focused
+                .as_ref()
+                .map(|w| (w.x + (w.width / 2) as i32, w.y + (w.height / 2) as i32))
+        });
+        // Capture everything when neither is known rather than nothing
+        if let Some((x, y)) = point
+            && monitors.iter().any(|monitor| monitor.contains(x, y))
+        {
+            monitors.retain(|monitor| monitor.contains(x, y));
+        }
+    }
+    let cursor = cursor.filter(|_| options.include_cursor);
+
+    let mut event = CaptureEvent::new();
+    event.focused_window = focused;
+    // Desktop geometry of each captured area, for the composite
+    let mut areas: HashMap<u32, (i32, i32, u32, u32)> = HashMap::new();
+
+    let captures = monitors.into_iter().filter_map(|monitor_info| {
+        let mut monitor_settings =
+            monitor::find_settings(&settings.monitors, &monitor_info.identity()).cloned();
+        if let Some(apps) = &settings.apps {
+            monitor_settings =
+                app::apply_focused(apps, event.focused_window.as_ref(), monitor_settings);
+        }
+        let settings = monitor_settings;
+        if let Some(settings) = &settings {
+            if settings.exclude {
+                debug!(monitor_name = %monitor_info.name, "Skipping excluded monitor");
+                return None;
+            }
+            if let Some(secs) = settings.interval_secs
+                && history
+                    .last_captured
+                    .get(&monitor_info.fingerprint)
+                    .is_some_and(|last| last.elapsed() < Duration::from_secs(secs))
+            {
+                return None;
+            }
+        }
+        if history.backing_off(&monitor_info.fingerprint) {
+            return None;
+        }
+        let Some(pending) = history.start_capture(&monitor_info.fingerprint) else {
+            debug!(monitor_name = %monitor_info.name, "Skipping monitor, its last capture is still running");
+            return None;
+        };
+        let target = monitor_info.target();
+        let region = settings.as_ref().and_then(|settings| settings.region);
+        let backend = backend.clone();
+        // The previous probe to compare with, unless a full capture is due
+        let probe = options.probe.map(|force_interval| {
+            let force_interval = settings
+                .as_ref()
+                .and_then(|settings| settings.force_interval_secs)
+                .map_or(force_interval, Duration::from_secs);
+            let due = history
+                .last_captured
+                .get(&monitor_info.fingerprint)
+                .is_none_or(|last| last.elapsed() >= force_interval);
+            let previous = history.probes.get(&monitor_info.fingerprint).copied();
+            previous.filter(|_| !due)
+        });
+        let task = tokio::task::spawn_blocking(move || {
+            let _pending = pending;
+            let Some(previous) = probe else {
+                return backend
+                    .capture(&target, region)
+                    .map(|frame| (None, Some(frame)));
+            };
+            let current = backend.probe(&target, region).unwrap_or_else(|e| {
+                debug!(error = %e, "Failed to probe monitor");
+                None
+            });
+            if current.is_some() && current == previous {
+                return Ok((current, None));
+            }
+            Ok((current, Some(backend.capture(&target, region)?)))
+        });
+        Some(async move { (monitor_info, settings, time::timeout(deadline, task).await) })
+    });
+
+    for (monitor_info, settings, result) in join_all(captures.collect::<Vec<_>>()).await {
+        let error = match result {
+            Ok(Ok(Ok((probe, frame)))) => {
+                if let Some(probe) = probe {
+                    history
+                        .probes
+                        .insert(monitor_info.fingerprint.clone(), probe);
+                }
+                let Some(Frame {
+                    mut image,
+                    region,
+                    mut damage,
+                }) = frame
+                else {
+                    debug!(monitor_name = %monitor_info.name, "Monitor unchanged since the last probe");
+                    continue;
+                };
+                // Blackouts go first, so that nothing downstream, in any
+                // mode, sees the pixels they cover
+                if let Some(settings) = &settings {
+                    let regions = mask::blackout_regions(settings);
+                    if !regions.is_empty() {
+                        let area = region.unwrap_or(Rect {
+                            x: 0,
+                            y: 0,
+                            width: monitor_info.width,
+                            height: monitor_info.height,
+                        });
+                        let rects = mask::area_rects(area, &image, &regions);
+                        mask::fill(&mut image, &rects);
+                    }
+                }
+                if let Some(cursor) = &cursor {
+                    let target = monitor_info.target();
+                    cursor::draw(
+                        &mut image,
+                        cursor,
+                        &target,
+                        region,
+                        options.highlight_clicks,
+                    );
+                }
+                if let Some(max_dimension) = options.max_dimension {
+                    downscale(&mut image, damage.as_mut(), max_dimension);
+                }
+                let (name, fingerprint) = match &options.anonymize {
+                    Some(key) => (
+                        format!(
+                            "monitor-{}_{}_{}_{}_{}",
+                            monitor::salted_hash(&monitor_info.name, key),
+                            monitor_info.width,
+                            monitor_info.height,
+                            monitor_info.x,
+                            monitor_info.y
+                        ),
+                        monitor::salted_hash(&monitor_info.fingerprint, key),
+                    ),
+                    None => (
+                        monitor_info.get_friendly_name(),
+                        monitor_info.fingerprint.clone(),
+                    ),
+                };
+                let mut upload_info = UploadImageInfo::new(
+                    name,
+                    monitor_info.id,
+                    fingerprint,
+                    format!(
+                        "{}/{}_{}.webp",
+                        time_policy.format(event.timestamp, "%Y/%m/%d/%H"),
+                        time_policy.format(event.timestamp, "%Y%m%d_%H%M%S%3f"),
+                        monitor_info.id
+                    ),
+                );
+                upload_info.utc_offset = time_policy.utc_offset(event.timestamp);
+                upload_info.scale_factor = Some(monitor_info.scale_factor);
+                upload_info.region = region;
+                if let Some(damage) = damage {
+                    event.damage.insert(monitor_info.id, damage);
+                }
+                upload_info.settings = settings;
+                upload_info.target = Some(monitor_info.target());
+                areas.insert(
+                    monitor_info.id,
+                    match region {
+                        Some(r) => (
+                            monitor_info.x + r.x as i32,
+                            monitor_info.y + r.y as i32,
+                            r.width,
+                            r.height,
+                        ),
+                        None => (
+                            monitor_info.x,
+                            monitor_info.y,
+                            monitor_info.width,
+                            monitor_info.height,
+                        ),
+                    },
+                );
+                if let Some(failures) = history.succeeded(&monitor_info.fingerprint) {
+                    info!(
+                        monitor_id = monitor_info.id,
+                        monitor_name = %monitor_info.get_friendly_name(),
+                        failures,
+                        "Monitor capture recovered"
+                    );
+                }
+                event.add_image(monitor_info.id, image, upload_info);
+                continue;
+            }
+            Ok(Ok(Err(e))) => e,
+            Ok(Err(e)) => e.into(),
+            Err(_) => anyhow::anyhow!("Capture exceeded the {:?} deadline", deadline),
+        };
+        match history.failed(&monitor_info.fingerprint) {
+            (1, _) => error!(
+                monitor_id = monitor_info.id,
+                monitor_name = %monitor_info.get_friendly_name(),
+                error = %error,
+                "Failed to capture monitor"
+            ),
+            (failures, retry_in) => warn!(
+                monitor_id = monitor_info.id,
+                monitor_name = %monitor_info.get_friendly_name(),
+                error = %error,
+                failures,
+                ?retry_in,
+                "Monitor keeps failing, backing off"
+            ),
+        }
+    }
+
+    if options.composite && !event.images.is_empty() {
+        stitch_monitors(&mut event, &areas, time_policy);
+    }
+    Ok(event)
 }
 
-/// Capture a screenshot from the monitor at the given screen coordinates.
-fn capture_monitor(x: i32, y: i32) -> Result<DynamicImage, Error> {
-    let monitor = Monitor::from_point(x, y)?;
-    let image = monitor.capture_image()?;
-    let image = DynamicImage::ImageRgba8(image);
-    Ok(image)
+/// Shrink `image` to fit `max_dimension` on its longer side, scaling `damage` along.
+fn downscale(image: &mut DynamicImage, damage: Option<&mut Vec<Rect>>, max_dimension: u32) {
+    let (width, height) = (image.width(), image.height());
+    if width.max(height) <= max_dimension {
+        return;
+    }
+    *image = image.resize(max_dimension, max_dimension, FilterType::Triangle);
+    let factor = image.width() as f64 / width as f64;
+    for rect in damage.into_iter().flatten() {
+        *rect = Rect {
+            x: (rect.x as f64 * factor) as u32,
+            y: (rect.y as f64 * factor) as u32,
+            width: ((rect.width as f64 * factor).ceil() as u32).max(1),
+            height: ((rect.height as f64 * factor).ceil() as u32).max(1),
+        };
+    }
+}
+
+/// Replace the monitor images of `event` with one composite image.
+fn stitch_monitors(
+    event: &mut CaptureEvent,
+    areas: &HashMap<u32, (i32, i32, u32, u32)>,
+    time_policy: TimePolicy,
+) {
+    let tiles: Vec<Tile> = event
+        .images
+        .drain()
+        .filter_map(|(id, image)| {
+            let &(x, y, width, height) = areas.get(&id)?;
+            Some(Tile {
+                image,
+                x,
+                y,
+                width,
+                height,
+                damage: event.damage.remove(&id),
+            })
+        })
+        .collect();
+    event.monitors.clear();
+    event.damage.clear();
+    let Some((image, damage)) = composite::stitch(&tiles) else {
+        return;
+    };
+    let mut upload_info = UploadImageInfo::new(
+        format!("composite_{}_{}", image.width(), image.height()),
+        COMPOSITE_ID,
+        "composite".to_string(),
+        format!(
+            "{}/{}_composite.webp",
+            time_policy.format(event.timestamp, "%Y/%m/%d/%H"),
+            time_policy.format(event.timestamp, "%Y%m%d_%H%M%S%3f"),
+        ),
+    );
+    upload_info.utc_offset = time_policy.utc_offset(event.timestamp);
+    if let Some(damage) = damage {
+        event.damage.insert(COMPOSITE_ID, damage);
+    }
+    event.add_image(COMPOSITE_ID, image, upload_info);
 }
 
 // #[async_trait]
@@ -116,8 +1063,46 @@ impl Producer<CaptureEvent> for TimerCaptureProducer {
             // Pin the future so we can borrow it in the select! loop
             tokio::pin!(timeout_future);
 
+            let guards = Arc::new(std::mem::take(&mut self.guards));
+            let mut paused: Option<String> = None;
+            let mut battery_ticks: u32 = 0;
+            let mut off_hours = false;
+            let mut emitted: u64 = 0;
+
             loop {
-                tokio::select! {
+                if let Some(schedule) = &self.schedule {
+                    match schedule.until_open(Utc::now()) {
+                        Some(wait) => {
+                            if !off_hours {
+                                info!(
+                                    resume_in_secs = wait.as_secs(),
+                                    "Outside working hours, sleeping"
+                                );
+                                off_hours = true;
+                            }
+                            tokio::select! {
+                                _ = self.token.cancelled() => {
+                                    info!("TimerCaptureProducer cancelled");
+                                    break;
+                                }
+                                _ = &mut timeout_future => {
+                                    info!("TimerCaptureProducer timed out");
+                                    break;
+                                }
+                                _ = sleep(wait.min(SCHEDULE_RECHECK)) => continue,
+                            }
+                        }
+                        None if off_hours => {
+                            info!("Working hours started");
+                            off_hours = false;
+                            // Don't replay the ticks missed while sleeping
+                            self.ticks.reset();
+                        }
+                        None => {}
+                    }
+                }
+
+                let wake = tokio::select! {
                     _ = self.token.cancelled() => {
                         info!("TimerCaptureProducer cancelled");
                         break;
@@ -126,64 +1111,177 @@ impl Producer<CaptureEvent> for TimerCaptureProducer {
                         info!("TimerCaptureProducer timed out");
                         break;
                     }
-                    _ = self.interval.tick() => {
-                        // Hot-plug support: refresh monitor list each capture cycle
-                        // This handles monitors being connected/disconnected at runtime
-                        match tokio::task::spawn_blocking(|| {
-                            let monitors = Monitor::all()?;
-                            let mut event = CaptureEvent::new();
-
-                            for monitor in monitors {
-                                let monitor_info = match MonitorInfo::new(monitor) {
-                                    Ok(info) => info,
-                                    Err(e) => {
-                                        error!(error = %e, "Failed to get monitor info");
-                                        continue;
-                                    }
-                                };
-
-                                match capture_monitor(monitor_info.x, monitor_info.y) {
-                                    Ok(image) => {
-                                        let upload_info = UploadImageInfo::new(
-                                            monitor_info.get_friendly_name(),
-                                            monitor_info.id,
-                                            format!(
-                                                "{}/{}.webp",
-                                                event.timestamp.format("%Y/%m/%d/%H"),
-                                                format!("{}_{}", event.timestamp.format("%Y%m%d_%H%M%S%3f"), monitor_info.id)
-                                            ),
-                                        );
-                                        event.add_image(monitor_info.id, image, upload_info);
-                                    }
-                                    Err(e) => {
-                                        error!(
-                                            monitor_id = monitor_info.id,
-                                            monitor_name = %monitor_info.get_friendly_name(),
-                                            error = %e,
-                                            "Failed to capture monitor"
-                                        );
-                                    }
-                                }
+                    _ = self.ticks.tick() => Wake::Tick,
+                    _ = capture_requested(self.capture_requests.as_deref()) => {
+                        info!("Capture requested");
+                        Wake::Requested
+                    }
+                    _ = capture_requested(self.burst.as_ref().map(|burst| &*burst.requests)) => {
+                        Wake::BurstStart
+                    }
+                    _ = burst_due(self.burst.as_ref()) => Wake::BurstCapture,
+                    _ = capture_requested(self.clip.as_ref().map(|clip| &*clip.requests)) => {
+                        Wake::ClipStart
+                    }
+                    _ = burst_due(self.clip.as_ref()) => Wake::ClipCapture,
+                };
+                if let Some(burst) = &mut self.burst {
+                    match wake {
+                        Wake::BurstStart => {
+                            if burst.can_start() {
+                                info!(captures = burst.count, "Starting capture burst");
+                                burst.start();
                             }
-                            Ok::<_, Error>(event)
-                        }).await {
-                            Ok(Ok(event)) => {
-                                info!(
-                                    captured = event.images.len(),
-                                    "Captured screenshots from monitors"
-                                );
-                                if tx.send(event).await.is_err() {
-                                    info!("Receiver dropped, stopping TimerCaptureProducer");
-                                    break;
+                            continue;
+                        }
+                        Wake::BurstCapture => {
+                            burst.advance();
+                        }
+                        _ => {}
+                    }
+                }
+                let mut clip_frame = None;
+                if let Some(clip) = &mut self.clip {
+                    match wake {
+                        Wake::ClipStart => {
+                            if clip.can_start() {
+                                self.clips += 1;
+                                info!(frames = clip.count, "Recording clip");
+                                clip.start();
+                            }
+                            continue;
+                        }
+                        Wake::ClipCapture => {
+                            clip_frame = Some(ClipFrame {
+                                clip: self.clips,
+                                index: clip.advance(),
+                                count: clip.count,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                // Explicit requests, bursts and clips bypass load shedding and
+                // battery throttling, but not the guards
+                let requested = wake != Wake::Tick;
+                if !requested
+                    && let Some(shedder) = &mut self.shedder
+                    && !shedder.on_tick(tx.capacity() == 0)
+                {
+                    debug!("Skipping capture tick while shedding load");
+                    continue;
+                }
+
+                if !requested && let Some((profile, interval_factor)) = &self.power {
+                    if *profile.borrow() == PowerProfile::BatterySaver {
+                        battery_ticks = battery_ticks.wrapping_add(1);
+                        if !battery_ticks.is_multiple_of(*interval_factor) {
+                            debug!("Skipping capture tick in battery-saver mode");
+                            continue;
+                        }
+                    } else {
+                        battery_ticks = 0;
+                    }
+                }
+
+                if !guards.is_empty() {
+                    let guards = guards.clone();
+                    let reason = tokio::task::spawn_blocking(move || {
+                        guards.iter().find_map(|guard| guard.pause_reason())
+                    })
+                    .await
+                    .unwrap_or(None);
+                    match (&paused, &reason) {
+                        (None, Some(reason)) => info!(reason, "Capture paused"),
+                        (Some(_), None) => info!("Capture resumed"),
+                        _ => {}
+                    }
+                    paused = reason;
+                    if paused.is_some() {
+                        continue;
+                    }
+                }
+
+                // Hot-plug support: refresh monitor list each capture cycle
+                // This handles monitors being connected/disconnected at runtime,
+                // as well as scale factor and arrangement changes
+                let captured = capture_all(
+                    self.monitor_timeout,
+                    self.time_policy,
+                    &self.backend,
+                    &self.settings,
+                    &self.monitor_filter,
+                    &mut self.history,
+                    self.options,
+                )
+                .await;
+                match captured {
+                    Ok(mut event) => {
+                        event.trigger = match wake {
+                            Wake::Requested => CaptureTrigger::Requested,
+                            Wake::BurstCapture => CaptureTrigger::Burst,
+                            Wake::ClipCapture => {
+                                clip_frame.map_or(CaptureTrigger::Tick, CaptureTrigger::Clip)
+                            }
+                            _ => CaptureTrigger::Tick,
+                        };
+                        event.in_meeting = self
+                            .meeting
+                            .as_ref()
+                            .is_some_and(|active| active.load(Ordering::Relaxed));
+                        info!(
+                            captured = event.images.len(),
+                            "Captured screenshots from monitors"
+                        );
+                        let (sent, closed) = if self.shedder.is_some() {
+                            // Never block on a full queue, drop the capture instead
+                            match tx.try_send(event) {
+                                Err(TrySendError::Full(_)) => {
+                                    warn!("Capture queue full, dropping capture");
+                                    (false, false)
                                 }
+                                Err(TrySendError::Closed(_)) => (false, true),
+                                Ok(()) => (true, false),
                             }
-                            Ok(Err(e)) => {
-                                error!(error = %e, "Failed to enumerate monitors");
+                        } else {
+                            let closed = tx.send(event).await.is_err();
+                            (!closed, closed)
+                        };
+                        if closed {
+                            info!("Receiver dropped, stopping TimerCaptureProducer");
+                            break;
+                        }
+                        if sent {
+                            emitted += 1;
+                            if self.max_captures.is_some_and(|max| emitted >= max) {
+                                info!(emitted, "TimerCaptureProducer reached max_captures");
+                                break;
                             }
-                            Err(e) => {
-                                error!(error = %e, "Failed to spawn capture task");
+                        }
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "No monitors available, waiting for displays");
+                        loop {
+                            tokio::select! {
+                                _ = self.token.cancelled() => break,
+                                _ = &mut timeout_future => break,
+                                _ = sleep(MONITOR_RETRY) => {}
+                            }
+                            match tokio::task::spawn_blocking(list_monitors).await {
+                                Ok(Ok(monitors)) => {
+                                    info!(
+                                        monitors = monitors.len(),
+                                        "Monitors available, resuming capture"
+                                    );
+                                    break;
+                                }
+                                Ok(Err(e)) => debug!(error = %e, "Still no monitors"),
+                                Err(e) => debug!(error = %e, "Monitor lookup task failed"),
                             }
                         }
+                        // Capture right away; a cancellation or timeout that
+                        // ended the wait is seen by the next select
+                        self.ticks.reset();
                     }
                 }
             }
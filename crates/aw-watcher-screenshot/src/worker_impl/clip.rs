@@ -0,0 +1,430 @@
+//! Short video clips after large changes.
+//!
+//! The filter asks the producer for a recording when a monitor changed by at
+//! least `clip.change_threshold`. The producer then captures the frames of
+//! the clip, marked with `CaptureTrigger::Clip`, which pass the capture
+//! guards and the privacy stages like any other frame. This module provides
+//! the `Processor` that collects them per monitor, encodes them with an
+//! ffmpeg subprocess and sends the clip downstream as its own event, cached
+//! and uploaded like an image with a video content type.
+
+use crate::config::{ClipConfig, ClipFormat};
+use crate::event::{CaptureEvent, CaptureTrigger, Clip, ClipFrame, UploadImageInfo};
+use crate::priority::spawn_heavy;
+use crate::retention;
+use crate::timezone::TimePolicy;
+use crate::worker::Processor;
+use anyhow::{Context, Error, Result};
+use chrono::{DateTime, Utc};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{debug, error, info};
+
+/// How long to wait for the frames of a recording beyond its duration
+/// before encoding what arrived.
+const FINISH_SLACK: Duration = Duration::from_secs(10);
+
+/// Fewest frames worth encoding as a clip.
+const MIN_FRAMES: usize = 2;
+
+/// Processor that assembles clip frames into clips.
+///
+/// Runs after the stages that inspect or alter pixels, which see every clip
+/// frame. Other events are passed on untouched.
+pub struct ClipProcessor {
+    config: ClipConfig,
+    time_policy: TimePolicy,
+}
+
+/// Frames of one monitor in a recording.
+struct Track {
+    /// Metadata of the first frame, with the flags and redactions of all.
+    info: UploadImageInfo,
+    frames: BTreeMap<u32, Arc<DynamicImage>>,
+    /// A privacy stage withheld one of the frames; the clip is dropped.
+    withheld: bool,
+}
+
+/// A clip recording whose frames are being collected.
+struct Recording {
+    clip: u64,
+    /// Capture time of the first frame that arrived.
+    timestamp: DateTime<Utc>,
+    /// When to stop waiting for the remaining frames.
+    deadline: Instant,
+    tracks: HashMap<u32, Track>,
+}
+
+impl Recording {
+    fn new(clip: u64, timestamp: DateTime<Utc>, deadline: Instant) -> Self {
+        Self {
+            clip,
+            timestamp,
+            deadline,
+            tracks: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, mut event: CaptureEvent, index: u32) {
+        for (id, monitor) in event.monitors.drain() {
+            let image = event.images.remove(&id);
+            let track = self.tracks.entry(id).or_insert_with(|| Track {
+                info: monitor.clone(),
+                frames: BTreeMap::new(),
+                withheld: false,
+            });
+            match image {
+                Some(image) => {
+                    track.frames.insert(index, image);
+                }
+                None => track.withheld = true,
+            }
+            if track.info.flag.is_none() {
+                track.info.flag = monitor.flag;
+            }
+            for redaction in monitor.redactions {
+                match track
+                    .info
+                    .redactions
+                    .iter_mut()
+                    .find(|r| r.rule == redaction.rule && r.action == redaction.action)
+                {
+                    Some(existing) => existing.matches = existing.matches.max(redaction.matches),
+                    None => track.info.redactions.push(redaction),
+                }
+            }
+        }
+    }
+}
+
+impl ClipProcessor {
+    pub fn new(config: ClipConfig) -> Self {
+        Self {
+            config,
+            time_policy: TimePolicy::default(),
+        }
+    }
+
+    /// Format object keys according to `policy`.
+    pub fn with_time_policy(mut self, policy: TimePolicy) -> Self {
+        self.time_policy = policy;
+        self
+    }
+
+    /// Encode the clips of a recording in the background and send each as
+    /// its own event.
+    fn finish(&self, recording: Recording, tx: &Sender<CaptureEvent>) {
+        for (id, track) in recording.tracks {
+            if track.withheld {
+                info!(monitor_id = id, "Dropping clip with a withheld frame");
+                continue;
+            }
+            let frames = fill_gaps(&track.frames);
+            if frames.len() < MIN_FRAMES {
+                debug!(
+                    monitor_id = id,
+                    frames = frames.len(),
+                    "Too few frames for a clip"
+                );
+                continue;
+            }
+            let info = self.clip_info(track.info, recording.timestamp);
+            let (config, tx) = (self.config.clone(), tx.clone());
+            let timestamp = recording.timestamp;
+            tokio::spawn(async move {
+                let content_type = config.format.content_type();
+                let data = match spawn_heavy(move || encode(&frames, &config)).await {
+                    Ok(Ok(data)) => data,
+                    Ok(Err(e)) => {
+                        error!(monitor_id = id, error = %e, "Failed to encode clip");
+                        return;
+                    }
+                    Err(e) => {
+                        error!(error = %e, "Failed to spawn clip encoding task");
+                        return;
+                    }
+                };
+                info!(monitor_id = id, size_bytes = data.len(), "Recorded clip");
+                let mut event = CaptureEvent::new();
+                event.timestamp = timestamp;
+                event.monitors.insert(id, info);
+                event.clip = Some(Clip {
+                    monitor_id: id,
+                    data,
+                    content_type,
+                });
+                let _ = tx.send(event).await;
+            });
+        }
+    }
+
+    /// Metadata of a clip, keyed like the frames with a `_clip` suffix and
+    /// flagged when any of its frames was sensitive.
+    fn clip_info(&self, frame: UploadImageInfo, timestamp: DateTime<Utc>) -> UploadImageInfo {
+        let id = frame.monitor_id;
+        let mut object_key = format!(
+            "{}/{}_{}_clip.{}",
+            self.time_policy.format(timestamp, "%Y/%m/%d/%H"),
+            self.time_policy.format(timestamp, "%Y%m%d_%H%M%S%3f"),
+            id,
+            self.config.format.extension()
+        );
+        if frame.is_sensitive() {
+            object_key = retention::flagged_name(&object_key);
+        }
+        let mut info = UploadImageInfo::new(
+            frame.monitor_name,
+            id,
+            frame.monitor_fingerprint,
+            object_key,
+        );
+        info.scale_factor = frame.scale_factor;
+        info.region = frame.region;
+        info.host = frame.host;
+        info.flag = frame.flag;
+        info.redactions = frame.redactions;
+        info.utc_offset = self.time_policy.utc_offset(timestamp);
+        info
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for ClipProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        let frames = self.config.frames();
+        let wait = self.config.frame_interval() * frames + FINISH_SLACK;
+
+        Ok(tokio::spawn(async move {
+            let mut recording: Option<Recording> = None;
+            // Recordings are numbered from 1; frames of finished ones that
+            // arrive late are dropped
+            let mut finished = 0;
+            loop {
+                let event = tokio::select! {
+                    event = rx.recv() => match event {
+                        Some(event) => event,
+                        None => break,
+                    },
+                    _ = deadline_passed(recording.as_ref().map(|r| r.deadline)) => {
+                        if let Some(recording) = recording.take() {
+                            debug!(clip = recording.clip, "Clip frames missing, encoding the rest");
+                            finished = recording.clip;
+                            self.finish(recording, &tx);
+                        }
+                        continue;
+                    }
+                };
+                let CaptureTrigger::Clip(ClipFrame { clip, index, count }) = event.trigger else {
+                    if let Err(e) = tx.send(event).await {
+                        info!(error = %e, "ClipProcessor: receiver dropped, stopping");
+                        break;
+                    }
+                    continue;
+                };
+                if clip <= finished {
+                    debug!(clip, index, "Dropping late clip frame");
+                    continue;
+                }
+                if let Some(previous) = recording.take_if(|r| r.clip != clip) {
+                    finished = previous.clip;
+                    self.finish(previous, &tx);
+                }
+                recording
+                    .get_or_insert_with(|| {
+                        Recording::new(clip, event.timestamp, Instant::now() + wait)
+                    })
+                    .add(event, index);
+                if index + 1 >= count
+                    && let Some(recording) = recording.take()
+                {
+                    finished = recording.clip;
+                    self.finish(recording, &tx);
+                }
+            }
+            if let Some(recording) = recording.take() {
+                self.finish(recording, &tx);
+            }
+            info!("ClipProcessor finished");
+        }))
+    }
+}
+
+/// Wait until `deadline`, forever if there is none.
+async fn deadline_passed(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Frames for every index from the first to the last one captured,
+/// repeating the previous frame where one is missing (skipped as unchanged
+/// by the probe, shed, or paused by a guard).
+fn fill_gaps(frames: &BTreeMap<u32, Arc<DynamicImage>>) -> Vec<Arc<DynamicImage>> {
+    let (Some((&first, _)), Some((&last, _))) = (frames.first_key_value(), frames.last_key_value())
+    else {
+        return Vec::new();
+    };
+    let mut previous = None;
+    (first..=last)
+        .filter_map(|index| {
+            if let Some(frame) = frames.get(&index) {
+                previous = Some(frame.clone());
+            }
+            previous.clone()
+        })
+        .collect()
+}
+
+/// ffmpeg output arguments for `format`; both use 4:2:0 chroma, which needs
+/// even frame sizes.
+fn output_args(format: ClipFormat) -> &'static [&'static str] {
+    match format {
+        ClipFormat::Webm => &[
+            "-c:v",
+            "libvpx-vp9",
+            "-deadline",
+            "realtime",
+            "-cpu-used",
+            "8",
+            "-crf",
+            "40",
+            "-b:v",
+            "0",
+            "-pix_fmt",
+            "yuv420p",
+            "-f",
+            "webm",
+        ],
+        // Fragmented, since a pipe cannot be seeked back to write the index
+        ClipFormat::Mp4 => &[
+            "-c:v",
+            "libx264",
+            "-preset",
+            "veryfast",
+            "-crf",
+            "28",
+            "-pix_fmt",
+            "yuv420p",
+            "-movflags",
+            "frag_keyframe+empty_moov",
+            "-f",
+            "mp4",
+        ],
+    }
+}
+
+/// Encode `frames` at the configured frame rate, in the size of the first.
+fn encode(frames: &[Arc<DynamicImage>], config: &ClipConfig) -> Result<Vec<u8>, Error> {
+    let first = frames.first().context("Clip without frames")?;
+    let (width, height) = (first.width() & !1, first.height() & !1);
+    if width == 0 || height == 0 {
+        anyhow::bail!("Monitor too small for a clip");
+    }
+    let fit = |image: &DynamicImage| -> RgbaImage {
+        let image = image.to_rgba8();
+        if image.dimensions() == (width, height) {
+            image
+        } else if (image.width() & !1, image.height() & !1) == (width, height) {
+            imageops::crop_imm(&image, 0, 0, width, height).to_image()
+        } else {
+            imageops::resize(&image, width, height, FilterType::Triangle)
+        }
+    };
+
+    let mut child = Command::new(&config.command)
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .args(["-s", &format!("{}x{}", width, height)])
+        .args(["-r", &config.fps.max(1).to_string(), "-i", "-"])
+        .args(output_args(config.format))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", config.command))?;
+
+    // Drain stdout from a second thread so a full pipe cannot deadlock us
+    let mut stdin = child.stdin.take().context("ffmpeg stdin not captured")?;
+    let mut stdout = child.stdout.take().context("ffmpeg stdout not captured")?;
+    let (written, data) = std::thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            let mut data = Vec::new();
+            stdout.read_to_end(&mut data).map(|_| data)
+        });
+        let written = frames
+            .iter()
+            .try_for_each(|frame| stdin.write_all(fit(frame).as_raw()));
+        drop(stdin);
+        (written, reader.join())
+    });
+
+    let output = child.wait_with_output()?;
+    written.context("Failed to write frames to ffmpeg")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "ffmpeg exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    data.map_err(|_| anyhow::anyhow!("ffmpeg output reader panicked"))?
+        .context("Failed to read ffmpeg output")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(value: u8) -> Arc<DynamicImage> {
+        Arc::new(DynamicImage::ImageLuma8(image::GrayImage::from_pixel(
+            2,
+            2,
+            image::Luma([value]),
+        )))
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let frames: BTreeMap<u32, Arc<DynamicImage>> =
+            [(1, image(1)), (2, image(2)), (5, image(5))].into();
+        let filled = fill_gaps(&frames);
+        let values: Vec<u8> = filled.iter().map(|frame| frame.as_bytes()[0]).collect();
+        assert_eq!(values, vec![1, 2, 2, 2, 5]);
+        assert!(fill_gaps(&BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_withheld_frame_drops_track() {
+        let info = |key: &str| UploadImageInfo::new("m".into(), 0, "f".into(), key.into());
+        let mut recording = Recording::new(1, Utc::now(), Instant::now());
+
+        let mut event = CaptureEvent::new();
+        event.add_image(0, (*image(0)).clone(), info("a"));
+        recording.add(event, 0);
+        assert!(!recording.tracks[&0].withheld);
+
+        // Withheld by a privacy stage: metadata without an image
+        let mut event = CaptureEvent::new();
+        let mut withheld = info("");
+        withheld.flag = Some("nsfw".to_string());
+        event.monitors.insert(0, withheld);
+        recording.add(event, 1);
+        let track = &recording.tracks[&0];
+        assert!(track.withheld);
+        assert_eq!(track.info.flag.as_deref(), Some("nsfw"));
+        assert_eq!(track.frames.len(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+//! Face blurring processor.
+//!
+//! This module provides a `Processor` that runs a face detection model from
+//! `[models]` over every accepted screenshot and blurs the detected faces
+//! before later stages store or upload the frame, so video calls on the
+//! captured displays do not end up in the archive.
+//!
+//! The model's output for each image is read as rows of
+//! `[x1, y1, x2, y2, score]` with coordinates relative to the image size
+//! (0 to 1), the usual layout of lightweight detectors such as UltraFace or
+//! YuNet exported with their post-processing.
+
+use crate::config::FaceBlurConfig;
+use crate::event::{CaptureEvent, Rect};
+use crate::inference::{ModelInput, ModelRegistry};
+use crate::mask;
+use crate::priority::spawn_heavy;
+use crate::worker::Processor;
+use anyhow::{Error, Result};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Values per detection in the model output.
+const DETECTION_SIZE: usize = 5;
+
+/// Blurred margin around a face, relative to its size, so hair and chin
+/// outlines are covered too.
+const MARGIN: f32 = 0.15;
+
+/// Processor that blurs faces in every image of an event.
+pub struct FaceBlurProcessor {
+    config: FaceBlurConfig,
+    registry: Arc<ModelRegistry>,
+}
+
+impl FaceBlurProcessor {
+    pub fn new(config: FaceBlurConfig, registry: Arc<ModelRegistry>) -> Self {
+        Self { config, registry }
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for FaceBlurProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        let config = Arc::new(self.config);
+        let registry = self.registry;
+
+        Ok(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let config = config.clone();
+                let registry = registry.clone();
+                let event = match spawn_heavy(move || blur_faces(event, &config, &registry)).await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!(error = %e, "Failed to spawn face blur task");
+                        continue;
+                    }
+                };
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "FaceBlurProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("FaceBlurProcessor finished");
+        }))
+    }
+}
+
+/// Blur the faces in every image of `event`. When the model fails, the
+/// images are dropped rather than passed on unchecked; `None` when no image
+/// is left.
+fn blur_faces(
+    mut event: CaptureEvent,
+    config: &FaceBlurConfig,
+    registry: &ModelRegistry,
+) -> Option<CaptureEvent> {
+    let ids: Vec<u32> = event.images.keys().copied().collect();
+    let inputs: Vec<_> = ids
+        .iter()
+        .map(|id| ModelInput::Image(&event.images[id]))
+        .collect();
+    let name = config.model.as_deref().unwrap_or_default();
+    let outputs = match registry.get(name).and_then(|model| model.run(&inputs)) {
+        Ok(outputs) => outputs,
+        Err(e) => {
+            warn!(model = name, error = %e, "Face detection failed, dropping images");
+            return None;
+        }
+    };
+
+    for (id, output) in ids.iter().zip(outputs) {
+        let image = event.images.get_mut(id).expect("listed above");
+        let faces = detections(&output, config.min_score, image.width(), image.height());
+        if faces.is_empty() {
+            continue;
+        }
+        let image = Arc::make_mut(image);
+        for face in &faces {
+            mask::blur(image, *face, config.blur_sigma.max(1.0));
+        }
+        info!(monitor_id = id, faces = faces.len(), "Blurred faces");
+    }
+    Some(event)
+}
+
+/// Face rectangles in image pixels, with a margin, from one model output.
+fn detections(output: &[f32], min_score: f32, width: u32, height: u32) -> Vec<Rect> {
+    output
+        .chunks_exact(DETECTION_SIZE)
+        .filter(|detection| detection[4] >= min_score)
+        .filter_map(|detection| {
+            let [x1, y1, x2, y2] = [detection[0], detection[1], detection[2], detection[3]];
+            let (margin_x, margin_y) = ((x2 - x1) * MARGIN, (y2 - y1) * MARGIN);
+            let left = ((x1 - margin_x).clamp(0.0, 1.0) * width as f32) as u32;
+            let top = ((y1 - margin_y).clamp(0.0, 1.0) * height as f32) as u32;
+            let right = ((x2 + margin_x).clamp(0.0, 1.0) * width as f32).ceil() as u32;
+            let bottom = ((y2 + margin_y).clamp(0.0, 1.0) * height as f32).ceil() as u32;
+            (right > left && bottom > top).then(|| Rect {
+                x: left,
+                y: top,
+                width: right - left,
+                height: bottom - top,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detections() {
+        let output = [
+            0.5, 0.5, 0.6, 0.7, 0.9, // confident face
+            0.1, 0.1, 0.2, 0.2, 0.3, // below min_score
+            0.95, 0.0, 1.2, 0.1, 0.8, // clipped to the image
+            0.4, 0.4, 0.4, 0.5, 0.9, // empty box
+        ];
+        assert_eq!(
+            detections(&output, 0.6, 1000, 500),
+            vec![
+                // Rounded outwards
+                Rect {
+                    x: 484,
+                    y: 235,
+                    width: 131,
+                    height: 130,
+                },
+                Rect {
+                    x: 912,
+                    y: 0,
+                    width: 88,
+                    height: 58,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Low-fidelity privacy mode processor.
+//!
+//! This module provides a `Processor` that, while low-fidelity mode is on,
+//! pixelates every frame or shrinks it to a thumbnail. The stored images
+//! still show which apps and layouts were on screen, but text is no longer
+//! readable. The mode is switched at runtime through the control channel
+//! (`low-fidelity` / `full-fidelity`).
+
+use crate::config::{LowFidelityConfig, LowFidelityMode};
+use crate::event::CaptureEvent;
+use crate::priority::spawn_heavy;
+use crate::worker::Processor;
+use crate::worker_impl::meeting::pixelate;
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use image::imageops::FilterType;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+/// Processor that degrades frames while low-fidelity mode is on.
+///
+/// Runs right after `FilterProcessor`, so OCR and embeddings only ever see
+/// the degraded frame.
+pub struct LowFidelityProcessor {
+    active: Arc<AtomicBool>,
+    mode: LowFidelityMode,
+    pixel_size: u32,
+    max_dimension: u32,
+}
+
+impl LowFidelityProcessor {
+    pub fn new(config: &LowFidelityConfig, active: Arc<AtomicBool>) -> Self {
+        Self {
+            active,
+            mode: config.mode,
+            pixel_size: config.pixel_size.max(1),
+            max_dimension: config.max_dimension.max(1),
+        }
+    }
+
+    fn degrade(&self, image: &DynamicImage) -> DynamicImage {
+        match self.mode {
+            LowFidelityMode::Pixelate => pixelate(image, self.pixel_size),
+            LowFidelityMode::Thumbnail => {
+                image.resize(self.max_dimension, self.max_dimension, FilterType::Triangle)
+            }
+        }
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for LowFidelityProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        let processor = Arc::new(self);
+        Ok(tokio::spawn(async move {
+            while let Some(mut event) = rx.recv().await {
+                if processor.active.load(Ordering::Relaxed) && !event.images.is_empty() {
+                    let worker = processor.clone();
+                    event = match spawn_heavy(move || {
+                        for image in event.images.values_mut() {
+                            *image = Arc::new(worker.degrade(image));
+                        }
+                        event
+                    })
+                    .await
+                    {
+                        Ok(event) => event,
+                        Err(e) => {
+                            error!(error = %e, "Failed to spawn low-fidelity task");
+                            continue;
+                        }
+                    };
+                    debug!(mode = ?processor.mode, "Stored frame at low fidelity");
+                }
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "LowFidelityProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("LowFidelityProcessor finished");
+        }))
+    }
+}
@@ -1,91 +1,594 @@
 //! Screenshot filtering processor using perceptual hashing.
 //!
 //! This module provides a `Processor` that filters captured screenshots
-//! based on perceptual hash (dhash) comparison to skip unchanged screens.
+//! based on perceptual hash comparison (dhash by default, see `crate::hash`)
+//! to skip unchanged screens.
+//!
+//! Each frame runs through the chain of steps listed in `capture.filters`
+//! (exclusion, window blocklist, masks, debounce, hashing) in that order,
+//! and is dropped by the first step that rejects it. Blackout regions are
+//! already painted by the capture producer.
+//!
+//! Clip frames (see `worker_impl::clip`) are never compared: they only go
+//! through the exclusion and blocklist steps and keep the monitors whose
+//! change requested the recording.
 
-use crate::config::CaptureConfig;
-use crate::event::CaptureEvent;
+use crate::config::{
+    CaptureConfig, FilterKind, LoadSheddingConfig, MonitorConfig, NotificationArea,
+};
+use crate::event::{
+    CaptureEvent, CaptureTrigger, ChangeScore, ClipFrame, FilterStats, FocusedWindow, Rect,
+    UploadImageInfo,
+};
+use crate::guard::window::WindowRules;
+use crate::hash::{
+    PerceptualHash, color_histogram, hamming_distance, heatmap, heatmap_luma, histogram_distance,
+    tile_hashes,
+};
+use crate::mask;
+use crate::monitor::MonitorIdentity;
 use crate::worker::Processor;
 use anyhow::{Error, Result};
 use chrono::{DateTime, TimeDelta, Utc};
-use image::{DynamicImage, imageops};
-use std::collections::HashMap;
+use image::DynamicImage;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::Notify;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::task::JoinHandle;
-use tracing::info;
+use tracing::{debug, info};
 
 /// State tracking for a single monitor to support skip detection.
 struct MonitorState {
     last_dhash: Option<u64>,
     last_time: Option<DateTime<Utc>>,
+    /// Distance of the most recent hash to `last_dhash` at the time it was computed.
+    last_distance: Option<u32>,
+    /// Tile hashes of the last accepted frame, with `capture.tile_grid`.
+    last_tiles: Vec<u64>,
+    /// Fraction of tiles of the most recent frame that differ from `last_tiles`.
+    last_tiles_changed: Option<f32>,
+    /// Color histogram of the last accepted frame, with
+    /// `capture.histogram_threshold`.
+    last_histogram: Vec<f32>,
+    /// Heatmap luma grid of the last accepted frame, with
+    /// `capture.heatmap_grid`.
+    last_luma: Vec<u8>,
+    /// Change heatmap of the most recent accepted frame.
+    last_heatmap: Option<Vec<Vec<u8>>>,
+    /// Damage reported since the last accepted frame, merged across the
+    /// captures dropped since into its bounding box. `None` while unknown:
+    /// before the first accepted frame, or once a capture came without a
+    /// damage report.
+    damage: Option<Vec<Rect>>,
+    /// Damage of the most recent accepted frame since the one before it.
+    changed_region: Option<Rect>,
+    /// Decisions since `stats_since`.
+    stats: FilterStats,
+    stats_since: DateTime<Utc>,
+}
+
+/// What the next frames of a monitor are compared against.
+#[derive(Default)]
+struct Reference {
+    dhash: Option<u64>,
+    time: Option<DateTime<Utc>>,
+    tiles: Vec<u64>,
+    histogram: Vec<f32>,
+    luma: Vec<u8>,
 }
 
 impl MonitorState {
+    fn reference(&self) -> Reference {
+        Reference {
+            dhash: self.last_dhash,
+            time: self.last_time,
+            tiles: self.last_tiles.clone(),
+            histogram: self.last_histogram.clone(),
+            luma: self.last_luma.clone(),
+        }
+    }
+
+    /// Compare against `reference` again, as if the frames since were not
+    /// accepted.
+    fn restore(&mut self, reference: Reference) {
+        self.last_dhash = reference.dhash;
+        self.last_time = reference.time;
+        self.last_tiles = reference.tiles;
+        self.last_histogram = reference.histogram;
+        self.last_luma = reference.luma;
+        // The damage of the frames since was not kept
+        self.damage = None;
+    }
+
     fn new() -> Self {
         Self {
             last_dhash: None,
             last_time: None,
+            last_distance: None,
+            last_tiles: Vec::new(),
+            last_tiles_changed: None,
+            last_histogram: Vec::new(),
+            last_luma: Vec::new(),
+            last_heatmap: None,
+            damage: None,
+            changed_region: None,
+            stats: FilterStats::default(),
+            stats_since: Utc::now(),
+        }
+    }
+}
+
+/// Why a frame was passed on or dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Decision {
+    Accepted,
+    /// Accepted because the force interval elapsed.
+    Forced,
+    Similar,
+    RateLimited,
+    /// The monitor is excluded in its `[[monitor]]` entry.
+    Excluded,
+    Blocked,
+}
+
+impl Decision {
+    fn skips(self) -> bool {
+        !matches!(self, Decision::Accepted | Decision::Forced)
+    }
+}
+
+impl FilterStats {
+    fn record(&mut self, decision: Decision) {
+        match decision {
+            Decision::Accepted => self.accepted += 1,
+            Decision::Forced => {
+                self.accepted += 1;
+                self.forced += 1;
+            }
+            Decision::Similar => self.skipped_similar += 1,
+            Decision::RateLimited => self.skipped_ratelimit += 1,
+            Decision::Blocked => self.skipped_blocked += 1,
+            // Excluded monitors are left out of the counts
+            Decision::Excluded => return,
         }
+        self.captured += 1;
     }
 }
 
+/// Hashes of a frame, kept as the monitor's reference once it is accepted.
+struct Hashes {
+    dhash: u64,
+    tiles: Vec<u64>,
+    histogram: Vec<f32>,
+}
+
+/// One monitor's frame on its way through the filter chain.
+struct Frame<'a> {
+    id: u32,
+    image: &'a mut Arc<DynamicImage>,
+    monitor: Option<&'a UploadImageInfo>,
+    settings: Option<&'a MonitorConfig>,
+    damage: Option<&'a Vec<Rect>>,
+    focused_window: Option<&'a FocusedWindow>,
+    /// Minimum time since the last accepted frame, by capture trigger.
+    debounce: TimeDelta,
+    now: DateTime<Utc>,
+    /// The monitor's force interval elapsed since its last accepted frame.
+    force_due: bool,
+    /// Copy of the image with the regions left out of the comparison masked.
+    masked: Option<DynamicImage>,
+    hashes: Option<Hashes>,
+}
+
+/// A step of the filter chain, see `capture.filters`.
+trait FrameFilter: Send {
+    /// Look at a frame; `Some` ends the chain with that decision.
+    fn check(&self, frame: &mut Frame, state: &mut MonitorState) -> Option<Decision>;
+
+    /// Whether the step also applies to clip frames.
+    fn checks_clips(&self) -> bool {
+        false
+    }
+}
+
+/// Drops monitors excluded in their `[[monitor]]` entry.
+struct ExcludeFilter;
+
+impl FrameFilter for ExcludeFilter {
+    fn check(&self, frame: &mut Frame, _state: &mut MonitorState) -> Option<Decision> {
+        frame
+            .settings
+            .is_some_and(|s| s.exclude)
+            .then_some(Decision::Excluded)
+    }
+
+    fn checks_clips(&self) -> bool {
+        true
+    }
+}
+
+/// Drops frames captured while a blocklisted window had focus.
+struct BlocklistFilter {
+    rules: WindowRules,
+}
+
+impl FrameFilter for BlocklistFilter {
+    fn check(&self, frame: &mut Frame, _state: &mut MonitorState) -> Option<Decision> {
+        frame
+            .focused_window
+            .is_some_and(|window| self.rules.matches(window))
+            .then_some(Decision::Blocked)
+    }
+
+    fn checks_clips(&self) -> bool {
+        true
+    }
+}
+
+/// Leaves the monitor's ignore regions and the notification area out of
+/// the comparison, see `comparison_mask`.
+struct MaskFilter {
+    notifications: Option<NotificationArea>,
+}
+
+impl FrameFilter for MaskFilter {
+    fn check(&self, frame: &mut Frame, _state: &mut MonitorState) -> Option<Decision> {
+        if let Some(monitor) = frame.monitor {
+            frame.masked =
+                comparison_mask(monitor, frame.settings, self.notifications, frame.image);
+        }
+        None
+    }
+}
+
+/// Drops frames arriving within the debounce of the last accepted frame.
+struct RateLimitFilter;
+
+impl FrameFilter for RateLimitFilter {
+    fn check(&self, frame: &mut Frame, state: &mut MonitorState) -> Option<Decision> {
+        let last_time = state.last_time?;
+        (!frame.force_due && frame.now - last_time < frame.debounce)
+            .then_some(Decision::RateLimited)
+    }
+}
+
+/// Drops frames similar to the last accepted one, based on:
+/// - Backend damage reports (no damaged region since the last accepted frame
+///   means unchanged, no hashing needed)
+/// - Perceptual hash similarity (dhash threshold), of the whole frame or
+///   of each tile with `tile_grid` (up to `tile_tolerance` tiles may change)
+/// - Color histograms, overriding a similar hash with `histogram_threshold`
+///
+/// Frames are accepted regardless once the force interval elapsed.
+struct DhashFilter {
+    hasher: Arc<dyn PerceptualHash>,
+    dhash_threshold: u32,
+    tile_grid: u32,
+    tile_tolerance: u32,
+    histogram_threshold: Option<f32>,
+    histogram_bins: u32,
+}
+
+impl FrameFilter for DhashFilter {
+    fn check(&self, frame: &mut Frame, state: &mut MonitorState) -> Option<Decision> {
+        let dhash_threshold = frame
+            .settings
+            .and_then(|s| s.dhash_threshold)
+            .unwrap_or(self.dhash_threshold);
+
+        if state.damage.as_ref().is_some_and(Vec::is_empty)
+            && state.last_dhash.is_some()
+            && state.last_time.is_some()
+            && !frame.force_due
+        {
+            state.last_distance = Some(0);
+            state.last_tiles_changed = (self.tile_grid > 0).then_some(0.0);
+            return Some(Decision::Similar);
+        }
+
+        let image = frame.masked.as_ref().unwrap_or(&**frame.image);
+        let dhash = self.hasher.hash(image);
+        let tiles = if self.tile_grid > 0 {
+            tile_hashes(self.hasher.as_ref(), image, self.tile_grid)
+        } else {
+            Vec::new()
+        };
+        let histogram = if self.histogram_threshold.is_some() {
+            color_histogram(image, self.histogram_bins)
+        } else {
+            Vec::new()
+        };
+
+        state.last_distance = state
+            .last_dhash
+            .map(|last_dhash| hamming_distance(dhash, last_dhash));
+        let changed_tiles =
+            (!tiles.is_empty() && tiles.len() == state.last_tiles.len()).then(|| {
+                tiles
+                    .iter()
+                    .zip(&state.last_tiles)
+                    .filter(|(tile, last)| hamming_distance(**tile, **last) >= dhash_threshold)
+                    .count() as u32
+            });
+        state.last_tiles_changed = changed_tiles.map(|changed| changed as f32 / tiles.len() as f32);
+
+        let similar = match (changed_tiles, state.last_distance) {
+            (Some(changed), _) => changed <= self.tile_tolerance,
+            (None, Some(distance)) => distance < dhash_threshold,
+            (None, None) => false,
+        };
+        // Second stage: hashes can collide on frames whose colors differ
+        let colors_changed = similar
+            && self.histogram_threshold.is_some_and(|threshold| {
+                histogram.len() == state.last_histogram.len()
+                    && histogram_distance(&histogram, &state.last_histogram) >= threshold
+            });
+        if colors_changed {
+            debug!(
+                monitor_id = frame.id,
+                "Hash unchanged but colors differ, accepting frame"
+            );
+        }
+        frame.hashes = Some(Hashes {
+            dhash,
+            tiles,
+            histogram,
+        });
+
+        if frame.force_due {
+            Some(Decision::Forced)
+        } else if similar && !colors_changed {
+            Some(Decision::Similar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Clip recordings requested after large changes, see `with_clips`.
+struct Clips {
+    threshold: u32,
+    requests: Arc<Notify>,
+    /// Monitors whose change asked for the next recording.
+    requested: HashSet<u32>,
+    /// The recording in progress and the monitors it keeps.
+    recording: Option<(u64, HashSet<u32>)>,
+}
+
 /// Screenshot filter processor that removes unchanged screens.
 ///
 /// This processor receives `CaptureEvent`s and produces `FilteredCaptureEvent`s
 /// containing only the monitors that have changed since the last capture.
 pub struct FilterProcessor {
     config: CaptureConfig,
+    /// Steps of `capture.filters`, in order.
+    chain: Vec<Box<dyn FrameFilter>>,
     monitor_states: HashMap<u32, MonitorState>,
+    /// When set, low-change frames are dropped while the output queue is full.
+    low_change_distance: Option<u32>,
+    /// Distance at which the producer is asked for a burst of follow-up captures.
+    burst: Option<(u32, Arc<Notify>)>,
+    clips: Option<Clips>,
+    /// How often decision counters are attached to accepted frames.
+    stats_interval: Option<TimeDelta>,
 }
 
 impl FilterProcessor {
-    pub fn new(config: CaptureConfig) -> Self {
-        Self {
+    pub fn new(config: CaptureConfig) -> Result<Self, Error> {
+        let mut chain: Vec<Box<dyn FrameFilter>> = Vec::new();
+        for (index, kind) in config.filters.iter().enumerate() {
+            if config.filters[..index].contains(kind) {
+                anyhow::bail!("Filter {:?} is listed more than once", kind);
+            }
+            if *kind == FilterKind::Masks && config.filters[..index].contains(&FilterKind::Dhash) {
+                anyhow::bail!("Filter Masks must come before Dhash, which compares masked frames");
+            }
+            chain.push(match kind {
+                FilterKind::Exclude => Box::new(ExcludeFilter),
+                FilterKind::Blocklist => Box::new(BlocklistFilter {
+                    rules: WindowRules::new(&config.blocked_apps, &config.blocked_titles)?,
+                }),
+                FilterKind::Masks => Box::new(MaskFilter {
+                    notifications: config.ignore_notifications,
+                }),
+                FilterKind::Ratelimit => Box::new(RateLimitFilter),
+                FilterKind::Dhash => Box::new(DhashFilter {
+                    hasher: config.hash_algorithm.hasher(),
+                    dhash_threshold: config.dhash_threshold,
+                    tile_grid: config.tile_grid,
+                    tile_tolerance: config.tile_tolerance,
+                    histogram_threshold: config.histogram_threshold,
+                    histogram_bins: config.histogram_bins,
+                }),
+            });
+        }
+        Ok(Self {
+            chain,
+            stats_interval: config
+                .filter_stats_secs
+                .and_then(|secs| TimeDelta::try_seconds(secs as i64)),
             config,
             monitor_states: HashMap::new(),
-        }
+            low_change_distance: None,
+            burst: None,
+            clips: None,
+        })
     }
 
-    /// Determine if the current capture should be skipped based on:
-    /// - Rate limiting (< 100ms since last capture)
-    /// - Perceptual hash similarity (dhash threshold)
-    /// - Force interval (always capture after configured seconds)
-    fn should_skip(&mut self, monitor_id: u32, image: &DynamicImage) -> bool {
-        let dhash = dhash(image);
+    /// Drop frames below the configured change distance instead of waiting
+    /// on a full output queue.
+    pub fn with_load_shedding(mut self, config: &LoadSheddingConfig) -> Self {
+        self.low_change_distance = Some(config.low_change_distance);
+        self
+    }
+
+    /// Notify `requests` when an accepted frame changed by at least `threshold`.
+    pub fn with_burst(mut self, threshold: u32, requests: Arc<Notify>) -> Self {
+        self.burst = Some((threshold, requests));
+        self
+    }
+
+    /// Notify `requests` for a clip of the monitors whose accepted frame
+    /// changed by at least `threshold`.
+    pub fn with_clips(mut self, threshold: u32, requests: Arc<Notify>) -> Self {
+        self.clips = Some(Clips {
+            threshold,
+            requests,
+            requested: HashSet::new(),
+            recording: None,
+        });
+        self
+    }
+
+    /// Keep the images of a clip frame for the monitors being recorded.
+    /// Excluded or blocked frames are withheld, keeping their metadata with
+    /// an empty `object_key`, so the clip of the monitor is dropped. Monitor
+    /// state and statistics are left alone; clip frames never become a
+    /// reference.
+    fn filter_clip_frame(&mut self, event: &mut CaptureEvent, clip_frame: ClipFrame) {
+        let Some(clips) = &mut self.clips else {
+            event.images.clear();
+            event.monitors.clear();
+            return;
+        };
+        if clips
+            .recording
+            .as_ref()
+            .is_none_or(|(clip, _)| *clip != clip_frame.clip)
+        {
+            clips.recording = Some((clip_frame.clip, std::mem::take(&mut clips.requested)));
+        }
+        let recorded = clips.recording.as_ref().map(|(_, monitors)| monitors);
         let now = Utc::now();
+        let mut scratch = MonitorState::new();
+        let mut withheld = HashSet::new();
+        event
+            .images
+            .retain(|id, _| recorded.is_some_and(|monitors| monitors.contains(id)));
+        event.images.retain(|id, image| {
+            let monitor = event.monitors.get(id);
+            let mut frame = Frame {
+                id: *id,
+                image,
+                monitor,
+                settings: monitor.and_then(|m| m.settings.as_deref()),
+                damage: None,
+                focused_window: event.focused_window.as_ref(),
+                debounce: TimeDelta::zero(),
+                now,
+                force_due: false,
+                masked: None,
+                hashes: None,
+            };
+            let skips = self
+                .chain
+                .iter()
+                .filter(|filter| filter.checks_clips())
+                .any(|filter| filter.check(&mut frame, &mut scratch).is_some());
+            if skips {
+                withheld.insert(*id);
+            }
+            !skips
+        });
+        event
+            .monitors
+            .retain(|id, _| event.images.contains_key(id) || withheld.contains(id));
+        for id in withheld {
+            if let Some(monitor) = event.monitors.get_mut(&id) {
+                monitor.object_key.clear();
+            }
+        }
+    }
 
+    /// Run a frame through the filter chain. Accepted frames become the
+    /// monitor's reference for the next ones.
+    fn decide(&mut self, mut frame: Frame) -> Decision {
+        let force_interval = TimeDelta::try_seconds(
+            frame
+                .settings
+                .and_then(|s| s.force_interval_secs)
+                .unwrap_or(self.config.force_interval_secs) as i64,
+        )
+        .unwrap();
         let state = self
             .monitor_states
-            .entry(monitor_id)
+            .entry(frame.id)
             .or_insert_with(MonitorState::new);
-
-        if let Some(last_time) = state.last_time {
-            // Use configured force interval
-            if now - last_time
-                > TimeDelta::try_seconds(self.config.force_interval_secs as i64).unwrap()
-            {
-                state.last_dhash = Some(dhash);
-                state.last_time = Some(now);
-                return false;
+        frame.force_due = state
+            .last_time
+            .is_some_and(|last_time| frame.now - last_time > force_interval);
+        // Backends report damage since their previous capture, which may
+        // have been dropped rather than become the reference
+        state.damage = match (state.damage.take(), frame.damage) {
+            (Some(mut damage), Some(rects)) => {
+                damage.extend_from_slice(rects);
+                Some(Rect::bounding(&damage).into_iter().collect())
             }
+            _ => None,
+        };
 
-            // Rate limit check (100ms debounce)
-            if now - last_time < TimeDelta::try_milliseconds(100).unwrap() {
-                return true;
+        let decision = self
+            .chain
+            .iter()
+            .find_map(|filter| filter.check(&mut frame, state))
+            .unwrap_or(Decision::Accepted);
+        if !decision.skips() {
+            state.last_time = Some(frame.now);
+            state.changed_region = state.damage.as_deref().and_then(Rect::bounding);
+            state.damage = Some(Vec::new());
+            if let Some(hashes) = frame.hashes {
+                state.last_dhash = Some(hashes.dhash);
+                state.last_tiles = hashes.tiles;
+                state.last_histogram = hashes.histogram;
             }
-        }
-
-        if let Some(last_dhash) = state.last_dhash {
-            // Use configured dhash threshold
-            if hamming_distance(dhash, last_dhash) < self.config.dhash_threshold {
-                return true;
+            if self.config.heatmap_grid > 0 {
+                let image = frame.masked.as_ref().unwrap_or(&**frame.image);
+                let luma = heatmap_luma(image, self.config.heatmap_grid);
+                state.last_heatmap =
+                    Some(heatmap(&state.last_luma, &luma)).filter(|map| !map.is_empty());
+                state.last_luma = luma;
             }
         }
+        state.stats.record(decision);
+        decision
+    }
+}
 
-        state.last_dhash = Some(dhash);
-        state.last_time = Some(now);
-        false
+/// Returns a copy of the image to hash with the monitor's `ignore_regions`
+/// (unless already blacked out) and, with `notifications`, the notification
+/// area painted black, when anything is left out of the comparison.
+fn comparison_mask(
+    monitor: &UploadImageInfo,
+    settings: Option<&MonitorConfig>,
+    notifications: Option<NotificationArea>,
+    image: &DynamicImage,
+) -> Option<DynamicImage> {
+    let mut hidden = match settings {
+        Some(settings) if !settings.black_out_ignored && !settings.ignore_regions.is_empty() => {
+            mask::image_rects(monitor, image, &settings.ignore_regions)
+        }
+        _ => Vec::new(),
+    };
+    // Notifications are only ever left out of the comparison
+    if let Some(area) = notifications
+        && let Some(identity) =
+            MonitorIdentity::from_event_name(&monitor.monitor_name, &monitor.monitor_fingerprint)
+    {
+        let rect = mask::notification_rect(
+            area,
+            identity.width,
+            identity.height,
+            monitor.scale_factor.unwrap_or(1.0),
+        );
+        hidden.extend(mask::image_rects(monitor, image, &[rect]));
+    }
+    if hidden.is_empty() {
+        return None;
     }
+    let mut masked = image.clone();
+    mask::fill(&mut masked, &hidden);
+    Some(masked)
 }
 
 impl Processor<CaptureEvent, CaptureEvent> for FilterProcessor {
@@ -96,12 +599,138 @@ impl Processor<CaptureEvent, CaptureEvent> for FilterProcessor {
     ) -> Result<JoinHandle<()>, Error> {
         let handler = tokio::spawn(async move {
             while let Some(mut event) = rx.recv().await {
+                if let CaptureTrigger::Clip(clip_frame) = event.trigger {
+                    self.filter_clip_frame(&mut event, clip_frame);
+                    if !event.monitors.is_empty() && tx.send(event).await.is_err() {
+                        info!("FilterProcessor: receiver dropped, stopping");
+                        break;
+                    }
+                    continue;
+                }
                 let original_count = event.images.len();
-                event
-                    .images
-                    .retain(|id, image| !self.should_skip(*id, image));
+                let debounce = match event.trigger {
+                    CaptureTrigger::Tick => {
+                        TimeDelta::try_milliseconds(self.config.debounce_ms as i64)
+                            .unwrap_or(TimeDelta::MAX)
+                    }
+                    CaptureTrigger::Requested | CaptureTrigger::Burst | CaptureTrigger::Clip(_) => {
+                        TimeDelta::zero()
+                    }
+                };
+                let now = Utc::now();
+                // With a full output queue low-change frames are shed below;
+                // they must not become the reference then
+                let shedding = self.low_change_distance.filter(|_| tx.capacity() == 0);
+                let references: HashMap<u32, Reference> = match shedding {
+                    Some(_) => event
+                        .images
+                        .keys()
+                        .map(|id| {
+                            let state = self.monitor_states.get(id);
+                            (*id, state.map(MonitorState::reference).unwrap_or_default())
+                        })
+                        .collect(),
+                    None => HashMap::new(),
+                };
+                event.images.retain(|id, image| {
+                    let monitor = event.monitors.get(id);
+                    let frame = Frame {
+                        id: *id,
+                        image,
+                        monitor,
+                        settings: monitor.and_then(|m| m.settings.as_deref()),
+                        damage: event.damage.get(id),
+                        focused_window: event.focused_window.as_ref(),
+                        debounce,
+                        now,
+                        force_due: false,
+                        masked: None,
+                        hashes: None,
+                    };
+                    !self.decide(frame).skips()
+                });
+                event.change_distances = event
+                    .monitors
+                    .keys()
+                    .filter_map(|id| {
+                        let state = self.monitor_states.get(id)?;
+                        Some((*id, state.last_distance?))
+                    })
+                    .collect();
                 // Sync monitors with images - remove monitors that were filtered out
                 event.monitors.retain(|id, _| event.images.contains_key(id));
+                for (id, monitor) in event.monitors.iter_mut() {
+                    monitor.dhash = self
+                        .monitor_states
+                        .get(id)
+                        .and_then(|state| state.last_dhash)
+                        .map(|dhash| format!("{:016x}", dhash));
+                    monitor.hash_algorithm = monitor
+                        .dhash
+                        .as_ref()
+                        .map(|_| self.config.hash_algorithm.name().to_string());
+                    monitor.changed_region = self
+                        .monitor_states
+                        .get(id)
+                        .and_then(|state| state.changed_region);
+                    monitor.heatmap = self
+                        .monitor_states
+                        .get_mut(id)
+                        .and_then(|state| state.last_heatmap.take());
+                    monitor.change = self.monitor_states.get(id).and_then(|state| {
+                        let distance = state.last_distance?;
+                        Some(ChangeScore::new(distance, state.last_tiles_changed))
+                    });
+                    if let Some(interval) = self.stats_interval
+                        && let Some(state) = self.monitor_states.get_mut(id)
+                        && event.timestamp - state.stats_since >= interval
+                    {
+                        let mut stats = std::mem::take(&mut state.stats);
+                        stats.window_secs = (event.timestamp - state.stats_since).num_seconds();
+                        state.stats_since = event.timestamp;
+                        info!(
+                            monitor_id = id,
+                            window_secs = stats.window_secs,
+                            captured = stats.captured,
+                            accepted = stats.accepted,
+                            forced = stats.forced,
+                            skipped_similar = stats.skipped_similar,
+                            skipped_ratelimit = stats.skipped_ratelimit,
+                            skipped_blocked = stats.skipped_blocked,
+                            "Filter statistics"
+                        );
+                        monitor.filter_stats = Some(stats);
+                    }
+                }
+                if let Some((threshold, requests)) = &self.burst
+                    && event.images.keys().any(|id| {
+                        event
+                            .change_distances
+                            .get(id)
+                            .is_some_and(|distance| distance >= threshold)
+                    })
+                {
+                    debug!("Large change, requesting a capture burst");
+                    requests.notify_one();
+                }
+                if let Some(clips) = &mut self.clips {
+                    let changed: HashSet<u32> = event
+                        .images
+                        .keys()
+                        .filter(|id| {
+                            event
+                                .change_distances
+                                .get(id)
+                                .is_some_and(|distance| *distance >= clips.threshold)
+                        })
+                        .copied()
+                        .collect();
+                    if !changed.is_empty() {
+                        debug!(monitors = ?changed, "Large change, requesting a clip");
+                        clips.requested = changed;
+                        clips.requests.notify_one();
+                    }
+                }
                 let filtered_count = event.images.len();
                 info!(
                     original = original_count,
@@ -110,6 +739,28 @@ impl Processor<CaptureEvent, CaptureEvent> for FilterProcessor {
                     "Filter processed images"
                 );
 
+                // Events without images carry only metadata and are kept
+                if let Some(min_distance) = shedding
+                    && !event.images.is_empty()
+                    && event.images.keys().all(|id| {
+                        event
+                            .change_distances
+                            .get(id)
+                            .is_some_and(|distance| *distance < min_distance)
+                    })
+                {
+                    debug!(
+                        images = event.images.len(),
+                        "Output queue full, dropping low-change frames"
+                    );
+                    for (id, reference) in references {
+                        if let Some(state) = self.monitor_states.get_mut(&id) {
+                            state.restore(reference);
+                        }
+                    }
+                    continue;
+                }
+
                 if let Err(e) = tx.send(event).await {
                     info!(error = %e, "FilterProcessor: receiver dropped, stopping");
                     break;
@@ -122,61 +773,92 @@ impl Processor<CaptureEvent, CaptureEvent> for FilterProcessor {
     }
 }
 
-/// Compute perceptual hash (difference hash) for an image.
-///
-/// The dhash algorithm:
-/// 1. Resize image to 9x8
-/// 2. Convert to grayscale
-/// 3. Compare adjacent pixels horizontally
-/// 4. Generate 64-bit hash based on comparisons
-pub fn dhash(image: &DynamicImage) -> u64 {
-    let resolution = 8;
-    let resized = imageops::resize(
-        image,
-        resolution + 1,
-        resolution,
-        imageops::FilterType::Nearest,
-    );
-    let gray = imageops::grayscale(&resized);
-
-    let mut hash = 0u64;
-    for y in 0..resolution {
-        for x in 0..resolution {
-            let left = gray.get_pixel(x, y)[0];
-            let right = gray.get_pixel(x + 1, y)[0];
-            if left < right {
-                hash |= 1 << (y * resolution + x);
-            }
-        }
-    }
-    hash
-}
-
-/// Compute Hamming distance between two hashes.
-///
-/// Returns the number of bits that differ between the two hashes.
-pub fn hamming_distance(hash1: u64, hash2: u64) -> u32 {
-    (hash1 ^ hash2).count_ones()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+
+    fn frame(image: &mut Arc<DynamicImage>, debounce: TimeDelta) -> Frame<'_> {
+        Frame {
+            id: 0,
+            image,
+            monitor: None,
+            settings: None,
+            damage: None,
+            focused_window: None,
+            debounce,
+            now: Utc::now(),
+            force_due: false,
+            masked: None,
+            hashes: None,
+        }
+    }
 
     #[test]
-    fn test_dhash_identical() {
-        // Two identical images should have hamming distance of 0
-        let img = DynamicImage::new_rgba8(100, 100);
-        let hash1 = dhash(&img);
-        let hash2 = dhash(&img);
-        assert_eq!(hamming_distance(hash1, hash2), 0);
+    fn test_filter_order() {
+        let mut config = Config::default_config().capture;
+        let mut image = Arc::new(DynamicImage::new_rgb8(64, 64));
+        let debounce = TimeDelta::hours(1);
+
+        let mut processor = FilterProcessor::new(config.clone()).unwrap();
+        assert_eq!(
+            processor.decide(frame(&mut image, debounce)),
+            Decision::Accepted
+        );
+        assert_eq!(
+            processor.decide(frame(&mut image, debounce)),
+            Decision::RateLimited
+        );
+
+        config.filters = vec![FilterKind::Dhash, FilterKind::Ratelimit];
+        let mut processor = FilterProcessor::new(config.clone()).unwrap();
+        processor.decide(frame(&mut image, debounce));
+        assert_eq!(
+            processor.decide(frame(&mut image, debounce)),
+            Decision::Similar
+        );
+
+        config.filters = vec![FilterKind::Dhash, FilterKind::Masks];
+        assert!(FilterProcessor::new(config.clone()).is_err());
+        config.filters = vec![FilterKind::Dhash, FilterKind::Dhash];
+        assert!(FilterProcessor::new(config).is_err());
     }
 
     #[test]
-    fn test_hamming_distance() {
-        assert_eq!(hamming_distance(0b0000, 0b0000), 0);
-        assert_eq!(hamming_distance(0b0001, 0b0000), 1);
-        assert_eq!(hamming_distance(0b1111, 0b0000), 4);
-        assert_eq!(hamming_distance(0xFF, 0x00), 8);
+    fn test_damage_across_dropped_frames() {
+        let config = Config::default_config().capture;
+        let mut processor = FilterProcessor::new(config).unwrap();
+        let mut blank = Arc::new(DynamicImage::new_rgb8(64, 64));
+        let mut striped = Arc::new(DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+            64,
+            64,
+            |x, _| image::Rgb([if (x / 4) % 2 == 0 { 255 } else { 0 }; 3]),
+        )));
+        let changed = vec![Rect {
+            x: 0,
+            y: 0,
+            width: 64,
+            height: 64,
+        }];
+        let unchanged = Vec::new();
+
+        let mut first = frame(&mut blank, TimeDelta::zero());
+        first.damage = Some(&unchanged);
+        assert_eq!(processor.decide(first), Decision::Accepted);
+        // The change is dropped, and the next capture reports no damage
+        let mut dropped = frame(&mut striped, TimeDelta::hours(1));
+        dropped.damage = Some(&changed);
+        assert_eq!(processor.decide(dropped), Decision::RateLimited);
+        let mut still = frame(&mut striped, TimeDelta::zero());
+        still.damage = Some(&unchanged);
+        assert_eq!(processor.decide(still), Decision::Accepted);
+        assert_eq!(
+            processor.monitor_states[&0].changed_region,
+            Some(changed[0])
+        );
+
+        let mut still = frame(&mut striped, TimeDelta::zero());
+        still.damage = Some(&unchanged);
+        assert_eq!(processor.decide(still), Decision::Similar);
     }
 }
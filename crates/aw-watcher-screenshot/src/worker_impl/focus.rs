@@ -0,0 +1,157 @@
+//! Focused window spotlight processor.
+//!
+//! This module provides a `Processor` that keeps the focused window sharp
+//! and blurs the rest of every monitor, so background chats and mail stay
+//! unreadable in stored images. The window's desktop geometry is recorded
+//! in `CaptureEvent::focused_window` by the capture producer; monitors the
+//! window is not on are blurred entirely.
+
+use crate::config::FocusBlurConfig;
+use crate::event::{CaptureEvent, FocusedWindow, Rect, UploadImageInfo};
+use crate::mask;
+use crate::monitor::MonitorIdentity;
+use crate::priority::spawn_heavy;
+use crate::worker::Processor;
+use anyhow::{Error, Result};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Processor that blurs everything but the focused window.
+pub struct FocusBlurProcessor {
+    blur_sigma: f32,
+}
+
+impl FocusBlurProcessor {
+    pub fn new(config: &FocusBlurConfig) -> Self {
+        Self {
+            blur_sigma: config.blur_sigma.max(1.0),
+        }
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for FocusBlurProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        let sigma = self.blur_sigma;
+        Ok(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let event = if event.images.is_empty() {
+                    event
+                } else {
+                    match spawn_heavy(move || blur_background(event, sigma)).await {
+                        Ok(event) => event,
+                        Err(e) => {
+                            error!(error = %e, "Failed to spawn focus blur task");
+                            continue;
+                        }
+                    }
+                };
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "FocusBlurProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("FocusBlurProcessor finished");
+        }))
+    }
+}
+
+/// Blur every image of `event` outside the focused window.
+fn blur_background(mut event: CaptureEvent, sigma: f32) -> CaptureEvent {
+    for (id, image) in event.images.iter_mut() {
+        let (width, height) = (image.width(), image.height());
+        let sharp = event
+            .monitors
+            .get(id)
+            .zip(event.focused_window.as_ref())
+            .and_then(|(info, window)| {
+                let rect = window_rect(info, window)?;
+                mask::image_rects(info, image, &[rect]).pop()
+            });
+        let original = sharp.map(|rect| image.crop_imm(rect.x, rect.y, rect.width, rect.height));
+        let image = Arc::make_mut(image);
+        mask::blur(
+            image,
+            Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+            sigma,
+        );
+        if let (Some(rect), Some(original)) = (sharp, original) {
+            image::imageops::replace(image, &original, rect.x as i64, rect.y as i64);
+        }
+    }
+    event
+}
+
+/// The part of the monitor described by `info` covered by `window`, in
+/// monitor pixels; `None` when the window is on another monitor or the
+/// monitor geometry is unknown.
+fn window_rect(info: &UploadImageInfo, window: &FocusedWindow) -> Option<Rect> {
+    let monitor = MonitorIdentity::from_event_name(&info.monitor_name, &info.monitor_fingerprint)?;
+    let left = (window.x as i64 - monitor.x as i64).max(0);
+    let top = (window.y as i64 - monitor.y as i64).max(0);
+    let right =
+        (window.x as i64 + window.width as i64 - monitor.x as i64).min(monitor.width as i64);
+    let bottom =
+        (window.y as i64 + window.height as i64 - monitor.y as i64).min(monitor.height as i64);
+    (right > left && bottom > top).then(|| Rect {
+        x: left as u32,
+        y: top as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_rect() {
+        let info = UploadImageInfo::new(
+            "DELL_1920_1080_1920_0".to_string(),
+            2,
+            "0".to_string(),
+            String::new(),
+        );
+        let window = |x, y, width, height| FocusedWindow {
+            app_name: "slack".to_string(),
+            title: String::new(),
+            pid: 1,
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            window_rect(&info, &window(2020, 100, 800, 600)),
+            Some(Rect {
+                x: 100,
+                y: 100,
+                width: 800,
+                height: 600,
+            })
+        );
+        // Straddling the left monitor: only the part on this one
+        assert_eq!(
+            window_rect(&info, &window(1520, -10, 800, 600)),
+            Some(Rect {
+                x: 0,
+                y: 0,
+                width: 400,
+                height: 590,
+            })
+        );
+        assert_eq!(window_rect(&info, &window(0, 0, 800, 600)), None);
+    }
+}
@@ -0,0 +1,82 @@
+//! Meeting redaction processor.
+//!
+//! This module provides a `Processor` that pixelates every frame while a
+//! meeting is detected (see `guard::meeting`), for setups that keep capturing
+//! during calls instead of pausing.
+
+use crate::event::CaptureEvent;
+use crate::priority::spawn_heavy;
+use crate::worker::Processor;
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use image::imageops::FilterType;
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Processor that pixelates frames captured during a meeting, as stamped
+/// by the capture producer (`CaptureEvent::in_meeting`).
+///
+/// Runs right after `FilterProcessor` so later stages (OCR, storage) never
+/// see the unredacted frame.
+pub struct MeetingRedactProcessor {
+    pixel_size: u32,
+}
+
+impl MeetingRedactProcessor {
+    pub fn new(pixel_size: u32) -> Self {
+        Self {
+            pixel_size: pixel_size.max(1),
+        }
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for MeetingRedactProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        Ok(tokio::spawn(async move {
+            while let Some(mut event) = rx.recv().await {
+                if event.in_meeting {
+                    let pixel_size = self.pixel_size;
+                    event = match spawn_heavy(move || {
+                        for image in event.images.values_mut() {
+                            *image = Arc::new(pixelate(image, pixel_size));
+                        }
+                        event
+                    })
+                    .await
+                    {
+                        Ok(event) => event,
+                        Err(e) => {
+                            error!(error = %e, "Failed to spawn meeting redaction task");
+                            continue;
+                        }
+                    };
+                    info!("MeetingRedactProcessor: redacted frame captured during a meeting");
+                }
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "MeetingRedactProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("MeetingRedactProcessor finished");
+        }))
+    }
+}
+
+/// Replace the image by blocks of `pixel_size` pixels.
+pub fn pixelate(image: &DynamicImage, pixel_size: u32) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    image
+        .resize_exact(
+            (width / pixel_size).max(1),
+            (height / pixel_size).max(1),
+            FilterType::Triangle,
+        )
+        .resize_exact(width, height, FilterType::Nearest)
+}
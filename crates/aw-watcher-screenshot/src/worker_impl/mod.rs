@@ -1,6 +1,20 @@
+pub mod analytics;
 pub mod awserver;
 pub mod cache;
 pub mod capture;
+pub mod clip;
+pub mod face;
+pub mod fidelity;
 pub mod filter;
+pub mod focus;
+pub mod meeting;
+pub mod nsfw;
+pub mod ocr;
 pub mod passthrough;
+pub mod privacy;
+pub mod qrcode;
+pub mod redact;
+pub mod remote;
 pub mod s3;
+pub mod spill;
+pub mod summary;
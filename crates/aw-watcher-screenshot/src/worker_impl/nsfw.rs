@@ -0,0 +1,150 @@
+//! Unsafe content classifier gate.
+//!
+//! This module provides a `Processor` that runs an image classifier from
+//! `[models]` over every accepted screenshot and flags frames whose unsafe
+//! classes score above the threshold. Depending on `nsfw.action`, flagged
+//! frames are kept with a flag in their metadata, or their image is dropped
+//! so only the metadata reaches aw-server and nothing is cached or uploaded.
+
+use crate::config::{NsfwAction, NsfwConfig};
+use crate::event::CaptureEvent;
+use crate::inference::{ModelInput, ModelRegistry};
+use crate::priority::spawn_heavy;
+use crate::worker::Processor;
+use anyhow::{Error, Result};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Flag recorded in the metadata of flagged frames.
+const FLAG: &str = "nsfw";
+
+/// Processor that flags or withholds frames classified as unsafe.
+pub struct NsfwProcessor {
+    config: NsfwConfig,
+    registry: Arc<ModelRegistry>,
+}
+
+impl NsfwProcessor {
+    pub fn new(config: NsfwConfig, registry: Arc<ModelRegistry>) -> Self {
+        Self { config, registry }
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for NsfwProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        let config = Arc::new(self.config);
+        let registry = self.registry;
+
+        Ok(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let config = config.clone();
+                let registry = registry.clone();
+                let event =
+                    match spawn_heavy(move || classify_event(event, &config, &registry)).await {
+                        Ok(event) => event,
+                        Err(e) => {
+                            error!(error = %e, "Failed to spawn content classification task");
+                            continue;
+                        }
+                    };
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "NsfwProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("NsfwProcessor finished");
+        }))
+    }
+}
+
+/// Classify every image of `event` and flag or withhold the unsafe ones.
+/// When the model fails, all images are withheld rather than passed on
+/// unchecked, whatever the action.
+fn classify_event(
+    mut event: CaptureEvent,
+    config: &NsfwConfig,
+    registry: &ModelRegistry,
+) -> CaptureEvent {
+    let ids: Vec<u32> = event.images.keys().copied().collect();
+    let inputs: Vec<_> = ids
+        .iter()
+        .map(|id| ModelInput::Image(&event.images[id]))
+        .collect();
+    let name = config.model.as_deref().unwrap_or_default();
+    let flagged: Vec<u32> = match registry.get(name).and_then(|model| model.run(&inputs)) {
+        Ok(outputs) => ids
+            .iter()
+            .zip(outputs)
+            .filter(|(_, output)| unsafe_score(output, config) >= config.threshold)
+            .map(|(id, _)| *id)
+            .collect(),
+        Err(e) => {
+            warn!(model = name, error = %e, "Content classification failed, withholding images");
+            for id in ids {
+                withhold(&mut event, id);
+            }
+            return event;
+        }
+    };
+
+    for id in flagged {
+        info!(monitor_id = id, action = ?config.action, "Frame classified as unsafe");
+        if let Some(monitor) = event.monitors.get_mut(&id) {
+            monitor.flag = Some(FLAG.to_string());
+        }
+        if config.action == NsfwAction::Drop {
+            withhold(&mut event, id);
+        }
+    }
+    event
+}
+
+/// Drop the image of monitor `id`, keeping only its metadata.
+fn withhold(event: &mut CaptureEvent, id: u32) {
+    if let Some(monitor) = event.monitors.get_mut(&id) {
+        monitor.object_key.clear();
+    }
+    event.images.remove(&id);
+}
+
+/// Total probability of the unsafe classes in one model output.
+fn unsafe_score(output: &[f32], config: &NsfwConfig) -> f32 {
+    let probabilities = if config.softmax {
+        let max = output.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let exps: Vec<f32> = output.iter().map(|logit| (logit - max).exp()).collect();
+        let sum: f32 = exps.iter().sum();
+        exps.iter().map(|exp| exp / sum).collect()
+    } else {
+        output.to_vec()
+    };
+    config
+        .unsafe_classes
+        .iter()
+        .filter_map(|class| probabilities.get(*class))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsafe_score() {
+        let mut config = NsfwConfig {
+            unsafe_classes: vec![1, 3, 9],
+            softmax: false,
+            ..Default::default()
+        };
+        assert!((unsafe_score(&[0.1, 0.5, 0.1, 0.3], &config) - 0.8).abs() < 1e-6);
+        config.softmax = true;
+        assert!((unsafe_score(&[0.0, 0.0, 0.0, 0.0], &config) - 0.5).abs() < 1e-6);
+        assert!(unsafe_score(&[10.0, 0.0, 0.0, 0.0], &config) < 0.01);
+    }
+}
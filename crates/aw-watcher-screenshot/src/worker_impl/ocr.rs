@@ -0,0 +1,228 @@
+//! OCR and language detection processor.
+//!
+//! This module provides a `Processor` that recognizes text in accepted
+//! screenshots with the `tesseract` command line tool and identifies the
+//! language of the recognized text. Results are attached to the
+//! `CaptureEvent` for downstream stages and summarized in the image metadata.
+
+use crate::config::OcrConfig;
+use crate::event::{CaptureEvent, OcrResult, OcrWord, Rect};
+use crate::priority::spawn_heavy;
+use crate::worker::Processor;
+use anyhow::{Context, Error, Result};
+use futures::future::join_all;
+use image::{DynamicImage, ImageFormat};
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+/// Processor that runs OCR on every image of an event.
+pub struct OcrProcessor {
+    config: Arc<OcrConfig>,
+    /// Language detected in the previous frame of each monitor.
+    last_languages: HashMap<u32, String>,
+}
+
+impl OcrProcessor {
+    pub fn new(config: OcrConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            last_languages: HashMap::new(),
+        }
+    }
+
+    /// Tesseract languages to use for the next frame of a monitor.
+    fn languages_for(&self, monitor_id: u32) -> String {
+        if self.config.auto_language
+            && let Some(last) = self.last_languages.get(&monitor_id)
+            && self.config.languages.contains(last)
+        {
+            return last.clone();
+        }
+        self.config.languages.join("+")
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for OcrProcessor {
+    fn process(
+        mut self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        Ok(tokio::spawn(async move {
+            while let Some(mut event) = rx.recv().await {
+                let mut ocr_futures = Vec::new();
+                // Clip frames are not searched, their text is never stored
+                let clip_frame = event.is_clip_frame();
+                for (id, image) in event.images.iter().filter(|_| !clip_frame) {
+                    let id = *id;
+                    let image = image.clone();
+                    let config = self.config.clone();
+                    let languages = self.languages_for(id);
+                    // Already recognized by the redaction stage
+                    let recognized = event.ocr.get(&id).cloned();
+
+                    ocr_futures.push(async move {
+                        if let Some(ocr) = recognized {
+                            return Ok((id, ocr));
+                        }
+                        let result =
+                            spawn_heavy(move || recognize(&config.command, &languages, &image))
+                                .await?;
+                        Ok::<_, Error>((id, result?))
+                    });
+                }
+
+                for result in join_all(ocr_futures).await {
+                    let (id, ocr) = match result {
+                        Ok(ocr) => ocr,
+                        Err(e) => {
+                            error!(error = %e, "Failed to run OCR");
+                            continue;
+                        }
+                    };
+
+                    match &ocr.language {
+                        Some(language) => {
+                            self.last_languages.insert(id, language.clone());
+                        }
+                        None => {
+                            self.last_languages.remove(&id);
+                        }
+                    }
+                    if let Some(monitor) = event.monitors.get_mut(&id) {
+                        monitor.language = ocr.language.clone();
+                        if self.config.include_text {
+                            monitor.text = Some(ocr.text());
+                        }
+                    }
+                    debug!(
+                        monitor_id = id,
+                        words = ocr.words.len(),
+                        language = ?ocr.language,
+                        "OCR finished"
+                    );
+                    event.ocr.insert(id, ocr);
+                }
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "OcrProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("OcrProcessor finished");
+        }))
+    }
+}
+
+/// Run tesseract on an image and detect the language of the result.
+pub fn recognize(command: &str, languages: &str, image: &DynamicImage) -> Result<OcrResult, Error> {
+    // Grayscale PNM is cheap to encode and sufficient for recognition
+    let mut input = Cursor::new(Vec::new());
+    image
+        .to_luma8()
+        .write_to(&mut input, ImageFormat::Pnm)
+        .context("Failed to encode image for OCR")?;
+
+    let mut child = Command::new(command)
+        .args(["stdin", "stdout", "-l", languages, "tsv"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to start {}", command))?;
+
+    // Tesseract reads the whole image before writing any output
+    child
+        .stdin
+        .take()
+        .context("tesseract stdin unavailable")?
+        .write_all(input.get_ref())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!("tesseract exited with {}", output.status));
+    }
+
+    let words = parse_tsv(&String::from_utf8_lossy(&output.stdout));
+    let mut result = OcrResult {
+        words,
+        language: None,
+    };
+    result.language = detect_language(&result.text());
+    Ok(result)
+}
+
+/// Parse word-level rows from tesseract TSV output.
+fn parse_tsv(tsv: &str) -> Vec<OcrWord> {
+    let mut words = Vec::new();
+    let mut line_key = None;
+    let mut line = 0;
+
+    // Columns: level page block par line word left top width height conf text
+    for row in tsv.lines().skip(1) {
+        let cols: Vec<&str> = row.split('\t').collect();
+        if cols.len() < 12 || cols[0] != "5" {
+            continue;
+        }
+        let text = cols[11].trim();
+        let confidence: f32 = cols[10].parse().unwrap_or(-1.0);
+        if text.is_empty() || confidence < 0.0 {
+            continue;
+        }
+
+        let key = (cols[2], cols[3], cols[4]);
+        if line_key.is_some_and(|k| k != key) {
+            line += 1;
+        }
+        line_key = Some(key);
+
+        let coordinate = |col: &str| col.parse().unwrap_or(0);
+        words.push(OcrWord {
+            text: text.to_string(),
+            line,
+            bounds: Rect {
+                x: coordinate(cols[6]),
+                y: coordinate(cols[7]),
+                width: coordinate(cols[8]),
+                height: coordinate(cols[9]),
+            },
+        });
+    }
+    words
+}
+
+/// Detect the language of a text, as a tesseract language code.
+fn detect_language(text: &str) -> Option<String> {
+    let info = whatlang::detect(text).filter(|info| info.is_reliable())?;
+    let code = match info.lang().code() {
+        // whatlang reports ISO 639-3, tesseract names Chinese by script
+        "cmn" => "chi_sim",
+        code => code,
+    };
+    Some(code.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tsv_lines() {
+        let tsv = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext\n\
+                   5\t1\t1\t1\t1\t1\t10\t20\t30\t40\t96.5\tHello\n\
+                   5\t1\t1\t1\t1\t2\t50\t20\t30\t40\t95.0\tworld\n\
+                   4\t1\t1\t1\t2\t0\t10\t70\t80\t40\t-1\t\n\
+                   5\t1\t1\t1\t2\t1\t10\t70\t80\t40\t91.2\tAgain\n";
+        let result = OcrResult {
+            words: parse_tsv(tsv),
+            language: None,
+        };
+        assert_eq!(result.words.len(), 3);
+        assert_eq!(result.words[1].bounds.x, 50);
+        assert_eq!(result.text(), "Hello world\nAgain");
+    }
+}
@@ -0,0 +1,97 @@
+//! Time-of-day privacy rules.
+//!
+//! This module provides a `Processor` that reduces what is stored of frames
+//! captured during the periods of `[[privacy_rule]]` entries, e.g. only
+//! metadata between 18:00 and 09:00 or thumbnails on weekends, for machines
+//! shared between work and personal time. It runs right after the filter,
+//! so later stages only ever see the reduced frame.
+
+use crate::config::{PrivacyAction, PrivacyRule};
+use crate::event::CaptureEvent;
+use crate::priority::spawn_heavy;
+use crate::schedule::Schedule;
+use crate::worker::Processor;
+use crate::worker_impl::meeting::pixelate;
+use anyhow::{Error, Result};
+use image::imageops::FilterType;
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+/// Processor that applies the privacy rule in effect at capture time.
+pub struct PrivacyRuleProcessor {
+    rules: Vec<(Schedule, PrivacyRule)>,
+}
+
+impl PrivacyRuleProcessor {
+    pub fn new(rules: &[PrivacyRule]) -> Result<Self, Error> {
+        let rules = rules
+            .iter()
+            .map(|rule| Ok((Schedule::new(&rule.schedule())?, rule.clone())))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { rules })
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for PrivacyRuleProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        Ok(tokio::spawn(async move {
+            while let Some(mut event) = rx.recv().await {
+                let rule = self
+                    .rules
+                    .iter()
+                    .find(|(schedule, _)| schedule.contains(event.timestamp))
+                    .map(|(_, rule)| rule.clone());
+                if let Some(rule) = rule
+                    && !event.images.is_empty()
+                {
+                    debug!(action = ?rule.action, "Privacy rule in effect");
+                    event = match spawn_heavy(move || apply(event, &rule)).await {
+                        Ok(event) => event,
+                        Err(e) => {
+                            error!(error = %e, "Failed to spawn privacy rule task");
+                            continue;
+                        }
+                    };
+                }
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "PrivacyRuleProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("PrivacyRuleProcessor finished");
+        }))
+    }
+}
+
+/// Reduce the images of `event` as `rule` says. Metadata-only frames keep
+/// their monitor entries with an empty `object_key`.
+fn apply(mut event: CaptureEvent, rule: &PrivacyRule) -> CaptureEvent {
+    match rule.action {
+        PrivacyAction::Metadata => {
+            for (id, monitor) in event.monitors.iter_mut() {
+                if event.images.remove(id).is_some() {
+                    monitor.object_key.clear();
+                }
+            }
+        }
+        PrivacyAction::Thumbnail => {
+            let size = rule.max_dimension.max(1);
+            for image in event.images.values_mut() {
+                *image = Arc::new(image.resize(size, size, FilterType::Triangle));
+            }
+        }
+        PrivacyAction::Pixelate => {
+            for image in event.images.values_mut() {
+                *image = Arc::new(pixelate(image, rule.pixel_size.max(1)));
+            }
+        }
+    }
+    event
+}
@@ -0,0 +1,210 @@
+//! QR code detection processor.
+//!
+//! This module provides a `Processor` that scans accepted screenshots for QR
+//! codes and records the decoded payloads in the per-monitor metadata, or
+//! redacts them from the image when the configuration forbids storing codes.
+//! Only QR codes are detected (rqrr); linear barcodes are not.
+
+use crate::config::QrCodeConfig;
+use crate::event::CaptureEvent;
+use crate::priority::spawn_heavy;
+use crate::worker::Processor;
+use anyhow::{Error, Result};
+use image::{DynamicImage, GenericImage, Rgba};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Placeholder stored in metadata instead of a redacted payload.
+const REDACTED_PAYLOAD: &str = "[redacted]";
+
+/// A QR code found in an image.
+struct DetectedCode {
+    payload: String,
+    /// Axis-aligned bounding box as (x, y, width, height).
+    bounds: (u32, u32, u32, u32),
+}
+
+/// Processor that decodes QR codes in filtered screenshots.
+///
+/// Runs between `FilterProcessor` and `ToWebpProcessor` so only frames that
+/// will actually be stored are scanned.
+pub struct QrCodeProcessor {
+    config: QrCodeConfig,
+}
+
+impl QrCodeProcessor {
+    pub fn new(config: QrCodeConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for QrCodeProcessor {
+    fn process(
+        self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        let redact = self.config.redact;
+
+        Ok(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                // Decoding is CPU-bound, keep it off the async workers
+                let event = match spawn_heavy(move || scan_event(event, redact)).await {
+                    Ok(event) => event,
+                    Err(e) => {
+                        error!(error = %e, "Failed to spawn QR code scan task");
+                        continue;
+                    }
+                };
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "QrCodeProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+            info!("QrCodeProcessor finished");
+        }))
+    }
+}
+
+/// Scan every image in the event and attach the results to its monitor info.
+fn scan_event(mut event: CaptureEvent, redact: bool) -> CaptureEvent {
+    for (id, image) in event.images.iter_mut() {
+        let codes = detect_codes(image);
+        if codes.is_empty() {
+            continue;
+        }
+
+        info!(
+            monitor_id = id,
+            count = codes.len(),
+            redact,
+            "Detected QR codes"
+        );
+
+        if redact {
+            let image = Arc::make_mut(image);
+            for code in &codes {
+                black_out(image, code.bounds);
+            }
+        }
+
+        if let Some(monitor) = event.monitors.get_mut(id) {
+            monitor.qr_codes = codes
+                .into_iter()
+                .map(|code| {
+                    if redact {
+                        REDACTED_PAYLOAD.to_string()
+                    } else {
+                        code.payload
+                    }
+                })
+                .collect();
+        }
+    }
+    event
+}
+
+/// Detect and decode all QR codes in an image.
+fn detect_codes(image: &DynamicImage) -> Vec<DetectedCode> {
+    let gray = image.to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        gray.width() as usize,
+        gray.height() as usize,
+        |x, y| gray.get_pixel(x as u32, y as u32)[0],
+    );
+
+    let mut codes = Vec::new();
+    for grid in prepared.detect_grids() {
+        match grid.decode() {
+            Ok((_, payload)) => codes.push(DetectedCode {
+                payload,
+                bounds: bounding_box(&grid.bounds, gray.width(), gray.height()),
+            }),
+            Err(e) => info!(error = %e, "Found QR code that could not be decoded"),
+        }
+    }
+    codes
+}
+
+/// Compute the axis-aligned bounding box of a code's corners, clamped to the image.
+fn bounding_box(corners: &[rqrr::Point; 4], width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let clamp_x = |v: i32| v.clamp(0, width as i32) as u32;
+    let clamp_y = |v: i32| v.clamp(0, height as i32) as u32;
+
+    let min_x = clamp_x(corners.iter().map(|p| p.x).min().unwrap_or(0));
+    let max_x = clamp_x(corners.iter().map(|p| p.x).max().unwrap_or(0));
+    let min_y = clamp_y(corners.iter().map(|p| p.y).min().unwrap_or(0));
+    let max_y = clamp_y(corners.iter().map(|p| p.y).max().unwrap_or(0));
+
+    (min_x, min_y, max_x - min_x, max_y - min_y)
+}
+
+/// Paint a rectangle of the image black.
+fn black_out(image: &mut DynamicImage, (x, y, width, height): (u32, u32, u32, u32)) {
+    for py in y..y + height {
+        for px in x..x + width {
+            image.put_pixel(px, py, Rgba([0, 0, 0, 255]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    /// Version 1 QR code of "aw-watcher", `#` for dark modules.
+    const CODE: [&str; 21] = [
+        "#######...#.#.#######",
+        "#.....#.....#.#.....#",
+        "#.###.#.#.#...#.###.#",
+        "#.###.#.....#.#.###.#",
+        "#.###.#..#.##.#.###.#",
+        "#.....#..###..#.....#",
+        "#######.#.#.#.#######",
+        "........#.#..........",
+        "###.#####.#.###...#..",
+        ".##....##..#.####...#",
+        "#.#...#.##.#..####.##",
+        "...###.######...##...",
+        "..########.#....#...#",
+        "........##.....##.###",
+        "#######.##..#########",
+        "#.....#.#.#....#...##",
+        "#.###.#.#...##.###...",
+        "#.###.#..###.#..#..#.",
+        "#.###.#.####...###..#",
+        "#.....#.#.####.....#.",
+        "#######.#..#..#.#..##",
+    ];
+
+    /// Render `CODE` with `scale` pixels per module and a quiet zone.
+    fn render(scale: u32) -> DynamicImage {
+        let quiet = 4 * scale;
+        let side = CODE.len() as u32 * scale + 2 * quiet;
+        let image = GrayImage::from_fn(side, side, |x, y| {
+            let dark = x >= quiet
+                && y >= quiet
+                && CODE
+                    .get(((y - quiet) / scale) as usize)
+                    .and_then(|row| row.as_bytes().get(((x - quiet) / scale) as usize))
+                    == Some(&b'#');
+            Luma([if dark { 0 } else { 255 }])
+        });
+        DynamicImage::ImageLuma8(image)
+    }
+
+    #[test]
+    fn test_detect_codes() {
+        let codes = detect_codes(&render(8));
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].payload, "aw-watcher");
+        // Redaction must cover the whole code
+        let (x, y, width, height) = codes[0].bounds;
+        assert!(x <= 32 && y <= 32 && x + width >= 32 + 21 * 8 && y + height >= 32 + 21 * 8);
+        assert!(detect_codes(&DynamicImage::new_luma8(64, 64)).is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+//! Remote agent and server stages, see `crate::remote` for the protocol.
+
+use crate::config::MonitorConfig;
+use crate::encode::FrameEncoder;
+use crate::event::{CaptureEvent, UploadImageInfo};
+use crate::mask;
+use crate::monitor::{MonitorIdentity, find_settings, sanitize_name};
+use crate::priority;
+use crate::remote::{FrameHeader, read_frame, token_matches, write_frame};
+use crate::timezone::TimePolicy;
+use crate::worker::{Consumer, Producer};
+use anyhow::{Error, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+/// Delay before reconnecting after the server could not be reached.
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Largest difference between an agent's frame timestamp and the server's
+/// clock that is accepted; frames further off are stamped with the time they
+/// were received.
+const MAX_CLOCK_SKEW: TimeDelta = TimeDelta::minutes(5);
+
+/// Ships filtered frames to a watcher in server mode.
+///
+/// Frames are encoded losslessly and not stored on the agent; frames
+/// produced while the server is unreachable are dropped.
+pub struct RemoteSender {
+    address: String,
+    token: Option<String>,
+    host: String,
+    encoder: Arc<dyn FrameEncoder>,
+}
+
+impl RemoteSender {
+    pub fn new(
+        address: String,
+        token: Option<String>,
+        host: String,
+        encoder: Arc<dyn FrameEncoder>,
+    ) -> Self {
+        Self {
+            address,
+            token,
+            host,
+            encoder,
+        }
+    }
+}
+
+impl Consumer<CaptureEvent> for RemoteSender {
+    fn consume(self, mut rx: Receiver<CaptureEvent>) -> Result<JoinHandle<()>, Error> {
+        Ok(tokio::spawn(async move {
+            let mut stream: Option<TcpStream> = None;
+            let mut next_attempt = Instant::now();
+
+            while let Some(event) = rx.recv().await {
+                if event.monitors.is_empty() {
+                    continue;
+                }
+                if stream.is_none() {
+                    if Instant::now() < next_attempt {
+                        debug!("Not connected to the remote server, dropping event");
+                        continue;
+                    }
+                    match TcpStream::connect(&self.address).await {
+                        Ok(connected) => {
+                            info!(address = %self.address, "Connected to remote server");
+                            stream = Some(connected);
+                        }
+                        Err(e) => {
+                            warn!(address = %self.address, error = %e, "Failed to connect to remote server");
+                            next_attempt = Instant::now() + RECONNECT_DELAY;
+                            continue;
+                        }
+                    }
+                }
+
+                let encoder = self.encoder.clone();
+                let timestamp = event.timestamp;
+                let (images, datas) =
+                    match priority::spawn_heavy(move || encode_event(&event, encoder.as_ref()))
+                        .await
+                    {
+                        Ok(encoded) => encoded,
+                        Err(e) => {
+                            error!(error = %e, "Failed to encode frame for the remote server");
+                            continue;
+                        }
+                    };
+                let header = FrameHeader {
+                    token: self.token.clone(),
+                    host: self.host.clone(),
+                    timestamp,
+                    content_type: self.encoder.content_type().to_string(),
+                    images,
+                };
+                let datas: Vec<&[u8]> = datas.iter().map(Vec::as_slice).collect();
+
+                if let Some(connected) = stream.as_mut()
+                    && let Err(e) = write_frame(connected, &header, &datas).await
+                {
+                    warn!(error = %e, "Lost connection to remote server");
+                    stream = None;
+                }
+            }
+            info!("RemoteSender finished");
+        }))
+    }
+}
+
+/// Encode the images of `event` in monitor order. Monitors without an
+/// image, such as withheld or mirrored ones, are sent with empty data.
+fn encode_event(
+    event: &CaptureEvent,
+    encoder: &dyn FrameEncoder,
+) -> (Vec<(UploadImageInfo, u32)>, Vec<Vec<u8>>) {
+    let mut ids: Vec<u32> = event.monitors.keys().copied().collect();
+    ids.sort_unstable();
+    let mut images = Vec::with_capacity(ids.len());
+    let mut datas = Vec::with_capacity(ids.len());
+    for id in ids {
+        let mut data = Vec::new();
+        if let Some(image) = event.images.get(&id)
+            && let Err(e) = encoder.encode(image, &mut data)
+        {
+            error!(monitor_id = id, error = %e, "Failed to encode image");
+            continue;
+        }
+        images.push((event.monitors[&id].clone(), data.len() as u32));
+        datas.push(data);
+    }
+    (images, datas)
+}
+
+/// Receives frames from agents and feeds them into the pipeline in place
+/// of local capture.
+pub struct RemoteReceiver {
+    address: String,
+    token: Option<String>,
+    cancel: CancellationToken,
+    time_policy: TimePolicy,
+    monitor_settings: Vec<Arc<MonitorConfig>>,
+}
+
+impl RemoteReceiver {
+    pub fn new(address: String, token: Option<String>, cancel: CancellationToken) -> Self {
+        Self {
+            address,
+            token,
+            cancel,
+            time_policy: TimePolicy::default(),
+            monitor_settings: Vec::new(),
+        }
+    }
+
+    /// Name object keys according to `policy`.
+    pub fn with_time_policy(mut self, policy: TimePolicy) -> Self {
+        self.time_policy = policy;
+        self
+    }
+
+    /// Match `[[monitor]]` entries against the monitors of agents.
+    pub fn with_monitor_settings(mut self, settings: Vec<MonitorConfig>) -> Self {
+        self.monitor_settings = settings.into_iter().map(Arc::new).collect();
+        self
+    }
+}
+
+/// State shared by all agent connections.
+struct Agents {
+    token: Option<String>,
+    time_policy: TimePolicy,
+    monitor_settings: Vec<Arc<MonitorConfig>>,
+    /// Pipeline monitor ids of `(host, agent monitor id)` pairs, so monitors
+    /// of different agents are processed and cached apart.
+    monitor_ids: Mutex<HashMap<(String, u32), u32>>,
+}
+
+impl Agents {
+    fn monitor_id(&self, host: &str, agent_id: u32) -> u32 {
+        let mut ids = self.monitor_ids.lock().unwrap_or_else(|e| e.into_inner());
+        let next = ids.len() as u32;
+        *ids.entry((host.to_string(), agent_id)).or_insert(next)
+    }
+
+    /// Object key of a monitor's frame, built here rather than taken from
+    /// the agent.
+    fn object_key(&self, host: &str, timestamp: DateTime<Utc>, monitor_id: u32) -> String {
+        format!(
+            "{}/{}/{}_{}.webp",
+            host,
+            self.time_policy.format(timestamp, "%Y/%m/%d/%H"),
+            self.time_policy.format(timestamp, "%Y%m%d_%H%M%S%3f"),
+            monitor_id
+        )
+    }
+
+    fn resolve_settings(&self, monitor: &UploadImageInfo) -> Option<Arc<MonitorConfig>> {
+        let identity =
+            MonitorIdentity::from_event_name(&monitor.monitor_name, &monitor.monitor_fingerprint)?;
+        find_settings(&self.monitor_settings, &identity).cloned()
+    }
+}
+
+impl Producer<CaptureEvent> for RemoteReceiver {
+    fn produce(self, tx: Sender<CaptureEvent>) -> Result<JoinHandle<()>, Error> {
+        let listener = std::net::TcpListener::bind(&self.address)?;
+        let local_addr = listener.local_addr()?;
+        if !local_addr.ip().is_loopback() && self.token.is_none() {
+            anyhow::bail!(
+                "Refusing to listen on {} without remote.token, set one to accept agents from other machines",
+                local_addr
+            );
+        }
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        info!(%local_addr, "Remote server listening");
+
+        let agents = Arc::new(Agents {
+            token: self.token,
+            time_policy: self.time_policy,
+            monitor_settings: self.monitor_settings,
+            monitor_ids: Mutex::new(HashMap::new()),
+        });
+        let cancel = self.cancel;
+        Ok(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, peer)) => {
+                            tokio::spawn(handle_agent(
+                                stream,
+                                peer,
+                                agents.clone(),
+                                tx.clone(),
+                                cancel.clone(),
+                            ));
+                        }
+                        Err(e) => error!(error = %e, "Failed to accept agent connection"),
+                    },
+                }
+            }
+            info!("RemoteReceiver finished");
+        }))
+    }
+}
+
+async fn handle_agent(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    agents: Arc<Agents>,
+    tx: Sender<CaptureEvent>,
+    cancel: CancellationToken,
+) {
+    info!(%peer, "Agent connected");
+    loop {
+        let frame = tokio::select! {
+            _ = cancel.cancelled() => break,
+            frame = read_frame(&mut stream) => frame,
+        };
+        let (header, datas) = match frame {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                info!(%peer, "Agent disconnected");
+                break;
+            }
+            Err(e) => {
+                warn!(%peer, error = %e, "Invalid frame from agent, closing connection");
+                break;
+            }
+        };
+        if !token_matches(agents.token.as_deref(), header.token.as_deref()) {
+            warn!(%peer, "Agent sent a wrong token, closing connection");
+            break;
+        }
+
+        let agents = agents.clone();
+        let event =
+            tokio::task::spawn_blocking(move || to_capture_event(header, datas, &agents)).await;
+        match event {
+            Ok(event) if !event.monitors.is_empty() => {
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!(error = %e, "Failed to decode agent frame"),
+        }
+    }
+}
+
+/// Decode a frame into a capture event, tagging every monitor with the
+/// agent's host name. Object keys are rebuilt from the sanitized host name
+/// and the timestamp, and fields only the server's own stages set are reset.
+fn to_capture_event(header: FrameHeader, datas: Vec<Vec<u8>>, agents: &Agents) -> CaptureEvent {
+    let mut event = CaptureEvent::new();
+    let received = event.timestamp;
+    let host = sanitize_name(&header.host);
+    if (header.timestamp - received).abs() > MAX_CLOCK_SKEW {
+        warn!(host, agent_time = %header.timestamp, "Agent clock is off, using the receive time");
+    } else {
+        event.timestamp = header.timestamp;
+    }
+
+    for ((mut info, _), data) in header.images.into_iter().zip(datas) {
+        let monitor_id = agents.monitor_id(&host, info.monitor_id);
+        let mirror_of = info.mirror_of.map(|id| agents.monitor_id(&host, id));
+        info.monitor_id = monitor_id;
+        info.mirror_of = mirror_of;
+        info.object_key = if data.is_empty() && mirror_of.is_none() {
+            // Withheld on the agent
+            String::new()
+        } else {
+            agents.object_key(&host, event.timestamp, mirror_of.unwrap_or(monitor_id))
+        };
+        info.utc_offset = agents.time_policy.utc_offset(event.timestamp);
+        info.cache_file = None;
+        info.thumbnail = None;
+        info.uploaded = false;
+        info.host = Some(header.host.clone());
+        info.settings = agents.resolve_settings(&info);
+
+        if data.is_empty() {
+            event.monitors.insert(monitor_id, info);
+            continue;
+        }
+        match image::load_from_memory(&data) {
+            Ok(mut image) => {
+                // The server's own blackouts for this monitor, on top of the agent's
+                if let Some(settings) = &info.settings {
+                    let regions = mask::blackout_regions(settings);
+                    if !regions.is_empty() {
+                        let rects = mask::image_rects(&info, &image, &regions);
+                        mask::fill(&mut image, &rects);
+                    }
+                }
+                event.add_image(monitor_id, image, info)
+            }
+            Err(e) => warn!(host, error = %e, "Failed to decode agent image"),
+        }
+    }
+    event
+}
@@ -1,60 +1,375 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::config::S3Config;
+use crate::config::{NetworkConfig, S3Config};
 use crate::event::{AwEvent, ImageEvent, UploadS3Info};
+use crate::network::MeteredCheck;
+use crate::power::PowerProfile;
 use crate::worker::Processor;
 use anyhow::{Context, Error, Result};
-use futures::future::join_all;
+use futures::future::{self, join_all};
 use s3::creds::Credentials;
 use s3::{Bucket, Region};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{Mutex, watch};
 use tokio::task::JoinHandle;
 use tracing::{error, info, warn};
 
+/// Queued uploads sent after each event once the connection is unmetered,
+/// so draining a long queue does not stall the pipeline.
+const MAX_QUEUED_UPLOADS_PER_EVENT: usize = 16;
+
+/// Thumbnails are always WebP, see `cache.thumbnail_width`.
+const THUMBNAIL_CONTENT_TYPE: &str = "image/webp";
+
+/// Directory in the cache dir holding upload variants waiting in the queue.
+pub const UPLOAD_SPOOL_DIR: &str = "upload_spool";
+
+/// Serializes rewrites of the upload queue file between the S3 stage and
+/// retention.
+static QUEUE_LOCK: Mutex<()> = Mutex::const_new(());
+
 pub struct S3Processor {
     upload_config: UploadS3Info,
-    bucket: Arc<Box<Bucket>>,
+    bucket: Arc<Bucket>,
+    /// Uploads deferred while metered or on battery.
+    queue: Option<UploadQueue>,
+    metered: Option<MeteredCheck>,
+    power: Option<watch::Receiver<PowerProfile>>,
 }
 
 impl S3Processor {
     pub fn new(config: S3Config) -> Result<Self, Error> {
-        let region = Region::Custom {
-            region: config.region,
-            endpoint: config.endpoint.clone(),
-        };
-
-        let credentials = Credentials::new(
-            Some(&config.access_key),
-            Some(&config.secret_key),
-            None,
-            None,
-            None,
-        )
-        .context("Failed to create S3 credentials")?;
-
-        let bucket = Bucket::new(&config.bucket, region, credentials)
-            .context("Failed to create S3 bucket")?
-            .with_path_style();
+        let bucket = open_bucket(&config)?;
 
         Ok(Self {
             upload_config: UploadS3Info::new(config.endpoint, config.bucket, config.key_prefix),
-            bucket: Arc::new(bucket),
+            bucket: Arc::from(bucket),
+            queue: None,
+            metered: None,
+            power: None,
         })
     }
+
+    /// Queue uploads in `cache_dir` while the connection is metered.
+    pub fn with_metered_deferral(mut self, config: &NetworkConfig, cache_dir: &Path) -> Self {
+        self.metered = Some(MeteredCheck::new(Duration::from_secs(
+            config.metered_check_secs,
+        )));
+        self.queue = Some(UploadQueue::new(cache_dir));
+        self
+    }
+
+    /// Queue uploads in `cache_dir` in battery-saver mode.
+    pub fn with_power_profile(
+        mut self,
+        profile: watch::Receiver<PowerProfile>,
+        cache_dir: &Path,
+    ) -> Self {
+        self.power = Some(profile);
+        self.queue = Some(UploadQueue::new(cache_dir));
+        self
+    }
+}
+
+/// An upload waiting for an unmetered connection.
+#[derive(Serialize, Deserialize)]
+struct QueuedUpload {
+    path: PathBuf,
+    object_key: String,
+    content_type: String,
+}
+
+/// Uploads deferred to disk, one JSON object per line.
+struct UploadQueue {
+    path: PathBuf,
+    /// Upload variants waiting in the queue; the cache only has the cached
+    /// variant, see `s3.encoder`.
+    spool_dir: PathBuf,
+}
+
+impl UploadQueue {
+    fn new(cache_dir: &Path) -> Self {
+        Self {
+            path: cache_dir.join("upload_queue.jsonl"),
+            spool_dir: cache_dir.join(UPLOAD_SPOOL_DIR),
+        }
+    }
+
+    /// Write an upload variant to the spool and return its path. It is named
+    /// like the cached frame, so retention and purge recognize it.
+    async fn spool(&self, object_key: &str, data: &[u8]) -> Result<PathBuf, Error> {
+        fs::create_dir_all(&self.spool_dir).await?;
+        let file_name = object_key.rsplit('/').next().unwrap_or(object_key);
+        let path = self.spool_dir.join(file_name);
+        fs::write(&path, data).await?;
+        Ok(path)
+    }
+
+    async fn push(&self, uploads: &[QueuedUpload]) -> Result<(), Error> {
+        let _lock = QUEUE_LOCK.lock().await;
+        let mut lines = Vec::new();
+        for upload in uploads {
+            serde_json::to_writer(&mut lines, upload)?;
+            lines.push(b'\n');
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&lines).await?;
+        Ok(())
+    }
+
+    /// Return up to `max` queued uploads, oldest first, with their lines in
+    /// the queue; malformed entries come back as `None`. They stay queued
+    /// until `remove` is called with their lines.
+    async fn peek(&self, max: usize) -> Result<Vec<(String, Option<QueuedUpload>)>, Error> {
+        let _lock = QUEUE_LOCK.lock().await;
+        let content = read_queue(&self.path).await?;
+        Ok(content
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .take(max)
+            .map(|line| (line.to_string(), serde_json::from_str(line).ok()))
+            .collect())
+    }
+
+    /// Remove each of `lines` once from the queue.
+    async fn remove(&self, lines: &[String]) -> Result<(), Error> {
+        let _lock = QUEUE_LOCK.lock().await;
+        let content = read_queue(&self.path).await?;
+        let mut pending: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut kept = String::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            if let Some(index) = pending.iter().position(|pending| *pending == line) {
+                pending.swap_remove(index);
+                continue;
+            }
+            kept.push_str(line);
+            kept.push('\n');
+        }
+        write_queue(&self.path, &kept).await
+    }
+
+    /// Upload a batch of queued files in order, removing each entry only once
+    /// it is uploaded. Stops at the first failure, to retry from there later.
+    async fn drain(&self, bucket: &Bucket, max: usize) {
+        let entries = match self.peek(max).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!(path = %self.path.display(), error = %e, "Failed to read upload queue");
+                return;
+            }
+        };
+        if entries.is_empty() {
+            return;
+        }
+        info!("S3Processor: uploading {} queued images", entries.len());
+
+        let mut done = Vec::new();
+        for (line, upload) in entries {
+            let Some(upload) = upload else {
+                warn!(line, "Dropping malformed upload queue entry");
+                done.push(line);
+                continue;
+            };
+            let data = match fs::read(&upload.path).await {
+                Ok(data) => data,
+                Err(e) => {
+                    // The cached file was removed, nothing left to upload
+                    warn!(path = %upload.path.display(), error = %e, "Dropping queued upload");
+                    done.push(line);
+                    continue;
+                }
+            };
+            match bucket
+                .put_object_with_content_type(&upload.object_key, &data, &upload.content_type)
+                .await
+            {
+                Ok(_) => {
+                    info!("S3Processor: uploaded queued {}", upload.object_key);
+                    if upload.path.starts_with(&self.spool_dir) {
+                        let _ = fs::remove_file(&upload.path).await;
+                    }
+                    done.push(line);
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to upload queued {} to S3: {:?}",
+                        upload.object_key, e
+                    );
+                    break;
+                }
+            }
+        }
+        if !done.is_empty()
+            && let Err(e) = self.remove(&done).await
+        {
+            error!(path = %self.path.display(), error = %e, "Failed to update upload queue");
+        }
+    }
+}
+
+/// Content of the queue file, empty when there is none.
+async fn read_queue(path: &Path) -> Result<String, Error> {
+    match fs::read_to_string(path).await {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace the queue file with `content`, through a temporary file so a
+/// crash leaves either the old or the new queue.
+async fn write_queue(path: &Path, content: &str) -> Result<(), Error> {
+    if content.is_empty() {
+        return match fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    let temp = path.with_extension("jsonl.tmp");
+    fs::write(&temp, content).await?;
+    fs::rename(&temp, path).await?;
+    Ok(())
+}
+
+/// Remove the queued uploads `purge` selects by local path and object key
+/// from the queue in `cache_dir`, deleting their spooled variants. Returns
+/// how many were removed.
+pub async fn drop_queued(
+    cache_dir: &Path,
+    purge: impl Fn(&Path, &str) -> bool,
+) -> Result<usize, Error> {
+    let queue = UploadQueue::new(cache_dir);
+    let _lock = QUEUE_LOCK.lock().await;
+    let content = read_queue(&queue.path).await?;
+
+    let mut kept = String::new();
+    let mut dropped = 0;
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        let upload: QueuedUpload = match serde_json::from_str(line) {
+            Ok(upload) => upload,
+            Err(_) => {
+                // Left for `drain` to report
+                kept.push_str(line);
+                kept.push('\n');
+                continue;
+            }
+        };
+        if !purge(&upload.path, &upload.object_key) {
+            kept.push_str(line);
+            kept.push('\n');
+            continue;
+        }
+        if upload.path.starts_with(&queue.spool_dir) {
+            match fs::remove_file(&upload.path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        dropped += 1;
+    }
+
+    if dropped > 0 {
+        write_queue(&queue.path, &kept).await?;
+    }
+    Ok(dropped)
+}
+
+/// Create a handle to the configured S3 bucket.
+pub fn open_bucket(config: &S3Config) -> Result<Box<Bucket>, Error> {
+    let region = Region::Custom {
+        region: config.region.clone(),
+        endpoint: config.endpoint.clone(),
+    };
+
+    let credentials = Credentials::new(
+        Some(&config.access_key),
+        Some(&config.secret_key),
+        None,
+        None,
+        None,
+    )
+    .context("Failed to create S3 credentials")?;
+
+    let bucket = Bucket::new(&config.bucket, region, credentials)
+        .context("Failed to create S3 bucket")?
+        .with_path_style();
+    Ok(bucket)
 }
 
 impl Processor<ImageEvent, AwEvent> for S3Processor {
     fn process(
-        self,
+        mut self,
         mut rx: Receiver<ImageEvent>,
         tx: Sender<AwEvent>,
     ) -> Result<JoinHandle<()>, Error> {
         Ok(tokio::spawn(async move {
             while let Some(event) = rx.recv().await {
-                info!("S3Processor: uploading {} images", event.datas.len());
+                let metered = match &mut self.metered {
+                    Some(check) => check.is_metered().await,
+                    None => false,
+                };
+                let on_battery = self
+                    .power
+                    .as_ref()
+                    .is_some_and(|profile| *profile.borrow() == PowerProfile::BatterySaver);
+                let defer = metered || on_battery;
+
+                // Upload the upload variant when the cache stage made one
+                let variant = event.upload.is_some();
+                let (datas, content_type) = match event.upload {
+                    Some(upload) => (upload.datas, upload.content_type),
+                    None => (event.datas, event.content_type),
+                };
 
                 let mut upload_futures = Vec::new();
-                for (key, data) in event.datas {
+                let mut queued = Vec::new();
+                if defer {
+                    info!(
+                        metered,
+                        on_battery,
+                        "S3Processor: queueing {} images",
+                        datas.len()
+                    );
+                    for (key, data) in &datas {
+                        let (Some(image_info), Some(queue)) =
+                            (event.monitors.get(key), &self.queue)
+                        else {
+                            continue;
+                        };
+                        // The cached file, unless the upload is encoded differently
+                        let cached = event.files.get(key).filter(|_| !variant);
+                        let path = match cached {
+                            Some(path) => path.clone(),
+                            None => match queue.spool(&image_info.object_key, data).await {
+                                Ok(path) => path,
+                                Err(e) => {
+                                    error!(error = %e, "Failed to spool upload");
+                                    continue;
+                                }
+                            },
+                        };
+                        queued.push(QueuedUpload {
+                            path,
+                            object_key: image_info.object_key.clone(),
+                            content_type: content_type.to_string(),
+                        });
+                    }
+                } else {
+                    info!("S3Processor: uploading {} images", datas.len());
+                }
+
+                let datas = if defer { Default::default() } else { datas };
+                for (key, data) in datas {
                     let bucket = self.bucket.clone();
                     let Some(image_info) = event.monitors.get(&key) else {
                         warn!("Failed to get upload info for key {}", key);
@@ -64,7 +379,7 @@ impl Processor<ImageEvent, AwEvent> for S3Processor {
                     let object_key = image_info.object_key.clone();
                     let upload_task = async move {
                         match bucket
-                            .put_object_with_content_type(&object_key, &data, "image/webp")
+                            .put_object_with_content_type(&object_key, &data, content_type)
                             .await
                         {
                             Ok(_) => {
@@ -81,6 +396,48 @@ impl Processor<ImageEvent, AwEvent> for S3Processor {
                     upload_futures.push(upload_task);
                 }
 
+                let mut thumbnail_futures = Vec::new();
+                for (key, data) in event.thumbnails {
+                    let Some(thumbnail) =
+                        event.monitors.get(&key).and_then(|m| m.thumbnail.as_ref())
+                    else {
+                        continue;
+                    };
+                    let object_key = thumbnail.object_key.clone();
+                    if defer {
+                        let path = match (event.thumbnail_files.get(&key), &self.queue) {
+                            (Some(path), _) => path.clone(),
+                            (None, Some(queue)) => match queue.spool(&object_key, &data).await {
+                                Ok(path) => path,
+                                Err(e) => {
+                                    error!(error = %e, "Failed to spool thumbnail upload");
+                                    continue;
+                                }
+                            },
+                            (None, None) => continue,
+                        };
+                        queued.push(QueuedUpload {
+                            path,
+                            object_key: object_key.clone(),
+                            content_type: THUMBNAIL_CONTENT_TYPE.to_string(),
+                        });
+                        continue;
+                    }
+                    let bucket = self.bucket.clone();
+                    thumbnail_futures.push(async move {
+                        if let Err(e) = bucket
+                            .put_object_with_content_type(
+                                &object_key,
+                                &data,
+                                THUMBNAIL_CONTENT_TYPE,
+                            )
+                            .await
+                        {
+                            error!("Failed to upload thumbnail {} to S3: {:?}", object_key, e);
+                        }
+                    });
+                }
+
                 // Create AwEvent with all monitor info
                 let mut aw_event = AwEvent::new(
                     event.timestamp,
@@ -94,7 +451,8 @@ impl Processor<ImageEvent, AwEvent> for S3Processor {
                 }
 
                 // Run uploads and update status
-                let results = join_all(upload_futures).await;
+                let (results, _) =
+                    future::join(join_all(upload_futures), join_all(thumbnail_futures)).await;
 
                 for (success, key) in results {
                     if success {
@@ -107,6 +465,18 @@ impl Processor<ImageEvent, AwEvent> for S3Processor {
                     error!("Failed to send event to channel: {}", e);
                     break;
                 }
+
+                if let Some(queue) = &self.queue {
+                    if !queued.is_empty() {
+                        if let Err(e) = queue.push(&queued).await {
+                            error!(path = %queue.path.display(), error = %e, "Failed to queue uploads");
+                        }
+                    } else if !defer {
+                        queue
+                            .drain(&self.bucket, MAX_QUEUED_UPLOADS_PER_EVENT)
+                            .await;
+                    }
+                }
             }
             info!("S3Processor finished");
         }))
@@ -0,0 +1,402 @@
+//! Spill-to-disk processor.
+//!
+//! This module provides a `Processor` that sits in front of the encoder and
+//! bounds the memory held by frames waiting to be encoded. When the encoder
+//! falls behind, queued frames beyond `max_memory_events` are written to a
+//! spill directory as zstd-compressed raw RGBA and read back, in order, once
+//! the encoder has room again.
+//!
+//! Spill files live in a dedicated subdirectory of `spill.dir` (by default
+//! the cache dir) and are named `spill-{n}.rgba.zst`; only such files are
+//! ever deleted there, so pointing `spill.dir` at a directory with other data
+//! is safe. The frames are unredacted screen contents: the subdirectory is
+//! only accessible to the current user, and on Unix one owned by another user
+//! is refused. Each running instance locks a numbered slot in it, so
+//! instances sharing the directory never touch each other's files.
+
+use crate::config::SpillConfig;
+use crate::event::CaptureEvent;
+use crate::worker::Processor;
+use anyhow::{Context, Error, Result};
+use image::{DynamicImage, RgbaImage};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+/// Subdirectory of `spill.dir` (or the cache dir) holding spill files.
+const SPILL_SUBDIR: &str = "aw-watcher-screenshot-spill";
+const SPILL_PREFIX: &str = "spill-";
+const SPILL_EXTENSION: &str = ".rgba.zst";
+
+/// Lock file an instance holds in its slot while running.
+const LOCK_FILE: &str = "lock";
+
+/// Number of instances that can spill to the same directory at once.
+const MAX_SLOTS: u32 = 16;
+
+/// Directory the spill files of `config` are written to, in one numbered
+/// slot subdirectory per running instance.
+pub fn spill_dir(config: &SpillConfig, cache_dir: &Path) -> PathBuf {
+    config
+        .dir
+        .clone()
+        .unwrap_or_else(|| cache_dir.to_path_buf())
+        .join(SPILL_SUBDIR)
+}
+
+/// Create `dir` accessible only to the current user. On Unix an existing
+/// directory must be owned by the current user and is made private.
+fn create_private_dir(dir: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+        match fs::DirBuilder::new().mode(0o700).create(dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+        // Not followed, a link could point anywhere
+        let metadata = fs::symlink_metadata(dir)?;
+        if !metadata.is_dir() {
+            anyhow::bail!("{} is not a directory", dir.display());
+        }
+        if metadata.uid() != unsafe { libc::getuid() } {
+            anyhow::bail!("{} is not owned by the current user", dir.display());
+        }
+        if metadata.mode() & 0o077 != 0 {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+/// Options creating files readable and writable only by the current user.
+fn private_file() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+/// Lock the first slot under `dir` no other instance holds. Returns the
+/// slot's directory and its lock, held until the file is dropped.
+fn lock_slot(dir: &Path) -> Result<(PathBuf, fs::File), Error> {
+    for slot in 0..MAX_SLOTS {
+        let slot_dir = dir.join(slot.to_string());
+        create_private_dir(&slot_dir)?;
+        // Not truncated, another instance may hold it
+        let lock = private_file()
+            .truncate(false)
+            .open(slot_dir.join(LOCK_FILE))?;
+        match lock.try_lock() {
+            Ok(()) => return Ok((slot_dir, lock)),
+            Err(fs::TryLockError::WouldBlock) => continue,
+            Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+    anyhow::bail!(
+        "All {} spill slots in {} are in use",
+        MAX_SLOTS,
+        dir.display()
+    )
+}
+
+/// Whether a file name is one the spiller writes.
+pub fn is_spill_file(name: &str) -> bool {
+    name.strip_prefix(SPILL_PREFIX)
+        .and_then(|rest| rest.strip_suffix(SPILL_EXTENSION))
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Delete the spill files in `dir`, leaving anything else in place. Returns
+/// how many were deleted.
+pub fn clear_spill_files(dir: &Path) -> Result<usize, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut deleted = 0;
+    for entry in entries {
+        let entry = entry?;
+        let is_spill =
+            entry.file_type()?.is_file() && entry.file_name().to_str().is_some_and(is_spill_file);
+        if is_spill {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("Failed to delete {}", entry.path().display()))?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// A queued event, either in memory or with its images on disk.
+enum Queued {
+    Memory(CaptureEvent),
+    /// The event without images, plus the spill file of each image.
+    Spilled(CaptureEvent, HashMap<u32, PathBuf>),
+}
+
+/// Processor that spills waiting frames to disk under backlog.
+///
+/// Runs directly before `ToWebpProcessor`, after all stages that inspect
+/// pixels.
+pub struct SpillProcessor {
+    config: SpillConfig,
+    /// This instance's slot, see `lock_slot`.
+    dir: PathBuf,
+    _lock: fs::File,
+    next_file: u64,
+}
+
+impl SpillProcessor {
+    pub fn new(config: SpillConfig, cache_dir: &Path) -> Result<Self, Error> {
+        let root = spill_dir(&config, cache_dir);
+        if let Some(parent) = root.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        create_private_dir(&root)
+            .with_context(|| format!("Failed to create spill directory {}", root.display()))?;
+        let (dir, lock) = lock_slot(&root)?;
+        // Frames a previous run left in this slot cannot be matched to events anymore
+        let stale = clear_spill_files(&dir)
+            .with_context(|| format!("Failed to clear spill directory {}", dir.display()))?;
+        if stale > 0 {
+            info!(files = stale, "Deleted stale spill files");
+        }
+
+        Ok(Self {
+            config,
+            dir,
+            _lock: lock,
+            next_file: 0,
+        })
+    }
+
+    /// Write the images of an event to disk, keeping the rest in memory.
+    async fn spill(&mut self, mut event: CaptureEvent) -> Queued {
+        let mut files = HashMap::new();
+        for (id, image) in std::mem::take(&mut event.images) {
+            let path = self.dir.join(format!(
+                "{}{}{}",
+                SPILL_PREFIX, self.next_file, SPILL_EXTENSION
+            ));
+            self.next_file += 1;
+
+            let level = self.config.compression_level;
+            let target = path.clone();
+            let result =
+                tokio::task::spawn_blocking(move || write_frame(&target, &image, level)).await;
+            match result {
+                Ok(Ok(())) => {
+                    files.insert(id, path);
+                }
+                Ok(Err(e)) => error!(monitor_id = id, error = %e, "Failed to spill frame"),
+                Err(e) => error!(error = %e, "Failed to spawn spill task"),
+            }
+        }
+        debug!(images = files.len(), "Spilled frames to disk");
+        Queued::Spilled(event, files)
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for SpillProcessor {
+    fn process(
+        mut self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        Ok(tokio::spawn(async move {
+            let mut queue: VecDeque<Queued> = VecDeque::new();
+            let mut in_memory = 0;
+            let mut on_disk = 0;
+            let mut input_open = true;
+
+            while input_open || !queue.is_empty() {
+                tokio::select! {
+                    // Prefer draining the queue so frames stay in order
+                    biased;
+                    permit = tx.reserve(), if !queue.is_empty() => {
+                        let Ok(permit) = permit else {
+                            info!("SpillProcessor: receiver dropped, stopping");
+                            break;
+                        };
+                        let event = match queue.pop_front() {
+                            Some(Queued::Memory(event)) => {
+                                in_memory -= 1;
+                                event
+                            }
+                            Some(Queued::Spilled(event, files)) => {
+                                on_disk -= 1;
+                                restore(event, files).await
+                            }
+                            None => continue,
+                        };
+                        permit.send(event);
+                    }
+                    event = rx.recv(), if input_open => {
+                        let Some(event) = event else {
+                            input_open = false;
+                            continue;
+                        };
+
+                        if queue.is_empty() && tx.capacity() > 0 {
+                            if tx.send(event).await.is_err() {
+                                info!("SpillProcessor: receiver dropped, stopping");
+                                break;
+                            }
+                        } else if in_memory < self.config.max_memory_events {
+                            in_memory += 1;
+                            queue.push_back(Queued::Memory(event));
+                        } else if on_disk >= self.config.max_spilled_events {
+                            warn!(
+                                queued = queue.len(),
+                                "Spill queue full, dropping frame"
+                            );
+                        } else {
+                            on_disk += 1;
+                            let spilled = self.spill(event).await;
+                            queue.push_back(spilled);
+                            warn!(queued = queue.len(), "Encoder backlog, spilling frames to disk");
+                        }
+                    }
+                }
+            }
+            info!("SpillProcessor finished");
+        }))
+    }
+}
+
+/// Read the spilled images of an event back and delete their files.
+async fn restore(mut event: CaptureEvent, files: HashMap<u32, PathBuf>) -> CaptureEvent {
+    for (id, path) in files {
+        let result = tokio::task::spawn_blocking(move || {
+            let image = read_frame(&path);
+            let _ = fs::remove_file(&path);
+            image
+        })
+        .await;
+        match result {
+            Ok(Ok(image)) => {
+                event.images.insert(id, Arc::new(image));
+                continue;
+            }
+            Ok(Err(e)) => error!(monitor_id = id, error = %e, "Failed to restore spilled frame"),
+            Err(e) => error!(error = %e, "Failed to spawn restore task"),
+        }
+        // Drop metadata of frames that could not be restored; withheld
+        // frames and clips had no image to spill and keep theirs
+        event.monitors.remove(&id);
+    }
+    event
+}
+
+/// Write a frame as `width`, `height` (u32 LE) followed by zstd-compressed RGBA.
+fn write_frame(path: &Path, image: &DynamicImage, level: i32) -> Result<(), Error> {
+    let converted;
+    let rgba = match image {
+        DynamicImage::ImageRgba8(buffer) => buffer,
+        other => {
+            converted = other.to_rgba8();
+            &converted
+        }
+    };
+
+    let mut data = Vec::with_capacity(8);
+    data.extend_from_slice(&rgba.width().to_le_bytes());
+    data.extend_from_slice(&rgba.height().to_le_bytes());
+    data.extend(zstd::bulk::compress(rgba.as_raw(), level)?);
+    private_file().truncate(true).open(path)?.write_all(&data)?;
+    Ok(())
+}
+
+fn read_frame(path: &Path) -> Result<DynamicImage, Error> {
+    let data = fs::read(path)?;
+    if data.len() < 8 {
+        return Err(anyhow::anyhow!("Truncated spill file {}", path.display()));
+    }
+    let width = u32::from_le_bytes(data[0..4].try_into()?);
+    let height = u32::from_le_bytes(data[4..8].try_into()?);
+    let raw = zstd::bulk::decompress(&data[8..], width as usize * height as usize * 4)?;
+    let image = RgbaImage::from_raw(width, height, raw)
+        .ok_or_else(|| anyhow::anyhow!("Corrupt spill file {}", path.display()))?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("spill-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frame.rgba.zst");
+
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(17, 9, |x, y| {
+            image::Rgba([x as u8, y as u8, 7, 255])
+        }));
+        write_frame(&path, &image, 1).unwrap();
+        let restored = read_frame(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(restored.as_bytes(), image.as_bytes());
+    }
+
+    #[test]
+    fn test_clear_spill_files() {
+        let dir = std::env::temp_dir().join(format!("spill-clear-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "spill-0.rgba.zst",
+            "spill-17.rgba.zst",
+            "notes.txt",
+            "spill-x.rgba.zst",
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        assert_eq!(clear_spill_files(&dir).unwrap(), 2);
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(left, vec!["notes.txt", "spill-x.rgba.zst"]);
+    }
+
+    #[test]
+    fn test_lock_slot() {
+        let dir = std::env::temp_dir().join(format!("spill-slots-{}", std::process::id()));
+        create_private_dir(&dir).unwrap();
+
+        let (first, first_lock) = lock_slot(&dir).unwrap();
+        let (second, _second_lock) = lock_slot(&dir).unwrap();
+        assert_ne!(first, second);
+        drop(first_lock);
+        let (reused, _reused_lock) = lock_slot(&dir).unwrap();
+        assert_eq!(reused, first);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&dir), 0o700);
+            assert_eq!(mode(&first), 0o700);
+            assert_eq!(mode(&first.join(LOCK_FILE)), 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
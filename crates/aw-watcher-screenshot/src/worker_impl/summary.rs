@@ -0,0 +1,499 @@
+//! Daily activity summary processor.
+//!
+//! This module provides a `Processor` that aggregates a day's captures into a
+//! single structured summary: focused applications, the most frequent OCR
+//! words and change intensity per hour. At the end of each day the summary is
+//! posted to a dedicated aw-server bucket and written as `summary.json` /
+//! `summary.md` into that day's cache directory, optionally with a digest
+//! written by `summary.digest_command`. Capture events are passed through
+//! unchanged.
+
+use crate::config::{AwServerConfig, CacheConfig, SummaryConfig};
+use crate::event::CaptureEvent;
+use crate::timezone::TimePolicy;
+use crate::worker::Processor;
+use anyhow::{Context, Error, Result};
+use aw_client_lite::AwClient;
+use aw_models::Event;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Distinct OCR words tracked per day before words seen only once are
+/// forgotten.
+const MAX_TRACKED_WORDS: usize = 50_000;
+
+/// Frequent words too generic to be keywords; shorter words are skipped
+/// anyway.
+const STOP_WORDS: &[&str] = &[
+    "about", "been", "from", "have", "into", "more", "some", "than", "that", "their", "them",
+    "then", "there", "they", "this", "were", "what", "when", "which", "will", "with", "would",
+    "your",
+];
+
+/// A frequent OCR word and the number of stored frames it appeared in.
+#[derive(Serialize, Debug, PartialEq)]
+struct Keyword {
+    word: String,
+    frames: u64,
+}
+
+/// Change intensity accumulated for one hour of the day.
+#[derive(Serialize, Default, Clone, Copy)]
+struct HourIntensity {
+    samples: u64,
+    total_distance: u64,
+}
+
+impl HourIntensity {
+    fn mean_distance(&self) -> f64 {
+        self.total_distance as f64 / self.samples.max(1) as f64
+    }
+}
+
+/// Aggregated activity for a single day.
+#[derive(Serialize)]
+struct DailySummary {
+    date: NaiveDate,
+    first_capture: DateTime<Utc>,
+    last_capture: DateTime<Utc>,
+    /// UTC offset of the day boundaries, when they follow local time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    utc_offset: Option<String>,
+    /// Number of capture ticks seen.
+    samples: u64,
+    /// Number of images that passed the filter and were stored.
+    stored_images: u64,
+    /// Samples per focused application.
+    apps: BTreeMap<String, u64>,
+    /// Mean hash distance per hour of the day.
+    hourly_change: BTreeMap<u32, f64>,
+    /// Most frequent OCR words, see `summary.keywords`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keywords: Vec<Keyword>,
+    /// Natural-language digest from `summary.digest_command`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    #[serde(skip)]
+    hours: BTreeMap<u32, HourIntensity>,
+    /// Stored frames per OCR word.
+    #[serde(skip)]
+    words: HashMap<String, u64>,
+    #[serde(skip)]
+    time_policy: TimePolicy,
+}
+
+impl DailySummary {
+    fn new(date: NaiveDate, timestamp: DateTime<Utc>, time_policy: TimePolicy) -> Self {
+        Self {
+            date,
+            first_capture: timestamp,
+            last_capture: timestamp,
+            utc_offset: time_policy.utc_offset(timestamp),
+            samples: 0,
+            stored_images: 0,
+            apps: BTreeMap::new(),
+            hourly_change: BTreeMap::new(),
+            keywords: Vec::new(),
+            digest: None,
+            hours: BTreeMap::new(),
+            words: HashMap::new(),
+            time_policy,
+        }
+    }
+
+    fn record(&mut self, event: &CaptureEvent) {
+        self.last_capture = event.timestamp;
+        self.samples += 1;
+        self.stored_images += event.images.len() as u64;
+
+        if let Some(window) = &event.focused_window {
+            *self.apps.entry(window.app_name.clone()).or_default() += 1;
+        }
+
+        let hour_of_day = self.time_policy.hour(event.timestamp);
+        let hour = self.hours.entry(hour_of_day).or_default();
+        hour.samples += 1;
+        hour.total_distance += event.change_distances.values().copied().max().unwrap_or(0) as u64;
+        self.hourly_change.insert(hour_of_day, hour.mean_distance());
+
+        // Words of stored frames only, each counted once per frame
+        for id in event.images.keys() {
+            let Some(ocr) = event.ocr.get(id) else {
+                continue;
+            };
+            let words: HashSet<String> = ocr
+                .words
+                .iter()
+                .filter_map(|word| keyword(&word.text))
+                .collect();
+            for word in words {
+                *self.words.entry(word).or_default() += 1;
+            }
+        }
+        if self.words.len() > MAX_TRACKED_WORDS {
+            self.words.retain(|_, frames| *frames > 1);
+        }
+    }
+
+    /// Fill in the `max` most frequent OCR words.
+    fn finish(&mut self, max: usize) {
+        let mut keywords: Vec<Keyword> = self
+            .words
+            .drain()
+            .map(|(word, frames)| Keyword { word, frames })
+            .collect();
+        keywords.sort_by(|a, b| b.frames.cmp(&a.frames).then_with(|| a.word.cmp(&b.word)));
+        keywords.truncate(max);
+        self.keywords = keywords;
+    }
+
+    /// Applications ordered by how often they were focused, most frequent first.
+    fn top_apps(&self) -> Vec<(&String, &u64)> {
+        let mut apps: Vec<_> = self.apps.iter().collect();
+        apps.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        apps
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "# Activity summary for {}\n", self.date);
+        let _ = writeln!(
+            md,
+            "- Captures: {} ({} images stored)",
+            self.samples, self.stored_images
+        );
+        let _ = writeln!(
+            md,
+            "- Active from {} to {}\n",
+            self.time_policy.format(self.first_capture, "%H:%M"),
+            self.time_policy.format(self.last_capture, "%H:%M")
+        );
+
+        let _ = writeln!(md, "## Applications\n");
+        let _ = writeln!(md, "| Application | Samples |");
+        let _ = writeln!(md, "|---|---|");
+        for (app, samples) in self.top_apps() {
+            let _ = writeln!(md, "| {} | {} |", app, samples);
+        }
+
+        let _ = writeln!(md, "\n## Change intensity per hour\n");
+        let _ = writeln!(md, "| Hour | Mean distance |");
+        let _ = writeln!(md, "|---|---|");
+        for (hour, distance) in &self.hourly_change {
+            let _ = writeln!(md, "| {:02}:00 | {:.1} |", hour, distance);
+        }
+
+        if !self.keywords.is_empty() {
+            let _ = writeln!(md, "\n## Keywords\n");
+            let _ = writeln!(md, "| Word | Frames |");
+            let _ = writeln!(md, "|---|---|");
+            for keyword in &self.keywords {
+                let _ = writeln!(md, "| {} | {} |", keyword.word, keyword.frames);
+            }
+        }
+        if let Some(digest) = &self.digest {
+            let _ = writeln!(md, "\n## Digest\n\n{}", digest);
+        }
+        md
+    }
+
+    fn to_event(&self) -> Event {
+        let data = match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new(),
+        };
+
+        Event {
+            id: None,
+            timestamp: self.first_capture,
+            duration: self.last_capture - self.first_capture,
+            data,
+        }
+    }
+}
+
+/// A word worth listing as a keyword, lowercased: at least four letters and
+/// not a stop word.
+fn keyword(text: &str) -> Option<String> {
+    let word = text
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+    (word.chars().count() >= 4
+        && word.chars().all(char::is_alphabetic)
+        && !STOP_WORDS.contains(&word.as_str()))
+    .then_some(word)
+}
+
+/// Processor that produces one summary per day of captures.
+///
+/// Runs after OCR, so the keywords come from the text of stored frames
+/// (with redacted words masked).
+pub struct DailySummaryProcessor {
+    config: SummaryConfig,
+    client: AwClient,
+    bucket_id: String,
+    cache_dir: PathBuf,
+    time_policy: TimePolicy,
+
+    current: Option<DailySummary>,
+}
+
+impl DailySummaryProcessor {
+    pub async fn new(
+        config: SummaryConfig,
+        cache_config: &CacheConfig,
+        aw_config: &AwServerConfig,
+    ) -> Result<Self, Error> {
+        let client = AwClient::new(&aw_config.host, aw_config.port);
+        let bucket_id = format!("{}_{}", config.bucket_id, aw_config.hostname);
+
+        let bucket = serde_json::json!({
+            "id": bucket_id,
+            "client": aw_config.bucket_id,
+            "hostname": aw_config.hostname,
+            "type": "uno.guan810.screenshot.summary"
+        });
+
+        client.create_bucket(&bucket).await?;
+        info!("Summary bucket {} initialized.", bucket_id);
+
+        Ok(Self {
+            config,
+            client,
+            bucket_id,
+            cache_dir: PathBuf::from(&cache_config.cache_dir),
+            time_policy: TimePolicy::default(),
+            current: None,
+        })
+    }
+
+    /// Draw day boundaries according to `policy`.
+    pub fn with_time_policy(mut self, policy: TimePolicy) -> Self {
+        self.time_policy = policy;
+        self
+    }
+
+    /// Account one capture tick, returning the finished day if it rolled over.
+    fn record(&mut self, event: &CaptureEvent) -> Option<DailySummary> {
+        let date = self.time_policy.date(event.timestamp);
+
+        let finished = match &self.current {
+            Some(summary) if summary.date != date => self.current.take(),
+            _ => None,
+        };
+        self.current
+            .get_or_insert_with(|| DailySummary::new(date, event.timestamp, self.time_policy))
+            .record(event);
+
+        finished
+    }
+
+    async fn publish(&self, mut summary: DailySummary) {
+        info!(date = %summary.date, samples = summary.samples, "Publishing daily summary");
+        summary.finish(self.config.keywords);
+
+        let dir = self
+            .cache_dir
+            .join(summary.date.format("%Y/%m/%d").to_string());
+        if let Some(command) = &self.config.digest_command {
+            match self.digest(command, &dir, &summary).await {
+                Ok(digest) if !digest.is_empty() => summary.digest = Some(digest),
+                Ok(_) => warn!(command, "Digest command wrote nothing"),
+                Err(e) => error!(command, error = %e, "Failed to write the daily digest"),
+            }
+        }
+        let summary = &summary;
+        if let Err(e) = self.write_files(&dir, summary).await {
+            error!(path = %dir.display(), error = %e, "Failed to write daily summary");
+        }
+
+        if let Err(e) = self
+            .client
+            .insert_event(&self.bucket_id, &summary.to_event())
+            .await
+        {
+            error!("Failed to publish daily summary event: {}", e);
+        }
+    }
+
+    /// Run the digest command on the Markdown summary in the day's cache
+    /// directory and return its trimmed output.
+    async fn digest(
+        &self,
+        command: &str,
+        dir: &Path,
+        summary: &DailySummary,
+    ) -> Result<String, Error> {
+        fs::create_dir_all(dir).await?;
+        let mut child = Command::new(command)
+            .args(&self.config.digest_args)
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .context("digest command stdin unavailable")?;
+        let input = summary.to_markdown();
+        let run = async {
+            // Dropping stdin closes it, so the command sees the end of input
+            let (written, output) = tokio::join!(
+                async move { stdin.write_all(input.as_bytes()).await },
+                child.wait_with_output()
+            );
+            written?;
+            output
+        };
+        let timeout = Duration::from_secs(self.config.digest_timeout_secs);
+        let output = tokio::time::timeout(timeout, run)
+            .await
+            .context("Digest command timed out")??;
+        if !output.status.success() {
+            anyhow::bail!(
+                "exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    async fn write_files(&self, dir: &Path, summary: &DailySummary) -> Result<(), Error> {
+        fs::create_dir_all(dir).await?;
+        fs::write(
+            dir.join("summary.json"),
+            serde_json::to_vec_pretty(summary)?,
+        )
+        .await?;
+        fs::write(dir.join("summary.md"), summary.to_markdown()).await?;
+        Ok(())
+    }
+}
+
+impl Processor<CaptureEvent, CaptureEvent> for DailySummaryProcessor {
+    fn process(
+        mut self,
+        mut rx: Receiver<CaptureEvent>,
+        tx: Sender<CaptureEvent>,
+    ) -> Result<JoinHandle<()>, Error> {
+        Ok(tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if !event.is_clip_frame()
+                    && let Some(finished) = self.record(&event)
+                {
+                    self.publish(finished).await;
+                }
+
+                if let Err(e) = tx.send(event).await {
+                    info!(error = %e, "DailySummaryProcessor: receiver dropped, stopping");
+                    break;
+                }
+            }
+
+            // Publish the partial day on shutdown
+            if let Some(summary) = self.current.take() {
+                self.publish(summary).await;
+            }
+            info!("DailySummaryProcessor finished");
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{FocusedWindow, OcrResult, OcrWord, Rect};
+
+    fn event_at(timestamp: DateTime<Utc>, app: &str) -> CaptureEvent {
+        let mut event = CaptureEvent::new();
+        event.timestamp = timestamp;
+        event.focused_window = Some(FocusedWindow {
+            app_name: app.to_string(),
+            title: String::new(),
+            pid: 0,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        });
+        event
+    }
+
+    #[test]
+    fn test_summary_counts_apps() {
+        let ts = "2024-05-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut summary = DailySummary::new(ts.date_naive(), ts, TimePolicy::default());
+        summary.record(&event_at(ts, "code"));
+        summary.record(&event_at(ts, "firefox"));
+        summary.record(&event_at(ts, "code"));
+
+        assert_eq!(summary.samples, 3);
+        assert_eq!(summary.top_apps()[0], (&"code".to_string(), &2));
+        assert!(summary.to_markdown().contains("| code | 2 |"));
+    }
+
+    #[test]
+    fn test_summary_keywords() {
+        let ts = "2024-05-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut summary = DailySummary::new(ts.date_naive(), ts, TimePolicy::default());
+        let frame = |text: &str| {
+            let mut event = event_at(ts, "code");
+            event
+                .images
+                .insert(0, std::sync::Arc::new(image::DynamicImage::new_rgb8(1, 1)));
+            let words = text
+                .split(' ')
+                .map(|word| OcrWord {
+                    text: word.to_string(),
+                    bounds: Rect {
+                        x: 0,
+                        y: 0,
+                        width: 1,
+                        height: 1,
+                    },
+                    line: 0,
+                })
+                .collect();
+            event.ocr.insert(
+                0,
+                OcrResult {
+                    words,
+                    language: None,
+                },
+            );
+            event
+        };
+        summary.record(&frame("Invoice invoice, with [redacted] 2024 tax"));
+        summary.record(&frame("Quarterly invoice"));
+        // OCR of a frame that is not stored
+        let mut withheld = frame("secret");
+        withheld.images.clear();
+        summary.record(&withheld);
+
+        summary.finish(2);
+        let keyword = |word: &str, frames| Keyword {
+            word: word.to_string(),
+            frames,
+        };
+        assert_eq!(
+            summary.keywords,
+            vec![keyword("invoice", 2), keyword("quarterly", 1)]
+        );
+        assert!(summary.to_markdown().contains("| invoice | 2 |"));
+    }
+}